@@ -0,0 +1,60 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmark of the project config lookup in a deep tree, the cached lookup
+//! against the one that walks up the tree on every call. The formatted
+//! outputs are remembered so rustfmt only runs once per mode, the time is
+//! spent resolving the configuration. Run it with
+//! `cargo bench --bench config_lookup`
+
+extern crate rustanaconda;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::process;
+use std::time::{Duration, Instant};
+
+use rustanaconda::{format_string, FormatOptions};
+
+// directories between the project config and the file
+const DEPTH: usize = 64;
+
+const CALLS: u32 = 1000;
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + duration.subsec_nanos() as u64 / 1000
+}
+
+fn main() {
+    let root = env::temp_dir().join(format!("anaconda-bench-{}", process::id()));
+    let dir = (0..DEPTH).fold(root.clone(), |dir, level| dir.join(format!("level{}", level)));
+    fs::create_dir_all(&dir).unwrap();
+    File::create(root.join("rustfmt.toml")).and_then(|mut file| file.write_all(b"max_width = 100\n")).unwrap();
+
+    for &bypass in &[false, true] {
+        let options = FormatOptions {
+            config_path: Some(dir.to_string_lossy().into_owned()),
+            bypass_config_cache: bypass,
+        };
+        let start = Instant::now();
+        for _ in 0..CALLS {
+            format_string("fn main() {}\n", &options).unwrap();
+        }
+        println!("{} levels, {}: {} us per call",
+                 DEPTH,
+                 if bypass { "uncached" } else { "cached" },
+                 micros(start.elapsed()) / CALLS as u64);
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,58 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmark of `format_dir` over a directory of many files, on a single
+//! thread and on the default pool (a thread per CPU). The files are not
+//! formatted so every run formats all of them. Run it with
+//! `cargo bench --bench format_dir`
+
+extern crate rustanaconda;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::process;
+use std::time::{Duration, Instant};
+
+use rustanaconda::set_max_threads;
+use rustanaconda::project::{format_dir, DirOptions, Mode};
+
+const FILES: usize = 64;
+
+const FUNCTIONS: usize = 200;
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
+}
+
+fn main() {
+    let root = env::temp_dir().join(format!("anaconda-bench-dir-{}", process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let code: String = (0..FUNCTIONS).map(|i| format!("fn f{}() {{\n    let x=1;\n}}\n\n", i)).collect();
+    for i in 0..FILES {
+        File::create(root.join(format!("file{}.rs", i))).and_then(|mut file| file.write_all(code.as_bytes())).unwrap();
+    }
+
+    for &threads in &[1, 0] {
+        set_max_threads(threads);
+        let start = Instant::now();
+        let report = format_dir(&root, &DirOptions::default(), Mode::Check).unwrap();
+        assert_eq!(report.files.len(), FILES);
+        println!("{} files, {}: {} ms",
+                 FILES,
+                 if threads == 1 { "1 thread" } else { "a thread per CPU" },
+                 millis(start.elapsed()));
+    }
+    set_max_threads(0);
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,153 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exports the version of the rustfmt crate that the library is built with
+//! (`rustfmt` or `rustfmt-nightly`, depending on the backend feature) as
+//! `ANACONDA_RUSTFMT_VERSION`, it is read from the Cargo.lock file, and
+//! the channel of the compiler that builds it (and rustfmt) as
+//! `ANACONDA_RUSTC_CHANNEL`. The full compiler version, the target, the
+//! profile and the git commit of the checkout (if any) are exported as
+//! `ANACONDA_RUSTC_VERSION`, `ANACONDA_TARGET`, `ANACONDA_PROFILE` and
+//! `ANACONDA_GIT_COMMIT` for the build info. Anything that can't be found is
+//! left out, the build never fails because of it.
+//!
+//! Cargo doesn't tell build scripts where the lock file is. When the crate is
+//! built as a dependency its manifest dir is in the registry or a git checkout
+//! so the lock file is searched in the parents of the manifest dir and then in
+//! the parents of `OUT_DIR` (the target dir is usually in the project that
+//! depends on this crate). With a `CARGO_TARGET_DIR` outside of that project
+//! the lock file is not found and the rustfmt version is left out.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The value of a `key = "value"` line of the lock file
+fn lock_value<'a>(line: Option<&'a str>, key: &str) -> Option<&'a str> {
+    let prefix = format!("{} = \"", key);
+    match line {
+        Some(line) if line.starts_with(&prefix[..]) && line.ends_with('"') && line.len() > prefix.len() => {
+            Some(&line[prefix.len()..line.len() - 1])
+        }
+        _ => None,
+    }
+}
+
+/// Returns the version and the source (the registry or the repository and
+/// commit, None for path dependencies) of the rustfmt package of the lock file
+fn rustfmt_version(lock: &str, package: &str) -> Option<(String, Option<String>)> {
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if line != name {
+            continue;
+        }
+        return lock_value(lines.next(), "version").map(|version| {
+            let source = lock_value(lines.next(), "source").map(String::from);
+            (String::from(version), source)
+        });
+    }
+    None
+}
+
+/// Returns the first Cargo.lock of the ancestors of the manifest dir or of the
+/// out dir
+fn find_lock(manifest_dir: &Path) -> Option<PathBuf> {
+    let out_dir = env::var("OUT_DIR").map(PathBuf::from).unwrap_or_default();
+    for start in &[manifest_dir, &out_dir] {
+        let mut dir = Some(*start);
+        while let Some(current) = dir {
+            let lock_path = current.join("Cargo.lock");
+            if lock_path.is_file() {
+                return Some(lock_path);
+            }
+            dir = current.parent();
+        }
+    }
+    None
+}
+
+/// Returns the channel of the `rustc --version` output
+fn channel(version: &str) -> &'static str {
+    let release = version.split_whitespace().nth(1).unwrap_or("");
+    if release.contains("-nightly") || release.contains("-dev") {
+        "nightly"
+    } else if release.contains("-beta") {
+        "beta"
+    } else {
+        "stable"
+    }
+}
+
+/// Returns the commit checked out in the directory, the files that change
+/// with it are watched
+fn git_commit(dir: &Path) -> Option<String> {
+    let git_dir = dir.join(".git");
+    if !git_dir.is_dir() {
+        return None;
+    }
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    let mut head = String::new();
+    if File::open(git_dir.join("HEAD")).and_then(|mut file| file.read_to_string(&mut head)).is_ok() {
+        if head.starts_with("ref: ") {
+            println!("cargo:rerun-if-changed={}", git_dir.join(head[5..].trim()).display());
+        }
+    }
+    let output = match Command::new("git").arg("rev-parse").arg("HEAD").current_dir(dir).output() {
+        Ok(ref output) if output.status.success() => output.stdout.clone(),
+        _ => return None,
+    };
+    let commit = String::from_utf8_lossy(&output).trim().to_string();
+    if commit.is_empty() { None } else { Some(commit) }
+}
+
+fn main() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    if let Ok(output) = Command::new(rustc).arg("--version").output() {
+        let version = String::from_utf8_lossy(&output.stdout);
+        println!("cargo:rustc-env=ANACONDA_RUSTC_CHANNEL={}", channel(&version));
+        println!("cargo:rustc-env=ANACONDA_RUSTC_VERSION={}", version.trim());
+    }
+    for &(var, name) in &[("TARGET", "ANACONDA_TARGET"), ("PROFILE", "ANACONDA_PROFILE")] {
+        if let Ok(value) = env::var(var) {
+            println!("cargo:rustc-env={}={}", name, value);
+        }
+    }
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    if let Some(commit) = git_commit(Path::new(&manifest_dir)) {
+        println!("cargo:rustc-env=ANACONDA_GIT_COMMIT={}", commit);
+    }
+
+    let lock_path = match find_lock(Path::new(&manifest_dir)) {
+        Some(lock_path) => lock_path,
+        None => return,
+    };
+    let mut lock = String::new();
+    if File::open(&lock_path).and_then(|mut file| file.read_to_string(&mut lock)).is_err() {
+        return;
+    }
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+    let package = match env::var("CARGO_FEATURE_BACKEND_RUSTFMT_NIGHTLY") {
+        Ok(_) => "rustfmt-nightly",
+        Err(_) => "rustfmt",
+    };
+    if let Some((version, source)) = rustfmt_version(&lock, package) {
+        println!("cargo:rustc-env=ANACONDA_RUSTFMT_VERSION={}", version);
+        if let Some(source) = source {
+            println!("cargo:rustc-env=ANACONDA_RUSTFMT_SOURCE={}", source);
+        }
+    }
+}
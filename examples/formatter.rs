@@ -0,0 +1,50 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Check whether the files given as arguments are formatted with the
+//! configuration of the current directory and a wider `max_width`:
+//!
+//! ```text
+//! cargo run --example formatter -- src/lib.rs src/daemon.rs
+//! ```
+
+extern crate rustanaconda;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use rustanaconda::formatter::{Formatter, Mode};
+
+fn main() {
+    let formatter = match Formatter::builder().override_("max_width", "120").write_mode(Mode::Check).build() {
+        Ok(formatter) => formatter,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut unformatted = 0;
+    for path in env::args().skip(1) {
+        match formatter.format_file(Path::new(&path)) {
+            Ok(ref output) if output.changed => {
+                println!("{} is not formatted", path);
+                unformatted += 1;
+            }
+            Ok(_) => println!("{} is formatted", path),
+            Err(e) => println!("{}: {}", path, e),
+        }
+    }
+    process::exit(if unformatted == 0 { 0 } else { 1 });
+}
@@ -0,0 +1,104 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Debugging aid for bug reports. The formatted code is compared line by line
+//! with the original one and every change gets a comment with the options
+//! that most likely caused it. librustfmt doesn't tell what each of its passes
+//! did so this is a guess, good enough to point the maintainers somewhere.
+//! Only compiled in debug builds.
+
+use backend::Config;
+use changes::{hunks, squash};
+use scanner::{self, Kind};
+
+fn trimmed<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    lines.iter().map(|line| line.trim()).collect()
+}
+
+/// Guess which options are behind the change of `removed` lines into `added`
+fn reasons(removed: &[&str], added: &[&str], config: &Config) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if removed.iter().any(|line| line.len() > config.max_width()) {
+        reasons.push(format!("max_width = {}", config.max_width()));
+    }
+
+    if squash(removed) == squash(added) {
+        if removed.len() == added.len() && trimmed(removed) == trimmed(added) {
+            if removed.iter().zip(added).any(|(a, b)| a.trim_right() != *b) {
+                reasons.push(format!("tab_spaces = {}, hard_tabs = {}", config.tab_spaces(), config.hard_tabs()));
+            } else {
+                reasons.push(String::from("trailing whitespace"));
+            }
+        } else if removed.len() != added.len() {
+            if reasons.is_empty() {
+                reasons.push(format!("ideal_width = {}", config.ideal_width()));
+            }
+        } else {
+            reasons.push(String::from("spacing"));
+        }
+        return reasons;
+    }
+
+    let (mut sorted_removed, mut sorted_added) = (removed.to_vec(), added.to_vec());
+    sorted_removed.sort();
+    sorted_added.sort();
+    if sorted_removed == sorted_added && added.iter().any(|line| line.trim_left().starts_with("use ")) {
+        reasons.push(format!("reorder_imports = {}", config.reorder_imports()));
+    } else if removed.iter().any(|line| line.trim_left().starts_with("//")) {
+        reasons.push(format!("wrap_comments = {}, normalize_comments = {}",
+                             config.wrap_comments(),
+                             config.normalize_comments()));
+    } else if reasons.is_empty() {
+        reasons.push(String::from("rewritten"));
+    }
+    reasons
+}
+
+/// Returns the formatted code with a `// rustfmt: ...` comment before every
+/// change, changes that start inside a multiline literal are not annotated
+pub fn annotate(original: &str, formatted: &str, config: &Config) -> String {
+    let original_lines: Vec<&str> = original.lines().collect();
+    let formatted_lines: Vec<&str> = formatted.lines().collect();
+    let kinds = scanner::kinds(formatted);
+    let mut starts = vec![0];
+    starts.extend(formatted.match_indices('\n').map(|(i, _)| i + 1));
+
+    let mut notes: Vec<(usize, String)> = Vec::new();
+    for ((from_i, to_i), (from_j, to_j)) in hunks(&original_lines, &formatted_lines) {
+        let in_literal = starts.get(from_j).and_then(|&start| kinds.get(start)) == Some(&Kind::Literal);
+        if in_literal {
+            continue;
+        }
+        let mut note = reasons(&original_lines[from_i..to_i], &formatted_lines[from_j..to_j], config).join("; ");
+        if to_j == from_j {
+            note = format!("removed {} lines ({})", to_i - from_i, note);
+        }
+        notes.push((from_j, note));
+    }
+
+    let mut output = String::with_capacity(formatted.len());
+    let mut notes = notes.into_iter().peekable();
+    for (number, line) in formatted_lines.iter().enumerate() {
+        while notes.peek().map(|&(at, _)| at == number).unwrap_or(false) {
+            let (_, note) = notes.next().unwrap();
+            let indent = &line[..line.len() - line.trim_left().len()];
+            output.push_str(&format!("{}// rustfmt: {}\n", indent, note));
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    for (_, note) in notes {
+        output.push_str(&format!("// rustfmt: {}\n", note));
+    }
+    output
+}
@@ -0,0 +1,212 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! rustfmt style command line arguments, so the hosts can pass the library
+//! the same flags that their users would give to the `rustfmt` binary.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use getopts::{Matches, Options};
+use serde_json::{self, Value};
+
+use backend::{Config, WriteMode};
+use {try_project_config_with, AnacondaError, FmtResult};
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "Show this message");
+    opts.optmulti("",
+                  "config",
+                  "Override configuration options",
+                  "[key1=val1,key2=val2...]");
+    opts.optopt("",
+                "config-path",
+                "Resolve the configuration from this file or directory",
+                "[Path for the configuration file]");
+    opts.optopt("", "edition", "Rust edition of the code", "[2015]");
+    opts.optopt("", "write-mode", "How the output is written", "[plain]");
+    opts
+}
+
+/// The usage message of the arguments
+pub fn usage() -> String {
+    options().usage("Arguments: [options]")
+}
+
+/// Returns the `key=value` pairs given with `--config`
+fn overrides(matches: &Matches) -> Result<Vec<(String, String)>, String> {
+    let mut overrides = Vec::new();
+    for list in matches.opt_strs("config") {
+        for pair in list.split(',') {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+                (Some(key), Some(value)) if !key.is_empty() => overrides.push((String::from(key), String::from(value))),
+                _ => return Err(format!("invalid config `{}`, key=value was expected", pair)),
+            }
+        }
+    }
+    Ok(overrides)
+}
+
+/// Apply the `--config` overrides on top of the given config
+pub fn apply(config: &mut Config, overrides: &[(String, String)]) -> Result<(), String> {
+    for &(ref key, ref value) in overrides {
+        // rustfmt panics for unknown keys and values that don't parse
+        let result = panic::catch_unwind(AssertUnwindSafe(|| config.override_value(key, value)));
+        if result.is_err() {
+            return Err(format!("unknown option `{}` or invalid value `{}`", key, value));
+        }
+    }
+    Ok(())
+}
+
+/// Parse the arguments and return the configuration that they describe (the
+/// one resolved from `--config-path` or the current directory with the
+/// `--config` overrides applied) and the write mode. Unknown flags, missing
+/// values and file arguments are errors, None means that `--help` was given
+pub fn parse_options(args: &[String]) -> FmtResult<Option<(Config, WriteMode)>> {
+    let matches = try!(options().parse(args).map_err(|e| AnacondaError::InvalidInput(e.to_string())));
+    if matches.opt_present("help") {
+        return Ok(None);
+    }
+    if let Some(free) = matches.free.first() {
+        return Err(AnacondaError::InvalidInput(format!("unexpected argument `{}`", free)));
+    }
+    // the libsyntax of this rustfmt only knows about the 2015 edition, the
+    // options of the other editions come from `rustfmt.<edition>.toml`
+    if let Some(edition) = matches.opt_str("edition") {
+        if edition != "2015" {
+            let reason = format!("unsupported edition `{}`, only 2015 is supported", edition);
+            return Err(AnacondaError::InvalidInput(reason));
+        }
+    }
+    // the output is always returned to the host, nothing is written to disk
+    let write_mode = match matches.opt_str("write-mode") {
+        None => WriteMode::Plain,
+        Some(ref mode) if mode == "plain" => WriteMode::Plain,
+        Some(mode) => {
+            let reason = format!("unsupported write mode `{}`, only plain is supported", mode);
+            return Err(AnacondaError::InvalidInput(reason));
+        }
+    };
+
+    let overrides = try!(overrides(&matches).map_err(AnacondaError::InvalidInput));
+    let (mut config, _) = try!(try_project_config_with(matches.opt_str("config-path"), &[]));
+    try!(apply(&mut config, &overrides).map_err(AnacondaError::InvalidInput));
+    config.set_write_mode(write_mode);
+    Ok(Some((config, write_mode)))
+}
+
+/// Returns the options of a JSON object as `key=value` pairs, the warnings
+/// tell about the options whose value is not a string, a number or a boolean
+pub fn json_overrides(json: &str) -> Result<(Vec<(String, String)>, Vec<String>), String> {
+    let config: Value = try!(serde_json::from_str(json).map_err(|e| format!("invalid JSON config: {}", e)));
+    let options = try!(config.as_object().ok_or(String::from("the JSON config must be an object")));
+    let mut overrides = Vec::new();
+    let mut warnings = Vec::new();
+    for (key, value) in options {
+        let value = match *value {
+            Value::String(ref value) => value.clone(),
+            Value::Number(ref value) => value.to_string(),
+            Value::Bool(value) => value.to_string(),
+            _ => {
+                warnings.push(format!("option `{}` ignored, its value must be a string, a number or a boolean", key));
+                continue;
+            }
+        };
+        overrides.push((key.clone(), value));
+    }
+    Ok((overrides, warnings))
+}
+
+/// Apply the overrides like `apply` does skipping the ones that rustfmt
+/// rejects, a warning is returned for every skipped override
+pub fn apply_known(config: &mut Config, overrides: &[(String, String)]) -> Vec<String> {
+    overrides.iter()
+        .filter_map(|&(ref key, ref value)| {
+            apply(config, &[(key.clone(), value.clone())]).err().map(|reason| format!("{}, ignored", reason))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use AnacondaError;
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    fn invalid_input(result: FmtResult<Option<(Config, WriteMode)>>) -> String {
+        match result {
+            Err(AnacondaError::InvalidInput(reason)) => reason,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("the arguments were accepted"),
+        }
+    }
+
+    #[test]
+    fn config_flags_override_the_options() {
+        let flags = args(&["--config", "max_width=120,tab_spaces=2", "--config", "hard_tabs=true"]);
+        let (config, _) = parse_options(&flags).unwrap().unwrap();
+        assert_eq!(config.max_width(), 120);
+        assert_eq!(config.tab_spaces(), 2);
+        assert!(config.hard_tabs());
+        let reason = invalid_input(parse_options(&args(&["--config", "max_width"])));
+        assert_eq!(reason, "invalid config `max_width`, key=value was expected");
+        let reason = invalid_input(parse_options(&args(&["--config", "no_such_option=1"])));
+        assert_eq!(reason, "unknown option `no_such_option` or invalid value `1`");
+    }
+
+    #[test]
+    fn only_the_2015_edition_is_supported() {
+        assert!(parse_options(&args(&["--edition", "2015"])).unwrap().is_some());
+        let reason = invalid_input(parse_options(&args(&["--edition", "2018"])));
+        assert_eq!(reason, "unsupported edition `2018`, only 2015 is supported");
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        assert!(invalid_input(parse_options(&args(&["--no-such-flag"]))).contains("no-such-flag"));
+    }
+
+    #[test]
+    fn valid_flags_are_parsed() {
+        let flags = args(&["--write-mode", "plain", "--config-path", ".", "--config", "max_width=90"]);
+        let (config, write_mode) = parse_options(&flags).unwrap().unwrap();
+        assert_eq!(write_mode, WriteMode::Plain);
+        assert_eq!(config.write_mode(), WriteMode::Plain);
+        assert_eq!(config.max_width(), 90);
+    }
+
+    #[test]
+    fn missing_values_and_free_arguments_are_rejected() {
+        assert!(invalid_input(parse_options(&args(&["--config-path"]))).contains("config-path"));
+        assert!(invalid_input(parse_options(&args(&["--edition"]))).contains("edition"));
+        assert_eq!(invalid_input(parse_options(&args(&["src/lib.rs"]))),
+                   "unexpected argument `src/lib.rs`");
+        assert_eq!(invalid_input(parse_options(&args(&["--write-mode", "overwrite"]))),
+                   "unsupported write mode `overwrite`, only plain is supported");
+    }
+
+    #[test]
+    fn help_returns_no_config() {
+        assert!(parse_options(&args(&["--help"])).unwrap().is_none());
+        assert!(parse_options(&args(&["-h", "--config", "max_width=90"])).unwrap().is_none());
+        let usage = usage();
+        for flag in &["--help", "--config", "--config-path", "--edition", "--write-mode"] {
+            assert!(usage.contains(flag), "{} is not in the usage", flag);
+        }
+    }
+}
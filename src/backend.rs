@@ -0,0 +1,284 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The rustfmt that does the formatting. Everything that depends on the API
+//! of the rustfmt crate goes through this module: the config and the summary
+//! types, building a config from TOML, the write mode and formatting text.
+//!
+//! The backend is chosen with a cargo feature, `backend-rustfmt` (the
+//! default) builds against the `rustfmt` crate and `backend-rustfmt-nightly`
+//! against `rustfmt-nightly`. The first one has the options of `Config` as
+//! public fields and the second one as methods, so the other modules read
+//! and set them through the accessors of the `Config` of this module only.
+//!
+//! Both backends parse with the syntex port of the compiler parser, `parse`
+//! uses it directly for the checks that don't format.
+
+#[cfg(all(feature = "backend-rustfmt", feature = "backend-rustfmt-nightly"))]
+compile_error!("the `backend-rustfmt` and `backend-rustfmt-nightly` features are mutually exclusive");
+
+#[cfg(not(any(feature = "backend-rustfmt", feature = "backend-rustfmt-nightly")))]
+compile_error!("a backend is required, enable `backend-rustfmt` or `backend-rustfmt-nightly`");
+
+use std::cell::RefCell;
+#[cfg(feature = "backend-rustfmt")]
+use std::panic;
+use std::rc::Rc;
+
+#[cfg(feature = "backend-rustfmt")]
+use rustfmt as imp;
+#[cfg(feature = "backend-rustfmt-nightly")]
+use rustfmt_nightly as imp;
+
+use self::imp::{Input, config, format_input};
+pub use self::imp::Summary;
+pub use self::imp::config::{BraceStyle, ControlBraceStyle, NewlineStyle, ReportTactic, WriteMode};
+use syntex_errors::{DiagnosticBuilder, Handler, Level};
+use syntex_errors::emitter::Emitter;
+use syntex_syntax::codemap::{CodeMap, FilePathMapping};
+use syntex_syntax::parse::{self, ParseSess};
+
+/// Name of the backend compiled in, `get_build_info` reports it
+#[cfg(feature = "backend-rustfmt")]
+pub const NAME: &'static str = "rustfmt";
+#[cfg(feature = "backend-rustfmt-nightly")]
+pub const NAME: &'static str = "rustfmt-nightly";
+
+/// The rustfmt config, its options are read and set with the accessors below.
+/// rustfmt can't tell the default `newline_style` from one set to the same
+/// value so whether the config file (or an override) sets it is kept aside,
+/// the line endings of the buffer are kept when it is not set
+#[derive(Clone, Default)]
+pub struct Config {
+    options: config::Config,
+    newline_style_set: bool,
+}
+
+impl Config {
+    /// Set the option from its name and its value, rustfmt panics for unknown
+    /// options and values that don't parse
+    pub fn override_value(&mut self, key: &str, value: &str) {
+        self.options.override_value(key, value);
+        if key == "newline_style" {
+            self.newline_style_set = true;
+        }
+    }
+
+    /// Whether `newline_style` was set instead of left to its default
+    pub fn newline_style_set(&self) -> bool {
+        self.newline_style_set
+    }
+}
+
+#[cfg(feature = "backend-rustfmt")]
+macro_rules! get {
+    ($options:expr, $option:ident) => ($options.$option)
+}
+
+#[cfg(feature = "backend-rustfmt")]
+macro_rules! set {
+    ($options:expr, $option:ident, $value:expr) => ($options.$option = $value)
+}
+
+#[cfg(feature = "backend-rustfmt-nightly")]
+macro_rules! get {
+    ($options:expr, $option:ident) => ($options.$option())
+}
+
+#[cfg(feature = "backend-rustfmt-nightly")]
+macro_rules! set {
+    ($options:expr, $option:ident, $value:expr) => ($options.set().$option($value))
+}
+
+macro_rules! options {
+    (read: $($option:ident: $ty:ty),+; write: $($set_option:ident, $setter:ident: $set_ty:ty),+) => {
+        impl Config {
+            $(pub fn $option(&self) -> $ty {
+                get!(self.options, $option)
+            })+
+
+            $(pub fn $set_option(&self) -> $set_ty {
+                get!(self.options, $set_option)
+            }
+
+            pub fn $setter(&mut self, value: $set_ty) {
+                set!(self.options, $set_option, value)
+            })+
+        }
+    }
+}
+
+options! {
+    read:
+    disable_all_formatting: bool,
+    report_todo: ReportTactic,
+    report_fixme: ReportTactic,
+    single_line_if_else_max_width: usize,
+    chains_overflow_last: bool,
+    match_block_trailing_comma: bool;
+    write:
+    max_width, set_max_width: usize,
+    ideal_width, set_ideal_width: usize,
+    tab_spaces, set_tab_spaces: usize,
+    hard_tabs, set_hard_tabs: bool,
+    newline_style, set_newline_style: NewlineStyle,
+    fn_brace_style, set_fn_brace_style: BraceStyle,
+    item_brace_style, set_item_brace_style: BraceStyle,
+    control_brace_style, set_control_brace_style: ControlBraceStyle,
+    error_on_line_overflow, set_error_on_line_overflow: bool,
+    reorder_imports, set_reorder_imports: bool,
+    reorder_imported_names, set_reorder_imported_names: bool,
+    format_strings, set_format_strings: bool,
+    take_source_hints, set_take_source_hints: bool,
+    wrap_comments, set_wrap_comments: bool,
+    normalize_comments, set_normalize_comments: bool,
+    wrap_match_arms, set_wrap_match_arms: bool,
+    write_mode, set_write_mode: WriteMode
+}
+
+/// Whether the flat `key = value` TOML file sets the option
+fn sets_option(toml: &str, option: &str) -> bool {
+    toml.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#') && line.contains('='))
+        .any(|line| line.split('=').next().map(str::trim) == Some(option))
+}
+
+/// rustfmt panics for files that are not valid TOML so the panic is turned
+/// into an error
+#[cfg(feature = "backend-rustfmt")]
+fn options_from_toml(toml: &str) -> Result<config::Config, String> {
+    panic::catch_unwind(|| config::Config::from_toml(toml)).map_err(|_| String::from("the config can not be parsed"))
+}
+
+#[cfg(feature = "backend-rustfmt-nightly")]
+fn options_from_toml(toml: &str) -> Result<config::Config, String> {
+    config::Config::from_toml(toml).map_err(|e| format!("the config can not be parsed: {}", e))
+}
+
+/// Build a config from the contents of a `rustfmt.toml`
+pub fn config_from_toml(toml: &str) -> Result<Config, String> {
+    let options = try!(options_from_toml(toml));
+    Ok(Config {
+        options: options,
+        newline_style_set: sets_option(toml, "newline_style"),
+    })
+}
+
+/// The output is always returned to the host, rustfmt never writes files
+pub fn set_plain(config: &mut Config) {
+    config.set_write_mode(WriteMode::Plain);
+}
+
+/// Keeps the errors of the parser instead of printing them
+struct Diagnostics {
+    codemap: Rc<CodeMap>,
+    errors: Rc<RefCell<Vec<String>>>,
+}
+
+impl Emitter for Diagnostics {
+    fn emit(&mut self, db: &DiagnosticBuilder) {
+        match db.level {
+            Level::Bug | Level::Fatal | Level::PhaseFatal | Level::Error => {}
+            _ => return,
+        }
+        let error = match db.span.primary_span() {
+            Some(span) => format!("line {}: {}", self.codemap.lookup_char_pos(span.lo).line, db.message()),
+            None => db.message(),
+        };
+        self.errors.borrow_mut().push(error);
+    }
+}
+
+/// Parse the code without formatting it, the error is the first one that
+/// the parser reports
+pub fn parse(code: &str) -> Result<(), String> {
+    let codemap = Rc::new(CodeMap::new(FilePathMapping::empty()));
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let emitter = Diagnostics { codemap: codemap.clone(), errors: errors.clone() };
+    let sess = ParseSess::with_span_handler(Handler::with_emitter(false, false, Box::new(emitter)), codemap);
+    let result = parse::parse_crate_from_source_str(String::from("<buffer>"), String::from(code), &sess);
+    if let Err(mut db) = result {
+        db.emit();
+    }
+    let errors = errors.borrow();
+    match errors.first() {
+        Some(error) => Err(error.clone()),
+        None => Ok(()),
+    }
+}
+
+/// Format the code, the output is None when rustfmt was not able to produce
+/// any (e.g. the code does not parse). The warnings of rustfmt are logged
+pub fn format_text(code: String, config: &Config) -> (Summary, Option<String>) {
+    let mut out: Vec<u8> = Vec::new();
+    match format_input(Input::Text(code), &config.options, Some(&mut out)) {
+        Ok((summary, _, report)) => {
+            if report.has_warnings() {
+                warn!("{}", report);
+            }
+            if summary.has_parsing_errors() || summary.has_operational_errors() {
+                return (summary, None);
+            }
+            (summary, String::from_utf8(out).ok())
+        }
+        Err((e, mut summary)) => {
+            error!("rustfmt failed: {}", e);
+            summary.add_operational_error();
+            (summary, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testing::TempDir;
+    use {FormatOptions, format_string};
+    use super::parse;
+
+    /// The input, the `rustfmt.toml` of its project, the status and the
+    /// output that every backend has to give
+    const CONFORMANCE: &'static [(&'static str, &'static str, i32, Option<&'static str>)] = &[
+        ("fn main() {\n    let x = 1;\n}\n", "", 0, Some("fn main() {\n    let x = 1;\n}\n")),
+        ("fn main() {\n    let x=1;   \n}\n", "", 0, Some("fn main() {\n    let x = 1;\n}\n")),
+        // still wider than max_width after formatting
+        ("fn main() {\n    let long_name = 1;\n}\n",
+         "max_width = 20\n",
+         3,
+         Some("fn main() {\n    let long_name = 1;\n}\n")),
+        ("fn main() { @@ }\n", "", 2, None),
+        ("fn main() {\n    let x=1;\n}\n", "max_width 100\n", 1, None),
+    ];
+
+    #[test]
+    fn parse_returns_the_first_error_of_the_parser() {
+        assert_eq!(parse("fn main() {\n    let x = 1;\n}\n"), Ok(()));
+        let error = parse("fn main() {\n    let x = @;\n}\n").unwrap_err();
+        assert!(error.starts_with("line 2: "), "{}", error);
+        assert!(parse("fn main() {").is_err());
+    }
+
+    #[test]
+    fn the_backend_conforms_to_the_expected_statuses() {
+        for (i, &(input, toml, status, output)) in CONFORMANCE.iter().enumerate() {
+            let dir = TempDir::new(&format!("conformance-{}", i));
+            dir.write("rustfmt.toml", toml);
+            let path = dir.write("main.rs", input).to_string_lossy().into_owned();
+            let options = FormatOptions { config_path: Some(path), ..FormatOptions::default() };
+            let result = format_string(input, &options);
+            let got = result.as_ref().map(|output| output.status()).unwrap_or_else(|e| e.status());
+            assert_eq!(got, status, "status of case {}", i);
+            assert_eq!(result.ok().map(|result| result.text), output.map(String::from), "output of case {}", i);
+        }
+    }
+}
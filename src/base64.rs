@@ -0,0 +1,134 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Standard base64 (RFC 4648) so hosts with encoding troubles can pass the
+//! buffers as plain ASCII. The decoder ignores whitespace and accepts the
+//! input with or without padding.
+
+const ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &b)| group | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+fn value(c: u8) -> Option<u32> {
+    match c {
+        b'A'...b'Z' => Some((c - b'A') as u32),
+        b'a'...b'z' => Some((c - b'a') as u32 + 26),
+        b'0'...b'9' => Some((c - b'0') as u32 + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+pub fn decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut output = Vec::with_capacity(text.len() / 4 * 3);
+    let mut group = 0u32;
+    let mut count = 0;
+    let mut padding = 0;
+    for (position, c) in text.bytes().enumerate() {
+        if (c as char).is_whitespace() {
+            continue;
+        }
+        if c == b'=' {
+            padding += 1;
+            continue;
+        }
+        if padding > 0 {
+            return Err(format!("data after the padding at byte {}", position));
+        }
+        group = group << 6 | match value(c) {
+            Some(value) => value,
+            None => return Err(format!("invalid character at byte {}", position)),
+        };
+        count += 1;
+        if count == 4 {
+            output.extend_from_slice(&[(group >> 16) as u8, (group >> 8) as u8, group as u8]);
+            group = 0;
+            count = 0;
+        }
+    }
+
+    match count {
+        0 if padding == 0 => {}
+        2 if padding == 0 || padding == 2 => output.push((group >> 4) as u8),
+        3 if padding == 0 || padding == 1 => {
+            output.extend_from_slice(&[(group >> 10) as u8, (group >> 2) as u8]);
+        }
+        _ => return Err(String::from("truncated data or wrong padding")),
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the test vectors of RFC 4648
+    const VECTORS: [(&'static str, &'static str); 7] = [("", ""),
+                                                        ("f", "Zg=="),
+                                                        ("fo", "Zm8="),
+                                                        ("foo", "Zm9v"),
+                                                        ("foob", "Zm9vYg=="),
+                                                        ("fooba", "Zm9vYmE="),
+                                                        ("foobar", "Zm9vYmFy")];
+
+    #[test]
+    fn encodes_the_rfc_vectors() {
+        for &(plain, encoded) in VECTORS.iter() {
+            assert_eq!(encode(plain.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn decodes_with_and_without_padding() {
+        for &(plain, encoded) in VECTORS.iter() {
+            assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+            assert_eq!(decode(encoded.trim_right_matches('=')).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn decode_ignores_whitespace() {
+        assert_eq!(decode("Zm9v\r\nYmFy\n").unwrap(), b"foobar");
+        assert_eq!(decode(" Zm 9v\tYg = = ").unwrap(), b"foob");
+    }
+
+    #[test]
+    fn decode_rejects_broken_input() {
+        assert_eq!(decode("Zm9v!").unwrap_err(), "invalid character at byte 4");
+        assert_eq!(decode("Zg==Zg==").unwrap_err(), "data after the padding at byte 4");
+        assert_eq!(decode("Z").unwrap_err(), "truncated data or wrong padding");
+        assert_eq!(decode("Zm8==").unwrap_err(), "truncated data or wrong padding");
+    }
+
+    #[test]
+    fn round_trips_every_byte() {
+        let bytes: Vec<u8> = (0..256).map(|b| b as u8).collect();
+        for len in 0..bytes.len() {
+            assert_eq!(decode(&encode(&bytes[..len])).unwrap(), &bytes[..len]);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Command line entry point for the hosts that can't load the library,
+//! `anaconda-rust --daemon` serves JSON (or MessagePack) requests on stdin
+//! and stdout and `anaconda-rust --socket PATH` serves them on a Unix domain
+//! socket. `anaconda-rust --lsp` is a Language Server Protocol formatter.
+
+#[cfg(all(unix, feature = "ffi"))]
+extern crate libc;
+extern crate rustanaconda;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+const USAGE: &'static str = "usage: anaconda-rust (--daemon | --socket PATH) [--max-in-flight N] | --lsp";
+
+#[cfg(all(unix, feature = "ffi"))]
+extern fn on_signal(_: libc::c_int) {
+    rustanaconda::server::stop();
+}
+
+#[cfg(all(unix, feature = "ffi"))]
+fn listen(path: &str) -> io::Result<()> {
+    unsafe {
+        libc::signal(libc::SIGINT, on_signal as extern fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_signal as extern fn(libc::c_int) as libc::sighandler_t);
+    }
+    rustanaconda::server::listen(path.as_ref())
+}
+
+#[cfg(not(all(unix, feature = "ffi")))]
+fn listen(_: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other,
+                       "the socket server is only available on Unix with the ffi feature"))
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if let Some(position) = args.iter().position(|arg| arg == "--max-in-flight") {
+        match args.get(position + 1).and_then(|n| n.parse().ok()) {
+            Some(n) => rustanaconda::daemon::set_max_in_flight(n),
+            None => {
+                let _ = writeln!(io::stderr(), "{}", USAGE);
+                process::exit(2);
+            }
+        }
+        args.drain(position..position + 2);
+    }
+    let result = match args.get(0).map(|arg| &arg[..]) {
+        Some("--daemon") => {
+            let stdin = io::stdin();
+            rustanaconda::daemon::serve(stdin.lock(), io::stdout())
+        }
+        Some("--socket") if args.len() == 2 => listen(&args[1]),
+        Some("--lsp") => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            match rustanaconda::lsp::serve(stdin.lock(), stdout.lock()) {
+                Ok(code) => process::exit(code),
+                Err(e) => Err(e),
+            }
+        }
+        _ => {
+            let _ = writeln!(io::stderr(), "{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        let _ = writeln!(io::stderr(), "anaconda-rust: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cancel format calls that the host doesn't need anymore (e.g. the user kept
+//! typing). rustfmt can't be stopped in the middle of a pass, so the work runs
+//! on a worker thread and the token is only checked before it starts and while
+//! the caller waits for it. A cancelled worker is left to end on its own and
+//! its result is thrown away.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// how often the caller checks the token while the worker runs
+const POLL_MS: u64 = 10;
+
+/// Tells a running call that its result is not needed anymore, it can be
+/// cancelled from any thread
+#[derive(Debug, Default)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancel the calls using the token, a token can't be reused afterwards
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Run `work` on a worker thread and wait for it, None when the token is
+/// cancelled before it ends and Err when it panics
+pub fn run<T, F>(token: &CancelToken, work: F) -> Option<thread::Result<T>>
+    where T: Send + 'static,
+          F: FnOnce() -> T + Send + 'static
+{
+    if token.is_cancelled() {
+        return None;
+    }
+    let (sender, receiver) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        // the caller is gone when the call was cancelled
+        let _ = sender.send(work());
+    });
+    loop {
+        match receiver.recv_timeout(Duration::from_millis(POLL_MS)) {
+            Ok(_) | Err(RecvTimeoutError::Timeout) if token.is_cancelled() => return None,
+            Ok(result) => return Some(Ok(result)),
+            Err(RecvTimeoutError::Timeout) => continue,
+            // the worker panicked before sending its result
+            Err(RecvTimeoutError::Disconnected) => return Some(worker.join().map(|_| unreachable!())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn a_token_cancelled_from_another_thread_ends_the_wait() {
+        let token = Arc::new(CancelToken::new());
+        let canceller = {
+            let token = token.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                token.cancel();
+            })
+        };
+        let start = Instant::now();
+        let result = run(&token, || thread::sleep(Duration::from_secs(5)));
+        assert!(result.is_none());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn a_cancelled_token_does_not_start_the_work() {
+        let token = CancelToken::new();
+        token.cancel();
+        let started = Arc::new(AtomicBool::new(false));
+        let flag = started.clone();
+        assert!(run(&token, move || flag.store(true, Ordering::SeqCst)).is_none());
+        thread::sleep(Duration::from_millis(20));
+        assert!(!started.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn the_result_of_the_work_is_returned() {
+        let token = CancelToken::new();
+        assert_eq!(run(&token, || 42).unwrap().unwrap(), 42);
+        assert!(run(&token, || -> i32 { panic!("rustfmt panicked") }).unwrap().is_err());
+    }
+}
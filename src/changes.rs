@@ -0,0 +1,232 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Line level comparison of a buffer and its formatted output. librustfmt
+//! doesn't tell what each of its passes did, so the changes are classified
+//! looking at the hunks of the diff, that is good enough to tell apart the
+//! usual suspects (import sorting, whitespace, wrapping and braces). Offsets
+//! of the buffer are mapped into the output the same way, by its code.
+
+/// Coarse categories of the changes that rustfmt makes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    ImportsReordered,
+    WhitespaceNormalized,
+    LinesWrapped,
+    BracesMoved,
+    CodeRewritten,
+}
+
+impl Change {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Change::ImportsReordered => "imports reordered",
+            Change::WhitespaceNormalized => "whitespace normalized",
+            Change::LinesWrapped => "lines wrapped",
+            Change::BracesMoved => "braces moved",
+            Change::CodeRewritten => "code rewritten",
+        }
+    }
+}
+
+/// The lines without any whitespace, two hunks with the same squashed text
+/// differ only in whitespace and line breaks
+pub fn squash(lines: &[&str]) -> String {
+    lines.iter().flat_map(|line| line.chars()).filter(|c| !c.is_whitespace()).collect()
+}
+
+/// Returns the changed hunks as pairs of (original, formatted) line ranges,
+/// unchanged lines are the longest common subsequence of both
+pub fn hunks(original: &[&str], formatted: &[&str]) -> Vec<((usize, usize), (usize, usize))> {
+    // the common start and end never change, only the middle needs the
+    // (quadratic) table
+    let prefix = original.iter().zip(formatted).take_while(|&(a, b)| a == b).count();
+    let suffix = original[prefix..]
+        .iter()
+        .rev()
+        .zip(formatted[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    middle_hunks(&original[prefix..original.len() - suffix],
+                 &formatted[prefix..formatted.len() - suffix])
+        .into_iter()
+        .map(|((a, b), (c, d))| ((a + prefix, b + prefix), (c + prefix, d + prefix)))
+        .collect()
+}
+
+/// The changed ranges of the lines, see `hunks`
+fn middle_hunks(original: &[&str], formatted: &[&str]) -> Vec<((usize, usize), (usize, usize))> {
+    let (n, m) = (original.len(), formatted.len());
+    let mut table = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i][j] = if original[i] == formatted[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && original[i] == formatted[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (start_i, start_j) = (i, j);
+        while (i < n || j < m) && !(i < n && j < m && original[i] == formatted[j]) {
+            if j == m || (i < n && table[i + 1][j] >= table[i][j + 1]) {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        hunks.push(((start_i, i), (start_j, j)));
+    }
+    hunks
+}
+
+fn is_brace(line: &&str) -> bool {
+    let line = line.trim();
+    line == "{" || line.starts_with("} else") || line == "where"
+}
+
+fn is_import(line: &&str) -> bool {
+    line.trim_left().starts_with("use ")
+}
+
+/// Classify the change of the `removed` lines into the `added` ones
+fn classify(removed: &[&str], added: &[&str]) -> Change {
+    if squash(removed) != squash(added) {
+        Change::CodeRewritten
+    } else if removed.len() == added.len() {
+        Change::WhitespaceNormalized
+    } else if removed.iter().chain(added).any(is_brace) {
+        Change::BracesMoved
+    } else {
+        Change::LinesWrapped
+    }
+}
+
+/// Classify the changes of the imports, a reorder usually moves lines far
+/// away from where they were so the whole set is compared at once
+fn imports_change(removed: &[&str], added: &[&str]) -> Option<Change> {
+    if removed == added {
+        return None;
+    }
+    let mut squashed_removed: Vec<String> = removed.iter().map(|line| squash(&[line])).collect();
+    let mut squashed_added: Vec<String> = added.iter().map(|line| squash(&[line])).collect();
+    if squashed_removed == squashed_added {
+        return Some(Change::WhitespaceNormalized);
+    }
+    squashed_removed.sort();
+    squashed_added.sort();
+    if squashed_removed == squashed_added {
+        Some(Change::ImportsReordered)
+    } else {
+        Some(Change::CodeRewritten)
+    }
+}
+
+/// Returns the categories of the changes between both buffers, sorted and
+/// without repetitions
+pub fn summary(original: &str, formatted: &str) -> Vec<Change> {
+    let (original_imports, original): (Vec<&str>, Vec<&str>) = original.lines().partition(is_import);
+    let (formatted_imports, formatted): (Vec<&str>, Vec<&str>) = formatted.lines().partition(is_import);
+
+    let mut changes: Vec<Change> = hunks(&original, &formatted)
+        .into_iter()
+        .map(|((from_i, to_i), (from_j, to_j))| classify(&original[from_i..to_i], &formatted[from_j..to_j]))
+        .collect();
+    changes.extend(imports_change(&original_imports, &formatted_imports));
+    changes.sort();
+    changes.dedup();
+    changes
+}
+
+/// Returns the offset of the formatted text that corresponds to the byte
+/// `offset` of the original one. Formatting mostly moves whitespace around
+/// so the code before the offset is matched by its non whitespace characters,
+/// an offset followed by code in its line stays right before that code and
+/// any other offset stays right after the code that precedes it
+pub fn map_offset(original: &str, formatted: &str, offset: usize) -> usize {
+    if offset >= original.len() {
+        return formatted.len();
+    }
+    let mut offset = offset;
+    while !original.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = original[..offset].chars().filter(|c| !c.is_whitespace()).count();
+    let rest_of_line = original[offset..].split('\n').next().unwrap_or("");
+    let code_follows = !rest_of_line.trim().is_empty();
+
+    let mut seen = 0;
+    for (position, c) in formatted.char_indices() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if seen == before && code_follows {
+            return position;
+        }
+        seen += 1;
+        if seen == before && !code_follows {
+            return position + c.len_utf8();
+        }
+    }
+    if before == 0 && !code_follows {
+        return 0;
+    }
+    formatted.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reordered_imports() {
+        let original = "use std::io;\nuse std::fmt;\n\nfn main() {}\n";
+        let formatted = "use std::fmt;\nuse std::io;\n\nfn main() {}\n";
+        assert_eq!(summary(original, formatted), vec![Change::ImportsReordered]);
+    }
+
+    #[test]
+    fn whitespace_only_changes() {
+        let original = "fn main() {\n  let x=1;   \n}\n";
+        let formatted = "fn main() {\n    let x = 1;\n}\n";
+        assert_eq!(summary(original, formatted), vec![Change::WhitespaceNormalized]);
+        assert_eq!(summary(formatted, formatted), vec![]);
+    }
+
+    #[test]
+    fn wrapped_lines_and_moved_braces() {
+        let original = "fn main() {\n    foo(a, b);\n}\n";
+        let formatted = "fn main() {\n    foo(a,\n        b);\n}\n";
+        assert_eq!(summary(original, formatted), vec![Change::LinesWrapped]);
+        let original = "fn main()\n{\n    foo();\n}\n";
+        let formatted = "fn main() {\n    foo();\n}\n";
+        assert_eq!(summary(original, formatted), vec![Change::BracesMoved]);
+    }
+
+    #[test]
+    fn map_offset_follows_the_code() {
+        let original = "fn main() {\n  let x=1;\n}\n";
+        let formatted = "fn main() {\n    let x = 1;\n}\n";
+        assert_eq!(map_offset(original, formatted, original.find("x").unwrap()), formatted.find("x").unwrap());
+        assert_eq!(map_offset(original, formatted, original.len()), formatted.len());
+    }
+}
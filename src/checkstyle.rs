@@ -0,0 +1,104 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checkstyle XML reports, the format that rustfmt writes with
+//! `--write-mode checkstyle` and that CI dashboards already read. Every line
+//! that formatting changes is an error of the file.
+
+use changes;
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The errors of the file, (1-based) line and message, sorted by line
+fn errors(original: &str, formatted: &str) -> Vec<(usize, String)> {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = formatted.lines().collect();
+    let mut errors = Vec::new();
+    for ((removed_start, removed_end), (added_start, added_end)) in changes::hunks(&old, &new) {
+        let removed = removed_end - removed_start;
+        let added = added_end - added_start;
+        for k in 0..removed {
+            let message = if k < added {
+                format!("Should be `{}`", new[added_start + k])
+            } else {
+                String::from("Delete this line")
+            };
+            errors.push((removed_start + k + 1, message));
+        }
+        // the lines left are inserted after the last line of the hunk
+        if added > removed {
+            let line = if removed_start + removed > 0 { removed_start + removed } else { 1 };
+            errors.push((line, format!("Insert `{}`", new[added_start + removed..added_end].join("\n"))));
+        }
+    }
+    errors
+}
+
+/// The report of a single file, a file without errors still has its element
+pub fn report(path: &str, original: &str, formatted: &str) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<checkstyle version=\"4.3\">\n");
+    xml.push_str(&format!("<file name=\"{}\">\n", escape(path)));
+    for (line, message) in errors(original, formatted) {
+        xml.push_str(&format!("<error line=\"{}\" severity=\"warning\" message=\"{}\" />\n",
+                              line,
+                              escape(&message).replace('\n', "&#10;")));
+    }
+    xml.push_str("</file>\n</checkstyle>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::report;
+
+    #[test]
+    fn the_report_escapes_the_paths_and_the_messages() {
+        let original = "fn main() {\n    let s=\"<&'>\";\n}\n";
+        let formatted = "fn main() {\n    let s = \"<&'>\";\n}\n";
+        assert_eq!(report("src/<a&b>\".rs", original, formatted),
+                   "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<checkstyle version=\"4.3\">\n\
+                    <file name=\"src/&lt;a&amp;b&gt;&quot;.rs\">\n\
+                    <error line=\"2\" severity=\"warning\" \
+                    message=\"Should be `    let s = &quot;&lt;&amp;&apos;&gt;&quot;;`\" />\n\
+                    </file>\n</checkstyle>\n");
+    }
+
+    #[test]
+    fn a_formatted_file_still_has_its_element() {
+        let code = "fn main() {}\n";
+        assert_eq!(report("a.rs", code, code),
+                   "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<checkstyle version=\"4.3\">\n\
+                    <file name=\"a.rs\">\n</file>\n</checkstyle>\n");
+    }
+
+    #[test]
+    fn inserted_lines_are_one_error_with_escaped_newlines() {
+        let report = report("a.rs", "fn f() {}\n", "fn f() {}\n\nfn g() {}\n");
+        assert!(report.contains("<error line=\"1\" severity=\"warning\" message=\"Insert `&#10;fn g() {}`\" />"),
+                "{}",
+                report);
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Process wide cache of the `rustfmt.toml` lookups. Canonicalizing the path
+//! and walking up the tree costs more than formatting a small buffer and the
+//! editor repeats the same lookup on every save, so the result is kept for a
+//! while. Creating or removing a file changes the modification time of its
+//! directory, an entry is dropped as soon as any directory of its walk does.
+//! The calls that can't accept a stale result skip the cache with `bypass`.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{Duration, Instant, SystemTime};
+
+use limit_value;
+
+// maximum number of directories that we remember
+const CAPACITY: usize = 256;
+
+const DEFAULT_TTL_MS: usize = 5000;
+
+// time to live of the entries in milliseconds plus one, 1 disables the cache
+static TTL_MS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+struct Entry {
+    config_file: Option<PathBuf>,
+    // directories visited by the lookup and their modification times
+    dirs: Vec<(PathBuf, Option<SystemTime>)>,
+    stored: Instant,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<HashMap<PathBuf, Entry>> = Mutex::new(HashMap::new());
+}
+
+thread_local! {
+    // whether the lookups of the thread skip the cache, see `bypass`
+    static BYPASS: Cell<bool> = Cell::new(false);
+}
+
+/// The modification time of the directory, the lookup takes it before it
+/// looks inside so a change made during the walk invalidates the entry
+pub fn modified(dir: &Path) -> Option<SystemTime> {
+    fs::metadata(dir).and_then(|md| md.modified()).ok()
+}
+
+/// The time to live of the entries, None when the cache is disabled or
+/// bypassed by the calling thread
+fn ttl() -> Option<Duration> {
+    let ms = limit_value(&TTL_MS, DEFAULT_TTL_MS) as u64;
+    if ms == 0 || BYPASS.with(|bypass| bypass.get()) {
+        return None;
+    }
+    Some(Duration::from_millis(ms))
+}
+
+// restores the bypass flag of the thread when the call ends, even if it panics
+struct BypassGuard(bool);
+
+impl Drop for BypassGuard {
+    fn drop(&mut self) {
+        let previous = self.0;
+        BYPASS.with(|bypass| bypass.set(previous));
+    }
+}
+
+/// Run the closure without the cache, its lookups neither read nor fill it.
+/// Unlike `set_ttl(0)` the other calls (and threads) keep their entries
+pub fn bypass<T, F: FnOnce() -> T>(f: F) -> T {
+    let previous = BYPASS.with(|bypass| {
+        let previous = bypass.get();
+        bypass.set(true);
+        previous
+    });
+    let _guard = BypassGuard(previous);
+    f()
+}
+
+/// Returns the cached result of the lookup that started at `start` (an
+/// absolute path), None when there is no valid entry for it
+pub fn get(start: &Path) -> Option<Option<PathBuf>> {
+    let ttl = try_opt!(ttl());
+
+    let mut cache = match CACHE.lock() {
+        Ok(cache) => cache,
+        Err(_) => return None,
+    };
+    let valid = match cache.get(start) {
+        Some(entry) => {
+            entry.stored.elapsed() < ttl && entry.dirs.iter().all(|&(ref dir, time)| modified(dir) == time)
+        }
+        None => return None,
+    };
+    if !valid {
+        cache.remove(start);
+        return None;
+    }
+    cache.get(start).map(|entry| entry.config_file.clone())
+}
+
+/// Remember the result of a lookup and the directories that it visited with
+/// the modification times they had when it looked inside them
+pub fn insert(start: PathBuf, config_file: Option<PathBuf>, dirs: Vec<(PathBuf, Option<SystemTime>)>) {
+    if ttl().is_none() {
+        return;
+    }
+
+    let mut cache = match CACHE.lock() {
+        Ok(cache) => cache,
+        Err(_) => return,
+    };
+    if cache.len() >= CAPACITY && !cache.contains_key(&start) {
+        let oldest = cache.iter().min_by_key(|&(_, entry)| entry.stored).map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            cache.remove(&oldest);
+        }
+    }
+    cache.insert(start,
+                 Entry {
+                     config_file: config_file,
+                     dirs: dirs,
+                     stored: Instant::now(),
+                 });
+}
+
+/// Set the time to live of the entries in milliseconds, 0 disables the cache
+pub fn set_ttl(ms: usize) {
+    TTL_MS.store(ms.saturating_add(1), Ordering::Relaxed);
+    if ms == 0 {
+        clear();
+    }
+}
+
+pub fn clear() {
+    if let Ok(mut cache) = CACHE.lock() {
+        cache.clear();
+    }
+}
+
+/// Forget every entry and go back to the default time to live
+pub fn reset() {
+    TTL_MS.store(0, Ordering::Relaxed);
+    clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use testing::{lock_globals, TempDir};
+    use super::*;
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let _globals = lock_globals();
+        set_ttl(50);
+        let start = PathBuf::from("/anaconda-config-cache/ttl");
+        insert(start.clone(), Some(start.join("rustfmt.toml")), Vec::new());
+        assert_eq!(get(&start), Some(Some(start.join("rustfmt.toml"))));
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(get(&start), None);
+        reset();
+    }
+
+    #[test]
+    fn entries_are_dropped_when_a_directory_of_the_walk_changes() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("config-cache");
+        let start = dir.path().to_path_buf();
+        insert(start.clone(), None, vec![(start.clone(), modified(&start))]);
+        assert_eq!(get(&start), Some(None));
+
+        // file times are not precise enough to tell changes apart right away
+        thread::sleep(Duration::from_millis(50));
+        dir.write("rustfmt.toml", "max_width = 80\n");
+        assert_eq!(get(&start), None);
+
+        // the directory changed after the walk looked inside it
+        insert(start.clone(), None, vec![(start.clone(), Some(UNIX_EPOCH))]);
+        assert_eq!(get(&start), None);
+    }
+
+    #[test]
+    fn the_oldest_entry_is_evicted_when_the_cache_is_full() {
+        let _globals = lock_globals();
+        let first = PathBuf::from("/anaconda-config-cache/first");
+        insert(first.clone(), None, Vec::new());
+        for i in 0..CAPACITY {
+            insert(PathBuf::from(format!("/anaconda-config-cache/{}", i)), None, Vec::new());
+        }
+
+        assert_eq!(get(&first), None);
+        assert_eq!(CACHE.lock().unwrap().len(), CAPACITY);
+        reset();
+    }
+
+    #[test]
+    fn bypassed_lookups_neither_read_nor_fill_the_cache() {
+        let _globals = lock_globals();
+        let cached = PathBuf::from("/anaconda-config-cache/cached");
+        let bypassed = PathBuf::from("/anaconda-config-cache/bypassed");
+        insert(cached.clone(), None, Vec::new());
+        bypass(|| insert(bypassed.clone(), None, Vec::new()));
+
+        assert_eq!(bypass(|| get(&cached)), None);
+        assert_eq!(get(&cached), Some(None));
+        assert_eq!(get(&bypassed), None);
+    }
+}
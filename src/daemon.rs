@@ -0,0 +1,743 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Daemon mode for the hosts that can't load the library. Every line of the
+//! input is a JSON request and gets exactly one JSON response line:
+//!
+//! ```text
+//! {"id": 1, "method": "format", "params": {"code": "...", "path": "..."}}
+//! {"id": 1, "status": 0, "result": "..."}
+//! ```
+//!
+//! Methods are `format`, `format_outcome` (the result is the JSON object of
+//! `outcome::FormatOutcome` instead of the code), `check_project` (the `path`
+//! of a package or directory, the result is the JSON object of
+//! `project::CheckReport`; `generated_markers` adds the text that marks
+//! generated files, `generated_globs` are their paths and `include_generated`
+//! checks them too), `check_staged` (the `files` to check, objects
+//! with a `path` and optionally the `contents` to check instead of the file,
+//! the result is the JSON object of `project::ProjectReport`), `fragment`
+//! (with a `kind` param), `complete`, `definitions` and `documentation`
+//! (with `line` and `col` params), `version`, `build_info` (the JSON object
+//! of `get_build_info`), `shutdown` (ends the session) and `stop_server`
+//! (stops the socket server, in stdio mode it is the same as `shutdown`).
+//! Failed requests get an `error` member instead of the result, `format`
+//! responses carry `"cached": true` when the buffer was the last output for
+//! the path and the formatter was skipped. The config lookup cache lives as
+//! long as the process.
+//!
+//! Requests with an `id` run in their own threads (4 at a time unless it is
+//! changed with `set_max_in_flight`) and they are answered as soon as they
+//! are done, so a quick format is not stuck behind a slow one. Requests
+//! without an id wait for the running ones and are answered in order. The
+//! `cancel` method (with an `id` param) answers a running request with a
+//! `cancelled` error and throws its result away, its result is `true` if
+//! there was such a request.
+//!
+//! A `format` request with an id for the same path and buffer as one still
+//! in flight doesn't run again, it gets the same response with its own id.
+//! Both responses carry `"coalesced": true`. A request for the same path
+//! with a different buffer answers the one in flight (and the ones coalesced
+//! into it) with a `superseded` error, unless its `supersede` param is false.
+//!
+//! The same requests and responses can be encoded as MessagePack maps one
+//! after another instead of JSON lines, the encoding of a session is chosen
+//! by its first byte. MessagePack avoids escaping big buffers.
+//!
+//! `watch` (with `dir`, optional `patterns` globs, `*.rs` by default, a
+//! `mode` of `check` or `overwrite` and `ignore`, false to watch the files
+//! that git ignores and `target` too) keeps an eye on the matching files of a
+//! directory and returns a watch id. Every file created or modified after
+//! that is checked, or formatted in place, once it stops changing, and an
+//! event without an id is sent for it:
+//!
+//! ```text
+//! {"event": "watch", "watch": 1, "path": "...", "status": "needs_format"}
+//! ```
+//!
+//! The status is `formatted`, `needs_format`, `reformatted`, `skipped` (files
+//! over the input size limit) or `error`, the last two with an `error`
+//! member. A `started` event for the directory tells what was ignored in its
+//! `skipped` member (e.g. `skipped 3,214 ignored files`, null when nothing
+//! was). `unwatch` (with a `watch` param) stops a watch and the
+//! watches of a session are stopped when the session ends.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+
+use serde_json::{self, Value};
+
+use fragment::FragmentKind;
+use msgpack;
+use project;
+use watch::{self, Watch};
+use {build_info, find_definition, get_documentation, racer_complete, render_fragment, try_load_config};
+use {fnv1a, format_outcome, format_owned, limit_value, FormatOptions, PROJECT_NEEDS_FORMAT};
+
+// requests with an id that a session runs at the same time by default
+const DEFAULT_MAX_IN_FLIGHT: usize = 4;
+
+// maximum number of requests in flight per session plus one, 0 is the default
+static MAX_IN_FLIGHT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn string_param(params: &Value, name: &str) -> Result<String, String> {
+    match params.get(name).and_then(|value| value.as_str()) {
+        Some(value) => Ok(String::from(value)),
+        None => Err(format!("missing string param `{}`", name)),
+    }
+}
+
+/// An optional array of strings
+fn strings_param(params: &Value, name: &str) -> Result<Option<Vec<String>>, String> {
+    match params.get(name) {
+        None => Ok(None),
+        Some(&Value::Array(ref values)) if values.iter().all(|value| value.is_string()) => {
+            Ok(Some(values.iter().filter_map(|value| value.as_str()).map(String::from).collect()))
+        }
+        Some(_) => Err(format!("`{}` must be an array of strings", name)),
+    }
+}
+
+fn number_param(params: &Value, name: &str) -> Result<u64, String> {
+    match params.get(name).and_then(|value| value.as_u64()) {
+        Some(value) => Ok(value),
+        None => Err(format!("missing number param `{}`", name)),
+    }
+}
+
+/// Tests keep a request in flight for a while with a `delay_ms` param
+#[cfg(test)]
+fn delay(params: &Value) {
+    if let Some(ms) = params.get("delay_ms").and_then(|ms| ms.as_u64()) {
+        thread::sleep(::std::time::Duration::from_millis(ms));
+    }
+}
+
+#[cfg(not(test))]
+fn delay(_: &Value) {}
+
+/// Run the method of a request returning its status code, its result and
+/// whether the formatter was skipped because the buffer was already formatted
+fn dispatch(method: &str, params: &Value) -> Result<(i32, Value, bool), String> {
+    match method {
+        "version" => Ok((0, json!(option_env!("CARGO_PKG_VERSION").unwrap_or("unknown")), false)),
+        "build_info" => Ok((0, build_info(), false)),
+        "format" => {
+            let path = try!(string_param(params, "path"));
+            let options = FormatOptions { config_path: Some(path), ..FormatOptions::default() };
+            match format_owned(try!(string_param(params, "code")), &options) {
+                Ok(output) => Ok((output.status(), Value::String(output.text), output.cached)),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        "format_outcome" => {
+            let path = try!(string_param(params, "path"));
+            let options = FormatOptions { config_path: Some(path), ..FormatOptions::default() };
+            let outcome = format_outcome(&try!(string_param(params, "code")), &options);
+            let result = try!(serde_json::to_value(&outcome).map_err(|e| e.to_string()));
+            Ok((outcome.status, result, false))
+        }
+        "check_project" => {
+            let target = try!(string_param(params, "path"));
+            let mut options = project::DirOptions::default();
+            if let Some(markers) = try!(strings_param(params, "generated_markers")) {
+                options.generated.markers.extend(markers);
+            }
+            if let Some(globs) = try!(strings_param(params, "generated_globs")) {
+                options.generated.globs = globs;
+            }
+            options.generated.include = match params.get("include_generated") {
+                None => false,
+                Some(&Value::Bool(include)) => include,
+                Some(_) => return Err(String::from("`include_generated` must be a boolean")),
+            };
+            let report = try!(project::check_project(Path::new(&target), &options));
+            let result = try!(serde_json::to_value(&report).map_err(|e| e.to_string()));
+            Ok((report.status(), result, false))
+        }
+        "check_staged" => {
+            let files = match params.get("files") {
+                Some(&Value::Array(ref files)) => files,
+                _ => return Err(String::from("`files` must be an array")),
+            };
+            let mut staged = Vec::new();
+            for file in files {
+                staged.push(project::StagedFile {
+                    path: try!(string_param(file, "path")),
+                    contents: file.get("contents").and_then(|contents| contents.as_str()).map(String::from),
+                });
+            }
+            let report = project::check_files(staged);
+            let status = match report.failures() {
+                (_, true) => 1,
+                (true, false) => PROJECT_NEEDS_FORMAT,
+                (false, false) => 0,
+            };
+            let result = try!(serde_json::to_value(&report).map_err(|e| e.to_string()));
+            Ok((status, result, false))
+        }
+        "fragment" => {
+            let path = try!(string_param(params, "path"));
+            let config = try!(try_load_config(Some(path)).map_err(|e| e.to_string()));
+            let kind = try!(number_param(params, "kind"));
+            let kind = try!(FragmentKind::from_c_int(kind as i32).ok_or(format!("unknown fragment kind {}", kind)));
+            render_fragment(try!(string_param(params, "code")), config, kind)
+                .map(|output| (0, Value::String(output), false))
+        }
+        "complete" | "definitions" | "documentation" => {
+            let code = try!(string_param(params, "code"));
+            let path = try!(string_param(params, "path"));
+            let line = try!(number_param(params, "line")) as usize;
+            let col = try!(number_param(params, "col")) as usize;
+            let result = match method {
+                "complete" => racer_complete(code, path, line, col),
+                "definitions" => find_definition(code, path, line, col),
+                _ => get_documentation(code, path, line, col),
+            };
+            if result.starts_with("error\t") {
+                return Err(String::from(&result[6..]));
+            }
+            Ok((0, Value::String(result), false))
+        }
+        _ => Err(format!("unknown method `{}`", method)),
+    }
+}
+
+/// What to do after a response is sent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Next {
+    Continue,
+    // end the session (the stdio daemon or a socket connection)
+    Close,
+    StopServer,
+}
+
+/// Build the response for a request and tell what to do after sending it
+fn respond(request: Value) -> (Value, Next) {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match request.get("method").and_then(|method| method.as_str()) {
+        Some(method) => String::from(method),
+        None => return (json!({"id": id, "error": "missing method"}), Next::Continue),
+    };
+    match &method[..] {
+        "shutdown" => return (json!({"id": id, "status": 0, "result": ""}), Next::Close),
+        "stop_server" => return (json!({"id": id, "status": 0, "result": ""}), Next::StopServer),
+        _ => {}
+    }
+
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    // a panic in rustfmt must not take the daemon down with it
+    delay(&params);
+    let result = panic::catch_unwind(|| dispatch(&method, &params));
+    let response = match result {
+        Ok(Ok((status, result, true))) => json!({"id": id, "status": status, "result": result, "cached": true}),
+        Ok(Ok((status, result, false))) => json!({"id": id, "status": status, "result": result}),
+        Ok(Err(reason)) => json!({"id": id, "error": reason}),
+        Err(_) => json!({"id": id, "error": format!("{} panicked", method)}),
+    };
+    (response, Next::Continue)
+}
+
+/// How the requests and responses of a session are encoded
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Json,
+    MessagePack,
+}
+
+fn send<W: Write>(output: &Mutex<W>, response: &Value, encoding: Encoding) -> io::Result<()> {
+    let mut output = output.lock().unwrap_or_else(PoisonError::into_inner);
+    match encoding {
+        Encoding::Json => try!(writeln!(output, "{}", response)),
+        Encoding::MessagePack => try!(msgpack::write(&mut *output, response)),
+    }
+    output.flush()
+}
+
+/// Returns the path of a format request and the hash of its buffer
+fn document(request: &Value) -> Option<(String, u64)> {
+    if request.get("method").and_then(|method| method.as_str()) != Some("format") {
+        return None;
+    }
+    let params = match request.get("params") {
+        Some(params) => params,
+        None => return None,
+    };
+    match (params.get("path").and_then(|path| path.as_str()), params.get("code").and_then(|code| code.as_str())) {
+        (Some(path), Some(code)) => Some((String::from(path), fnv1a(code.as_bytes()))),
+        _ => None,
+    }
+}
+
+/// A format request in flight for a path and the requests for the same
+/// buffer that get its response
+struct Leader {
+    key: String,
+    id: Value,
+    hash: u64,
+    followers: Vec<Value>,
+}
+
+/// A client connection, requests with an id run in their own thread and
+/// their responses are sent as soon as they are ready
+struct Session<W> {
+    output: Arc<Mutex<W>>,
+    encoding: Encoding,
+    // the running jobs by id and whether they were cancelled
+    jobs: Arc<(Mutex<HashMap<String, bool>>, Condvar)>,
+    // the format request in flight for every path
+    documents: Arc<Mutex<HashMap<String, Leader>>>,
+    // the directories being watched by watch id, dropping them stops them
+    watches: Mutex<HashMap<u64, Watch>>,
+    next_watch: AtomicUsize,
+}
+
+impl<W: Write + Send + 'static> Session<W> {
+    fn new(output: W, encoding: Encoding) -> Session<W> {
+        Session {
+            output: Arc::new(Mutex::new(output)),
+            encoding: encoding,
+            jobs: Arc::new((Mutex::new(HashMap::new()), Condvar::new())),
+            documents: Arc::new(Mutex::new(HashMap::new())),
+            watches: Mutex::new(HashMap::new()),
+            next_watch: AtomicUsize::new(1),
+        }
+    }
+
+    fn send(&self, response: &Value) -> io::Result<()> {
+        send(&self.output, response, self.encoding)
+    }
+
+    /// Block until no more than `running` jobs are in flight
+    fn wait_jobs(&self, running: usize) {
+        let &(ref jobs, ref finished) = &*self.jobs;
+        let mut jobs = jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        while jobs.len() > running {
+            jobs = finished.wait(jobs).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Whether a request with the id is running or waiting for one
+    fn in_flight(&self, key: &str) -> bool {
+        self.jobs.0.lock().unwrap_or_else(PoisonError::into_inner).contains_key(key) ||
+        self.documents
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .any(|leader| leader.followers.iter().any(|follower| follower.to_string() == key))
+    }
+
+    /// Mark the job as cancelled, true if it was running and not cancelled yet
+    fn mark_cancelled(&self, key: &str) -> bool {
+        let mut jobs = self.jobs.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match jobs.get_mut(key) {
+            Some(cancelled) if !*cancelled => {
+                *cancelled = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stop waiting for the response of a coalesced request, true if it was
+    /// waiting
+    fn drop_follower(&self, key: &str) -> bool {
+        let mut documents = self.documents.lock().unwrap_or_else(PoisonError::into_inner);
+        for leader in documents.values_mut() {
+            if let Some(position) = leader.followers.iter().position(|follower| follower.to_string() == key) {
+                leader.followers.remove(position);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Mark the job as cancelled and answer it with an error, the job keeps
+    /// running until rustfmt or racer return but its result is thrown away
+    fn cancel(&self, id: &Value) -> io::Result<bool> {
+        let key = id.to_string();
+        let cancelled = self.mark_cancelled(&key) || self.drop_follower(&key);
+        if cancelled {
+            try!(self.send(&json!({"id": id, "error": "cancelled"})));
+        }
+        Ok(cancelled)
+    }
+
+    /// Attach a format request to the one in flight for the same path and
+    /// buffer, true if it was attached. A request for a different buffer
+    /// supersedes the one in flight (answered with a `superseded` error)
+    /// unless its `supersede` param is false
+    fn coalesce(&self, id: &Value, request: &Value) -> io::Result<bool> {
+        let (path, hash) = match document(request) {
+            Some(document) => document,
+            None => return Ok(false),
+        };
+        let supersede = request.get("params")
+            .and_then(|params| params.get("supersede"))
+            .and_then(|supersede| supersede.as_bool())
+            .unwrap_or(true);
+        let superseded = {
+            let mut documents = self.documents.lock().unwrap_or_else(PoisonError::into_inner);
+            match documents.get(&path).map(|leader| leader.hash == hash) {
+                Some(true) => {
+                    documents.get_mut(&path).unwrap().followers.push(id.clone());
+                    return Ok(true);
+                }
+                Some(false) if supersede => documents.remove(&path),
+                _ => None,
+            }
+        };
+
+        if let Some(leader) = superseded {
+            if self.mark_cancelled(&leader.key) {
+                try!(self.send(&json!({"id": leader.id, "error": "superseded"})));
+            }
+            for follower in leader.followers {
+                try!(self.send(&json!({"id": follower, "error": "superseded"})));
+            }
+        }
+        Ok(false)
+    }
+
+    fn spawn(&self, key: String, id: Value, request: Value) {
+        let path = document(&request).map(|(path, hash)| {
+            // the first request for a path leads, later ones for the same
+            // buffer are coalesced into it
+            let mut documents = self.documents.lock().unwrap_or_else(PoisonError::into_inner);
+            documents.entry(path.clone()).or_insert(Leader {
+                key: key.clone(),
+                id: id,
+                hash: hash,
+                followers: Vec::new(),
+            });
+            path
+        });
+        let output = self.output.clone();
+        let encoding = self.encoding;
+        let jobs = self.jobs.clone();
+        let documents = self.documents.clone();
+        thread::spawn(move || {
+            let (mut response, _) = respond(request);
+            let &(ref running, ref finished) = &*jobs;
+            // the job stays locked until its response is sent so the session
+            // can't end while it is half written
+            let mut running = running.lock().unwrap_or_else(PoisonError::into_inner);
+            let cancelled = running.remove(&key).unwrap_or(false);
+            let followers = match path {
+                Some(path) => {
+                    let mut documents = documents.lock().unwrap_or_else(PoisonError::into_inner);
+                    if documents.get(&path).map(|leader| leader.key == key).unwrap_or(false) {
+                        documents.remove(&path).map(|leader| leader.followers).unwrap_or_default()
+                    } else {
+                        Vec::new()
+                    }
+                }
+                None => Vec::new(),
+            };
+            if !followers.is_empty() {
+                response["coalesced"] = json!(true);
+            }
+            // a broken output is noticed by the session on its next write
+            if !cancelled {
+                let _ = send(&output, &response, encoding);
+            }
+            for follower in followers {
+                response["id"] = follower;
+                let _ = send(&output, &response, encoding);
+            }
+            drop(running);
+            finished.notify_all();
+        });
+    }
+
+    /// Start watching the `dir` param, the result is the watch id
+    fn watch(&self, params: &Value) -> Result<Value, String> {
+        let dir = match params.get("dir").and_then(|dir| dir.as_str()) {
+            Some(dir) if Path::new(dir).is_dir() => PathBuf::from(dir),
+            Some(dir) => return Err(format!("{} is not a directory", dir)),
+            None => return Err(String::from("missing param `dir`")),
+        };
+        let patterns = match params.get("patterns") {
+            None => vec![String::from("*.rs")],
+            Some(&Value::Array(ref patterns)) if patterns.iter().all(|pattern| pattern.is_string()) => {
+                patterns.iter().filter_map(|pattern| pattern.as_str()).map(String::from).collect()
+            }
+            Some(_) => return Err(String::from("`patterns` must be an array of strings")),
+        };
+        let mode = match params.get("mode").and_then(|mode| mode.as_str()) {
+            None | Some("check") => watch::Mode::Check,
+            Some("overwrite") => watch::Mode::Overwrite,
+            Some(mode) => return Err(format!("unknown watch mode {}", mode)),
+        };
+
+        let honor_ignores = match params.get("ignore") {
+            None => true,
+            Some(&Value::Bool(honor_ignores)) => honor_ignores,
+            Some(_) => return Err(String::from("`ignore` must be a boolean")),
+        };
+
+        let id = self.next_watch.fetch_add(1, Ordering::SeqCst) as u64;
+        let (output, encoding) = (self.output.clone(), self.encoding);
+        let notify = move |mut event: Value| {
+            event["watch"] = json!(id);
+            let _ = send(&output, &event, encoding);
+        };
+        let watch = Watch::start(dir, patterns, mode, honor_ignores, Box::new(notify));
+        self.watches.lock().unwrap_or_else(PoisonError::into_inner).insert(id, watch);
+        Ok(json!(id))
+    }
+
+    /// Stop the watch given by the `watch` param, the result is `true` if
+    /// there was such a watch
+    fn unwatch(&self, params: &Value) -> Result<Value, String> {
+        let id = match params.get("watch").and_then(|id| id.as_u64()) {
+            Some(id) => id,
+            None => return Err(String::from("missing param `watch`")),
+        };
+        // the lock is released before the watch thread is joined
+        let watch = self.watches.lock().unwrap_or_else(PoisonError::into_inner).remove(&id);
+        Ok(json!(watch.is_some()))
+    }
+
+    fn handle(&self, request: Value) -> io::Result<Next> {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request.get("method").and_then(|method| method.as_str()).map(String::from);
+        match method.as_ref().map(|method| &method[..]) {
+            Some("cancel") => {
+                let target = request.get("params").and_then(|params| params.get("id")).cloned();
+                let response = match target {
+                    Some(target) => json!({"id": id, "status": 0, "result": try!(self.cancel(&target))}),
+                    None => json!({"id": id, "error": "missing param `id`"}),
+                };
+                try!(self.send(&response));
+                return Ok(Next::Continue);
+            }
+            Some(method @ "watch") | Some(method @ "unwatch") => {
+                let params = request.get("params").cloned().unwrap_or(Value::Null);
+                let result = if method == "watch" { self.watch(&params) } else { self.unwatch(&params) };
+                let response = match result {
+                    Ok(result) => json!({"id": id, "status": 0, "result": result}),
+                    Err(reason) => json!({"id": id, "error": reason}),
+                };
+                try!(self.send(&response));
+                return Ok(Next::Continue);
+            }
+            Some("shutdown") | Some("stop_server") | None => {}
+            Some(_) if !id.is_null() => {
+                let key = id.to_string();
+                if self.in_flight(&key) {
+                    try!(self.send(&json!({"id": id, "error": "a request with the same id is in flight"})));
+                } else if !try!(self.coalesce(&id, &request)) {
+                    self.wait_jobs(limit_value(&MAX_IN_FLIGHT, DEFAULT_MAX_IN_FLIGHT) - 1);
+                    self.jobs.0.lock().unwrap_or_else(PoisonError::into_inner).insert(key.clone(), false);
+                    self.spawn(key, id, request);
+                }
+                return Ok(Next::Continue);
+            }
+            Some(_) => {}
+        }
+
+        // requests without an id are answered in order, after every job
+        self.wait_jobs(0);
+        let (response, next) = respond(request);
+        try!(self.send(&response));
+        Ok(next)
+    }
+}
+
+/// Serve JSON requests, one per line
+fn serve_json<R: BufRead, W: Write + Send + 'static>(input: R, session: &Session<W>) -> io::Result<Next> {
+    for line in input.lines() {
+        let line = try!(line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let next = match serde_json::from_str(&line) {
+            Ok(request) => try!(session.handle(request)),
+            Err(e) => {
+                try!(session.send(&json!({"id": Value::Null, "error": format!("malformed request: {}", e)})));
+                Next::Continue
+            }
+        };
+        if next != Next::Continue {
+            return Ok(next);
+        }
+    }
+    Ok(Next::Close)
+}
+
+/// Serve MessagePack requests, there is no way to find the next request after
+/// a malformed one so the session is closed after reporting it
+fn serve_msgpack<R: BufRead, W: Write + Send + 'static>(mut input: R, session: &Session<W>) -> io::Result<Next> {
+    loop {
+        let next = match msgpack::read(&mut input) {
+            Ok(Some(request)) => try!(session.handle(request)),
+            Ok(None) => return Ok(Next::Close),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                try!(session.send(&json!({"id": Value::Null, "error": format!("malformed request: {}", e)})));
+                Next::Close
+            }
+            Err(e) => return Err(e),
+        };
+        if next != Next::Continue {
+            return Ok(next);
+        }
+    }
+}
+
+/// Set how many requests with an id a session runs at the same time, 0 goes
+/// back to the default (4)
+pub fn set_max_in_flight(n: usize) {
+    MAX_IN_FLIGHT.store(if n == 0 { 0 } else { n.saturating_add(1) }, Ordering::Relaxed);
+}
+
+/// Serve requests from the input until it ends or the session is closed, the
+/// returned value is `Next::StopServer` if a request asked for it. The first
+/// byte of the session tells the encoding, MessagePack requests start with a
+/// map marker while JSON ones start with `{` or whitespace. The jobs still
+/// running are waited for before returning
+pub fn serve_session<R: BufRead, W: Write + Send + 'static>(mut input: R, output: W) -> io::Result<Next> {
+    let encoding = match try!(input.fill_buf()).first() {
+        Some(&byte) if msgpack::starts_map(byte) => Encoding::MessagePack,
+        Some(_) => Encoding::Json,
+        None => return Ok(Next::Close),
+    };
+    let session = Session::new(output, encoding);
+    let next = match encoding {
+        Encoding::Json => serve_json(input, &session),
+        Encoding::MessagePack => serve_msgpack(input, &session),
+    };
+    session.wait_jobs(0);
+    next
+}
+
+/// Serve requests from the input until it ends or a `shutdown` request comes
+pub fn serve<R: BufRead, W: Write + Send + 'static>(input: R, output: W) -> io::Result<()> {
+    serve_session(input, output).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{self, Value};
+
+    use testing::TempDir;
+    use super::*;
+
+    /// The output of a session, shared with the test
+    #[derive(Clone)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Serve the requests (one JSON value per line) and return the responses
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let input = requests.iter().map(|request| format!("{}\n", request)).collect::<String>();
+        let output = Output(Arc::new(Mutex::new(Vec::new())));
+        serve_session(input.as_bytes(), output.clone()).unwrap();
+        let bytes = output.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn requests_with_an_id_are_answered_out_of_order() {
+        let dir = TempDir::new("daemon-pipelining");
+        let path = dir.write("main.rs", "").to_string_lossy().into_owned();
+        let slow = json!({"id": 1, "method": "format", "params": {
+            "code": "fn main() {\n    let x=1;\n}\n", "path": path, "delay_ms": 300}});
+        let fast = json!({"id": 2, "method": "version"});
+        let responses = session(&[slow, fast]);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], 2);
+        assert_eq!(responses[1]["id"], 1);
+        assert_eq!(responses[1]["result"], "fn main() {\n    let x = 1;\n}\n");
+    }
+
+    #[test]
+    fn requests_without_an_id_wait_for_the_running_ones() {
+        let slow = json!({"id": 1, "method": "version", "params": {"delay_ms": 200}});
+        let ordered = json!({"method": "version"});
+        let responses = session(&[slow, ordered]);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["id"], Value::Null);
+    }
+
+    #[test]
+    fn cancel_answers_the_request_in_flight() {
+        let slow = json!({"id": "slow", "method": "version", "params": {"delay_ms": 300}});
+        let cancel = json!({"id": 2, "method": "cancel", "params": {"id": "slow"}});
+        let again = json!({"id": 3, "method": "cancel", "params": {"id": "slow"}});
+        let responses = session(&[slow, cancel, again]);
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0], json!({"id": "slow", "error": "cancelled"}));
+        assert_eq!(responses[1], json!({"id": 2, "status": 0, "result": true}));
+        assert_eq!(responses[2], json!({"id": 3, "status": 0, "result": false}));
+    }
+
+    fn format_request(id: u64, path: &str, code: &str) -> Value {
+        json!({"id": id, "method": "format", "params": {"code": code, "path": path, "delay_ms": 200}})
+    }
+
+    #[test]
+    fn identical_requests_are_coalesced() {
+        let dir = TempDir::new("daemon-coalesce");
+        let path = dir.write("main.rs", "").to_string_lossy().into_owned();
+        let code = "fn main() {\n    let x=1;\n}\n";
+        let burst = [format_request(1, &path, code), format_request(2, &path, code), format_request(3, &path, code)];
+        let mut responses = session(&burst);
+        responses.sort_by_key(|response| response["id"].as_u64());
+        assert_eq!(responses.len(), 3);
+        for (id, response) in responses.iter().enumerate() {
+            assert_eq!(response["id"], id as u64 + 1);
+            assert_eq!(response["coalesced"], true);
+            assert_eq!(response["result"], "fn main() {\n    let x = 1;\n}\n");
+        }
+    }
+
+    #[test]
+    fn newer_buffers_supersede_the_request_in_flight() {
+        let dir = TempDir::new("daemon-supersede");
+        let path = dir.write("main.rs", "").to_string_lossy().into_owned();
+        let old = "fn main() {\n    let x=1;\n}\n";
+        let new = "fn main() {\n    let x=2;\n}\n";
+        let burst = [format_request(1, &path, old), format_request(2, &path, old), format_request(3, &path, new)];
+        let responses = session(&burst);
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0], json!({"id": 1, "error": "superseded"}));
+        assert_eq!(responses[1], json!({"id": 2, "error": "superseded"}));
+        assert_eq!(responses[2], json!({"id": 3, "status": 0, "result": "fn main() {\n    let x = 2;\n}\n"}));
+
+        let mut keep = format_request(5, &path, new);
+        keep["params"]["supersede"] = json!(false);
+        let mut responses = session(&[format_request(4, &path, old), keep]);
+        responses.sort_by_key(|response| response["id"].as_u64());
+        assert_eq!(responses[0]["result"], "fn main() {\n    let x = 1;\n}\n");
+        assert_eq!(responses[1]["result"], "fn main() {\n    let x = 2;\n}\n");
+    }
+}
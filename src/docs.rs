@@ -0,0 +1,377 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Formatting of the Rust code blocks found inside doc comments. rustfmt
+//! leaves the contents of comments alone so examples in `///`, `//!` and
+//! `/** */` comments drift from the style of the real code, this pass finds
+//! the fenced blocks that rustdoc would compile, formats them on their own
+//! and splices them back keeping the comment prefix of the block.
+
+use backend::Config;
+use fragment::format_in_function;
+use scanner::{self, Kind};
+use format_to_string;
+
+// attributes that rustdoc accepts in the info string of a Rust code block
+const RUST_ATTRIBUTES: &'static [&'static str] = &["rust",
+                                                   "should_panic",
+                                                   "no_run",
+                                                   "compile_fail",
+                                                   "test_harness",
+                                                   "allow_fail"];
+
+/// A line of a doc comment, offsets are absolute byte offsets in the buffer
+/// and `end` never includes the line terminator
+struct DocLine {
+    start: usize,
+    content: usize,
+    end: usize,
+}
+
+/// Format every Rust code block found in the doc comments of the buffer, the
+/// returned notes describe the blocks that had to be left untouched
+pub fn format_blocks(code: &str, config: &Config) -> (String, Vec<String>) {
+    let mut notes = Vec::new();
+    let mut replacements = Vec::new();
+
+    for block in doc_comments(code) {
+        replacements.extend(format_comment(code, &block, config, &mut notes));
+    }
+
+    replacements.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut output = String::from(code);
+    for (start, end, text) in replacements.into_iter().rev() {
+        output = format!("{}{}{}", &output[..start], text, &output[end..]);
+    }
+    (output, notes)
+}
+
+fn line_number(code: &str, offset: usize) -> usize {
+    code[..offset].matches('\n').count() + 1
+}
+
+fn line_end(code: &str, start: usize) -> usize {
+    let end = code[start..].find('\n').map(|pos| start + pos).unwrap_or(code.len());
+    if end > start && code.as_bytes()[end - 1] == b'\r' {
+        end - 1
+    } else {
+        end
+    }
+}
+
+fn next_line(code: &str, start: usize) -> Option<usize> {
+    code[start..].find('\n').map(|pos| start + pos + 1).and_then(|next| {
+        if next < code.len() { Some(next) } else { None }
+    })
+}
+
+/// Collect the doc comments of the buffer as lists of lines, consecutive
+/// `///` (or `//!`) lines are grouped together as a single comment
+fn doc_comments(code: &str) -> Vec<Vec<DocLine>> {
+    let mut comments = Vec::new();
+    let mut current: Vec<DocLine> = Vec::new();
+    let mut current_marker = "";
+    let mut last_line_end = 0;
+
+    for span in scanner::scan(code).into_iter().filter(|s| s.kind == Kind::Comment) {
+        let text = &code[span.start..span.end];
+        let line_start = code[..span.start].rfind('\n').map(|pos| pos + 1).unwrap_or(0);
+        let alone = code[line_start..span.start].trim().is_empty();
+
+        let marker = if text.starts_with("///") && !text.starts_with("////") {
+            "///"
+        } else if text.starts_with("//!") {
+            "//!"
+        } else {
+            if (text.starts_with("/**") && !text.starts_with("/***") && text != "/**/") ||
+               text.starts_with("/*!") {
+                comments.push(block_comment_lines(code, span.start, span.end));
+            }
+            continue;
+        };
+
+        // a line doc comment continues the current one only if it is placed
+        // alone in the very next line and uses the same marker
+        let contiguous = !current.is_empty() && current_marker == marker &&
+                         next_line(code, last_line_end) == Some(line_start);
+        if !contiguous && !current.is_empty() {
+            comments.push(current);
+            current = Vec::new();
+        }
+        if !alone {
+            continue;
+        }
+
+        let mut content = span.start + 3;
+        if code[content..].starts_with(' ') {
+            content += 1;
+        }
+        let end = line_end(code, span.start);
+        current.push(DocLine { start: line_start, content: content.min(end), end: end });
+        current_marker = marker;
+        last_line_end = end;
+    }
+
+    if !current.is_empty() {
+        comments.push(current);
+    }
+    comments
+}
+
+/// Only the lines between the opening and closing lines of the comment are
+/// considered, a leading `*` on them is part of the prefix
+fn block_comment_lines(code: &str, start: usize, end: usize) -> Vec<DocLine> {
+    let mut lines = Vec::new();
+    let mut current = next_line(code, start);
+    while let Some(line_start) = current {
+        let line_end = line_end(code, line_start);
+        if line_end >= end {
+            break;
+        }
+
+        let text = &code[line_start..line_end];
+        let mut content = line_start + (text.len() - text.trim_left().len());
+        if code[content..line_end].starts_with('*') {
+            content += 1;
+            if code[content..line_end].starts_with(' ') {
+                content += 1;
+            }
+        }
+        lines.push(DocLine { start: line_start, content: content, end: line_end });
+        current = next_line(code, line_start);
+    }
+    lines
+}
+
+/// Returns the fence character, its length and the info string if the line
+/// opens a fenced code block
+fn fence(line: &str) -> Option<(char, usize, &str)> {
+    let line = line.trim_left();
+    for marker in &['`', '~'] {
+        let len = line.chars().take_while(|c| c == marker).count();
+        if len >= 3 {
+            return Some((*marker, len, line[len..].trim()));
+        }
+    }
+    None
+}
+
+fn closes_fence(line: &str, marker: char, len: usize) -> bool {
+    let line = line.trim();
+    line.chars().take_while(|c| *c == marker).count() >= len && line.chars().all(|c| c == marker)
+}
+
+/// Bare fences default to Rust, as rustdoc does
+fn is_rust(info: &str) -> bool {
+    info.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .all(|token| RUST_ATTRIBUTES.contains(&token) || token.starts_with("edition"))
+}
+
+fn format_comment(code: &str,
+                  lines: &[DocLine],
+                  config: &Config,
+                  notes: &mut Vec<String>)
+                  -> Vec<(usize, usize, String)> {
+    let mut replacements = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let opening = &code[lines[i].content..lines[i].end];
+        let (marker, len, info) = match fence(opening) {
+            Some(fence) => fence,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let closing = match (i + 1..lines.len())
+            .find(|&j| closes_fence(&code[lines[j].content..lines[j].end], marker, len)) {
+            Some(j) => j,
+            None => {
+                notes.push(format!("unterminated code block in doc comment at line {}",
+                                   line_number(code, lines[i].start)));
+                break;
+            }
+        };
+
+        if is_rust(info) && closing > i + 1 {
+            let line = line_number(code, lines[i].start);
+            match format_block(code, &lines[i..closing + 1], config) {
+                Ok(Some(replacement)) => replacements.push(replacement),
+                Ok(None) => {}
+                Err(reason) => {
+                    notes.push(format!("code block in doc comment at line {} left untouched: {}",
+                                       line,
+                                       reason))
+                }
+            }
+        }
+        i = closing + 1;
+    }
+    replacements
+}
+
+/// Format the code between the fences (first and last lines given), returns
+/// None when the block is already formatted
+fn format_block(code: &str,
+                lines: &[DocLine],
+                config: &Config)
+                -> Result<Option<(usize, usize, String)>, String> {
+    let opening = &lines[0];
+    let body = &lines[1..lines.len() - 1];
+    let prefix = &code[opening.start..opening.content];
+    let fence_text = &code[opening.content..opening.end];
+    let indent = fence_text.len() - fence_text.trim_left().len();
+    let newline = if code[opening.end..].starts_with("\r\n") { "\r\n" } else { "\n" };
+
+    // strip the fence indentation and the hidden line markers
+    let mut source = String::new();
+    let mut hidden = Vec::new();
+    for line in body {
+        let text = &code[line.content..line.end];
+        let strip = text.len() - text.trim_left().len();
+        let text = &text[strip.min(indent)..];
+        let trimmed = text.trim_left();
+        if trimmed == "#" || trimmed.starts_with("# ") {
+            hidden.push(true);
+            source.push_str(trimmed[1..].trim_left());
+        } else {
+            hidden.push(false);
+            source.push_str(text);
+        }
+        source.push('\n');
+    }
+
+    let width = prefix.chars().count() + indent;
+    let mut snippet_config = config.clone();
+    snippet_config.set_max_width(config.max_width().saturating_sub(width));
+    snippet_config.set_ideal_width(config.ideal_width().saturating_sub(width));
+    let formatted = try!(format_snippet(&source, &snippet_config));
+
+    let original: Vec<&str> = source.lines().collect();
+    let marks = if hidden.contains(&true) {
+        try!(map_hidden_lines(&original, &hidden, &formatted))
+    } else {
+        vec![false; formatted.len()]
+    };
+
+    let mut text = String::new();
+    for (line, &is_hidden) in formatted.iter().zip(marks.iter()) {
+        let line = match (is_hidden, line.is_empty()) {
+            (true, true) => format!("{}{}#", prefix, " ".repeat(indent)),
+            (true, false) => format!("{}{}# {}", prefix, " ".repeat(indent), line),
+            (false, true) => String::from(prefix.trim_right()),
+            (false, false) => format!("{}{}{}", prefix, " ".repeat(indent), line),
+        };
+        text.push_str(&line);
+        text.push_str(newline);
+    }
+
+    let start = body[0].start;
+    let end = lines[lines.len() - 1].start;
+    if code[start..end] == text[..] {
+        return Ok(None);
+    }
+    Ok(Some((start, end, text)))
+}
+
+/// Doc examples usually are a list of statements that rustdoc wraps into a
+/// main function, so if the snippet does not parse on its own we try again
+/// wrapped and remove the wrapper afterwards
+fn format_snippet(source: &str, config: &Config) -> Result<Vec<String>, String> {
+    let (summary, output) = format_to_string(String::from(source), config);
+    if let (false, Some(output)) = (summary.has_parsing_errors(), output) {
+        return Ok(output.lines().map(String::from).collect());
+    }
+
+    format_in_function(source, config)
+}
+
+fn squash(line: &str) -> String {
+    line.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+/// Align the formatted lines with the original ones (longest common
+/// subsequence of their contents ignoring whitespace) so we know which of them
+/// have to be hidden again, every hidden line must find its counterpart
+fn map_hidden_lines(original: &[&str], hidden: &[bool], formatted: &[String]) -> Result<Vec<bool>, String> {
+    let original: Vec<String> = original.iter().map(|line| squash(line)).collect();
+    let formatted: Vec<String> = formatted.iter().map(|line| squash(line)).collect();
+    let (n, m) = (original.len(), formatted.len());
+    let mut table = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i][j] = if original[i] == formatted[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+
+    let mut marks = vec![false; m];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if original[i] == formatted[j] {
+            marks[j] = hidden[i];
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            if hidden[i] {
+                return Err(String::from("hidden lines changed during formatting"));
+            }
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    if hidden[i..].contains(&true) {
+        return Err(String::from("hidden lines changed during formatting"));
+    }
+    Ok(marks)
+}
+
+#[cfg(test)]
+mod tests {
+    use backend::Config;
+    use super::format_blocks;
+
+    fn format(code: &str) -> (String, Vec<String>) {
+        format_blocks(code, &Config::default())
+    }
+
+    #[test]
+    fn a_fence_inside_a_longer_fence_is_not_a_block() {
+        let code = "/// ````text\n/// ```\n/// let x=1;\n/// ```\n/// ````\nfn f() {}\n";
+        assert_eq!(format(code), (String::from(code), Vec::new()));
+
+        let code = "/// ````\n/// let x=1;\n/// ```\n/// let y=2;\n/// ````\nfn f() {}\n";
+        let (output, _) = format(code);
+        assert!(output.starts_with("/// ````\n/// let x = 1;\n"), "{}", output);
+    }
+
+    #[test]
+    fn hidden_lines_stay_hidden() {
+        let code = "//! ```\n//! # let y=2;\n//! let x=y;\n//! # let z=x;\n//! ```\n";
+        assert_eq!(format(code).0, "//! ```\n//! # let y = 2;\n//! let x = y;\n//! # let z = x;\n//! ```\n");
+    }
+
+    #[test]
+    fn crlf_doc_blocks_keep_their_line_endings() {
+        let code = "/// ```\r\n/// let x=1;\r\n/// ```\r\nfn f() {}\r\n";
+        assert_eq!(format(code), (String::from("/// ```\r\n/// let x = 1;\r\n/// ```\r\nfn f() {}\r\n"), Vec::new()));
+        let code = "/**\r\n * ```\r\n * let x=1;\r\n * ```\r\n */\r\nfn f() {}\r\n";
+        assert_eq!(format(code).0, "/**\r\n * ```\r\n * let x = 1;\r\n * ```\r\n */\r\nfn f() {}\r\n");
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Edition specific configuration. A `rustfmt.<edition>.toml` file next to
+//! the project `rustfmt.toml` (e.g. `rustfmt.2018.toml`) overrides its
+//! options for the crates of that edition, the edition is the one in the
+//! `[package]` section of the nearest `Cargo.toml` (2015 when there is none).
+//!
+//! The edition only picks the options, the parser of rustfmt knows about the
+//! 2015 edition alone so the code of every crate is parsed as 2015 code (and
+//! `--edition` only accepts 2015 for the same reason).
+
+use std::path::{Path, PathBuf};
+
+use toml::{self, Value};
+use toml::value::Table;
+
+use {read_with_overlay, Overlay};
+
+const DEFAULT_EDITION: &'static str = "2015";
+
+/// Returns the value of `edition` in the `[package]` section of the manifest
+fn manifest_edition(manifest: &str) -> Option<String> {
+    let mut in_package = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+            (Some("edition"), Some(value)) if in_package => {
+                return Some(String::from(value.trim_matches('"').trim_matches('\'')));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the edition of the crate that contains the directory
+pub fn detect(dir: &Path, overlay: &Overlay) -> String {
+    let mut current = dir.to_path_buf();
+    loop {
+        if let Ok(manifest) = read_with_overlay(&current.join("Cargo.toml"), overlay) {
+            return manifest_edition(&manifest).unwrap_or_else(|| String::from(DEFAULT_EDITION));
+        }
+        if !current.pop() {
+            return String::from(DEFAULT_EDITION);
+        }
+    }
+}
+
+/// Returns the path of the edition specific file for the project config file
+pub fn config_file(project_file: &Path, edition: &str) -> PathBuf {
+    project_file.with_file_name(format!("rustfmt.{}.toml", edition))
+}
+
+fn parse_table(toml: &str) -> Result<Table, String> {
+    match toml.parse::<Value>() {
+        Ok(Value::Table(table)) => Ok(table),
+        Ok(_) => Err(String::from("the config is not a table")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// the tables of both sides are merged key by key, any other value of the
+// layer replaces the one of the base
+fn merge_tables(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        let merged = match (base.remove(&key), value) {
+            (Some(Value::Table(mut base)), Value::Table(layer)) => {
+                merge_tables(&mut base, layer);
+                Value::Table(base)
+            }
+            (_, value) => value,
+        };
+        base.insert(key, merged);
+    }
+}
+
+/// Returns the TOML of the base config with the options of the layer on top,
+/// the error tells why one of them is not valid TOML
+pub fn merge(base: &str, layer: &str) -> Result<String, String> {
+    let mut merged = try!(parse_table(base));
+    merge_tables(&mut merged, try!(parse_table(layer)));
+    toml::to_string(&Value::Table(merged)).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use testing::TempDir;
+    use try_load_config;
+    use super::*;
+
+    #[test]
+    fn the_layer_replaces_values_and_merges_tables() {
+        let base = "max_width = 80\nhard_tabs = true\n[section]\na = 1\nb = 2\n";
+        let layer = "max_width = 100\n[section]\nb = 3\n";
+        let merged = merge(base, layer).unwrap().parse::<Value>().unwrap();
+        let expected = "hard_tabs = true\nmax_width = 100\n[section]\na = 1\nb = 3\n".parse::<Value>().unwrap();
+        assert_eq!(merged, expected);
+        assert!(merge("max_width = ", layer).is_err());
+        assert!(merge(base, "[section\n").is_err());
+    }
+
+    #[test]
+    fn the_2021_config_overrides_the_base_config() {
+        let dir = TempDir::new("edition-2021");
+        dir.write("Cargo.toml", "[package]\nname = \"a\"\nedition = \"2021\"\n");
+        dir.write("rustfmt.toml", "max_width = 80\nhard_tabs = true\n");
+        dir.write("rustfmt.2021.toml", "max_width = 100\n");
+        let path = dir.write("src/main.rs", "fn main() {}\n").to_string_lossy().into_owned();
+        let config = try_load_config(Some(path)).unwrap();
+        assert_eq!(config.max_width(), 100);
+        assert!(config.hard_tabs());
+
+        dir.write("Cargo.toml", "[package]\nname = \"a\"\n");
+        let path = dir.path().join("src/main.rs").to_string_lossy().into_owned();
+        assert_eq!(try_load_config(Some(path)).unwrap().max_width(), 80);
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Process wide record of the last output of `format` for every path. The
+//! editor formats on every save, even when nothing was edited since the last
+//! one, and then the buffer is exactly the previous output: there is no need
+//! to run the formatter again as long as the effective config is the same.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// maximum number of paths that we remember
+const CAPACITY: usize = 64;
+
+struct Entry {
+    // hashes of the formatted output and of the config that produced it
+    output: u64,
+    config: u64,
+    used: Instant,
+}
+
+lazy_static! {
+    static ref FORMATTED: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+}
+
+/// Whether the buffer with the given hash is the last output formatted for
+/// the path with the same config
+pub fn is_formatted(path: &str, buffer: u64, config: u64) -> bool {
+    let mut formatted = match FORMATTED.lock() {
+        Ok(formatted) => formatted,
+        Err(_) => return false,
+    };
+    match formatted.get_mut(path) {
+        Some(ref mut entry) if entry.output == buffer && entry.config == config => {
+            entry.used = Instant::now();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Remember the output of a successful run for the path
+pub fn remember(path: String, output: u64, config: u64) {
+    let mut formatted = match FORMATTED.lock() {
+        Ok(formatted) => formatted,
+        Err(_) => return,
+    };
+    if formatted.len() >= CAPACITY && !formatted.contains_key(&path) {
+        let oldest = formatted.iter().min_by_key(|&(_, entry)| entry.used).map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            formatted.remove(&oldest);
+        }
+    }
+    formatted.insert(path,
+                     Entry {
+                         output: output,
+                         config: config,
+                         used: Instant::now(),
+                     });
+}
+
+/// Forget the path, the next run for it always formats
+pub fn forget(path: &str) {
+    if let Ok(mut formatted) = FORMATTED.lock() {
+        formatted.remove(path);
+    }
+}
+
+pub fn clear() {
+    if let Ok(mut formatted) = FORMATTED.lock() {
+        formatted.clear();
+    }
+}
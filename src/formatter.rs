@@ -0,0 +1,286 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A reusable formatter for Rust tools. The configuration is resolved (and
+//! the overrides applied) once when the formatter is built and shared by
+//! every buffer or file formatted with it:
+//!
+//! ```no_run
+//! use rustanaconda::formatter::{Formatter, Mode};
+//!
+//! let formatter = Formatter::builder()
+//!     .config_path("src/lib.rs")
+//!     .override_("max_width", "100")
+//!     .write_mode(Mode::Check)
+//!     .build()
+//!     .unwrap();
+//! let output = formatter.format("fn main(){}").unwrap();
+//! println!("needs formatting: {}", output.changed);
+//! ```
+//!
+//! `format_string` and the exported functions use a formatter underneath.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use args;
+use backend::Config;
+use config_cache;
+use lock;
+use {fnv1a, render_or_skip, size_limit_error, try_load_config};
+use {FormatError, FormatOutput, FormatSummary};
+
+/// What the formatter does with the formatted code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Return the formatted code
+    Plain,
+    /// Return the code as it was, `FormatOutput::changed` tells whether it
+    /// is not formatted
+    Check,
+    /// Like `Plain`, `format_file` writes the formatted code back too
+    Overwrite,
+}
+
+/// Builds a `Formatter`, see `Formatter::builder`
+#[derive(Clone, Debug)]
+pub struct FormatterBuilder {
+    config_path: Option<String>,
+    bypass_config_cache: bool,
+    overrides: Vec<(String, String)>,
+    mode: Mode,
+}
+
+impl FormatterBuilder {
+    /// Resolve the project configuration for this file or directory instead
+    /// of the current directory
+    pub fn config_path<P: Into<String>>(mut self, path: P) -> FormatterBuilder {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Look up the project configuration without the lookup cache, see
+    /// `FormatOptions::bypass_config_cache`
+    pub fn bypass_config_cache(mut self, bypass: bool) -> FormatterBuilder {
+        self.bypass_config_cache = bypass;
+        self
+    }
+
+    /// Override an option of the resolved configuration
+    pub fn override_(mut self, key: &str, value: &str) -> FormatterBuilder {
+        self.overrides.push((String::from(key), String::from(value)));
+        self
+    }
+
+    /// What to do with the formatted code, `Plain` by default
+    pub fn write_mode(mut self, mode: Mode) -> FormatterBuilder {
+        self.mode = mode;
+        self
+    }
+
+    /// Resolve the configuration and apply the overrides, unknown options
+    /// and invalid values are reported here
+    pub fn build(self) -> Result<Formatter, FormatError> {
+        let config_path = self.config_path.clone();
+        let config = if self.bypass_config_cache {
+            config_cache::bypass(|| try_load_config(config_path))
+        } else {
+            try_load_config(config_path)
+        };
+        let mut config = try!(config);
+        try!(args::apply(&mut config, &self.overrides).map_err(FormatError::ConfigParse));
+        Ok(Formatter {
+            config: config,
+            config_path: self.config_path,
+            mode: self.mode,
+        })
+    }
+}
+
+/// Formats buffers and files with the configuration resolved when it was
+/// built
+#[derive(Clone)]
+pub struct Formatter {
+    config: Config,
+    config_path: Option<String>,
+    mode: Mode,
+}
+
+impl Formatter {
+    pub fn builder() -> FormatterBuilder {
+        FormatterBuilder {
+            config_path: None,
+            bypass_config_cache: false,
+            overrides: Vec::new(),
+            mode: Mode::Plain,
+        }
+    }
+
+    /// Format the input, the last output for the config path is remembered
+    /// so formatting it again skips rustfmt
+    pub fn format(&self, input: &str) -> Result<FormatOutput, FormatError> {
+        self.format_buffer(String::from(input), self.config_path.clone())
+    }
+
+    /// Format the file, in `Overwrite` mode the output is written to the
+    /// file when it changed. The file is locked from the read to the
+    /// write, `FormatError::Busy` tells that another write didn't end in time
+    pub fn format_file(&self, path: &Path) -> Result<FormatOutput, FormatError> {
+        let lock = if self.mode == Mode::Overwrite {
+            Some(try!(lock::lock(path).map_err(FormatError::Busy)))
+        } else {
+            None
+        };
+        let size = try!(fs::metadata(path).map_err(FormatError::Io)).len();
+        if let Some(reason) = size_limit_error(size as usize) {
+            return Err(FormatError::TooLarge(reason));
+        }
+        let mut code = String::new();
+        try!(File::open(path).and_then(|mut file| file.read_to_string(&mut code)).map_err(FormatError::Io));
+        let output = try!(self.format_buffer(code, Some(path.to_string_lossy().into_owned())));
+        if let Some(lock) = lock {
+            if output.changed {
+                try!(lock.write(output.text.as_bytes()).map_err(FormatError::Io));
+            }
+        }
+        Ok(output)
+    }
+
+    /// Format the input and write the output to `out`, errors of the writer
+    /// are reported as `FormatError::Write`. This saves the copy of the output
+    /// only: the input is copied and the whole output is built in memory
+    /// before it is written
+    pub fn format_to_writer<W: Write>(&self, input: &str, out: &mut W) -> Result<FormatSummary, FormatError> {
+        self.write_buffer(String::from(input), self.config_path.clone(), out)
+    }
+
+    /// Format the buffer without copying it, `path` is the key of the
+    /// formatted outputs cache
+    pub fn format_buffer(&self, buffer: String, path: Option<String>) -> Result<FormatOutput, FormatError> {
+        let mut text = Vec::with_capacity(buffer.len());
+        let summary = try!(self.write_buffer(buffer, path, &mut text));
+        Ok(FormatOutput {
+            // only whole strings are written to the buffer
+            text: String::from_utf8(text).unwrap_or_default(),
+            changed: summary.changed,
+            formatting_errors: summary.formatting_errors,
+            cached: summary.cached,
+        })
+    }
+
+    /// Format the buffer and write the output (or the buffer itself in `Check`
+    /// mode) to `out`, the output is written only when formatting succeeds and
+    /// in a single `write_all`, it is never streamed. Buffers over the
+    /// `set_max_input_bytes` limit are `FormatError::TooLarge`
+    pub fn write_buffer<W: Write>(&self,
+                                  buffer: String,
+                                  path: Option<String>,
+                                  out: &mut W)
+                                  -> Result<FormatSummary, FormatError> {
+        if let Some(reason) = size_limit_error(buffer.len()) {
+            return Err(FormatError::TooLarge(reason));
+        }
+        debug!("Formatting {} bytes of {:?} in {:?} mode", buffer.len(), path, self.mode);
+        let hash = fnv1a(buffer.as_bytes());
+        let original = if self.mode == Mode::Check { Some(buffer.clone()) } else { None };
+        let (summary, output, cached) = render_or_skip(buffer, path, self.config.clone());
+        let output = match (summary.has_parsing_errors(), summary.has_operational_errors(), output) {
+            (true, _, _) => return Err(FormatError::InvalidInput(String::from("the code does not parse"))),
+            (_, true, _) | (_, _, None) => {
+                return Err(FormatError::Internal(String::from("the code can not be formatted")))
+            }
+            (_, _, Some(output)) => output,
+        };
+        let changed = !cached && fnv1a(output.as_bytes()) != hash;
+        debug!("Formatted {} bytes, changed: {}, formatting errors: {}",
+               output.len(),
+               changed,
+               summary.has_formatting_errors());
+        try!(out.write_all(original.as_ref().unwrap_or(&output).as_bytes()).map_err(FormatError::Write));
+        Ok(FormatSummary {
+            changed: changed,
+            formatting_errors: summary.has_formatting_errors(),
+            cached: cached,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{self, Read, Write};
+
+    use FormatError;
+    use testing::TempDir;
+    use super::*;
+
+    // accepts `limit` bytes and then fails every write
+    struct FailingWriter {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let room = self.limit - self.written.len();
+            if room == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "disk full"));
+            }
+            let len = ::std::cmp::min(room, buf.len());
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn format_to_writer_reports_the_errors_of_the_writer() {
+        let formatter = Formatter::builder().build().unwrap();
+        let mut out = FailingWriter {
+            written: Vec::new(),
+            limit: 10,
+        };
+        match formatter.format_to_writer("fn main() {\n    let x=1;\n}\n", &mut out) {
+            Err(FormatError::Write(e)) => assert_eq!(e.to_string(), "disk full"),
+            _ => panic!("the write error is not reported"),
+        }
+        assert_eq!(out.written, b"fn main() ");
+    }
+
+    #[test]
+    fn format_to_writer_writes_nothing_when_the_code_does_not_parse() {
+        let formatter = Formatter::builder().build().unwrap();
+        let mut out = Vec::new();
+        assert!(formatter.format_to_writer("fn main() {\n    @@\n}\n", &mut out).is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn format_file_leaves_the_file_alone_when_the_code_does_not_parse() {
+        let dir = TempDir::new("overwrite");
+        let code = "fn main() {\n    let x=@@;\n}\n";
+        let path = dir.write("main.rs", code);
+        let formatter = Formatter::builder().write_mode(Mode::Overwrite).build().unwrap();
+        match formatter.format_file(&path) {
+            Err(FormatError::InvalidInput(_)) => {}
+            _ => panic!("the parse error is not reported"),
+        }
+        let mut contents = String::new();
+        File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)).unwrap();
+        assert_eq!(contents, code);
+    }
+}
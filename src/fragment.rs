@@ -0,0 +1,285 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Formatting of code that is not a complete file. librustfmt only knows how
+//! to format whole crates so fragments are wrapped into a function, formatted
+//! and then unwrapped again, the wrapper never reaches the output.
+
+use backend::Config;
+use format_to_string;
+use scanner::{self, Kind};
+
+// the wrapper name is an unlikely identifier so it can't clash with the
+// fragment contents
+const WRAPPER: &'static str = "fn __anaconda__() ";
+const MODULE: &'static str = "mod __anaconda__ ";
+// `$name` metavariables don't parse so they are renamed to plain identifiers
+// with this prefix while the macro body is formatted
+const METAVAR: &'static str = "__anaconda_";
+
+/// What kind of code a fragment contains
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FragmentKind {
+    Expression,
+    Block,
+    Statements,
+    // unknown contents, every wrapping is tried until one parses
+    Any,
+}
+
+impl FragmentKind {
+    pub fn from_c_int(kind: i32) -> Option<FragmentKind> {
+        match kind {
+            0 => Some(FragmentKind::Expression),
+            1 => Some(FragmentKind::Block),
+            2 => Some(FragmentKind::Statements),
+            3 => Some(FragmentKind::Any),
+            _ => None,
+        }
+    }
+}
+
+fn format_wrapped(wrapped: String, config: &Config) -> Result<Vec<String>, String> {
+    let (summary, output) = format_to_string(wrapped, config);
+    match (summary.has_parsing_errors(), output) {
+        (false, Some(output)) => Ok(output.lines().map(String::from).collect()),
+        _ => Err(String::from("the code does not parse")),
+    }
+}
+
+/// Format the source as the body of the given wrapper (`fn` or `mod`), the
+/// returned lines are dedented back to the fragment level
+fn format_inside(wrapper: &str, source: &str, config: &Config) -> Result<Vec<String>, String> {
+    // the wrapper adds one level of indentation that the fragment won't have
+    let mut wrapped_config = config.clone();
+    wrapped_config.set_max_width(config.max_width() + config.tab_spaces());
+    wrapped_config.set_ideal_width(config.ideal_width() + config.tab_spaces());
+
+    let lines = try!(format_wrapped(format!("{}{{\n{}\n}}\n", wrapper, source.trim_right()),
+                                  &wrapped_config));
+    if lines.len() < 2 || lines[0] != format!("{}{{", wrapper) || lines[lines.len() - 1] != "}" {
+        return Err(String::from("the code can not be unwrapped after formatting"));
+    }
+
+    let indent = if config.hard_tabs() { String::from("\t") } else { " ".repeat(config.tab_spaces()) };
+    Ok(lines[1..lines.len() - 1]
+        .iter()
+        .map(|line| if line.starts_with(&indent[..]) { &line[indent.len()..] } else { line })
+        .map(String::from)
+        .collect())
+}
+
+/// Format a list of statements (or a single expression) as the body of a
+/// function, the returned lines are dedented back to the fragment level
+pub fn format_in_function(source: &str, config: &Config) -> Result<Vec<String>, String> {
+    format_inside(WRAPPER, source, config)
+}
+
+/// Format a list of items as the contents of a module
+fn format_in_module(source: &str, config: &Config) -> Result<Vec<String>, String> {
+    format_inside(MODULE, source, config)
+}
+
+/// Format the source as it is, like a whole file
+fn format_as_file(source: &str, config: &Config) -> Result<Vec<String>, String> {
+    format_wrapped(format!("{}\n", source.trim_right()), config)
+}
+
+/// Format a block (`{ ... }`) using it as the body of a function
+fn format_block(source: &str, config: &Config) -> Result<Vec<String>, String> {
+    let trimmed = source.trim();
+    if !trimmed.starts_with('{') || !trimmed.ends_with('}') {
+        return Err(String::from("the fragment is not a block"));
+    }
+
+    let mut lines = try!(format_wrapped(format!("{}{}\n", WRAPPER, trimmed), config));
+    if lines.is_empty() || !lines[0].starts_with(WRAPPER) {
+        return Err(String::from("the code can not be unwrapped after formatting"));
+    }
+    lines[0] = String::from(&lines[0][WRAPPER.len()..]);
+    Ok(lines)
+}
+
+/// Returns the indentation shared by every non blank line of the source
+fn baseline(source: &str) -> &str {
+    let mut lines = source.lines().filter(|line| !line.trim().is_empty());
+    let first = match lines.next() {
+        Some(line) => line,
+        None => return "",
+    };
+    let mut indent = &first[..first.len() - first.trim_left().len()];
+    for line in lines {
+        let common = indent.bytes().zip(line.bytes()).take_while(|&(a, b)| a == b).count();
+        indent = &indent[..common];
+    }
+    indent
+}
+
+/// Try the fragment as a whole file, as the body of a function and as the
+/// items of a module, the first wrapping that parses wins. The indentation
+/// of the fragment is removed before formatting and restored afterwards
+fn format_any(source: &str, config: &Config) -> Result<Vec<String>, String> {
+    let indent = baseline(source);
+    let dedented = source.lines()
+        .map(|line| if line.starts_with(indent) { &line[indent.len()..] } else { line.trim_left() })
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let mut dedented_config = config.clone();
+    dedented_config.set_max_width(config.max_width().saturating_sub(indent.len()));
+    dedented_config.set_ideal_width(config.ideal_width().saturating_sub(indent.len()));
+
+    let attempts: [(&str, fn(&str, &Config) -> Result<Vec<String>, String>); 3] =
+        [("file", format_as_file), ("statements", format_in_function), ("items", format_in_module)];
+    for &(name, attempt) in attempts.iter() {
+        if let Ok(lines) = attempt(&dedented, &dedented_config) {
+            debug!("Fragment formatted as {}", name);
+            return Ok(lines.into_iter()
+                .map(|line| if line.is_empty() { line } else { format!("{}{}", indent, line) })
+                .collect());
+        }
+    }
+    Err(String::from("the code does not parse as a file, as statements nor as items"))
+}
+
+/// Format the given fragment, the result keeps the trailing newline of the
+/// source (if any) and the error explains why the fragment wasn't formatted
+pub fn format_fragment(source: &str, kind: FragmentKind, config: &Config) -> Result<String, String> {
+    let lines = match kind {
+        FragmentKind::Expression | FragmentKind::Statements => try!(format_in_function(source, config)),
+        FragmentKind::Block => try!(format_block(source, config)),
+        FragmentKind::Any => try!(format_any(source, config)),
+    };
+
+    let mut output = lines.join("\n");
+    if source.ends_with('\n') {
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Check that the delimiters outside comments and literals are balanced and
+/// that every comment and literal is terminated
+pub fn check_balanced(source: &str) -> Result<(), String> {
+    let mut open = Vec::new();
+    for span in scanner::scan(source) {
+        if !span.closed {
+            return Err(format!("unterminated comment or literal at byte {}", span.start));
+        }
+        if span.kind != Kind::Code {
+            continue;
+        }
+        for (offset, c) in source[span.start..span.end].char_indices() {
+            let expected = match c {
+                '(' | '[' | '{' => {
+                    open.push(c);
+                    continue;
+                }
+                ')' => '(',
+                ']' => '[',
+                '}' => '{',
+                _ => continue,
+            };
+            if open.pop() != Some(expected) {
+                return Err(format!("unbalanced `{}` at byte {}", c, span.start + offset));
+            }
+        }
+    }
+    match open.last() {
+        Some(c) => Err(format!("unclosed `{}`", c)),
+        None => Ok(()),
+    }
+}
+
+/// Rename the `$name` metavariables of the code to `__anaconda_name`, other
+/// uses of `$` (repetitions) can't be formatted
+fn hide_metavars(source: &str) -> Result<String, String> {
+    if source.contains(METAVAR) {
+        return Err(format!("the code already uses the `{}` prefix", METAVAR));
+    }
+    let mut output = String::with_capacity(source.len());
+    for span in scanner::scan(source) {
+        let text = &source[span.start..span.end];
+        if span.kind != Kind::Code {
+            output.push_str(text);
+            continue;
+        }
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                output.push(c);
+                continue;
+            }
+            match chars.peek() {
+                Some(&next) if next == '_' || next.is_alphabetic() => output.push_str(METAVAR),
+                _ => return Err(String::from("macro repetitions can not be formatted")),
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Format the body of a macro (the code between the delimiters of a macro
+/// invocation or of a `macro_rules!` transcriber) as statements or items.
+/// Metavariables are kept, bodies with unbalanced delimiters or repetitions
+/// are rejected
+pub fn format_macro_body(source: &str, config: &Config) -> Result<String, String> {
+    try!(check_balanced(source));
+    let hidden = try!(hide_metavars(source));
+    let lines = try!(format_any(&hidden, config));
+
+    let mut output = lines.join("\n").replace(METAVAR, "$");
+    if source.ends_with('\n') {
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use backend::Config;
+    use super::*;
+
+    #[test]
+    fn formats_an_expression() {
+        let output = format_fragment("x=y", FragmentKind::Expression, &Config::default());
+        assert_eq!(output.unwrap(), "x = y");
+    }
+
+    #[test]
+    fn formats_a_statement_list() {
+        let output = format_fragment("let a=1;\nlet b=a;\n", FragmentKind::Statements, &Config::default());
+        assert_eq!(output.unwrap(), "let a = 1;\nlet b = a;\n");
+    }
+
+    #[test]
+    fn reports_fragments_that_do_not_parse() {
+        assert!(format_fragment("let a=@@;\n", FragmentKind::Statements, &Config::default()).is_err());
+        assert_eq!(FragmentKind::from_c_int(4), None);
+    }
+
+    #[test]
+    fn formats_macro_bodies_with_nested_braces() {
+        let body = "if $cond {\n    let v=$value;\n    if v {\n        foo();\n    }\n}\n";
+        let output = format_macro_body(body, &Config::default());
+        assert_eq!(output.unwrap(), "if $cond {\n    let v = $value;\n    if v {\n        foo();\n    }\n}\n");
+    }
+
+    #[test]
+    fn rejects_macro_bodies_that_can_not_be_wrapped() {
+        let config = Config::default();
+        assert_eq!(format_macro_body("if $cond {\n    { foo();\n}\n", &config).unwrap_err(), "unclosed `{`");
+        assert_eq!(format_macro_body("foo($($x),*);\n", &config).unwrap_err(),
+                   "macro repetitions can not be formatted");
+        assert!(format_macro_body("let __anaconda_x=1;\n", &config).is_err());
+    }
+}
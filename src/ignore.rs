@@ -0,0 +1,443 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Walk a directory the way git sees it. The `.gitignore` files of every
+//! level (and of the parents up to the root of the repository), the
+//! `.git/info/exclude` of the repository and the global `core.excludesFile`
+//! are honored and `target` is always skipped. A walk can ignore all of that
+//! for people that format vendored code on purpose, `.git` is never walked.
+//! Ignored directories are not entered, so their files are not counted, only
+//! the directories themselves.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// directories skipped by every walk that honors the ignore files
+const SKIPPED_DIRS: &'static [&'static str] = &["target"];
+
+/// Match a `/` separated path against a glob, `*` and `?` don't match `/`
+/// while `**` matches anything, `[...]` matches a class of characters
+pub fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(&b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = if pattern.get(2) == Some(&b'/') { &pattern[3..] } else { &pattern[2..] };
+            (0..path.len() + 1).any(|i| glob_match(rest, &path[i..]))
+        }
+        Some(&b'*') => {
+            let end = path.iter().position(|&c| c == b'/').unwrap_or(path.len());
+            (0..end + 1).any(|i| glob_match(&pattern[1..], &path[i..]))
+        }
+        Some(&b'?') => !path.is_empty() && path[0] != b'/' && glob_match(&pattern[1..], &path[1..]),
+        Some(&b'[') if pattern.iter().skip(2).any(|&c| c == b']') => {
+            let negated = pattern.get(1) == Some(&b'!') || pattern.get(1) == Some(&b'^');
+            let first = if negated { 2 } else { 1 };
+            // a `]` right after the opening bracket is part of the class
+            let end = first + 1 + pattern[first + 1..].iter().position(|&c| c == b']').unwrap_or(0);
+            if pattern.len() <= end || path.is_empty() || path[0] == b'/' {
+                return false;
+            }
+            let class = &pattern[first..end];
+            let mut found = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    found |= class[i] <= path[0] && path[0] <= class[i + 2];
+                    i += 3;
+                } else {
+                    found |= class[i] == path[0];
+                    i += 1;
+                }
+            }
+            found != negated && glob_match(&pattern[end + 1..], &path[1..])
+        }
+        Some(&b'\\') if pattern.len() > 1 => {
+            !path.is_empty() && path[0] == pattern[1] && glob_match(&pattern[2..], &path[1..])
+        }
+        Some(&c) => !path.is_empty() && path[0] == c && glob_match(&pattern[1..], &path[1..]),
+    }
+}
+
+/// A line of an ignore file
+#[derive(Clone, Debug)]
+struct Rule {
+    glob: String,
+    // `!pattern` includes again what a previous rule ignored
+    negated: bool,
+    // `pattern/` only matches directories
+    dir_only: bool,
+    // patterns with a `/` are matched against the path relative to the
+    // directory of the file, the rest against the name
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_right_matches('\r');
+        // trailing spaces are ignored unless they are escaped
+        let line = if line.ends_with("\\ ") { line } else { line.trim_right() };
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = if line.starts_with('!') { (true, &line[1..]) } else { (false, line) };
+        let line = if line.starts_with("\\#") || line.starts_with("\\!") { &line[1..] } else { line };
+        let (dir_only, line) = if line.ends_with('/') { (true, line.trim_right_matches('/')) } else { (false, line) };
+        if line.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            glob: String::from(line.trim_left_matches('/')),
+            negated: negated,
+            dir_only: dir_only,
+            anchored: line.contains('/'),
+        })
+    }
+
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let target = if self.anchored { relative } else { relative.rsplit('/').next().unwrap_or("") };
+        glob_match(self.glob.as_bytes(), target.as_bytes())
+    }
+}
+
+/// The rules of an ignore file and the directory they are relative to
+#[derive(Clone, Debug)]
+struct RuleSet {
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    fn read(base: &Path, file: &Path) -> Option<RuleSet> {
+        let mut contents = String::new();
+        if File::open(file).and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+            return None;
+        }
+        let rules: Vec<Rule> = contents.lines().filter_map(Rule::parse).collect();
+        if rules.is_empty() {
+            return None;
+        }
+        Some(RuleSet {
+            base: base.to_path_buf(),
+            rules: rules,
+        })
+    }
+}
+
+/// The ignore rules that apply to a directory, the later ones win
+#[derive(Clone, Debug, Default)]
+pub struct Ignore {
+    sets: Vec<RuleSet>,
+}
+
+/// The directory of the repository that contains `dir`, if any
+fn repository_root(dir: &Path) -> Option<PathBuf> {
+    let mut current = dir.to_path_buf();
+    loop {
+        // `.git` is a file in worktrees and submodules
+        if current.join(".git").exists() {
+            return Some(current);
+        }
+        if !current.pop() {
+            return None;
+        }
+    }
+}
+
+/// The `core.excludesFile` of the user git config or the default one
+fn global_excludes_file() -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let mut config = String::new();
+    if let Some(ref home) = home {
+        let _ = File::open(home.join(".gitconfig")).and_then(|mut file| file.read_to_string(&mut config));
+    }
+    let mut in_core = false;
+    let configured = config.lines().map(str::trim).filter_map(|line| {
+        if line.starts_with('[') {
+            in_core = line.to_lowercase() == "[core]";
+            return None;
+        }
+        let mut parts = line.splitn(2, '=');
+        match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+            (Some(key), Some(value)) if in_core && key.to_lowercase() == "excludesfile" => {
+                Some(String::from(value.trim_matches('"')))
+            }
+            _ => None,
+        }
+    }).last();
+    match (configured, home) {
+        (Some(ref path), Some(ref home)) if path.starts_with("~/") => Some(home.join(&path[2..])),
+        (Some(path), _) => Some(PathBuf::from(path)),
+        (None, home) => {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| home.map(|home| home.join(".config")))
+                .map(|config| config.join("git").join("ignore"))
+        }
+    }
+}
+
+impl Ignore {
+    /// The rules that apply to the root of a walk: the global ones, the ones
+    /// of the repository and the `.gitignore` files of the parents of the root
+    /// inside the repository. The root's own file is added by `enter`
+    pub fn for_root(root: &Path) -> Ignore {
+        let repository = repository_root(root);
+        let base = repository.clone().unwrap_or_else(|| root.to_path_buf());
+        let mut ignore = Ignore::default();
+        if let Some(set) = global_excludes_file().and_then(|file| RuleSet::read(&base, &file)) {
+            ignore.sets.push(set);
+        }
+        if let Some(repository) = repository {
+            let exclude = repository.join(".git").join("info").join("exclude");
+            ignore.sets.extend(RuleSet::read(&repository, &exclude));
+            let mut parents = Vec::new();
+            let mut current = root.to_path_buf();
+            while current != repository && current.pop() {
+                parents.push(current.clone());
+            }
+            for dir in parents.iter().rev() {
+                ignore.sets.extend(RuleSet::read(dir, &dir.join(".gitignore")));
+            }
+        }
+        ignore
+    }
+
+    /// The rules that apply inside the directory, the ones of its parent plus
+    /// its own `.gitignore`
+    pub fn enter(&self, dir: &Path) -> Ignore {
+        let mut ignore = self.clone();
+        ignore.sets.extend(RuleSet::read(dir, &dir.join(".gitignore")));
+        ignore
+    }
+
+    /// Whether the path is ignored, the last rule that matches decides
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for set in &self.sets {
+            let relative = match path.strip_prefix(&set.base) {
+                Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+            for rule in &set.rules {
+                if rule.matches(&relative, is_dir) {
+                    ignored = !rule.negated;
+                }
+            }
+        }
+        ignored
+    }
+}
+
+/// The files under a directory
+#[derive(Debug, Default)]
+pub struct Walk {
+    pub files: Vec<PathBuf>,
+    /// Files that were skipped because they are ignored
+    pub ignored_files: usize,
+    /// Directories that were not entered because they are ignored
+    pub ignored_dirs: usize,
+    /// Symlinked directories that were not followed
+    pub links: usize,
+}
+
+impl Walk {
+    /// Describe what was skipped, e.g. `skipped 3,214 ignored files and 1
+    /// ignored directory`, None when nothing was
+    pub fn skipped(&self) -> Option<String> {
+        fn count(n: usize, one: &str, many: &str) -> String {
+            // thousands separated by commas, `3,214`
+            let digits = n.to_string();
+            let mut grouped = String::new();
+            for (i, digit) in digits.chars().enumerate() {
+                if i > 0 && (digits.len() - i) % 3 == 0 {
+                    grouped.push(',');
+                }
+                grouped.push(digit);
+            }
+            format!("{} ignored {}", grouped, if n == 1 { one } else { many })
+        }
+        match (self.ignored_files, self.ignored_dirs) {
+            (0, 0) => None,
+            (files, 0) => Some(format!("skipped {}", count(files, "file", "files"))),
+            (0, dirs) => Some(format!("skipped {}", count(dirs, "directory", "directories"))),
+            (files, dirs) => {
+                Some(format!("skipped {} and {}",
+                             count(files, "file", "files"),
+                             count(dirs, "directory", "directories")))
+            }
+        }
+    }
+}
+
+fn walk_dir(dir: &Path,
+            ignore: Option<&Ignore>,
+            follow_links: bool,
+            visited: &mut HashSet<PathBuf>,
+            walk: &mut Walk) {
+    // a followed link can lead to a directory that is already being walked
+    if follow_links && !visited.insert(fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())) {
+        return;
+    }
+    let ignore = ignore.map(|ignore| ignore.enter(dir));
+    let mut entries: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).collect(),
+        Err(_) => return,
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let is_link = entry.file_type().map(|file_type| file_type.is_symlink()).unwrap_or(false);
+        // broken links are skipped
+        let is_dir = match fs::metadata(&path) {
+            Ok(metadata) => metadata.is_dir(),
+            Err(_) => continue,
+        };
+        if is_dir && is_link && !follow_links {
+            walk.links += 1;
+            continue;
+        }
+        // the repository itself is never walked nor counted
+        if is_dir && entry.file_name() == ".git" {
+            continue;
+        }
+        let skipped = is_dir && ignore.is_some() &&
+                      SKIPPED_DIRS.iter().any(|skipped| entry.file_name() == **skipped);
+        if skipped || ignore.as_ref().map_or(false, |ignore| ignore.is_ignored(&path, is_dir)) {
+            if is_dir {
+                walk.ignored_dirs += 1;
+            } else {
+                walk.ignored_files += 1;
+            }
+        } else if is_dir {
+            walk_dir(&path, ignore.as_ref(), follow_links, visited, walk);
+        } else {
+            walk.files.push(path);
+        }
+    }
+}
+
+/// Collect the files under the directory in a stable order, `honor_ignores`
+/// false walks every directory but `.git` and symlinked directories are only
+/// walked when `follow_links` is true. The files are relative to the current
+/// directory when the root is
+pub fn walk(root: &Path, honor_ignores: bool, follow_links: bool) -> Walk {
+    let mut walk = Walk::default();
+    // the repository of a relative root may be above the current directory
+    let absolute = match env::current_dir() {
+        Ok(current) if root.is_relative() => current.join(root),
+        _ => root.to_path_buf(),
+    };
+    let ignore = if honor_ignores { Some(Ignore::for_root(&absolute)) } else { None };
+    walk_dir(&absolute, ignore.as_ref(), follow_links, &mut HashSet::new(), &mut walk);
+    if absolute != root {
+        walk.files = walk.files
+            .into_iter()
+            .map(|file| file.strip_prefix(&absolute).map(|relative| root.join(relative)).unwrap_or(file))
+            .collect();
+    }
+    walk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use testing::TempDir;
+
+    fn relative(walk: &Walk, root: &Path) -> Vec<String> {
+        walk.files.iter().map(|file| file.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/")).collect()
+    }
+
+    #[test]
+    fn rules_parse_negations_and_directories() {
+        assert!(Rule::parse("# a comment").is_none());
+        assert!(Rule::parse("   ").is_none());
+        assert!(Rule::parse("!").is_none());
+
+        let rule = Rule::parse("!keep.log").unwrap();
+        assert!(rule.negated && !rule.dir_only && !rule.anchored);
+        let rule = Rule::parse("build/").unwrap();
+        assert!(rule.dir_only && !rule.anchored);
+        assert!(rule.matches("src/build", true) && !rule.matches("src/build", false));
+        let rule = Rule::parse("/docs/*.rs").unwrap();
+        assert!(rule.anchored && rule.matches("docs/a.rs", false) && !rule.matches("src/docs/a.rs", false));
+        assert!(!Rule::parse("\\!bang").unwrap().negated);
+    }
+
+    #[test]
+    fn walks_honor_nested_ignore_files() {
+        let dir = TempDir::new("ignore_walk");
+        dir.write(".git/HEAD", "ref: refs/heads/master\n");
+        dir.write(".gitignore", "*.log\n!keep.log\nbuild/\n");
+        dir.write("a.rs", "");
+        dir.write("keep.log", "");
+        dir.write("x.log", "");
+        dir.write("build/gen.rs", "");
+        dir.write("target/debug.rs", "");
+        // `generated.rs` is only ignored below `src` and `x.log` is included again
+        dir.write("src/.gitignore", "generated.rs\n!x.log\n");
+        dir.write("src/build", "");
+        dir.write("src/generated.rs", "");
+        dir.write("src/main.rs", "");
+        dir.write("src/x.log", "");
+        dir.write("generated.rs", "");
+
+        let walk = walk(dir.path(), true, false);
+        assert_eq!(relative(&walk, dir.path()),
+                   vec![".gitignore", "a.rs", "generated.rs", "keep.log", "src/.gitignore", "src/build", "src/main.rs",
+                        "src/x.log"]);
+        assert_eq!((walk.ignored_files, walk.ignored_dirs), (2, 2));
+        assert_eq!(walk.skipped(), Some(String::from("skipped 2 ignored files and 2 ignored directories")));
+    }
+
+    #[test]
+    fn walks_can_ignore_the_ignore_files() {
+        let dir = TempDir::new("ignore_all");
+        dir.write(".git/HEAD", "");
+        dir.write(".gitignore", "*.rs\n");
+        dir.write("target/debug.rs", "");
+        dir.write("main.rs", "");
+
+        let walk = walk(dir.path(), false, false);
+        assert_eq!(relative(&walk, dir.path()), vec![".gitignore", "main.rs", "target/debug.rs"]);
+        assert_eq!(walk.skipped(), None);
+    }
+
+    #[test]
+    fn parent_ignore_files_apply_to_a_nested_root() {
+        let dir = TempDir::new("ignore_parent");
+        dir.write(".git/HEAD", "");
+        dir.write(".gitignore", "fixtures/\n");
+        dir.write("crate/src/main.rs", "");
+        dir.write("crate/src/fixtures/bad.rs", "");
+
+        let root: PathBuf = dir.path().join("crate");
+        let walk = walk(&root, true, false);
+        assert_eq!(relative(&walk, &root), vec!["src/main.rs"]);
+        assert_eq!(walk.ignored_dirs, 1);
+    }
+
+    #[test]
+    fn globs_match_classes_and_separators() {
+        assert!(glob_match(b"*.rs", b"main.rs") && !glob_match(b"*.rs", b"src/main.rs"));
+        assert!(glob_match(b"**/*.rs", b"src/bin/main.rs") && glob_match(b"**/*.rs", b"main.rs"));
+        assert!(glob_match(b"file[0-9].rs", b"file7.rs") && !glob_match(b"file[!0-9].rs", b"file7.rs"));
+        assert!(glob_match(b"a?c", b"abc") && !glob_match(b"a?c", b"a/c"));
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Imports only formatting. Runs of consecutive `use` and `extern crate`
+//! items (with their attributes) are cut out of the buffer, formatted on
+//! their own as if they were a whole file and put back in place, so any byte
+//! that doesn't belong to an import is left exactly as it was.
+
+use backend::{Config, Summary};
+use scanner::{self, Kind};
+use format_to_string;
+
+/// Format the import items of the buffer, runs of imports that rustfmt is not
+/// able to format are left untouched and their errors added to the summary
+pub fn format_imports(code: &str, config: &Config) -> (String, Summary) {
+    let mut summary = Summary::new();
+    let mut output = String::from(code);
+
+    for (start, end) in import_runs(code).into_iter().rev() {
+        let indent = &code[start..start + code[start..].len() - code[start..].trim_left().len()];
+        let newline = if code[start..end].contains("\r\n") { "\r\n" } else { "\n" };
+
+        let mut source = String::new();
+        for line in code[start..end].lines() {
+            source.push_str(if line.starts_with(indent) { &line[indent.len()..] } else { line });
+            source.push('\n');
+        }
+
+        let mut run_config = config.clone();
+        run_config.set_max_width(config.max_width().saturating_sub(indent.len()));
+        let (run_summary, formatted) = format_to_string(source, &run_config);
+        summary.add(run_summary);
+        let formatted = match formatted {
+            Some(formatted) => formatted,
+            None => continue,
+        };
+
+        let text = formatted.trim_right()
+            .lines()
+            .map(|line| if line.is_empty() { String::new() } else { format!("{}{}", indent, line) })
+            .collect::<Vec<String>>()
+            .join(newline);
+        output = format!("{}{}{}", &output[..start], text, &output[end..]);
+    }
+    (output, summary)
+}
+
+/// Returns the group of an import item: `extern crate` items, then `std`,
+/// `core` and `alloc` imports, then the imports of other crates and last the
+/// `self`, `super` and `crate` ones
+fn group_of(item: &str) -> usize {
+    let mut rest = item;
+    while rest.starts_with("#[") {
+        rest = rest[rest.find(']').map(|end| end + 1).unwrap_or(rest.len())..].trim_left();
+    }
+    let path = match rest.find("use") {
+        Some(start) if !rest.starts_with("extern") => rest[start + 3..].trim_left().trim_left_matches("::"),
+        _ => return 0,
+    };
+    let first = path.split(|c: char| c == ':' || c == ';' || c == '{' || c.is_whitespace()).next().unwrap_or("");
+    match first {
+        "std" | "core" | "alloc" => 1,
+        "self" | "super" | "crate" => 3,
+        _ => 2,
+    }
+}
+
+/// Sort the items of every run of imports into groups (see `group_of`)
+/// separated by a blank line, the items keep their order inside a group so
+/// the result only depends on the input
+pub fn group_imports(code: &str) -> String {
+    let kinds = scanner::kinds(code);
+    let mut output = String::from(code);
+
+    for (start, end) in import_runs(code).into_iter().rev() {
+        let indent = &code[start..start + code[start..].len() - code[start..].trim_left().len()];
+        let newline = if code[start..end].contains("\r\n") { "\r\n" } else { "\n" };
+
+        let mut items: Vec<(usize, &str)> = Vec::new();
+        let mut i = skip_whitespace(code, &kinds, start);
+        while i < end {
+            let item_end = match import_end(code, &kinds, i) {
+                Some(item_end) => item_end,
+                None => break,
+            };
+            items.push((group_of(&code[i..item_end]), &code[i..item_end]));
+            i = skip_whitespace(code, &kinds, item_end);
+        }
+        if i < end {
+            continue;
+        }
+        items.sort_by_key(|&(group, _)| group);
+
+        let mut text = String::new();
+        for (n, &(group, item)) in items.iter().enumerate() {
+            if n > 0 {
+                text.push_str(newline);
+                if items[n - 1].0 != group {
+                    text.push_str(newline);
+                }
+            }
+            text.push_str(indent);
+            text.push_str(item);
+        }
+        output = format!("{}{}{}", &output[..start], text, &output[end..]);
+    }
+    output
+}
+
+/// Returns the (start, end) byte ranges of every run of consecutive import
+/// items, a run starts at the beginning of the line of its first item and ends
+/// right after the `;` of its last item
+fn import_runs(code: &str) -> Vec<(usize, usize)> {
+    let bytes = code.as_bytes();
+    let kinds = scanner::kinds(code);
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut previous: Option<u8> = None;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if kinds[i] != Kind::Code || (bytes[i] as char).is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let line_start = code[..i].rfind('\n').map(|pos| pos + 1).unwrap_or(0);
+        let item_start = match previous {
+            None | Some(b';') | Some(b'{') | Some(b'}') => code[line_start..i].trim().is_empty(),
+            _ => false,
+        };
+
+        if let Some(end) = if item_start { import_end(code, &kinds, i) } else { None } {
+            // the import continues the last run if only whitespace separates them
+            let extends = match runs.last() {
+                Some(&(_, last_end)) => code[last_end..line_start].trim().is_empty(),
+                None => false,
+            };
+            if extends {
+                runs.last_mut().unwrap().1 = end;
+            } else {
+                runs.push((line_start, end));
+            }
+            previous = Some(b';');
+            i = end;
+            continue;
+        }
+
+        previous = Some(bytes[i]);
+        i += 1;
+    }
+    runs
+}
+
+fn skip_whitespace(code: &str, kinds: &[Kind], mut i: usize) -> usize {
+    while i < code.len() && kinds[i] == Kind::Code && (code.as_bytes()[i] as char).is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+fn keyword(code: &str, i: usize, word: &str) -> Option<usize> {
+    let end = i + word.len();
+    if !code[i..].starts_with(word) {
+        return None;
+    }
+    match code.as_bytes().get(end) {
+        Some(&b) if b == b'_' || (b as char).is_alphanumeric() => None,
+        _ => Some(end),
+    }
+}
+
+/// Returns the position right after the closing delimiter of the group that
+/// opens at `i` (only code bytes are taken into account)
+fn group_end(code: &str, kinds: &[Kind], i: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (offset, b) in code[i..].bytes().enumerate() {
+        if kinds[i + offset] != Kind::Code {
+            continue;
+        }
+        match b {
+            b'[' | b'(' | b'{' => depth += 1,
+            b']' | b')' | b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + offset + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// If an import item (with its outer attributes) starts at `i` returns the
+/// position right after its terminating `;`
+fn import_end(code: &str, kinds: &[Kind], mut i: usize) -> Option<usize> {
+    while code[i..].starts_with("#[") {
+        i = skip_whitespace(code, kinds, try_opt!(group_end(code, kinds, i + 1)));
+    }
+
+    if let Some(end) = keyword(code, i, "pub") {
+        i = skip_whitespace(code, kinds, end);
+        if code[i..].starts_with('(') {
+            i = skip_whitespace(code, kinds, try_opt!(group_end(code, kinds, i)));
+        }
+    }
+
+    i = match keyword(code, i, "use") {
+        Some(end) => end,
+        None => {
+            let end = try_opt!(keyword(code, i, "extern"));
+            try_opt!(keyword(code, skip_whitespace(code, kinds, end), "crate"))
+        }
+    };
+
+    let mut depth = 0;
+    for (offset, b) in code[i..].bytes().enumerate() {
+        if kinds[i + offset] != Kind::Code {
+            continue;
+        }
+        match b {
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return None,
+            b'}' => depth -= 1,
+            b';' if depth == 0 => return Some(i + offset + 1),
+            _ => {}
+        }
+    }
+    None
+}
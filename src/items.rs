@@ -0,0 +1,268 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Partial formatting at item granularity. Top level items are complete
+//! pieces of Rust code on their own, so we can cut them out of the buffer,
+//! format them as if they were a whole file and put them back without
+//! touching anything else.
+
+use backend::{Config, Summary};
+use scanner::{self, Kind};
+use format_to_string;
+
+// keywords that introduce a named item, the name is the next identifier
+const ITEM_KINDS: &'static [&'static str] = &["fn", "struct", "enum", "union", "trait", "type", "mod", "const",
+                                              "static", "macro_rules", "impl"];
+
+/// Returns the line ranges (1-based, inclusive) of the top level items of the
+/// buffer, the comments and attributes preceding an item belong to it
+pub fn top_level_items(code: &str) -> Vec<(usize, usize)> {
+    let bytes = code.as_bytes();
+    let kinds = scanner::kinds(code);
+    let mut items: Vec<(usize, usize)> = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut depth = 0;
+    let mut line = 1;
+
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'\n' {
+            line += 1;
+            continue;
+        }
+        if (b as char).is_whitespace() {
+            continue;
+        }
+
+        // a trailing comment in the line where an item ends belongs to it
+        let trailing = items.last().map(|&(_, end)| end == line).unwrap_or(false);
+        if start.is_none() && !(trailing && kinds[i] == Kind::Comment) {
+            start = Some(line);
+        }
+        if kinds[i] != Kind::Code {
+            continue;
+        }
+
+        match b {
+            b'{' | b'(' | b'[' => depth += 1,
+            b'}' | b')' | b']' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 && (b == b';' || b == b'}') {
+            if let Some(first) = start.take() {
+                // items sharing a line are merged into a single one
+                match items.last_mut() {
+                    Some(last) if last.1 >= first => last.1 = line,
+                    _ => items.push((first, line)),
+                }
+            }
+        }
+    }
+
+    if let Some(first) = start {
+        items.push((first, line - if code.ends_with('\n') { 1 } else { 0 }));
+    }
+    items
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The identifiers of the code with their byte offsets
+pub fn idents(code: &str) -> Vec<(usize, &str)> {
+    let mut idents = Vec::new();
+    let mut start = None;
+    for (i, c) in code.char_indices().chain(Some((code.len(), ' '))) {
+        match (start, is_ident(c)) {
+            (None, true) => start = Some(i),
+            (Some(first), false) => {
+                idents.push((first, &code[first..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    idents
+}
+
+/// Remove the generics at the start of the code, `<T: Trait<U>>` included
+fn skip_generics(code: &str) -> &str {
+    let code = code.trim_left();
+    if !code.starts_with('<') {
+        return code;
+    }
+    let mut depth = 0;
+    for (i, c) in code.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &code[i + 1..];
+                }
+            }
+            _ => {}
+        }
+    }
+    ""
+}
+
+/// The name of the type an impl is for, `Bar` in `impl<T> Foo for ::a::Bar<T>`
+fn impl_self_type(header: &str) -> Option<String> {
+    let header = skip_generics(header);
+    let header = idents(header)
+        .iter()
+        .find(|&&(_, word)| word == "where")
+        .map_or(header, |&(i, _)| &header[..i]);
+    let self_type = idents(header)
+        .iter()
+        .rev()
+        .find(|&&(_, word)| word == "for")
+        .map_or(header, |&(i, _)| &header[i + 3..]);
+    let path = self_type.split('<').next().unwrap_or("");
+    let name = path.rsplit("::").next().unwrap_or("");
+    let name = name.trim().trim_left_matches(|c: char| !is_ident(c));
+    idents(name).last().map(|&(_, word)| String::from(word))
+}
+
+/// Remove the attributes at the start of the code
+fn skip_attributes(mut code: &str) -> &str {
+    loop {
+        code = code.trim_left();
+        if !code.starts_with('#') {
+            return code;
+        }
+        let mut depth = 0;
+        let end = code.char_indices().find(|&(_, c)| {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+            c == ']' && depth == 0
+        });
+        match end {
+            Some((i, _)) => code = &code[i + 1..],
+            None => return "",
+        }
+    }
+}
+
+/// The kind and the name of an item given its code without comments nor
+/// literals, None for unnamed items (`use`, `extern crate`, macro calls...)
+fn item_name(code: &str) -> Option<(&'static str, String)> {
+    let code = skip_attributes(code);
+    let header = &code[..code.find(|c| c == '{' || c == ';').unwrap_or(code.len())];
+    let words = idents(header);
+    for (index, &(offset, word)) in words.iter().enumerate() {
+        let after = &header[offset + word.len()..];
+        let kind = match ITEM_KINDS.iter().find(|&&kind| kind == word) {
+            Some(&kind) => kind,
+            None if word == "use" || after.trim_left().starts_with('!') => return None,
+            None if word == "crate" && index > 0 && words[index - 1].1 == "extern" => return None,
+            // visibility and qualifiers
+            None => continue,
+        };
+        let next = words.get(index + 1).map(|&(_, next)| next);
+        match kind {
+            "const" if next == Some("fn") || next == Some("unsafe") => continue,
+            "impl" => return impl_self_type(after).map(|name| ("impl", name)),
+            _ => return next.map(|name| (kind, String::from(name))),
+        }
+    }
+    None
+}
+
+/// Find the line range of the top level item with the given name, the name
+/// can be prefixed with the kind of the item (`fn`, `struct`, `impl`...) to
+/// tell apart items with the same name. Impls are named after their type
+pub fn find_item(code: &str, name: &str) -> Result<(usize, usize), String> {
+    let mut parts = name.split_whitespace().rev();
+    let (name, kind) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(name), kind, None) => (name, kind),
+        _ => return Err(format!("invalid item name {:?}", name)),
+    };
+
+    let kinds = scanner::kinds(code);
+    let mut starts = vec![0];
+    starts.extend(code.match_indices('\n').map(|(i, _)| i + 1));
+    let offset = |line: usize| if line <= starts.len() { starts[line - 1] } else { code.len() };
+
+    let mut found = Vec::new();
+    for (start, end) in top_level_items(code) {
+        let (from, to) = (offset(start), offset(end + 1));
+        // comments and literals can't be mistaken for the item header
+        let bytes: Vec<u8> = code.as_bytes()[from..to]
+            .iter()
+            .zip(&kinds[from..to])
+            .map(|(&b, &kind)| if kind == Kind::Code { b } else { b' ' })
+            .collect();
+        if let Some((item_kind, item_name)) = item_name(&String::from_utf8_lossy(&bytes)) {
+            if item_name == name && kind.map_or(true, |kind| kind == item_kind) {
+                found.push((start, end));
+            }
+        }
+    }
+    match found.len() {
+        0 => Err(format!("no top level item named {}", name)),
+        1 => Ok(found[0]),
+        n => Err(format!("{} top level items are named {}, prefix the name with the kind", n, name)),
+    }
+}
+
+/// Format the top level items that contain any of the given lines (1-based),
+/// adjacent items are formatted together. Returns the new buffer, the summary
+/// of every format run and the (original) line ranges that were formatted,
+/// ranges that rustfmt is not able to format are left untouched.
+pub fn format_items(code: &str, config: &Config, lines: &[usize]) -> (String, Summary, Vec<(usize, usize)>) {
+    let items = top_level_items(code);
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    let mut last_selected: Option<usize> = None;
+    for (index, &(start, end)) in items.iter().enumerate() {
+        if !lines.iter().any(|&line| start <= line && line <= end) {
+            continue;
+        }
+        match (last_selected, regions.last_mut()) {
+            (Some(last), Some(region)) if last + 1 == index => region.1 = end,
+            _ => regions.push((start, end)),
+        }
+        last_selected = Some(index);
+    }
+
+    let mut starts = vec![0];
+    starts.extend(code.match_indices('\n').map(|(i, _)| i + 1));
+    let offset = |line: usize| if line <= starts.len() { starts[line - 1] } else { code.len() };
+
+    let mut summary = Summary::new();
+    let mut formatted = Vec::new();
+    let mut output = String::from(code);
+    for &(start, end) in regions.iter().rev() {
+        let (from, to) = (offset(start), offset(end + 1));
+        let source = &code[from..to];
+        let (region_summary, result) = format_to_string(String::from(source), config);
+        summary.add(region_summary);
+        if let Some(mut result) = result {
+            if !source.ends_with('\n') {
+                let len = result.trim_right_matches('\n').len();
+                result.truncate(len);
+            } else if source.ends_with("\r\n") && !result.ends_with("\r\n") {
+                result = result.replace('\n', "\r\n");
+            }
+            output = format!("{}{}{}", &output[..from], result, &output[to..]);
+            formatted.push((start, end));
+        }
+    }
+
+    formatted.reverse();
+    (output, summary, formatted)
+}
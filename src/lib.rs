@@ -11,324 +11,5070 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[macro_use]
+extern crate lazy_static;
+extern crate getopts;
+#[cfg(feature = "ffi")]
 extern crate libc;
+#[macro_use]
+extern crate log;
+extern crate num_cpus;
 extern crate racer;
+#[cfg(feature = "backend-rustfmt")]
 extern crate rustfmt;
+#[cfg(feature = "backend-rustfmt-nightly")]
+extern crate rustfmt_nightly;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate syntex_errors;
+extern crate syntex_syntax;
+extern crate toml;
 
-use libc::{c_char, c_int, uint32_t};
+// same as try! but for functions returning an Option
+macro_rules! try_opt {
+    ($expr:expr) => (match $expr {
+        Some(val) => val,
+        None => return None,
+    })
+}
+
+// same as try! for the configuration of the exported functions, the error is
+// logged and its status code (or what the second argument makes of it) returned
+macro_rules! try_config {
+    ($expr:expr) => (try_config!($expr, e => e.status()));
+    ($expr:expr, $e:ident => $on_error:expr) => (match $expr {
+        Ok(val) => val,
+        Err($e) => {
+            error!("{}", $e);
+            return $on_error;
+        }
+    })
+}
 
-use rustfmt::{Input, Summary, run};
-use rustfmt::config::{Config, WriteMode};
+#[cfg(debug_assertions)]
+mod annotate;
+mod args;
+mod backend;
+mod base64;
+pub mod cancel;
+mod changes;
+mod checkstyle;
+// only the exported functions clear the caches
+#[cfg_attr(not(feature = "ffi"), allow(dead_code))]
+mod config_cache;
+pub mod daemon;
+mod docs;
+mod edition;
+#[cfg_attr(not(feature = "ffi"), allow(dead_code))]
+mod format_cache;
+pub mod formatter;
+mod fragment;
+mod ignore;
+mod imports;
+mod items;
+// only the exported functions set the timeout
+#[cfg_attr(not(feature = "ffi"), allow(dead_code))]
+mod lock;
+#[cfg(feature = "ffi")]
+mod logging;
+pub mod lsp;
+mod msgpack;
+mod newlines;
+pub mod outcome;
+// only the exported functions set the threads cap
+#[cfg_attr(not(feature = "ffi"), allow(dead_code))]
+mod pool;
+pub mod project;
+mod scanner;
+#[cfg(all(unix, feature = "ffi"))]
+pub mod server;
+#[cfg(test)]
+mod testing;
+mod udiff;
+mod watch;
+mod whitespace;
+
+#[cfg(feature = "ffi")]
+use libc::{c_char, c_int, int32_t, size_t, uint32_t, uint8_t};
 
 use racer::core;
 use racer::core::Match;
 use racer::scopes;
 
+use backend::{BraceStyle, Config, ControlBraceStyle, NewlineStyle, Summary};
+use cancel::CancelToken;
+use formatter::Formatter;
+use outcome::{FormatOutcome, Timings};
+use fragment::FragmentKind;
+use scanner::Kind;
+
 use std::{env, error};
 use std::fs::{self, File};
 use std::io::{ErrorKind, Read, Write};
+#[cfg(feature = "ffi")]
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "ffi")]
 use std::ffi::{CString, CStr};
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 // rustfmt related
-type FmtError = Box<error::Error + Send + Sync>;
-type FmtResult<T> = std::result::Result<T, FmtError>;
+
+/// Why an operation of the library failed
+#[derive(Debug)]
+pub enum AnacondaError {
+    /// The project configuration can not be looked up or read
+    ConfigIo(std::io::Error),
+    /// The project configuration file is not valid
+    ConfigParse(String),
+    /// The arguments of the call are not valid
+    InvalidInput(String),
+    /// rustfmt reported errors, see `status` for how they are told apart
+    Summary(Summary),
+    /// rustfmt or racer panicked
+    Panic(String),
+}
+
+impl AnacondaError {
+    /// The status code that the exported functions return for the error:
+    ///
+    /// * 1 for operational errors: config lookups, invalid config files,
+    ///   panics and rustfmt summaries with operational errors
+    /// * 2 for rustfmt summaries with parse errors
+    /// * 3 for rustfmt summaries with formatting errors (lines that rustfmt
+    ///   could not fit in `max_width`)
+    /// * 4 for invalid arguments
+    ///
+    /// A summary without errors is 0, that's how `process_summary` gets the
+    /// status of successful calls too
+    pub fn status(&self) -> i32 {
+        match *self {
+            AnacondaError::ConfigIo(_) | AnacondaError::ConfigParse(_) | AnacondaError::Panic(_) => 1,
+            AnacondaError::InvalidInput(_) => INVALID_ARGUMENT,
+            AnacondaError::Summary(summary) => {
+                if summary.has_operational_errors() {
+                    1
+                } else if summary.has_parsing_errors() {
+                    2
+                } else if summary.has_formatting_errors() {
+                    3
+                } else {
+                    assert!(summary.has_no_errors());
+                    0
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for AnacondaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            AnacondaError::ConfigIo(ref e) => write!(f, "error resolving config: {}", e),
+            AnacondaError::ConfigParse(ref reason) => write!(f, "invalid config: {}", reason),
+            AnacondaError::InvalidInput(ref reason) => write!(f, "{}", reason),
+            AnacondaError::Summary(summary) => {
+                match self.status() {
+                    2 => write!(f, "the code does not parse"),
+                    3 => write!(f, "some lines exceed max_width"),
+                    _ if !summary.has_no_errors() => write!(f, "the code can not be formatted"),
+                    _ => write!(f, "no errors"),
+                }
+            }
+            AnacondaError::Panic(ref what) => write!(f, "{} panicked", what),
+        }
+    }
+}
+
+impl error::Error for AnacondaError {
+    fn description(&self) -> &str {
+        match *self {
+            AnacondaError::ConfigIo(_) => "error resolving config",
+            AnacondaError::ConfigParse(_) => "invalid config",
+            AnacondaError::InvalidInput(_) => "invalid input",
+            AnacondaError::Summary(_) => "rustfmt reported errors",
+            AnacondaError::Panic(_) => "panic",
+        }
+    }
+}
+
+impl From<std::io::Error> for AnacondaError {
+    fn from(e: std::io::Error) -> AnacondaError {
+        AnacondaError::ConfigIo(e)
+    }
+}
+
+pub type FmtResult<T> = std::result::Result<T, AnacondaError>;
+
+/// Files that only exist in the editor (e.g. unsaved views) given as (path,
+/// contents) pairs, they are looked up before the filesystem during a call
+type Overlay = [(PathBuf, String)];
+
+fn overlay_file<'a>(overlay: &'a Overlay, path: &Path) -> Option<&'a str> {
+    overlay.iter().find(|&&(ref file, _)| file == path).map(|&(_, ref contents)| &contents[..])
+}
 
 fn match_cli_path_or_file(config_path: Option<PathBuf>,
-                          input_file: &Path)
+                          input_file: &Path,
+                          overlay: &Overlay)
                           -> FmtResult<(Config, Option<PathBuf>)> {
 
     if let Some(config_file) = config_path {
-        let (toml, path) = try!(resolve_config(config_file.as_ref()));
-        if path.is_some() {
+        let (toml, path) = try!(resolve_config(config_file.as_ref(), overlay));
+        // the lookup stopped at the root of the workspace, the current
+        // directory could be anywhere outside of it
+        if path.is_some() || workspace_root(&config_file, overlay).is_some() {
             return Ok((toml, path));
         }
     }
-    resolve_config(input_file)
+    resolve_config(input_file, overlay)
+}
+
+/// The current directory or, when it can't be read (e.g. it was removed
+/// under the host), the given directory or the root of the filesystem
+fn current_dir_or(dir: Option<&Path>) -> PathBuf {
+    match (env::current_dir(), dir) {
+        (Ok(current), _) => current,
+        (Err(_), Some(dir)) if dir.is_absolute() => dir.to_path_buf(),
+        (Err(_), _) => PathBuf::from("/"),
+    }
+}
+
+/// Read the file from the overlay or from the filesystem
+fn read_with_overlay(path: &Path, overlay: &Overlay) -> std::io::Result<String> {
+    if let Some(contents) = overlay_file(overlay, path) {
+        return Ok(String::from(contents));
+    }
+    let mut file = try!(File::open(path));
+    let mut contents = String::new();
+    try!(file.read_to_string(&mut contents));
+    Ok(contents)
 }
 
-fn resolve_config(dir: &Path) -> FmtResult<(Config, Option<PathBuf>)> {
-    let path = try!(lookup_project_file(dir));
+/// Resolve the project config for the directory, the options of the edition
+/// specific file next to the project file (if any) take precedence over the
+/// ones of the project file
+fn resolve_config(dir: &Path, overlay: &Overlay) -> FmtResult<(Config, Option<PathBuf>)> {
+    let path = try!(lookup_project_file(dir, overlay));
     if path.is_none() {
         return Ok((Config::default(), None));
     }
     let path = path.unwrap();
-    let mut file = try!(File::open(&path));
-    let mut toml = String::new();
-    try!(file.read_to_string(&mut toml));
-    Ok((Config::from_toml(&toml), Some(path)))
+    debug!("Using the project config {}", path.display());
+    let mut toml = try!(read_with_overlay(&path, overlay));
+
+    let dir = if dir.is_relative() { current_dir_or(None).join(dir) } else { dir.to_path_buf() };
+    let edition_file = edition::config_file(&path, &edition::detect(&dir, overlay));
+    if let Ok(layer) = read_with_overlay(&edition_file, overlay) {
+        debug!("Merging the edition config {}", edition_file.display());
+        toml = try!(edition::merge(&toml, &layer).map_err(|e| {
+            AnacondaError::ConfigParse(format!("{} can not be merged: {}", edition_file.display(), e))
+        }));
+    }
+    match backend::config_from_toml(&toml) {
+        Ok(config) => Ok((config, Some(path))),
+        Err(_) => Err(AnacondaError::ConfigParse(format!("{} can not be parsed", path.display()))),
+    }
 }
 
-fn lookup_project_file(dir: &Path) -> FmtResult<Option<PathBuf>> {
-    let mut current = if dir.is_relative() {
-        try!(env::current_dir()).join(dir)
-    } else {
-        dir.to_path_buf()
-    };
+fn lookup_project_file(dir: &Path, overlay: &Overlay) -> FmtResult<Option<PathBuf>> {
+    let start = if dir.is_relative() { current_dir_or(None).join(dir) } else { dir.to_path_buf() };
+    debug!("Looking up the project config from {}", start.display());
+
+    // lookups through an overlay depend on the call so they are never cached
+    if !overlay.is_empty() {
+        return walk_project_file(start.clone(), overlay).map(|(config_file, _)| config_file);
+    }
+    if let Some(config_file) = config_cache::get(&start) {
+        debug!("Project config lookup cached: {:?}", config_file);
+        return Ok(config_file);
+    }
+    let (config_file, dirs) = try!(walk_project_file(start.clone(), overlay));
+    config_cache::insert(start, config_file.clone(), dirs);
+    Ok(config_file)
+}
 
-    current = try!(fs::canonicalize(current));
+/// Look for the project file from the given directory up to the root, the
+/// visited directories are returned too with the modification time they had
+/// before the lookup looked inside them
+fn walk_project_file(mut current: PathBuf,
+                     overlay: &Overlay)
+                     -> FmtResult<(Option<PathBuf>, Vec<(PathBuf, Option<SystemTime>)>)> {
+    // directories of unsaved files may not exist yet
+    current = match fs::canonicalize(&current) {
+        Ok(path) => path,
+        Err(_) if !overlay.is_empty() => current,
+        Err(e) => return Err(AnacondaError::from(e)),
+    };
 
+    let mut dirs = Vec::new();
     loop {
+        dirs.push((current.clone(), config_cache::modified(&current)));
         let config_file = current.join("rustfmt.toml");
+        if overlay_file(overlay, &config_file).is_some() {
+            return Ok((Some(config_file), dirs));
+        }
         match fs::metadata(&config_file) {
             // Only return if it's a file to handle the unlikely situation of a directory named
             // `rustfmt.toml`.
-            Ok(ref md) if md.is_file() => return Ok(Some(config_file)),
+            Ok(ref md) if md.is_file() => return Ok((Some(config_file), dirs)),
             // Return the error if it's something other than `NotFound`; otherwise we didn't find
             // the project file yet, and continue searching.
             Err(e) => {
                 if e.kind() != ErrorKind::NotFound {
-                    return Err(FmtError::from(e));
+                    return Err(AnacondaError::from(e));
                 }
             }
-            _ => {}
+            // The search goes on but the user probably expects it to be their config.
+            Ok(_) => warn!("{} is not a file, it is not used as the project config", config_file.display()),
+        }
+
+        // The workspace root is the last directory of its members' projects.
+        if is_workspace_root(&current, overlay) {
+            debug!("Project config lookup stopped at the workspace root {}", current.display());
+            return Ok((None, dirs));
         }
 
         // If the current directory has no parent, we're done searching.
         if !current.pop() {
-            return Ok(None);
+            return Ok((None, dirs));
         }
     }
 }
 
-pub fn rustfmt(buffer: String, cfg_path: Option<String>) -> i32 {
-    let config_path: Option<PathBuf> = cfg_path
-        .map(PathBuf::from)
+/// Whether the `Cargo.toml` of the directory has a `[workspace]` table, the
+/// manifest is not parsed, its table headers are enough
+fn is_workspace_root(dir: &Path, overlay: &Overlay) -> bool {
+    match read_with_overlay(&dir.join("Cargo.toml"), overlay) {
+        Ok(manifest) => {
+            manifest.lines().map(str::trim).any(|line| line == "[workspace]" || line.starts_with("[workspace."))
+        }
+        Err(_) => false,
+    }
+}
+
+/// The nearest directory (the given one included) that is the root of a
+/// Cargo workspace
+fn workspace_root(dir: &Path, overlay: &Overlay) -> Option<PathBuf> {
+    let mut current = if dir.is_relative() { current_dir_or(None).join(dir) } else { dir.to_path_buf() };
+    loop {
+        if is_workspace_root(&current, overlay) {
+            return Some(current);
+        }
+        if !current.pop() {
+            return None;
+        }
+    }
+}
+
+lazy_static! {
+    // user level config file used when a project doesn't have its own one
+    static ref FALLBACK_CONFIG: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Load the fallback config file set by the host (if any), when it can't be
+/// read the error is reported and the default configuration is used
+fn fallback_config() -> Option<Config> {
+    let path = match FALLBACK_CONFIG.lock() {
+        Ok(path) => try_opt!(path.clone()),
+        Err(_) => return None,
+    };
+    let mut toml = String::new();
+    let result = File::open(&path)
+        .and_then(|mut file| file.read_to_string(&mut toml))
+        .map_err(|e| e.to_string())
+        .and_then(|_| backend::config_from_toml(&toml));
+    match result {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!("Can not read the fallback config {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// The directory where the config lookup for the path starts, the path itself
+/// unless it is a file
+fn config_dir(cfg_path: Option<String>, overlay: &Overlay) -> Option<PathBuf> {
+    cfg_path.map(PathBuf::from)
         .and_then(|dir| {
-            if dir.is_file() {
+            if dir.is_file() || overlay_file(overlay, &dir).is_some() {
                 return dir.parent().map(|v| v.into());
             }
             Some(dir)
-        });
+        })
+}
+
+/// Look up the project config from the directory like `lookup_project_file`
+/// does (without the cache) and describe the directories that are visited
+fn trace_lookup(dir: &Path) -> FmtResult<(serde_json::Value, Option<PathBuf>)> {
+    let start = if dir.is_relative() { current_dir_or(None).join(dir) } else { dir.to_path_buf() };
+    let (config_file, dirs) = try!(walk_project_file(start.clone(), &[]));
+    let steps: Vec<serde_json::Value> = dirs.iter()
+        .enumerate()
+        .map(|(i, &(ref dir, _))| {
+            json!({
+                "dir": dir.to_string_lossy(),
+                "config": config_file.is_some() && i + 1 == dirs.len(),
+            })
+        })
+        .collect();
+    Ok((json!({"start": start.to_string_lossy(), "steps": steps}), config_file))
+}
+
+/// Describe how the project configuration that applies to the path is
+/// resolved, a JSON object with every lookup (the directory where it starts
+/// and the directories visited in order, symlinks resolved), the config file
+/// that is used, its canonical path and the edition specific file merged into
+/// it (if any) or, when there is no project config, the workspace root where
+/// the lookup stopped and the fallback config. The status is 0 or the one of
+/// the lookup error, then the object has an `error`
+pub fn resolve_config_trace(cfg_path: Option<String>) -> (i32, String) {
+    let config_path = config_dir(cfg_path.clone(), &[]);
+    let current_dir = current_dir_or(config_path.as_ref().map(|dir| dir.as_path()));
+
+    // the same lookups that `match_cli_path_or_file` does
+    let mut lookups = Vec::new();
+    let mut found = None;
+    let mut workspace = None;
+    for dir in config_path.into_iter().chain(Some(current_dir)) {
+        match trace_lookup(&dir) {
+            Ok((lookup, config_file)) => {
+                lookups.push(lookup);
+                if config_file.is_some() {
+                    found = config_file.map(|path| (dir, path));
+                    break;
+                }
+                if let Some(root) = workspace_root(&dir, &[]) {
+                    workspace = Some(root);
+                    break;
+                }
+            }
+            Err(e) => {
+                let trace = json!({"path": cfg_path, "lookups": lookups, "error": e.to_string()});
+                return (e.status(), trace.to_string());
+            }
+        }
+    }
+
+    let trace = match found {
+        Some((dir, path)) => {
+            let dir = if dir.is_relative() { current_dir_or(None).join(dir) } else { dir };
+            let edition_file = edition::config_file(&path, &edition::detect(&dir, &[]));
+            let edition_config = edition_file.to_string_lossy().into_owned();
+            json!({
+                "path": cfg_path,
+                "lookups": lookups,
+                "config": path.to_string_lossy(),
+                "canonical": fs::canonicalize(&path).ok().map(|path| path.to_string_lossy().into_owned()),
+                "edition_config": if edition_file.is_file() { Some(edition_config) } else { None },
+            })
+        }
+        None => {
+            let fallback = FALLBACK_CONFIG.lock().ok().and_then(|path| path.clone());
+            json!({
+                "path": cfg_path,
+                "lookups": lookups,
+                "config": null,
+                "workspace_root": workspace.map(|root: PathBuf| root.to_string_lossy().into_owned()),
+                "fallback": fallback.map(|path| path.to_string_lossy().into_owned()),
+            })
+        }
+    };
+    (0, trace.to_string())
+}
+
+/// Resolve the configuration that applies to the given path looking up the
+/// files in the overlay before the ones in the filesystem, the returned path
+/// is the project config file that was used (if any)
+fn try_project_config_with(cfg_path: Option<String>, overlay: &Overlay) -> FmtResult<(Config, Option<PathBuf>)> {
+    let config_path = config_dir(cfg_path, overlay);
 
     // try to read config from local directory
-    let (mut config, _) = match_cli_path_or_file(config_path, &env::current_dir().unwrap())
-        .expect("Error resolving config");
+    let current_dir = current_dir_or(config_path.as_ref().map(|dir| dir.as_path()));
+    let (mut config, path) = try!(match_cli_path_or_file(config_path, &current_dir, overlay));
+    if path.is_none() {
+        if let Some(fallback) = fallback_config() {
+            debug!("No project config, using the fallback config");
+            config = fallback;
+        }
+    }
 
     // write_mode is alwais Plain for anaconda_rust
-    config.write_mode = WriteMode::Plain;
+    backend::set_plain(&mut config);
+    Ok((config, path))
+}
 
-    // run the command and return status code
-    process_summary(run(Input::Text(buffer), &config))
+/// Options of `format_string`
+#[derive(Clone, Debug, Default)]
+pub struct FormatOptions {
+    /// File or directory the project configuration is resolved for, the
+    /// current directory is used when it is None
+    pub config_path: Option<String>,
+    /// Look up the project configuration again instead of taking it from the
+    /// lookup cache, for the calls that can't accept a stale result
+    pub bypass_config_cache: bool,
 }
 
-fn process_summary(error_summary: Summary) -> i32 {
-    let status_code: i32;
-    if error_summary.has_operational_errors() {
-        status_code = 1
-    } else if error_summary.has_parsing_errors() {
-        status_code = 2
-    } else if error_summary.has_formatting_errors() {
-        status_code = 3
-    } else {
-        assert!(error_summary.has_no_errors());
-        status_code = 0
-    }
+/// Result of `format_string`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatOutput {
+    pub text: String,
+    /// Whether formatting changed the input
+    pub changed: bool,
+    /// Whether rustfmt left some lines longer than `max_width`
+    pub formatting_errors: bool,
+    /// Whether the input was the last output formatted for the same path and
+    /// config so rustfmt was skipped
+    pub cached: bool,
+}
 
-    // flush standard output
-    std::io::stdout().flush().unwrap();
-    // return the excution code
-    status_code
+impl FormatOutput {
+    /// The status code that the exported functions return for the output
+    fn status(&self) -> i32 {
+        if self.formatting_errors { 3 } else { 0 }
+    }
 }
 
-// racer related
-fn racer_complete(code: String, path_str: String, line: usize, offset: usize) -> String {
-    // We can not allow rust from panicking or the Python
-    // process spawned by ST3 will die and die is sad.
-    //
-    // To prevent it we fire the call to the underlying
-    // libracer wrapped into a thread so we can catch
-    // any panic and response with an error
-    let child = thread::spawn(move || {
-        let mut output = String::new();
-        let cache = core::FileCache::new();
-        let path_buf = PathBuf::from(path_str);
-        let path = path_buf.as_path();
-        let session = core::Session::from_path(&cache, path, path);
+/// Result of `format_to_writer`, the same as `FormatOutput` without the text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FormatSummary {
+    pub changed: bool,
+    pub formatting_errors: bool,
+    pub cached: bool,
+}
 
-        // cache the contents of the given file path and code
-        cache.cache_file_contents(path, code);
+/// Why `format_string` didn't format the input
+#[derive(Debug)]
+pub enum FormatError {
+    /// The project configuration (or the file to format) can not be read
+    Io(std::io::Error),
+    /// The output can not be written, see `format_to_writer`
+    Write(std::io::Error),
+    /// The project configuration file is not valid
+    ConfigParse(String),
+    /// The input does not parse
+    InvalidInput(String),
+    /// rustfmt failed to format the input
+    Internal(String),
+    /// Another write of the file did not end before the lock timeout
+    Busy(String),
+    /// The input is bigger than the size limit, 4 MiB unless the host sets
+    /// another one with `set_max_input_bytes`
+    TooLarge(String),
+}
 
-        let src = session.load_file(path);
-        let point = scopes::coords_to_point(&src, line, offset);
+impl FormatError {
+    /// The status code that the exported functions return for the error
+    fn status(&self) -> i32 {
+        match *self {
+            FormatError::InvalidInput(_) => 2,
+            FormatError::Busy(_) => FILE_BUSY,
+            FormatError::TooLarge(_) => INPUT_TOO_LARGE,
+            _ => 1,
+        }
+    }
+}
 
-        for m in core::complete_from_file(&src, path, point, &session) {
-            output = format!("{}\n{}", output, match_fn(m, &session));
+impl From<AnacondaError> for FormatError {
+    fn from(e: AnacondaError) -> FormatError {
+        match e {
+            AnacondaError::ConfigIo(e) => FormatError::Io(e),
+            AnacondaError::ConfigParse(reason) => FormatError::ConfigParse(reason),
+            AnacondaError::InvalidInput(reason) => FormatError::InvalidInput(reason),
+            e @ AnacondaError::Summary(_) if e.status() == 2 => FormatError::InvalidInput(e.to_string()),
+            e => FormatError::Internal(e.to_string()),
         }
-        output
+    }
+}
 
-    });
-    match child.join() {
-        Ok(v) => format!("{}", v),
-        Err(e) => format!("error\tRacer Panicked: {:?}", e)
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            FormatError::Io(ref e) => write!(f, "{}", e),
+            FormatError::Write(ref e) => write!(f, "can not write the output: {}", e),
+            FormatError::ConfigParse(ref reason) => write!(f, "invalid config: {}", reason),
+            FormatError::InvalidInput(ref reason) |
+            FormatError::Internal(ref reason) |
+            FormatError::Busy(ref reason) |
+            FormatError::TooLarge(ref reason) => write!(f, "{}", reason),
+        }
     }
 }
 
-fn find_definition(code: String, path_str: String, line: usize, offset: usize) -> String {
-    let child = thread::spawn(move || {
-        let path_buf = PathBuf::from(path_str);
-        let path = path_buf.as_path();
-        let cache = core::FileCache::new();
-        let session = core::Session::from_path(&cache, path, path);
+impl error::Error for FormatError {
+    fn description(&self) -> &str {
+        match *self {
+            FormatError::Io(_) => "I/O error",
+            FormatError::Write(_) => "error writing the output",
+            FormatError::ConfigParse(_) => "invalid config",
+            FormatError::InvalidInput(_) => "the code does not parse",
+            FormatError::Internal(_) => "the code can not be formatted",
+            FormatError::Busy(_) => "the file is busy",
+            FormatError::TooLarge(_) => "the input is too large",
+        }
+    }
+}
 
-        // cache the contents of the given file path and code
-        cache.cache_file_contents(path, code);
+/// Resolve the configuration that applies to the given path like
+/// `try_project_config_with` does, rustfmt panics for config files that are
+/// not valid TOML so the panic is turned into an error
+fn try_load_config(cfg_path: Option<String>) -> Result<Config, FormatError> {
+    match std::panic::catch_unwind(|| try_project_config_with(cfg_path, &[])) {
+        Ok(result) => result.map(|(config, _)| config).map_err(FormatError::from),
+        Err(_) => Err(FormatError::ConfigParse(String::from("the config file can not be parsed"))),
+    }
+}
 
-        let src = session.load_file(path);
-        let pos = scopes::coords_to_point(&src, line, offset);
+/// Format the input with the configuration of the project, the same way the
+/// exported functions do but without printing anything to the standard
+/// output
+pub fn format_string(input: &str, options: &FormatOptions) -> Result<FormatOutput, FormatError> {
+    format_owned(String::from(input), options)
+}
 
-        match core::find_definition(&src, path, pos, &session) {
-            Some(m) => match_definition(m, &session),
-            None => String::new(),
-        }
-    });
-    match child.join() {
-        Ok(v) => format!("{}", v),
-        Err(e) => format!("error\tRacer Panicked: {:?}", e)
-    }
+/// Format the input like `format_string` does writing the output to `out`
+/// (a file, a socket...) instead of returning it. Nothing is written when the
+/// input can't be formatted, errors of the writer are `FormatError::Write`
+pub fn format_to_writer<W: Write>(input: &str,
+                                  options: &FormatOptions,
+                                  out: &mut W)
+                                  -> Result<FormatSummary, FormatError> {
+    try!(formatter(options)).write_buffer(String::from(input), options.config_path.clone(), out)
 }
 
-fn get_documentation(code: String, path_str: String, line: usize, offset: usize) -> String {
-    let child = thread::spawn(move || {
-        let cache = core::FileCache::new();
-        let path_buf = PathBuf::from(path_str);
-        let path = path_buf.as_path();
-        let session = core::Session::from_path(&cache, path, path);
+/// Format the input like `format_string` does and describe the result, with
+/// the time each step took and the edits that turn the input into the output
+pub fn format_outcome(input: &str, options: &FormatOptions) -> FormatOutcome {
+    let start = Instant::now();
+    let formatter = formatter(options);
+    let config_ms = millis(start.elapsed());
+    let start = Instant::now();
+    let result = formatter.and_then(|formatter| formatter.format(input));
+    let timings = Timings {
+        config_ms: config_ms,
+        format_ms: millis(start.elapsed()),
+    };
+    FormatOutcome::new(input, result, timings)
+}
 
-        // cache the contents of the given file path and code
-        cache.cache_file_contents(path, code);
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
+}
 
-        let src = session.load_file(path);
-        let point = scopes::coords_to_point(&src, line, offset);
+/// `format_string` for a buffer that is already owned, so it is not copied
+fn format_owned(buffer: String, options: &FormatOptions) -> Result<FormatOutput, FormatError> {
+    try!(formatter(options)).format_buffer(buffer, options.config_path.clone())
+}
 
-        let mut matches = core::complete_from_file(&src, path, point, &session);
-        match_doc_fn(matches.next().unwrap())
-    });
-    match child.join() {
-        Ok(v) => format!("{}", v),
-        Err(e) => format!("error\tRacer Panicked: {:?}", e)
+/// Format the buffer with the given configuration, nothing is looked up,
+/// cached or written to the standard output so a caller that loads a `Config`
+/// once can reuse it for many buffers. The output is returned even when some
+/// lines are still longer than `max_width`, the error has the summary when
+/// there is no output
+pub fn format_with(buffer: &str, config: &Config) -> FmtResult<String> {
+    let (summary, output) = render(String::from(buffer), config.clone(), OutputOptions::default());
+    match output {
+        Some(output) if !summary.has_parsing_errors() && !summary.has_operational_errors() => Ok(output),
+        _ => Err(AnacondaError::Summary(summary)),
     }
 }
 
-fn match_fn(m: Match, session: &core::Session) -> String {
-    if m.matchstr == "" {
-        String::from("MATCHSTR is empty - waddup?");
+fn formatter(options: &FormatOptions) -> Result<Formatter, FormatError> {
+    let mut builder = Formatter::builder();
+    if let Some(ref path) = options.config_path {
+        builder = builder.config_path(&path[..]);
     }
+    builder.bypass_config_cache(options.bypass_config_cache).build()
+}
 
-    let snippet = racer::snippets::snippet_for_match(&m, session);
-    format!("{}\t{}\t{}\t{:?}\t{}",
-            m.matchstr,
-            snippet,
-            m.filepath.to_str().unwrap(),
-            m.mtype,
-            m.contextstr.replace("\t", "\\t"),
-    )
+pub fn rustfmt(buffer: String, cfg_path: Option<String>) -> i32 {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    rustfmt_to(buffer, cfg_path, &mut stdout)
 }
 
-fn match_definition(m: Match, session: &core::Session) -> String {
-    let (linenum, offset) = scopes::point_to_coords_from_file(&m.filepath, m.point, session).unwrap();
-    if m.matchstr == "" {
-        String::from("MATCHSTR is empty - waddup?");
+/// `rustfmt` writing the output to `out` instead of the standard output, the
+/// output of rustfmt is written as it is without being copied first
+fn rustfmt_to<W: Write>(buffer: String, cfg_path: Option<String>, out: &mut W) -> i32 {
+    let options = FormatOptions { config_path: cfg_path.clone(), ..FormatOptions::default() };
+    let result = formatter(&options).and_then(|formatter| formatter.write_buffer(buffer, cfg_path, out));
+    let result = result.and_then(|summary| out.flush().map(|_| summary).map_err(FormatError::Write));
+    match result {
+        Ok(summary) => {
+            if summary.cached {
+                debug!("Buffer already formatted, rustfmt skipped");
+            }
+            if summary.formatting_errors { 3 } else { 0 }
+        }
+        Err(e) => {
+            error!("{}", e);
+            e.status()
+        }
     }
-
-    format!("{}\t{}\t{}", m.filepath.to_str().unwrap(), linenum.to_string(), offset.to_string())
 }
 
-fn match_doc_fn(m: Match) -> String {
-    if m.matchstr == "" {
-        String::from("MATCHSTR is mepty - waddup?");
+/// Format the buffer and return the output as bytes, they are empty when the
+/// buffer is not formatted
+pub fn rustfmt_bytes(buffer: String, cfg_path: Option<String>) -> (i32, Vec<u8>) {
+    match format_owned(buffer, &FormatOptions { config_path: cfg_path, ..FormatOptions::default() }) {
+        Ok(output) => (output.status(), output.text.into_bytes()),
+        Err(e) => (e.status(), Vec::new()),
     }
+}
 
-    format!("{:?}", m.docs)
+/// Hash of everything besides the buffer that changes the output of `format`
+fn config_generation(config: &Config) -> u64 {
+    let limit = limit_value(&MAX_LINE_BYTES, DEFAULT_MAX_LINE_BYTES);
+    fnv1a(format!("{}max_line_bytes = {}\n", serialize_config(config), limit).as_bytes())
 }
 
-// FFI related
+/// Format the buffer like `format` does unless it is the last output that
+/// was formatted for the same path and config, then the buffer is returned as
+/// it is and the third value is true
+fn render_or_skip(buffer: String, cfg_path: Option<String>, config: Config) -> (Summary, Option<String>, bool) {
+    let path = match cfg_path {
+        Some(path) if !path.is_empty() => path,
+        _ => {
+            let (summary, output) = render(buffer, config, OutputOptions::default());
+            return (summary, output, false);
+        }
+    };
+    let generation = config_generation(&config);
+    if format_cache::is_formatted(&path, fnv1a(buffer.as_bytes()), generation) {
+        debug!("{} is already formatted, rustfmt skipped", path);
+        return (Summary::new(), Some(buffer), true);
+    }
 
-/// This function converts a C char * string into a safe Rust String
-/// It assures that the c_str is not null using assert! macro so you
-/// must be certain that yo never pass null strings to any of the
-/// exported functions
-fn c_str_to_safe_string(c_str: *const libc::c_char) -> String {
-    unsafe {
-        assert!(!c_str.is_null());
-        CStr::from_ptr(c_str).to_string_lossy().into_owned()
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    match output {
+        Some(ref output) if process_summary(summary) == 0 => {
+            format_cache::remember(path, fnv1a(output.as_bytes()), generation);
+        }
+        _ => format_cache::forget(&path),
     }
+    (summary, output, false)
 }
 
-/// Converts a Rust String into a C char * and returns a pointer
-/// to it's inner memory
-///
-/// WARNING: this function forgets about the allocated memory so
-/// YOU MUST MAKE SURE to delete this memory yourself, there is
-/// a convenience exported function to do that, you can just
-/// call `free_c_char_mem` with the C string as parameter to
-/// free the allocated memory from your C compatible code
-fn to_c_str(s: String) -> *mut c_char {
-    let c_string = CString::new(s).unwrap();
-    c_string.into_raw()
+/// Format the buffer using the given configuration and print it to the
+/// standard output, this is what `format` does once the config is resolved
+fn print_with(buffer: String, config: Config) -> i32 {
+    format_with_options(buffer, config, OutputOptions::default())
 }
 
-/// Return this crate version as a C string
-///
-/// NOTE: You should free the allocated string memory after is not need anymore
-#[no_mangle]
-pub extern fn get_version() -> *mut c_char {
-    to_c_str(String::from(option_env!("CARGO_PKG_VERSION").unwrap_or("unknown")))
+/// Whether the formatted output ends with a newline, rustfmt always adds one
+/// but some projects enforce the opposite with other tools
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EofNewline {
+    Preserve,
+    Always,
+    Never,
 }
 
-/// This function can be used to free memory allocated by Rust
-///
-/// You can also free the memory in your C compatible app calling
-/// the stdlib free function for example
-#[no_mangle]
-pub extern fn free_c_char_mem(c: *mut c_char) {
-    unsafe {
-        if c.is_null() { return }
-        CString::from_raw(c)
-    };
+#[cfg(feature = "ffi")]
+impl EofNewline {
+    fn from_c_int(mode: c_int) -> Option<EofNewline> {
+        match mode {
+            0 => Some(EofNewline::Preserve),
+            1 => Some(EofNewline::Always),
+            2 => Some(EofNewline::Never),
+            _ => None,
+        }
+    }
 }
 
-/// Format the passed buffer using librustfmt and return back an operation
-/// status code, librustfmt uses the standard output to print the formating
-/// results so you should capture it in you C level code.
-///
-/// No memory need to be freed after use this function as it is automatically
-/// handled by Rust itself
-#[no_mangle]
-pub extern fn format(code: *const c_char, path: *const c_char) ->  c_int {
-    let config_path: Option<String> = Some(c_str_to_safe_string(path));
-    let buffer = c_str_to_safe_string(code);
-    rustfmt(buffer, config_path)
+/// Settings of the formatted output that are not part of the rustfmt config
+#[derive(Clone, Copy, Debug)]
+struct OutputOptions {
+    // forced newline style, None keeps the line endings of the buffer unless
+    // the project config sets `newline_style`
+    newline_style: Option<NewlineStyle>,
+    // write back the UTF-8 BOM found at the start of the buffer
+    keep_bom: bool,
+    eof_newline: EofNewline,
 }
 
-/// Look for code completions using libracer and return back a string with
+impl Default for OutputOptions {
+    fn default() -> OutputOptions {
+        OutputOptions {
+            newline_style: None,
+            keep_bom: true,
+            eof_newline: EofNewline::Preserve,
+        }
+    }
+}
+
+// UTF-8 byte order mark that some Windows editors add to the files
+const BOM: char = '\u{feff}';
+
+/// Format the buffer and print the output to the standard output following the
+/// given output options
+fn format_with_options(buffer: String, config: Config, options: OutputOptions) -> i32 {
+    let (summary, output) = render(buffer, config, options);
+    emit(summary, output)
+}
+
+/// Format the buffer with Unix newlines and return the output using the newline
+/// style of the options, line endings inside literals are never modified. A
+/// leading BOM is removed before formatting and written back if the options
+/// say so, an empty buffer produces an empty output
+fn render(buffer: String, config: Config, options: OutputOptions) -> (Summary, Option<String>) {
+    render_with(buffer, config, options, format_guarded)
+}
+
+/// Same as `render` but the Unix buffer without BOM is formatted by `format`
+/// instead of rustfmt alone, for the modes that do something else on top
+fn render_with<F>(mut buffer: String,
+                  mut config: Config,
+                  options: OutputOptions,
+                  format: F)
+                  -> (Summary, Option<String>)
+    where F: FnOnce(String, &Config) -> (Summary, Option<String>)
+{
+    let style = match options.newline_style {
+        Some(style) => style,
+        None if config.newline_style_set() => config.newline_style(),
+        None => newlines::dominant(&buffer),
+    };
+
+    let has_bom = buffer.starts_with(BOM);
+    if has_bom {
+        info!("UTF-8 BOM found at the start of the buffer");
+        // drained in place, the buffer can be a few megabytes long
+        buffer.drain(..BOM.len_utf8());
+    }
+    for (number, _) in buffer.lines().enumerate().filter(|&(_, line)| line.contains(BOM)) {
+        warn!("UTF-8 BOM found at line {}, left untouched", number + 1);
+    }
+
+    if newlines::is_mixed(&buffer) {
+        info!("Mixed line endings normalized to {:?}", style);
+    }
+
+    let eof_newline = match options.eof_newline {
+        EofNewline::Preserve => buffer.ends_with('\n'),
+        EofNewline::Always => true,
+        EofNewline::Never => false,
+    };
+    config.set_newline_style(NewlineStyle::Unix);
+    let (summary, output) = if buffer.is_empty() {
+        (Summary::new(), Some(String::new()))
+    } else {
+        format(newlines::normalize(buffer), &config)
+    };
+    let output = output.map(|mut output| {
+        let len = output.trim_right_matches('\n').len();
+        if len > 0 || !eof_newline {
+            output.truncate(len);
+        }
+        if len > 0 && eof_newline {
+            output.push('\n');
+        }
+        let mut output = newlines::restore(output, style);
+        if has_bom && options.keep_bom {
+            output.insert(0, BOM);
+        }
+        output
+    });
+    (summary, output)
+}
+
+/// Same as `render_with` for the modes that explain why they fail, the
+/// reason is returned instead of the summary
+fn render_or_reason<F>(buffer: String, config: Config, format: F) -> Result<String, String>
+    where F: FnOnce(&str, &Config) -> Result<String, String>
+{
+    let mut failure = None;
+    let (_, output) = render_with(buffer, config, OutputOptions::default(), |buffer, config| {
+        match format(&buffer, config) {
+            Ok(output) => (Summary::new(), Some(output)),
+            Err(reason) => {
+                failure = Some(reason);
+                (Summary::new(), None)
+            }
+        }
+    });
+    match (output, failure) {
+        (_, Some(reason)) => Err(reason),
+        (Some(output), None) => Ok(output),
+        (None, None) => Err(String::from("the code can not be formatted")),
+    }
+}
+
+/// Format the buffer leaving untouched the top level items that contain lines
+/// longer than the limit, rustfmt would take ages to format them
+fn format_guarded(buffer: String, config: &Config) -> (Summary, Option<String>) {
+    let limit = limit_value(&MAX_LINE_BYTES, DEFAULT_MAX_LINE_BYTES);
+    let long_lines: Vec<usize> = buffer.lines()
+        .enumerate()
+        .filter(|&(_, line)| limit != 0 && line.len() > limit)
+        .map(|(number, _)| number + 1)
+        .collect();
+    if long_lines.is_empty() {
+        return format_to_string(buffer, config);
+    }
+
+    // one line of every item is enough to get it formatted
+    let mut lines = Vec::new();
+    for (start, end) in items::top_level_items(&buffer) {
+        if long_lines.iter().any(|&line| start <= line && line <= end) {
+            warn!("Lines {} to {} left unformatted, they contain lines longer than {} bytes",
+                  start,
+                  end,
+                  limit);
+        } else {
+            lines.push(start);
+        }
+    }
+    let (output, summary, _) = items::format_items(&buffer, config, &lines);
+    (summary, Some(output))
+}
+
+/// Returns the shebang line (with its line ending and the BOM before it, if
+/// any) that starts the buffer, the number of blank lines that follow it and
+/// where the code starts. `#![...]` is an inner attribute, not a shebang
+fn split_shebang(code: &str) -> Option<(&str, usize, usize)> {
+    let start = if code.starts_with(BOM) { BOM.len_utf8() } else { 0 };
+    if !code[start..].starts_with("#!") || code[start + 2..].trim_left().starts_with('[') {
+        return None;
+    }
+
+    let line_end = code.find('\n').map(|pos| pos + 1).unwrap_or(code.len());
+    let mut blank_lines = 0;
+    let mut end = line_end;
+    while let Some(pos) = code[end..].find('\n') {
+        if !code[end..end + pos].trim().is_empty() {
+            break;
+        }
+        blank_lines += 1;
+        end += pos + 1;
+    }
+    Some((&code[..line_end], blank_lines, end))
+}
+
+/// Format the buffer and return the formatted text instead of printing it to
+/// the standard output, the text is None when rustfmt was not able to produce
+/// any output (e.g. the buffer does not parse). A leading shebang line is not
+/// Rust code so it is kept aside, with the blank lines after it, and put back
+/// verbatim
+fn format_to_string(buffer: String, config: &Config) -> (Summary, Option<String>) {
+    let (shebang, blank_lines, start) = match split_shebang(&buffer) {
+        Some(shebang) => shebang,
+        None => return format_code(buffer, config),
+    };
+    if buffer[start..].trim().is_empty() {
+        return (Summary::new(), Some(String::from(shebang)));
+    }
+
+    let newline = if shebang.ends_with("\r\n") { "\r\n" } else { "\n" };
+    let (summary, output) = format_code(String::from(&buffer[start..]), config);
+    (summary, output.map(|output| format!("{}{}{}", shebang, newline.repeat(blank_lines), output)))
+}
+
+fn format_code(buffer: String, config: &Config) -> (Summary, Option<String>) {
+    backend::format_text(buffer, config)
+}
+
+pub fn rustfmt_with_docs(buffer: String, cfg_path: Option<String>) -> i32 {
+    let config = try_config!(try_load_config(cfg_path));
+    let (summary, output) = render_with(buffer, config, OutputOptions::default(), |buffer, config| {
+        let (summary, output) = format_guarded(buffer, config);
+        let output = output.map(|output| {
+            let (output, notes) = docs::format_blocks(&output, config);
+            for note in notes {
+                info!("{}", note);
+            }
+            output
+        });
+        (summary, output)
+    });
+    emit(summary, output)
+}
+
+pub fn rustfmt_imports(buffer: String, cfg_path: Option<String>) -> i32 {
+    let config = try_config!(try_load_config(cfg_path));
+    let (summary, output) = render_with(buffer, config, OutputOptions::default(), |buffer, config| {
+        let (output, summary) = imports::format_imports(&buffer, config);
+        (summary, Some(output))
+    });
+    emit(summary, output)
+}
+
+/// Format the buffer like `rustfmt` does with the import options given
+/// instead of the ones of the project configuration, `reorder` sets both
+/// `reorder_imports` and `reorder_imported_names` and `group` splits the
+/// imports into groups separated by a blank line
+pub fn rustfmt_with_import_options(buffer: String, cfg_path: Option<String>, reorder: bool, group: bool) -> i32 {
+    let config = try_config!(try_load_config(cfg_path));
+    let (summary, output) = render_with_import_options(buffer, config, reorder, group);
+    emit(summary, output)
+}
+
+/// The output of `rustfmt_with_import_options`
+fn render_with_import_options(buffer: String,
+                              mut config: Config,
+                              reorder: bool,
+                              group: bool)
+                              -> (Summary, Option<String>) {
+    config.set_reorder_imports(reorder);
+    config.set_reorder_imported_names(reorder);
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    let output = if group { output.map(|output| imports::group_imports(&output)) } else { output };
+    (summary, output)
+}
+
+pub fn rustfmt_cleanup(buffer: String, cfg_path: Option<String>) -> i32 {
+    let config = try_config!(try_load_config(cfg_path));
+    emit(Summary::new(), Some(whitespace::cleanup(&buffer, &config)))
+}
+
+/// Strip the trailing whitespace of the buffer and make sure that it ends
+/// with a newline without running rustfmt nor reading any configuration
+pub fn rustfmt_normalize_whitespace(buffer: String) -> String {
+    whitespace::normalize(&buffer)
+}
+
+/// Format the fragment keeping its line endings like `render` does, the
+/// daemon `fragment` method shares it
+fn render_fragment(buffer: String, config: Config, kind: FragmentKind) -> Result<String, String> {
+    render_or_reason(buffer, config, |buffer, config| fragment::format_fragment(buffer, kind, config))
+}
+
+pub fn rustfmt_fragment(buffer: String, cfg_path: Option<String>, kind: FragmentKind) -> String {
+    let config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    match render_fragment(buffer, config, kind) {
+        Ok(output) => output,
+        Err(reason) => format!("error\t{}", reason),
+    }
+}
+
+pub fn rustfmt_macro_body(buffer: String, cfg_path: Option<String>) -> String {
+    let config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    match render_or_reason(buffer, config, fragment::format_macro_body) {
+        Ok(output) => output,
+        Err(reason) => format!("error\t{}", reason),
+    }
+}
+
+#[cfg(debug_assertions)]
+fn annotate(original: &str, formatted: String, config: &Config) -> String {
+    annotate::annotate(original, &formatted, config)
+}
+
+// release builds don't pay for the annotations
+#[cfg(not(debug_assertions))]
+fn annotate(_: &str, formatted: String, _: &Config) -> String {
+    formatted
+}
+
+/// Format the buffer and annotate every change with the options that most
+/// likely caused it (only in debug builds), this is a tool for bug reports
+pub fn rustfmt_annotated(buffer: String, cfg_path: Option<String>) -> String {
+    let config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    let result = render_or_reason(buffer, config, |buffer, config| {
+        let (summary, output) = format_guarded(String::from(buffer), config);
+        formatted_or_reason(summary, output).map(|output| annotate(buffer, output, config))
+    });
+    result.unwrap_or_else(|reason| format!("error\t{}", reason))
+}
+
+/// Format the buffer like `rustfmt` does resolving the configuration with the
+/// given virtual files (unsaved views) on top of the filesystem
+pub fn rustfmt_with_overlay(buffer: String, cfg_path: Option<String>, overlay: Vec<(PathBuf, String)>) -> i32 {
+    let (config, project_file) = try_config!(try_project_config_with(cfg_path, &overlay));
+    if let Some(path) = project_file {
+        if overlay_file(&overlay, &path).is_some() {
+            info!("Using the unsaved configuration of {}", path.display());
+        }
+    }
+    print_with(buffer, config)
+}
+
+/// Format a buffer that has no file on disk (e.g. an unsaved view) resolving
+/// the configuration from the directory it belongs to, that directory may not
+/// exist yet either so its closest existing ancestor is used instead
+pub fn rustfmt_virtual(buffer: String, project_dir: String) -> String {
+    let mut dir = PathBuf::from(project_dir);
+    while !dir.is_dir() && dir.pop() {}
+
+    let dir = dir.to_string_lossy().into_owned();
+    let config = try_config!(try_load_config(Some(dir)), e => format!("error\t{}", e));
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    formatted_or_reason(summary, output).unwrap_or_else(|reason| format!("error\t{}", reason))
+}
+
+/// Format a buffer encoded in base64 and return the output encoded the same
+/// way, the error explains why the buffer was not formatted
+pub fn rustfmt_b64(encoded: &str, cfg_path: Option<String>) -> Result<String, String> {
+    let bytes = try!(base64::decode(encoded).map_err(|e| format!("invalid base64: {}", e)));
+    let buffer = try!(String::from_utf8(bytes).map_err(|_| String::from("the buffer is not valid UTF-8")));
+    let config = try!(try_load_config(cfg_path).map_err(|e| e.to_string()));
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    let output = try!(formatted_or_reason(summary, output));
+    Ok(base64::encode(output.as_bytes()))
+}
+
+/// Format only the top level items touched by the given unified diff, the
+/// returned report lists the formatted line ranges (one `start\tend` per line)
+/// or starts with `error\t` when the diff doesn't apply to the buffer
+pub fn rustfmt_diff(buffer: String, cfg_path: Option<String>, diff: String) -> (i32, String) {
+    let path = cfg_path.clone().unwrap_or_default();
+    let lines = match udiff::changed_lines(&diff, &path, &buffer) {
+        Ok(lines) => lines,
+        Err(reason) => return (INVALID_ARGUMENT, format!("error\t{}", reason)),
+    };
+
+    let mut ranges = Vec::new();
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), format!("error\t{}", e)));
+    let (summary, output) = render_with(buffer, config, OutputOptions::default(), |buffer, config| {
+        let (output, summary, formatted) = items::format_items(&buffer, config, &lines);
+        ranges = formatted;
+        (summary, Some(output))
+    });
+    let report = ranges.iter()
+        .map(|&(start, end)| format!("{}\t{}", start, end))
+        .collect::<Vec<String>>()
+        .join("\n");
+    (emit(summary, output), report)
+}
+
+/// Format the buffer with the rustfmt defaults and the options of a JSON
+/// object (e.g. `{"max_width": 80, "hard_tabs": true}`) instead of the project
+/// configuration. Unknown options and invalid values are skipped and returned
+/// as warnings, the output starts with `error\t` when the JSON is not an object
+/// or the buffer can't be formatted
+pub fn rustfmt_json_config(buffer: String, config_json: &str) -> (String, Vec<String>) {
+    let (overrides, mut warnings) = match args::json_overrides(config_json) {
+        Ok(parsed) => parsed,
+        Err(reason) => return (format!("error\t{}", reason), Vec::new()),
+    };
+    let mut config = Config::default();
+    warnings.extend(args::apply_known(&mut config, &overrides));
+    backend::set_plain(&mut config);
+    for warning in &warnings {
+        warn!("{}", warning);
+    }
+
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    match formatted_or_reason(summary, output) {
+        Ok(output) => (output, warnings),
+        Err(reason) => (format!("error\t{}", reason), warnings),
+    }
+}
+
+/// Format only the top level item with the given name, see
+/// `items::find_item`. The rest of the buffer is returned untouched, the
+/// result starts with `error\t` when the item can't be found or formatted
+pub fn rustfmt_item(buffer: String, cfg_path: Option<String>, name: &str) -> String {
+    let (start, _) = match items::find_item(&buffer, name) {
+        Ok(range) => range,
+        Err(reason) => return format!("error\t{}", reason),
+    };
+    let mut ranges = Vec::new();
+    let config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    let (summary, output) = render_with(buffer, config, OutputOptions::default(), |buffer, config| {
+        let (output, summary, formatted) = items::format_items(&buffer, config, &[start]);
+        ranges = formatted;
+        (summary, Some(output))
+    });
+    match formatted_or_reason(summary, output) {
+        Ok(output) if !ranges.is_empty() => output,
+        Ok(_) => String::from("error\tthe item can not be formatted"),
+        Err(reason) => format!("error\t{}", reason),
+    }
+}
+
+fn run_git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = try!(Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("can not run git: {}", e)));
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Ask git for the changes of the file on disk relative to `reference`, the
+/// error explains why git could not tell (not a repository, untracked file,
+/// missing git binary...)
+fn git_diff(path: &Path, reference: &str) -> Result<String, String> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return Err(format!("{} is not a file", path.display())),
+    };
+
+    try!(run_git(dir, &["ls-files", "--error-unmatch", "--", name]));
+    run_git(dir, &["diff", "--no-color", "--no-ext-diff", "-U0", reference, "--", name])
+}
+
+/// Format the lines of the file that changed relative to the given git
+/// reference, when git can't tell what changed the whole file is formatted
+pub fn rustfmt_changed(path: String, reference: String) -> i32 {
+    if fs::metadata(&path).map(|md| too_large(md.len() as usize)).unwrap_or(false) {
+        return INPUT_TOO_LARGE;
+    }
+    let mut buffer = String::new();
+    if let Err(e) = File::open(&path).and_then(|mut file| file.read_to_string(&mut buffer)) {
+        error!("Can not read {}: {}", path, e);
+        return 1;
+    }
+
+    let reason = match git_diff(Path::new(&path), &reference) {
+        Ok(diff) => {
+            let (status, report) = rustfmt_diff(buffer.clone(), Some(path.clone()), diff);
+            if status != INVALID_ARGUMENT {
+                return status;
+            }
+            report.trim_left_matches("error\t").to_owned()
+        }
+        Err(reason) => reason,
+    };
+
+    info!("Formatting the whole file: {}", reason);
+    rustfmt(buffer, Some(path))
+}
+
+/// Format the buffer and print the unified diff that formatting makes like the
+/// rustfmt command line tool does in diff mode (nothing when the buffer is
+/// already formatted), the exit code is the one that rustfmt would return:
+/// 0 to 3 like the status codes of this crate and `CLI_CHANGED` when the
+/// formatted code differs from the given buffer, any other status of this
+/// crate is an operational error (1)
+pub fn rustfmt_cli_compat(buffer: String, cfg_path: Option<String>) -> i32 {
+    let path = cfg_path.clone().unwrap_or_default();
+    let config = try_config!(try_load_config(cfg_path), e => cli_exit_code(e.status()));
+    let (summary, output) = render(buffer.clone(), config, OutputOptions::default());
+    let changed = output.as_ref().map(|output| *output != buffer).unwrap_or(false);
+    let diff = output.map(|output| if changed { udiff::unified(&path, &buffer, &output) } else { String::new() });
+
+    match emit(summary, diff) {
+        0 if changed => CLI_CHANGED,
+        status => cli_exit_code(status),
+    }
+}
+
+/// The exit code of the rustfmt command line tool for a status of this crate,
+/// so `CLI_CHANGED` can't be mistaken for `INVALID_ARGUMENT`
+fn cli_exit_code(status: i32) -> i32 {
+    match status {
+        0 | 1 | 2 | 3 => status,
+        _ => 1,
+    }
+}
+
+/// Returns whether the code needs formatting or the status code of the error
+/// that prevented formatting it
+fn needs_format(code: &str, config: &Config) -> Result<bool, i32> {
+    let (summary, output) = render(String::from(code), config.clone(), OutputOptions::default());
+    match (output, process_summary(summary)) {
+        (Some(output), 0) => Ok(output != code),
+        (_, 0) => Err(1),
+        (_, status) => Err(status),
+    }
+}
+
+/// Format both the buffer and the file on disk with the same configuration and
+/// tell which of them need formatting
+pub fn rustfmt_compare_with_disk(buffer: String, path: String) -> i32 {
+    if fs::metadata(&path).map(|md| too_large(md.len() as usize)).unwrap_or(false) {
+        return INPUT_TOO_LARGE;
+    }
+    let mut disk = String::new();
+    if let Err(e) = File::open(&path).and_then(|mut file| file.read_to_string(&mut disk)) {
+        error!("Can not read {}: {}", path, e);
+        return DISK_UNREADABLE;
+    }
+
+    let config = try_config!(try_load_config(Some(path)));
+    let buffer_needs_format = match needs_format(&buffer, &config) {
+        Ok(needs) => needs,
+        Err(status) => {
+            error!("Can not format the buffer");
+            return status;
+        }
+    };
+    let disk_needs_format = match needs_format(&disk, &config) {
+        Ok(needs) => needs,
+        Err(status) => {
+            error!("Can not format the file on disk");
+            return status;
+        }
+    };
+
+    match (buffer_needs_format, disk_needs_format) {
+        (false, false) => 0,
+        (true, false) => BUFFER_NEEDS_FORMAT,
+        (false, true) => DISK_NEEDS_FORMAT,
+        (true, true) => BOTH_NEED_FORMAT,
+    }
+}
+
+/// Format the buffer applying the editor settings on top of the default config
+/// but only when the project doesn't have a config file, otherwise the project
+/// conventions win. Values lower than 1 are ignored
+pub fn rustfmt_with_editor_defaults(buffer: String,
+                                    cfg_path: Option<String>,
+                                    tab_spaces: i32,
+                                    max_width: i32)
+                                    -> i32 {
+    let config = try_config!(editor_config(cfg_path, tab_spaces, max_width));
+    print_with(buffer, config)
+}
+
+/// The configuration of `rustfmt_with_editor_defaults`
+fn editor_config(cfg_path: Option<String>, tab_spaces: i32, max_width: i32) -> FmtResult<Config> {
+    let (mut config, project_file) = try!(try_project_config_with(cfg_path, &[]));
+    if project_file.is_none() {
+        if tab_spaces > 0 {
+            config.set_tab_spaces(tab_spaces as usize);
+        }
+        if max_width > 0 {
+            config.set_max_width(max_width as usize);
+        }
+    }
+    Ok(config)
+}
+
+/// Format the buffer with the configuration given by rustfmt style command
+/// line arguments, `--help` logs the usage
+pub fn rustfmt_args(buffer: String, args: Vec<String>) -> i32 {
+    match args::parse_options(&args) {
+        Ok(Some((config, _))) => print_with(buffer, config),
+        Ok(None) => {
+            info!("{}", args::usage());
+            0
+        }
+        Err(e) => {
+            error!("Invalid arguments: {}", e);
+            INVALID_ARGUMENT
+        }
+    }
+}
+
+/// Format the buffer overriding the `error_on_line_overflow` option of the
+/// project configuration, lines that can't be wrapped are not errors if false
+pub fn rustfmt_lenient_overflow(buffer: String, cfg_path: Option<String>, error_on_overflow: bool) -> i32 {
+    let mut config = try_config!(try_load_config(cfg_path));
+    config.set_error_on_line_overflow(error_on_overflow);
+    print_with(buffer, config)
+}
+
+/// Format the buffer and then its output again and again until formatting
+/// doesn't change it, up to `passes` times. Returns the status and the pass
+/// where the output stopped changing (1 when the buffer was formatted), or
+/// `NOT_IDEMPOTENT` and the passes made when it never did. Nothing is printed
+pub fn rustfmt_stable_n(buffer: String, cfg_path: Option<String>, passes: usize) -> (i32, usize) {
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), 0));
+    format_until_stable(buffer, passes, |code| render(code, config.clone(), OutputOptions::default()))
+}
+
+fn format_until_stable<F>(buffer: String, passes: usize, mut format: F) -> (i32, usize)
+    where F: FnMut(String) -> (Summary, Option<String>)
+{
+    let mut current = buffer;
+    for pass in 1..passes + 1 {
+        let (summary, output) = format(current.clone());
+        let output = match output {
+            Some(output) if !summary.has_parsing_errors() && !summary.has_operational_errors() => output,
+            _ => return (process_summary(summary), pass),
+        };
+        if output == current {
+            return (process_summary(summary), pass);
+        }
+        debug!("Formatting pass {} changed the output", pass);
+        current = output;
+    }
+    (NOT_IDEMPOTENT, passes)
+}
+
+/// The (1-based) lines of the code wider than `max_width` columns, hard tabs
+/// count as `tab_spaces` columns
+fn wide_lines(code: &str, config: &Config) -> Vec<usize> {
+    let width = |line: &str| line.chars().map(|c| if c == '\t' { config.tab_spaces() } else { 1 }).sum::<usize>();
+    code.lines()
+        .enumerate()
+        .filter(|&(_, line)| width(line.trim_right_matches('\r')) > config.max_width())
+        .map(|(i, _)| i + 1)
+        .collect()
+}
+
+/// Format the buffer like `rustfmt` does but nothing is printed when some line
+/// of the output is still wider than `max_width`, then the status is
+/// `WIDTH_VIOLATION` and the second value has the offending lines
+pub fn rustfmt_enforce_width(buffer: String, cfg_path: Option<String>) -> (i32, Vec<usize>) {
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), Vec::new()));
+    let (summary, output) = render(buffer, config.clone(), OutputOptions::default());
+    let violations = output.as_ref().map(|output| wide_lines(output, &config)).unwrap_or_default();
+    if summary.has_parsing_errors() || summary.has_operational_errors() || violations.is_empty() {
+        return (emit(summary, output), violations);
+    }
+    (WIDTH_VIOLATION, violations)
+}
+
+pub fn rustfmt_with_bom(buffer: String, cfg_path: Option<String>, keep_bom: bool) -> i32 {
+    let options = OutputOptions { keep_bom: keep_bom, ..OutputOptions::default() };
+    let config = try_config!(try_load_config(cfg_path));
+    format_with_options(buffer, config, options)
+}
+
+/// Format the buffer like `rustfmt` does, the second value is how many bytes
+/// the output grew (or shrank, if negative) compared to the buffer
+pub fn rustfmt_byte_delta(buffer: String, cfg_path: Option<String>) -> (i32, i64) {
+    let original = buffer.len() as i64;
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), 0));
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    let delta = match output {
+        Some(ref output) => output.len() as i64 - original,
+        None => 0,
+    };
+    (emit(summary, output), delta)
+}
+
+/// Format the buffer like `rustfmt` does, the second value is a hex digest of
+/// the output (empty when there is no output) that is stable across runs
+pub fn rustfmt_hashed(buffer: String, cfg_path: Option<String>) -> (i32, String) {
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), String::new()));
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    let hash = match output {
+        Some(ref output) => format!("{:016x}", fnv1a(output.as_bytes())),
+        None => String::new(),
+    };
+    (emit(summary, output), hash)
+}
+
+// markers of the comments listed by `rustfmt_todos`
+const TODO_MARKERS: &'static [&'static str] = &["TODO", "FIXME", "XXX"];
+
+/// The (1-based) line, marker and text of every `TODO`, `FIXME` and `XXX` in
+/// the comments of the code, markers in literals don't count
+fn todo_markers(code: &str) -> Vec<(usize, &'static str, String)> {
+    let mut markers = Vec::new();
+    for span in scanner::scan(code).into_iter().filter(|span| span.kind == Kind::Comment) {
+        let first_line = code[..span.start].matches('\n').count() + 1;
+        for (n, line) in code[span.start..span.end].lines().enumerate() {
+            let words = items::idents(line);
+            let found = words.iter().filter_map(|&(start, word)| {
+                TODO_MARKERS.iter().find(|&&marker| marker == word).map(|&marker| (start, marker))
+            });
+            if let Some((start, marker)) = found.into_iter().next() {
+                let text = line[start + marker.len()..]
+                    .trim_right_matches("*/")
+                    .trim_left_matches(|c: char| c == ':' || c.is_whitespace())
+                    .trim();
+                markers.push((first_line + n, marker, String::from(text)));
+            }
+        }
+    }
+    markers
+}
+
+/// Format the buffer like `rustfmt` does, the second value is a JSON list
+/// with the `line`, `marker` and `text` of the TODO, FIXME and XXX comments
+/// of the output (or of the buffer when it was not formatted)
+pub fn rustfmt_todos(buffer: String, cfg_path: Option<String>) -> (i32, String) {
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), String::from("[]")));
+    let (summary, output) = render(buffer.clone(), config, OutputOptions::default());
+    let todos: Vec<serde_json::Value> = todo_markers(output.as_ref().unwrap_or(&buffer))
+        .into_iter()
+        .map(|(line, marker, text)| json!({"line": line, "marker": marker, "text": text}))
+        .collect();
+    (emit(summary, output), serde_json::Value::Array(todos).to_string())
+}
+
+/// Format the buffer returning the output (empty if there is none) and a
+/// report of the lines of the original buffer with trailing whitespace or
+/// mixed indentation, one `line\treason` per line
+pub fn rustfmt_whitespace_report(buffer: String, cfg_path: Option<String>) -> (i32, String, String) {
+    let report = whitespace::report(&buffer)
+        .iter()
+        .map(|&(line, reason)| format!("{}\t{}", line, reason))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), String::new(), report));
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    let status = process_summary(summary);
+    (status, formatted_or_reason(summary, output).unwrap_or_default(), report)
+}
+
+/// Format the buffer like `rustfmt` does, the second value describes the kind
+/// of changes that formatting made, one category per line
+pub fn rustfmt_change_summary(buffer: String, cfg_path: Option<String>) -> (i32, String) {
+    let original = buffer.clone();
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), String::new()));
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    let changes = match output {
+        Some(ref output) => {
+            changes::summary(&original, output)
+                .iter()
+                .map(|change| change.description())
+                .collect::<Vec<&str>>()
+                .join("\n")
+        }
+        None => String::new(),
+    };
+    (emit(summary, output), changes)
+}
+
+/// Format the buffer made of the given lines (joined with LF) and return the
+/// formatted lines, the list is empty when the buffer is not formatted. A
+/// last empty line stands for the newline at the end of the buffer and is
+/// kept in the output, without it the output doesn't end with a newline
+pub fn rustfmt_lines(lines: Vec<String>, cfg_path: Option<String>) -> (i32, Vec<String>) {
+    if lines.is_empty() {
+        return (0, lines);
+    }
+    let buffer = lines.join("\n");
+    let config = try_config!(try_load_config(cfg_path), e => (e.status(), Vec::new()));
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    let status = process_summary(summary);
+    match formatted_or_reason(summary, output) {
+        Ok(output) => (status, output.split('\n').map(String::from).collect()),
+        Err(_) => (status, Vec::new()),
+    }
+}
+
+/// Check whether the buffer parses without formatting it, the second value
+/// is the error of the parser when it doesn't (empty otherwise)
+pub fn rustfmt_validate(buffer: &str) -> (i32, String) {
+    match backend::parse(buffer) {
+        Ok(_) => (0, String::new()),
+        Err(error) => (2, error),
+    }
+}
+
+/// Format the buffer and return the output with the offset where the byte
+/// offset `cursor` of the buffer goes in it, or the reason why there is none
+pub fn rustfmt_with_cursor(buffer: String, cfg_path: Option<String>, cursor: usize) -> Result<(String, usize), String> {
+    let config = try!(try_load_config(cfg_path).map_err(|e| e.to_string()));
+    let (summary, output) = render(buffer.clone(), config, OutputOptions::default());
+    let output = try!(formatted_or_reason(summary, output));
+    let cursor = changes::map_offset(&buffer, &output, cursor);
+    Ok((output, cursor))
+}
+
+pub fn rustfmt_newline_at_eof(buffer: String, cfg_path: Option<String>, mode: EofNewline) -> i32 {
+    let options = OutputOptions { eof_newline: mode, ..OutputOptions::default() };
+    let config = try_config!(try_load_config(cfg_path));
+    format_with_options(buffer, config, options)
+}
+
+/// Newline conventions that can be forced on the formatted output, `Auto`
+/// picks whatever convention dominates the input buffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NewlineMode {
+    Auto,
+    Unix,
+    Windows,
+    Native,
+}
+
+#[cfg(feature = "ffi")]
+impl NewlineMode {
+    fn from_c_int(style: c_int) -> Option<NewlineMode> {
+        match style {
+            0 => Some(NewlineMode::Auto),
+            1 => Some(NewlineMode::Unix),
+            2 => Some(NewlineMode::Windows),
+            3 => Some(NewlineMode::Native),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "ffi")]
+fn brace_style_from_c_int(style: c_int) -> Option<BraceStyle> {
+    match style {
+        0 => Some(BraceStyle::AlwaysNextLine),
+        1 => Some(BraceStyle::PreferSameLine),
+        2 => Some(BraceStyle::SameLineWhere),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "ffi")]
+fn control_brace_style_from_c_int(style: c_int) -> Option<ControlBraceStyle> {
+    match style {
+        0 => Some(ControlBraceStyle::AlwaysSameLine),
+        1 => Some(ControlBraceStyle::AlwaysNextLine),
+        _ => None,
+    }
+}
+
+/// Format the buffer with the brace styles given instead of the ones of the
+/// project configuration, `item_style` is used for functions and the other
+/// items. The result is the output or `error\t` followed by the reason
+pub fn rustfmt_brace_style(buffer: String,
+                           cfg_path: Option<String>,
+                           item_style: BraceStyle,
+                           control_style: ControlBraceStyle)
+                           -> String {
+    let mut config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    config.set_fn_brace_style(item_style);
+    config.set_item_brace_style(item_style);
+    config.set_control_brace_style(control_style);
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    match formatted_or_reason(summary, output) {
+        Ok(output) => output,
+        Err(reason) => format!("error\t{}", reason),
+    }
+}
+
+/// Format the buffer with the alignment thresholds given (None keeps the one
+/// of the project configuration): fields of structs and variants of enums are
+/// aligned when their names differ in length by up to the threshold, 0
+/// disables the alignment. The options are unstable and some rustfmt builds
+/// don't have them. The result is the output or `error\t` followed by the
+/// reason
+pub fn rustfmt_with_alignment(buffer: String,
+                              cfg_path: Option<String>,
+                              field_threshold: Option<usize>,
+                              enum_threshold: Option<usize>)
+                              -> String {
+    let mut config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    let options = [("struct_field_align_threshold", field_threshold), ("enum_variant_align_threshold", enum_threshold)];
+    for &(key, threshold) in &options {
+        if let Some(threshold) = threshold {
+            if args::apply(&mut config, &[(String::from(key), threshold.to_string())]).is_err() {
+                return format!("error\tunstable option unavailable: this rustfmt has no `{}`", key);
+            }
+        }
+    }
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    match formatted_or_reason(summary, output) {
+        Ok(output) => output,
+        Err(reason) => format!("error\t{}", reason),
+    }
+}
+
+/// Format the buffer under every `rustfmt.toml` found under the root (the
+/// files that git ignores and `target` are skipped) and return the outputs by
+/// config path, an output is `error\t` followed by the reason when the config
+/// can't be read or the buffer can't be formatted under it. The error tells
+/// why the root can't be walked
+pub fn rustfmt_under_all_configs(buffer: &str,
+                                 root: &Path)
+                                 -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    if !root.is_dir() {
+        return Err(format!("{} is not a directory", root.display()));
+    }
+    let mut outputs = serde_json::Map::new();
+    let configs = ignore::walk(root, true, false)
+        .files
+        .into_iter()
+        .filter(|path| path.file_name().map_or(false, |name| name == "rustfmt.toml"));
+    for path in configs {
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let output = match resolve_config(&dir, &[]) {
+            Ok((config, _)) => {
+                let (summary, output) = render(String::from(buffer), config, OutputOptions::default());
+                formatted_or_reason(summary, output).unwrap_or_else(|reason| format!("error\t{}", reason))
+            }
+            Err(e) => format!("error\t{}", e),
+        };
+        outputs.insert(path.to_string_lossy().into_owned(), json!(output));
+    }
+    Ok(outputs)
+}
+
+/// Put the text of the original comments back into the formatted code, rustfmt
+/// reindents block comments and trims their trailing whitespace even when it
+/// doesn't normalize nor wrap them. Nothing is restored unless the comments of
+/// both buffers pair up
+fn restore_comments(original: &str, formatted: String) -> String {
+    let comments = |code: &str| {
+        scanner::scan(code).into_iter().filter(|span| span.kind == Kind::Comment).collect::<Vec<_>>()
+    };
+    let (before, after) = (comments(original), comments(&formatted));
+    // `//` and `/*` comments can't turn into each other
+    let same_kind = |(old, new): (&scanner::Span, &scanner::Span)| {
+        original[old.start..old.start + 2] == formatted[new.start..new.start + 2]
+    };
+    if before.len() != after.len() || !before.iter().zip(&after).all(same_kind) {
+        warn!("Comments moved by rustfmt, they are left as formatted");
+        return formatted;
+    }
+
+    let mut output = String::with_capacity(formatted.len());
+    let mut last = 0;
+    for (old, new) in before.iter().zip(&after) {
+        output.push_str(&formatted[last..new.start]);
+        output.push_str(&original[old.start..old.end]);
+        last = new.end;
+    }
+    output.push_str(&formatted[last..]);
+    output
+}
+
+/// Format the buffer leaving its comments byte for byte as they are, comment
+/// normalization and wrapping are disabled whatever the project configuration
+/// says. The result is the output or `error\t` followed by the reason
+pub fn rustfmt_preserve_comments(buffer: String, cfg_path: Option<String>) -> String {
+    let mut config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    config.set_normalize_comments(false);
+    config.set_wrap_comments(false);
+    let original = buffer.clone();
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    match formatted_or_reason(summary, output) {
+        Ok(output) => restore_comments(&original, output),
+        Err(reason) => format!("error\t{}", reason),
+    }
+}
+
+/// Disable the options that rewrite code which is already acceptable, see
+/// `format_low_churn` for the list
+fn low_churn(config: &mut Config) {
+    config.set_reorder_imports(false);
+    config.set_reorder_imported_names(false);
+    config.set_format_strings(false);
+    config.set_wrap_comments(false);
+    config.set_normalize_comments(false);
+    config.set_wrap_match_arms(false);
+    config.set_take_source_hints(true);
+}
+
+/// Format the buffer with the low churn preset on top of the project
+/// configuration. The result is the output or `error\t` followed by the
+/// reason
+pub fn rustfmt_low_churn(buffer: String, cfg_path: Option<String>) -> String {
+    let mut config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    low_churn(&mut config);
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    match formatted_or_reason(summary, output) {
+        Ok(output) => output,
+        Err(reason) => format!("error\t{}", reason),
+    }
+}
+
+/// Format the buffer under the project configuration on a worker thread, None
+/// when the token is cancelled before the output is ready. Otherwise the
+/// result is the output or `error\t` followed by the reason
+pub fn rustfmt_cancelable(buffer: String, cfg_path: Option<String>, token: &CancelToken) -> Option<String> {
+    let result = try_opt!(cancel::run(token, move || {
+        let config = try!(try_load_config(cfg_path).map_err(|e| e.to_string()));
+        let (summary, output) = render(buffer, config, OutputOptions::default());
+        formatted_or_reason(summary, output)
+    }));
+    Some(match result {
+        Ok(Ok(output)) => output,
+        Ok(Err(reason)) => format!("error\t{}", reason),
+        Err(e) => format!("error\trustfmt panicked: {:?}", e),
+    })
+}
+
+/// Format the buffer and return a checkstyle XML report of the lines that
+/// formatting changes, the file is named after the config path
+pub fn rustfmt_checkstyle(buffer: String, cfg_path: Option<String>) -> String {
+    let path = cfg_path.clone().unwrap_or_default();
+    let original = buffer.clone();
+    let config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    let (summary, output) = render(buffer, config, OutputOptions::default());
+    match formatted_or_reason(summary, output) {
+        Ok(output) => checkstyle::report(&path, &original, &output),
+        Err(reason) => format!("error\t{}", reason),
+    }
+}
+
+/// The output options that force the newline style of the mode, `Auto` keeps
+/// the dominant style of the buffer
+fn newline_options(buffer: &str, mode: NewlineMode) -> OutputOptions {
+    let style = match mode {
+        NewlineMode::Auto => newlines::dominant(buffer),
+        NewlineMode::Unix => NewlineStyle::Unix,
+        NewlineMode::Windows => NewlineStyle::Windows,
+        NewlineMode::Native => NewlineStyle::Native,
+    };
+    OutputOptions { newline_style: Some(style), ..OutputOptions::default() }
+}
+
+pub fn rustfmt_newline_style(buffer: String, cfg_path: Option<String>, mode: NewlineMode) -> i32 {
+    let config = try_config!(try_load_config(cfg_path));
+    let options = newline_options(&buffer, mode);
+    format_with_options(buffer, config, options)
+}
+
+/// Serialize the options that have some effect over the formatted output as
+/// `key = value` lines, rustfmt is able to read its Config from TOML but it
+/// doesn't know how to write it back so we have to do it ourselves
+fn serialize_config(config: &Config) -> String {
+    macro_rules! serialize {
+        ($($option:ident),+) => {
+            vec![$(format!("{} = {:?}", stringify!($option), config.$option())),+].join("\n")
+        }
+    }
+
+    serialize!(disable_all_formatting,
+               max_width,
+               ideal_width,
+               error_on_line_overflow,
+               tab_spaces,
+               newline_style,
+               fn_brace_style,
+               item_brace_style,
+               control_brace_style,
+               report_todo,
+               report_fixme,
+               reorder_imports,
+               reorder_imported_names,
+               single_line_if_else_max_width,
+               format_strings,
+               chains_overflow_last,
+               take_source_hints,
+               hard_tabs,
+               wrap_comments,
+               normalize_comments,
+               wrap_match_arms,
+               match_block_trailing_comma)
+}
+
+/// 64 bits FNV-1a hash, we don't use std's DefaultHasher because its output
+/// is not guaranteed to be the same between Rust releases
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn config_fingerprint_for(cfg_path: Option<String>) -> String {
+    let config = try_config!(try_load_config(cfg_path), e => format!("error\t{}", e));
+    format!("{:016x}", fnv1a(serialize_config(&config).as_bytes()))
+}
+
+/// Load the configuration in the given `.toml` file, for any other path the
+/// project configuration that applies to it is used. The error tells why the
+/// configuration can't be read
+fn load_config_file(path: String) -> Result<Config, String> {
+    if !path.ends_with(".toml") {
+        return try_load_config(Some(path)).map_err(|e| e.to_string());
+    }
+    let mut toml = String::new();
+    let mut config = try!(File::open(&path)
+        .and_then(|mut file| file.read_to_string(&mut toml))
+        .map_err(|e| e.to_string())
+        .and_then(|_| backend::config_from_toml(&toml))
+        .map_err(|e| format!("{}: {}", path, e)));
+    backend::set_plain(&mut config);
+    Ok(config)
+}
+
+/// Format the sample code with both configurations and tell whether they give
+/// the same output, None when a configuration can't be read (the reason is
+/// logged) or the sample can not be formatted with any of them
+pub fn rustfmt_configs_equivalent(path_a: String, path_b: String, sample: String) -> Option<bool> {
+    let configs = load_config_file(path_a)
+        .and_then(|config_a| load_config_file(path_b).map(|config_b| (config_a, config_b)));
+    let (config_a, config_b) = match configs {
+        Ok(configs) => configs,
+        Err(reason) => {
+            warn!("Can not compare the configs: {}", reason);
+            return None;
+        }
+    };
+    let (_, output_a) = render(sample.clone(), config_a, OutputOptions::default());
+    let (_, output_b) = render(sample, config_b, OutputOptions::default());
+    match (output_a, output_b) {
+        (Some(a), Some(b)) => Some(a == b),
+        _ => None,
+    }
+}
+
+// status code returned when an exported function receives an argument
+// that it does not know how to handle (e.g. an unknown enum value)
+const INVALID_ARGUMENT: i32 = 4;
+
+// status code returned when the buffer is bigger than the limit (4 MiB unless
+// it is changed with `set_max_input_bytes`), nothing is formatted in that case
+const INPUT_TOO_LARGE: i32 = 5;
+
+// status codes returned by `compare_with_disk`, 0 means that both the buffer
+// and the file on disk are already formatted
+const BUFFER_NEEDS_FORMAT: i32 = 6;
+const DISK_NEEDS_FORMAT: i32 = 7;
+const BOTH_NEED_FORMAT: i32 = 8;
+const DISK_UNREADABLE: i32 = 9;
+
+// status code returned by `format_enforce_width` when some line of the
+// output is wider than `max_width`
+const WIDTH_VIOLATION: i32 = 11;
+
+// status code returned by `format_cli_compat` when the formatted code differs
+// from the buffer, it is the exit code of rustfmt so it has the same value as
+// `INVALID_ARGUMENT` but `format_cli_compat` never returns that one, see
+// `cli_exit_code`
+const CLI_CHANGED: i32 = 4;
+
+// status code returned by `format_stable_n` when the output keeps changing
+// after every pass
+const NOT_IDEMPOTENT: i32 = 12;
+
+// status code of the format calls that give up waiting for another write of
+// the same file
+const FILE_BUSY: i32 = 13;
+
+// status code returned by `format_cargo_project` when some file of the
+// package (or directory) needs formatting
+const PROJECT_NEEDS_FORMAT: i32 = 10;
+
+/// Print the output of a format operation to the standard output in a single
+/// write, nothing at all is printed when the operation had parsing or
+/// operational errors so the caller never gets a partial result
+fn emit(mut summary: Summary, output: Option<String>) -> i32 {
+    if summary.has_parsing_errors() || summary.has_operational_errors() {
+        return process_summary(summary);
+    }
+    if let Some(output) = output {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        if let Err(e) = stdout.write_all(output.as_bytes()) {
+            error!("Error writing formatted output: {}", e);
+            summary.add_operational_error();
+        }
+    }
+    process_summary(summary)
+}
+
+/// Returns the output of a format operation or the reason why there is none,
+/// for the functions that return the formatted code instead of printing it
+fn formatted_or_reason(summary: Summary, output: Option<String>) -> Result<String, String> {
+    match (process_summary(summary), output) {
+        (2, _) => Err(String::from("the code does not parse")),
+        (1, _) | (_, None) => Err(String::from("the code can not be formatted")),
+        (_, Some(output)) => Ok(output),
+    }
+}
+
+fn process_summary(error_summary: Summary) -> i32 {
+    let status_code = AnacondaError::Summary(error_summary).status();
+
+    // flush standard output, the output is lost if it fails
+    if let Err(e) = std::io::stdout().flush() {
+        error!("Error writing formatted output: {}", e);
+        return 1;
+    }
+    // return the excution code
+    status_code
+}
+
+// racer related
+fn racer_complete(code: String, path_str: String, line: usize, offset: usize) -> String {
+    // We can not allow rust from panicking or the Python
+    // process spawned by ST3 will die and die is sad.
+    //
+    // To prevent it we fire the call to the underlying
+    // libracer wrapped into a thread so we can catch
+    // any panic and response with an error
+    let child = thread::spawn(move || {
+        let mut output = String::new();
+        let cache = core::FileCache::new();
+        let path_buf = PathBuf::from(path_str);
+        let path = path_buf.as_path();
+        let session = core::Session::from_path(&cache, path, path);
+
+        // cache the contents of the given file path and code
+        cache.cache_file_contents(path, code);
+
+        let src = session.load_file(path);
+        let point = scopes::coords_to_point(&src, line, offset);
+
+        for m in core::complete_from_file(&src, path, point, &session) {
+            output = format!("{}\n{}", output, match_fn(m, &session));
+        }
+        output
+
+    });
+    match child.join() {
+        Ok(v) => format!("{}", v),
+        Err(e) => format!("error\tRacer Panicked: {:?}", e)
+    }
+}
+
+fn find_definition(code: String, path_str: String, line: usize, offset: usize) -> String {
+    let child = thread::spawn(move || {
+        let path_buf = PathBuf::from(path_str);
+        let path = path_buf.as_path();
+        let cache = core::FileCache::new();
+        let session = core::Session::from_path(&cache, path, path);
+
+        // cache the contents of the given file path and code
+        cache.cache_file_contents(path, code);
+
+        let src = session.load_file(path);
+        let pos = scopes::coords_to_point(&src, line, offset);
+
+        match core::find_definition(&src, path, pos, &session) {
+            Some(m) => match_definition(m, &session),
+            None => String::new(),
+        }
+    });
+    match child.join() {
+        Ok(v) => format!("{}", v),
+        Err(e) => format!("error\tRacer Panicked: {:?}", e)
+    }
+}
+
+fn get_documentation(code: String, path_str: String, line: usize, offset: usize) -> String {
+    let child = thread::spawn(move || {
+        let cache = core::FileCache::new();
+        let path_buf = PathBuf::from(path_str);
+        let path = path_buf.as_path();
+        let session = core::Session::from_path(&cache, path, path);
+
+        // cache the contents of the given file path and code
+        cache.cache_file_contents(path, code);
+
+        let src = session.load_file(path);
+        let point = scopes::coords_to_point(&src, line, offset);
+
+        let mut matches = core::complete_from_file(&src, path, point, &session);
+        match_doc_fn(matches.next().unwrap())
+    });
+    match child.join() {
+        Ok(v) => format!("{}", v),
+        Err(e) => format!("error\tRacer Panicked: {:?}", e)
+    }
+}
+
+fn match_fn(m: Match, session: &core::Session) -> String {
+    if m.matchstr == "" {
+        String::from("MATCHSTR is empty - waddup?");
+    }
+
+    let snippet = racer::snippets::snippet_for_match(&m, session);
+    format!("{}\t{}\t{}\t{:?}\t{}",
+            m.matchstr,
+            snippet,
+            m.filepath.to_str().unwrap(),
+            m.mtype,
+            m.contextstr.replace("\t", "\\t"),
+    )
+}
+
+fn match_definition(m: Match, session: &core::Session) -> String {
+    let (linenum, offset) = scopes::point_to_coords_from_file(&m.filepath, m.point, session).unwrap();
+    if m.matchstr == "" {
+        String::from("MATCHSTR is empty - waddup?");
+    }
+
+    format!("{}\t{}\t{}", m.filepath.to_str().unwrap(), linenum.to_string(), offset.to_string())
+}
+
+fn match_doc_fn(m: Match) -> String {
+    if m.matchstr == "" {
+        String::from("MATCHSTR is mepty - waddup?");
+    }
+
+    format!("{:?}", m.docs)
+}
+
+// indentation related
+
+struct OpenDelimiter {
+    delimiter: u8,
+    level: usize,
+}
+
+fn closes(opener: u8, closer: u8) -> bool {
+    match (opener, closer) {
+        (b'{', b'}') | (b'(', b')') | (b'[', b']') => true,
+        _ => false,
+    }
+}
+
+/// Compute the indentation level (in block indents) that the given line
+/// (1-based) should start at, this is a heuristic that follows rustfmt's
+/// block indentation and not a real parse of the code, the error contains
+/// the reason why the buffer is too broken to give an answer
+fn indent_level(code: &str, line: usize) -> Result<usize, String> {
+    let mut starts = vec![0];
+    starts.extend(code.match_indices('\n').map(|(i, _)| i + 1));
+    if line == 0 || line > starts.len() {
+        return Err(format!("line {} is out of range, the buffer has {} lines", line, starts.len()));
+    }
+
+    let target = starts[line - 1];
+    let spans = scanner::scan(code);
+    if let Some(span) = spans.iter().find(|s| s.kind != Kind::Code && s.start < target && target < s.end) {
+        return Err(format!("line {} starts inside {}{} that begins at byte {}",
+                           line,
+                           if span.closed { "a " } else { "an unterminated " },
+                           if span.kind == Kind::Comment { "comment" } else { "literal" },
+                           span.start));
+    }
+
+    let kinds = scanner::kinds(code);
+    let bytes = code.as_bytes();
+    let mut stack: Vec<OpenDelimiter> = Vec::new();
+    // last two significant code bytes seen so far, used to tell whether the
+    // next line continues an expression and to spot attributes
+    let mut last: Option<u8> = None;
+    let mut before_last: Option<u8> = None;
+    let mut attribute_depth: Option<usize> = None;
+    let mut closed_attribute = false;
+
+    for (index, &start) in starts.iter().enumerate().take(line) {
+        let end = starts.get(index + 1).cloned().unwrap_or(bytes.len());
+        let first = (start..end)
+            .find(|&i| kinds[i] != Kind::Code || !(bytes[i] as char).is_whitespace())
+            .and_then(|i| if kinds[i] == Kind::Code { Some(bytes[i]) } else { None });
+
+        let base = stack.last().map(|open| open.level + 1).unwrap_or(0);
+        let level = match (first, stack.last()) {
+            (Some(c), Some(open)) if closes(open.delimiter, c) => open.level,
+            _ => {
+                let continues = match last {
+                    None | Some(b';') | Some(b'{') | Some(b'}') | Some(b',') | Some(b'(') | Some(b'[') => false,
+                    Some(b']') => !closed_attribute,
+                    Some(_) => true,
+                };
+                if continues { base + 1 } else { base }
+            }
+        };
+
+        if index + 1 == line {
+            return Ok(level);
+        }
+
+        for i in start..end {
+            if kinds[i] != Kind::Code || (bytes[i] as char).is_whitespace() {
+                continue;
+            }
+            let c = bytes[i];
+            closed_attribute = false;
+            match c {
+                b'{' | b'(' | b'[' => {
+                    let is_attribute = c == b'[' &&
+                                       (last == Some(b'#') || (last == Some(b'!') && before_last == Some(b'#')));
+                    if is_attribute && attribute_depth.is_none() {
+                        attribute_depth = Some(stack.len());
+                    }
+                    stack.push(OpenDelimiter { delimiter: c, level: level });
+                }
+                b'}' | b')' | b']' => {
+                    match stack.pop() {
+                        Some(ref open) if closes(open.delimiter, c) => {}
+                        Some(open) => {
+                            return Err(format!("mismatched `{}` at line {}, `{}` was expected",
+                                               c as char,
+                                               index + 1,
+                                               match open.delimiter {
+                                                   b'{' => '}',
+                                                   b'(' => ')',
+                                                   _ => ']',
+                                               }))
+                        }
+                        None => return Err(format!("unexpected `{}` at line {}", c as char, index + 1)),
+                    }
+                    if attribute_depth == Some(stack.len()) {
+                        attribute_depth = None;
+                        closed_attribute = true;
+                    }
+                }
+                _ => {}
+            }
+            before_last = last;
+            last = Some(c);
+        }
+    }
+    // the line is in range so the loop always reaches it
+    Err(format!("line {} was not reached", line))
+}
+
+pub fn rustfmt_indent(buffer: String, cfg_path: Option<String>, line: usize) -> i32 {
+    let config = try_config!(try_load_config(cfg_path), e => -1);
+    match indent_level(&buffer, line) {
+        Ok(level) => (level * config.tab_spaces()) as i32,
+        Err(reason) => {
+            error!("Can not compute indentation: {}", reason);
+            -1
+        }
+    }
+}
+
+/// The whitespace that the line should start with, a tab per level when
+/// `hard_tabs` is on and `tab_spaces` spaces per level otherwise
+fn indent_string(code: &str, line: usize, config: &Config) -> Result<String, String> {
+    let level = try!(indent_level(code, line));
+    if config.hard_tabs() {
+        Ok("\t".repeat(level))
+    } else {
+        Ok(" ".repeat(level * config.tab_spaces()))
+    }
+}
+
+/// Return the whitespace that the given line (1-based) should start with
+/// under the project configuration
+pub fn rustfmt_indent_string(buffer: String, cfg_path: Option<String>, line: usize) -> Result<String, String> {
+    let config = try!(try_load_config(cfg_path).map_err(|e| e.to_string()));
+    indent_string(&buffer, line, &config)
+}
+
+// FFI related
+
+#[cfg(feature = "ffi")]
+/// This function converts a C char * string into a safe Rust String
+/// It assures that the c_str is not null using assert! macro so you
+/// must be certain that yo never pass null strings to any of the
+/// exported functions
+fn c_str_to_safe_string(c_str: *const libc::c_char) -> String {
+    unsafe {
+        assert!(!c_str.is_null());
+        CStr::from_ptr(c_str).to_string_lossy().into_owned()
+    }
+}
+
+// size limit used until the host sets its own with `set_max_input_bytes`,
+// formatting a huge generated file can block the editor for minutes
+const DEFAULT_MAX_INPUT_BYTES: usize = 4 * 1024 * 1024;
+
+// maximum size in bytes of the buffers that we accept to format plus one, so
+// the initial 0 means the default limit and 1 means that there is no limit
+static MAX_INPUT_BYTES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// lines longer than this make rustfmt so slow that it looks hung
+const DEFAULT_MAX_LINE_BYTES: usize = 10 * 1024;
+
+// maximum length in bytes of the lines that we format plus one, like above
+static MAX_LINE_BYTES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Returns the value of a limit stored plus one, 0 means that there is no limit
+fn limit_value(limit: &AtomicUsize, default: usize) -> usize {
+    match limit.load(Ordering::Relaxed) {
+        0 => default,
+        n => n - 1,
+    }
+}
+
+/// Check a buffer size against the configured limit, the error has the size
+/// and the limit when it is too large
+fn size_limit_error(len: usize) -> Option<String> {
+    check_size(len, limit_value(&MAX_INPUT_BYTES, DEFAULT_MAX_INPUT_BYTES))
+}
+
+fn check_size(len: usize, limit: usize) -> Option<String> {
+    if limit == 0 || len <= limit {
+        return None;
+    }
+    Some(format!("input too large: {} bytes, limit {} bytes", len, limit))
+}
+
+/// Same as `size_limit_error` for the functions that only return a status,
+/// the error is logged
+fn too_large(len: usize) -> bool {
+    match size_limit_error(len) {
+        Some(reason) => {
+            warn!("{}", reason);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(feature = "ffi")]
+/// Check the length of the C string against the configured limit, this
+/// doesn't copy the string so it is cheap even for huge buffers
+fn input_size_error(c_str: *const libc::c_char) -> Option<String> {
+    unsafe {
+        assert!(!c_str.is_null());
+        size_limit_error(CStr::from_ptr(c_str).to_bytes().len())
+    }
+}
+
+#[cfg(feature = "ffi")]
+/// `input_size_error` for the functions that only return a status, the error
+/// is logged
+fn input_too_large(c_str: *const libc::c_char) -> bool {
+    unsafe {
+        assert!(!c_str.is_null());
+        too_large(CStr::from_ptr(c_str).to_bytes().len())
+    }
+}
+
+#[cfg(feature = "ffi")]
+/// Copy an array of `len` C strings into a vector, None when the array or any
+/// of its strings is NULL or the length can't be a real array length (e.g. a
+/// negative number passed as `size_t`). The array must be valid for `len`
+/// elements, there is no way to check that
+fn c_str_array(array: *const *const c_char, len: size_t) -> Option<Vec<String>> {
+    let len = len as usize;
+    if len == 0 {
+        return Some(Vec::new());
+    }
+    if array.is_null() || len > isize::max_value() as usize / std::mem::size_of::<*const c_char>() {
+        return None;
+    }
+    let items = unsafe { std::slice::from_raw_parts(array, len) };
+    if items.iter().any(|item| item.is_null()) {
+        return None;
+    }
+    Some(items.iter().map(|&item| c_str_to_safe_string(item)).collect())
+}
+
+#[cfg(feature = "ffi")]
+/// Converts a Rust String into a C char * and returns a pointer
+/// to it's inner memory
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself, there is
+/// a convenience exported function to do that, you can just
+/// call `free_c_char_mem` with the C string as parameter to
+/// free the allocated memory from your C compatible code
+fn to_c_str(s: String) -> *mut c_char {
+    // the bytes are moved into the C string, a NUL inside the text would end
+    // it early so those are dropped instead of panicking
+    let mut bytes = s.into_bytes();
+    bytes.retain(|&byte| byte != 0);
+    unsafe { CString::from_vec_unchecked(bytes).into_raw() }
+}
+
+#[cfg(feature = "ffi")]
+/// Converts a list of Rust Strings into an array of C char * and returns a
+/// pointer to its first element, NULL for empty lists
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself calling
+/// `free_c_char_array` with the array and its length
+fn to_c_str_array(items: Vec<String>) -> *mut *mut c_char {
+    if items.is_empty() {
+        return std::ptr::null_mut();
+    }
+    let array: Box<[*mut c_char]> = items.into_iter().map(to_c_str).collect::<Vec<_>>().into_boxed_slice();
+    Box::into_raw(array) as *mut *mut c_char
+}
+
+// version of the exported C interface, bumped whenever an exported function
+// changes its signature or its meaning
+const ABI_VERSION: u32 = 1;
+
+// features of the library that hosts may want to check before using them
+const CAPABILITIES: &'static [&'static str] = &["format",
+                                                "format_string",
+                                                "fragments",
+                                                "macro_bodies",
+                                                "imports",
+                                                "doc_comments",
+                                                "preserve_comments",
+                                                "low_churn",
+                                                "cancel",
+                                                "alignment",
+                                                "whitespace_cleanup",
+                                                "normalize_whitespace",
+                                                "diff",
+                                                "checkstyle",
+                                                "cursor",
+                                                "lines",
+                                                "bytes",
+                                                "validate_syntax",
+                                                "items",
+                                                "json_config",
+                                                "outcome",
+                                                "cargo_project",
+                                                "follow_mods",
+                                                "format_dir",
+                                                "check_project",
+                                                "check_staged",
+                                                "todos",
+                                                "enforce_width",
+                                                "stable_n",
+                                                "all_configs",
+                                                "complete",
+                                                "definitions",
+                                                "documentation",
+                                                "daemon",
+                                                "msgpack",
+                                                "lsp",
+                                                "watch"];
+
+/// Returns the version, ABI version, rustfmt version, channel and backend and
+/// the capabilities of the library as a JSON object
+pub fn library_info() -> String {
+    let mut capabilities: Vec<&str> = CAPABILITIES.to_vec();
+    if cfg!(debug_assertions) {
+        capabilities.push("annotate");
+    }
+    if cfg!(all(unix, feature = "ffi")) {
+        capabilities.push("socket_server");
+    }
+    json!({
+        "version": option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"),
+        "abi": ABI_VERSION,
+        "rustfmt": option_env!("ANACONDA_RUSTFMT_VERSION").unwrap_or("unknown"),
+        "channel": channel(),
+        "backend": backend::NAME,
+        "build": build_info(),
+        "capabilities": capabilities,
+    })
+        .to_string()
+}
+
+/// Returns what the library was built from and with, the values that the
+/// build script could not find are `unknown`
+pub fn build_info() -> serde_json::Value {
+    let unknown = |value: Option<&'static str>| value.unwrap_or("unknown");
+    json!({
+        "version": unknown(option_env!("CARGO_PKG_VERSION")),
+        "rustfmt": unknown(option_env!("ANACONDA_RUSTFMT_VERSION")),
+        "backend": backend::NAME,
+        "rustc": unknown(option_env!("ANACONDA_RUSTC_VERSION")),
+        "channel": channel(),
+        "target": unknown(option_env!("ANACONDA_TARGET")),
+        "profile": unknown(option_env!("ANACONDA_PROFILE")),
+        "git_commit": unknown(option_env!("ANACONDA_GIT_COMMIT")),
+    })
+}
+
+/// The channel of the compiler that built rustfmt, `stable` when the build
+/// script could not tell
+fn channel() -> &'static str {
+    channel_of(option_env!("ANACONDA_RUSTC_CHANNEL"))
+}
+
+fn channel_of(captured: Option<&str>) -> &'static str {
+    match captured {
+        Some("nightly") => "nightly",
+        Some("beta") => "beta",
+        _ => "stable",
+    }
+}
+
+/// Return the channel of the linked rustfmt: `stable`, `beta` or `nightly`.
+/// rustfmt is built by the same compiler as the library, the channel is the
+/// one of that compiler (captured at build time). Hosts use it to enable the
+/// options that only nightly builds support
+///
+/// NOTE: You should free the allocated string memory after is not need anymore
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn rustfmt_channel() -> *mut c_char {
+    to_c_str(String::from(channel()))
+}
+
+/// Return a JSON object describing the build for bug reports: the crate
+/// `version`, the bundled `rustfmt` version and the `backend` crate that
+/// provides it (`rustfmt` or `rustfmt-nightly`), the `rustc` version and the
+/// `channel` it was built with, the `target` triple, the build `profile` and
+/// the `git_commit` of the checkout. Values that were not available at build
+/// time are `unknown`. `library_info_json` and the daemon `build_info` method
+/// include the same object.
+///
+/// NOTE: You should free the allocated string memory after is not need anymore
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn get_build_info() -> *mut c_char {
+    to_c_str(build_info().to_string())
+}
+
+/// Hash of everything built into the library that changes the formatted
+/// output: the crate version, the backend, the version and source of rustfmt
+/// and the default configuration and line limit
+pub fn fingerprint() -> String {
+    let inputs = format!("{}\n{}\n{}\n{}\n{}\nmax_line_bytes = {}\n",
+                         option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"),
+                         backend::NAME,
+                         option_env!("ANACONDA_RUSTFMT_VERSION").unwrap_or("unknown"),
+                         option_env!("ANACONDA_RUSTFMT_SOURCE").unwrap_or(""),
+                         serialize_config(&Config::default()),
+                         DEFAULT_MAX_LINE_BYTES);
+    format!("{:016x}", fnv1a(inputs.as_bytes()))
+}
+
+/// Return the fingerprint of the formatting behavior of the library, 16 hex
+/// digits. Hosts key their caches of formatting results (formatted state
+/// indicators, check results...) on it and drop them when it changes.
+///
+/// Two builds from the same tree with the same rustfmt (version and source)
+/// always have the same fingerprint, whatever the compiler, target or
+/// profile. It changes when the crate version, the backend, the rustfmt
+/// dependency or the default configuration changes, so every release of the
+/// library changes it. It doesn't cover the settings of the host (limits,
+/// fallback config...) nor the project configs.
+///
+/// NOTE: You should free the allocated string memory after is not need anymore
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn get_fingerprint() -> *mut c_char {
+    to_c_str(fingerprint())
+}
+
+/// Return this crate version as a C string
+///
+/// NOTE: You should free the allocated string memory after is not need anymore
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn get_version() -> *mut c_char {
+    to_c_str(String::from(option_env!("CARGO_PKG_VERSION").unwrap_or("unknown")))
+}
+
+/// The major, minor and patch numbers of the crate version and whether it is
+/// a pre-release (e.g. `0.10.0-beta.1`)
+fn version_triple() -> (u64, u64, u64, bool) {
+    let number = |value: Option<&str>| value.and_then(|value| value.parse().ok()).unwrap_or(0);
+    (number(option_env!("CARGO_PKG_VERSION_MAJOR")),
+     number(option_env!("CARGO_PKG_VERSION_MINOR")),
+     number(option_env!("CARGO_PKG_VERSION_PATCH")),
+     option_env!("CARGO_PKG_VERSION_PRE").map_or(false, |pre| !pre.is_empty()))
+}
+
+/// Whether the library satisfies a host that needs the version given or a
+/// later one with the same major number. A pre-release comes before its
+/// version, `0.10.0-beta` doesn't satisfy `0.10.0` but it does `0.9.5`
+pub fn is_compatible(major: u64, minor: u64, patch: u64) -> bool {
+    satisfies(version_triple(), (major, minor, patch))
+}
+
+/// Whether the version (major, minor, patch, pre-release) satisfies the
+/// needed one like `is_compatible` says
+fn satisfies(version: (u64, u64, u64, bool), needed: (u64, u64, u64)) -> bool {
+    let (our_major, our_minor, our_patch, pre_release) = version;
+    let (major, minor, patch) = needed;
+    if our_major != major {
+        return false;
+    }
+    match (our_minor, our_patch).cmp(&(minor, patch)) {
+        std::cmp::Ordering::Greater => true,
+        std::cmp::Ordering::Equal => !pre_release,
+        std::cmp::Ordering::Less => false,
+    }
+}
+
+/// Return 1 when the library can be used by a host that needs the version
+/// `major.minor.patch` or a later one with the same major number, 0 otherwise
+/// (and for negative numbers). Hosts should call it instead of comparing the
+/// strings of `get_version`, `0.10` is later than `0.9`. A pre-release comes
+/// before the release with the same numbers
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn anaconda_is_compatible(major: c_int, minor: c_int, patch: c_int) -> c_int {
+    if major < 0 || minor < 0 || patch < 0 {
+        return 0;
+    }
+    is_compatible(major as u64, minor as u64, patch as u64) as c_int
+}
+
+/// Set the major, minor and patch numbers of the crate version, the
+/// pre-release suffix (if any) is left out. Returns 0 or 4 when any of the
+/// pointers is NULL
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn get_version_triple(major: *mut c_int, minor: *mut c_int, patch: *mut c_int) -> c_int {
+    if major.is_null() || minor.is_null() || patch.is_null() {
+        return INVALID_ARGUMENT;
+    }
+    let (our_major, our_minor, our_patch, _) = version_triple();
+    unsafe {
+        *major = our_major as c_int;
+        *minor = our_minor as c_int;
+        *patch = our_patch as c_int;
+    }
+    0
+}
+
+/// Set the maximum size (in bytes) of the buffers that the format functions
+/// accept, bigger buffers are not formatted and the status code 5 is returned
+/// instead and the size and the limit are logged. The
+/// functions that return a string return `error\tinput too large: N bytes,
+/// limit M bytes` and the project, directory and watch runs skip bigger files
+/// with a diagnostic. The default limit is 4 MiB, a value of 0 disables the
+/// limit
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn set_max_input_bytes(n: size_t) {
+    MAX_INPUT_BYTES.store((n as usize).saturating_add(1), Ordering::Relaxed);
+}
+
+/// Set the maximum length (in bytes) of the lines that the format functions
+/// format, top level items with longer lines (e.g. minified or generated code)
+/// are left untouched and logged while the rest of
+/// the buffer is formatted. The default limit is 10 KiB, 0 disables the limit
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn set_max_line_bytes(n: size_t) {
+    MAX_LINE_BYTES.store((n as usize).saturating_add(1), Ordering::Relaxed);
+    format_cache::clear();
+}
+
+/// Set for how long (in milliseconds) the location of the `rustfmt.toml` that
+/// applies to a directory is remembered, 5000 by default. Entries are dropped
+/// earlier if any directory between the start and the config file changes, a
+/// value of 0 disables the cache for every caller, `format_uncached` skips it
+/// for a single call
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn set_config_cache_ttl(ms: size_t) {
+    config_cache::set_ttl(ms as usize);
+}
+
+/// Set for how long (in milliseconds) a format call that overwrites a file
+/// waits for the other writes of the same file (other threads, the watcher or
+/// other processes) to end, 2000 by default. Then the file is reported busy,
+/// with the status 13 or a `busy` file status in the JSON reports. A value of
+/// 0 gives up right away
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn set_lock_timeout(ms: size_t) {
+    lock::set_timeout(ms as usize);
+}
+
+/// Set how many threads at most format the files of `format_cargo_project`,
+/// `format_dir`, `check_project` and `check_staged`, one per CPU by default.
+/// Hosts that share the machine with other work set it when they load the
+/// library, 0 goes back to one per CPU
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn set_max_threads(n: size_t) {
+    pool::set_max_threads(n as usize);
+}
+
+/// Set the config file used when the project has no `rustfmt.toml` (e.g. a user
+/// level default style), a NULL path goes back to the rustfmt defaults
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn set_fallback_config_path(path: *const c_char) {
+    let path = if path.is_null() { None } else { Some(PathBuf::from(c_str_to_safe_string(path))) };
+    if let Ok(mut fallback) = FALLBACK_CONFIG.lock() {
+        *fallback = path;
+    }
+}
+
+/// Restore every global setting of the library to its default value, hosts
+/// that reuse the library across unrelated projects (and tests) can call it to
+/// start from a clean slate. Those are the input and line limits, the lock
+/// timeout, the threads cap, the fallback config, the config lookup cache and the record of
+/// formatted buffers (both are emptied too), the requests in flight of the
+/// daemon sessions, the log level and sink and the JSON config warnings of the
+/// calling thread
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn reset_state() {
+    MAX_INPUT_BYTES.store(0, Ordering::Relaxed);
+    MAX_LINE_BYTES.store(0, Ordering::Relaxed);
+    set_fallback_config_path(std::ptr::null());
+    config_cache::reset();
+    format_cache::clear();
+    lock::reset();
+    pool::reset();
+    daemon::set_max_in_flight(0);
+    logging::reset();
+    JSON_CONFIG_WARNINGS.with(|last| last.borrow_mut().clear());
+    WIDTH_VIOLATIONS.with(|last| last.borrow_mut().clear());
+    STABLE_PASSES.with(|last| last.set(0));
+}
+
+/// Set the level of the log records sent to the host: 0 off (the default), 1
+/// error, 2 warn, 3 info, 4 debug and 5 trace. The records are printed to the
+/// standard error unless a callback or a file is set. Returns 4 for unknown
+/// levels and 1 when the host process has installed another logger
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn anaconda_set_log_level(level: c_int) -> c_int {
+    match logging::level_from_c_int(level) {
+        Some(level) if logging::set_level(level) => 0,
+        Some(_) => 1,
+        None => INVALID_ARGUMENT,
+    }
+}
+
+/// Send the log records to the callback instead of the standard error, NULL
+/// goes back to the standard error. The callback gets the level and the
+/// message, it is called from whatever thread logs the record
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn anaconda_set_log_callback(callback: Option<logging::Callback>) {
+    logging::set_callback(callback);
+}
+
+/// Append the log records to the file instead of printing them to the
+/// standard error, NULL goes back to the standard error. Returns 1 when the
+/// file can't be opened
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn anaconda_set_log_file(path: *const c_char) -> c_int {
+    let path = if path.is_null() { None } else { Some(PathBuf::from(c_str_to_safe_string(path))) };
+    match logging::set_file(path.as_ref().map(|path| path.as_path())) {
+        Ok(_) => 0,
+        Err(e) => {
+            let _ = writeln!(std::io::stderr(), "Can not open the log file: {}", e);
+            1
+        }
+    }
+}
+
+/// This function can be used to free memory allocated by Rust
+///
+/// You can also free the memory in your C compatible app calling
+/// the stdlib free function for example
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn free_c_char_mem(c: *mut c_char) {
+    unsafe {
+        if c.is_null() { return }
+        CString::from_raw(c)
+    };
+}
+
+/// Return a JSON object describing the library so hosts can log it on
+/// startup: `{"version": ..., "abi": ..., "rustfmt": ..., "channel": ...,
+/// "backend": ..., "build": {...}, "capabilities": [...]}` where `abi` is the
+/// version number of the exported interface, `rustfmt` the version of the
+/// bundled rustfmt (`unknown` when the build couldn't tell), `channel` the one
+/// `rustfmt_channel` returns, `backend` the rustfmt crate compiled in and
+/// `build` the one of `get_build_info`.
+/// `annotate` is only a capability of debug builds.
+///
+/// NOTE: You should free the allocated string memory after is not need anymore
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn library_info_json() -> *mut c_char {
+    to_c_str(library_info())
+}
+
+/// This function frees an array of C strings allocated by Rust (like the
+/// lines of `format_lines`), `count` must be the length that Rust reported
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn free_c_char_array(array: *mut *mut c_char, count: c_int) {
+    if array.is_null() || count <= 0 {
+        return;
+    }
+    let array = unsafe { Box::from_raw(std::slice::from_raw_parts_mut(array, count as usize)) };
+    for &item in array.iter() {
+        free_c_char_mem(item);
+    }
+}
+
+/// This function frees a byte buffer allocated by Rust (like the output of
+/// `format_to_bytes`), `len` must be the length that Rust reported
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn free_bytes(ptr: *mut uint8_t, len: size_t) {
+    if ptr.is_null() || len == 0 {
+        return;
+    }
+    unsafe { Box::from_raw(std::slice::from_raw_parts_mut(ptr, len)) };
+}
+
+/// Format the passed buffer using librustfmt and return back an operation
+/// status code, librustfmt uses the standard output to print the formating
+/// results so you should capture it in you C level code.
+///
+/// The line endings of the buffer (its dominant style if they are mixed) are
+/// preserved unless the project configuration sets a Windows or Native
+/// `newline_style`, line endings inside string literals are never modified.
+///
+/// A UTF-8 BOM at the start of the buffer is kept in the output, BOMs in any
+/// other place are left alone, both cases are logged.
+///
+/// The output is printed in a single write once formatting is complete, when
+/// the buffer does not parse (or any other error stops rustfmt) nothing at all
+/// is printed so the buffer is never replaced with a partial result.
+///
+/// Top level items with lines longer than `set_max_line_bytes` are printed
+/// back untouched and logged.
+///
+/// When the buffer is the last output formatted for the same path and the
+/// configuration didn't change since then rustfmt is not run at all, the
+/// buffer is printed back and the skip is logged.
+///
+/// No memory need to be freed after use this function as it is automatically
+/// handled by Rust itself
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format(code: *const c_char, path: *const c_char) ->  c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    unsafe {
+        assert!(!path.is_null());
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        format_c_str(CStr::from_ptr(code), CStr::from_ptr(path), &mut stdout)
+    }
+}
+
+/// Same as `format` but the `rustfmt.toml` that applies to the path is looked
+/// up again instead of taken from the lookup cache (see
+/// `set_config_cache_ttl`), for the calls that can't accept a stale result.
+/// The cache is neither read nor filled, the other calls keep using it
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_uncached(code: *const c_char, path: *const c_char) -> c_int {
+    config_cache::bypass(|| format(code, path))
+}
+
+#[cfg(feature = "ffi")]
+/// `format` for the borrowed C strings, the buffer is copied once into the
+/// String that rustfmt takes and the path once into the key of the formatted
+/// outputs cache, the output is written to `out`
+fn format_c_str<W: Write>(code: &CStr, path: &CStr, out: &mut W) -> c_int {
+    rustfmt_to(code.to_string_lossy().into_owned(), Some(path.to_string_lossy().into_owned()), out)
+}
+
+/// Look for code completions using libracer and return back a string with
 /// a result per line with fields separated by tabs (\t)
 ///
 /// WARNING: this function forgets about the allocated memory so
 /// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn complete(code: *const c_char, path: *const c_char, line: uint32_t, col: uint32_t) -> *mut c_char {
+    to_c_str(racer_complete(c_str_to_safe_string(code), c_str_to_safe_string(path), line as usize, col as usize))
+}
+
+/// Look for definitions of the word under the cursor using libracer and
+/// return a string with a result per line with fields separated by tabs
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn definitions(code: *const c_char, path: *const c_char, line: uint32_t, col: uint32_t) -> *mut c_char {
+    to_c_str(find_definition(c_str_to_safe_string(code), c_str_to_safe_string(path), line as usize, col as usize))
+}
+
+/// Look for documentation about the word under the cursor using libracer
+/// and return a string with a result per line with fields separated by tabs
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn documentation(code: *const c_char, path: *const c_char, line: uint32_t, col: uint32_t) -> *mut c_char {
+    to_c_str(get_documentation(c_str_to_safe_string(code), c_str_to_safe_string(path), line as usize, col as usize))
+}
+
+/// Format the passed buffer like `format` does but forcing the newline style
+/// of the output regardless of what the project configuration says.
+///
+/// Valid styles are 0 (Auto, keep the dominant style of the buffer), 1 (Unix),
+/// 2 (Windows) and 3 (Native), any other value returns 4 without formatting
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_newline_style(code: *const c_char, path: *const c_char, style: c_int) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let mode = match NewlineMode::from_c_int(style) {
+        Some(mode) => mode,
+        None => return INVALID_ARGUMENT,
+    };
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_newline_style(c_str_to_safe_string(code), config_path, mode)
+}
+
+/// Format the passed buffer with the given brace styles overriding the ones
+/// of the project configuration and return the result. `item_style` sets
+/// both `fn_brace_style` and `item_brace_style`: 0 (AlwaysNextLine), 1
+/// (PreferSameLine) or 2 (SameLineWhere). `control_style` sets
+/// `control_brace_style`: 0 (AlwaysSameLine) or 1 (AlwaysNextLine).
+///
+/// When a style is not valid or the buffer can not be formatted the returned
+/// string starts with `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_brace_style(code: *const c_char,
+                                      path: *const c_char,
+                                      item_style: c_int,
+                                      control_style: c_int)
+                                      -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let item_style = match brace_style_from_c_int(item_style) {
+        Some(style) => style,
+        None => return to_c_str(format!("error\tunknown brace style {}", item_style)),
+    };
+    let control_style = match control_brace_style_from_c_int(control_style) {
+        Some(style) => style,
+        None => return to_c_str(format!("error\tunknown control brace style {}", control_style)),
+    };
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    to_c_str(rustfmt_brace_style(c_str_to_safe_string(code), config_path, item_style, control_style))
+}
+
+/// Format the passed buffer leaving its comments untouched and return the
+/// result, `normalize_comments` and `wrap_comments` are disabled whatever the
+/// project configuration says and the text of every comment is kept byte for
+/// byte (e.g. hand drawn diagrams in doc comments) while the code around them
+/// is formatted.
+///
+/// When the buffer can not be formatted the returned string starts with
+/// `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_preserve_comments(code: *const c_char, path: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    to_c_str(rustfmt_preserve_comments(c_str_to_safe_string(code), config_path))
+}
+
+/// Format the passed buffer aligning the fields of structs (and the variants
+/// of enums) whose names differ in length by up to `field_threshold` (and
+/// `enum_threshold`) characters, 0 disables the alignment and a negative value
+/// keeps the one of the project configuration. These are the unstable
+/// `struct_field_align_threshold` and `enum_variant_align_threshold` options.
+///
+/// When the buffer can not be formatted the returned string starts with
+/// `error\t` followed by the reason, `error\tunstable option unavailable`
+/// when the bundled rustfmt doesn't know one of the options.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_alignment(code: *const c_char,
+                                    path: *const c_char,
+                                    field_threshold: c_int,
+                                    enum_threshold: c_int)
+                                    -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let threshold = |value: c_int| if value < 0 { None } else { Some(value as usize) };
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    to_c_str(rustfmt_with_alignment(c_str_to_safe_string(code),
+                                    config_path,
+                                    threshold(field_threshold),
+                                    threshold(enum_threshold)))
+}
+
+/// Format the passed buffer with a preset that only fixes clear violations
+/// and leaves the borderline choices of the code as they are, to keep the
+/// diffs small. On top of the project configuration it sets:
+///
+/// * `reorder_imports = false` and `reorder_imported_names = false`, imports
+///   and the names inside them keep their order
+/// * `format_strings = false`, string literals are not broken
+/// * `wrap_comments = false` and `normalize_comments = false`, comments are
+///   not rewrapped nor turned from `/* */` into `//`
+/// * `wrap_match_arms = false`, match arms are not wrapped in blocks
+/// * `take_source_hints = true`, some of the layout of the source is kept
+///
+/// This rustfmt has no `reorder_modules` option, `mod` declarations are never
+/// reordered. When the buffer can not be formatted the returned string starts
+/// with `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_low_churn(code: *const c_char, path: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    to_c_str(rustfmt_low_churn(c_str_to_safe_string(code), config_path))
+}
+
+/// Format the passed buffer like `format_string` does but give up as soon as
+/// the token is cancelled (from any thread), NULL is returned in that case and
+/// only in that case. rustfmt can't be interrupted, the token is checked
+/// before the run and while waiting for it, a cancelled run ends on its own in
+/// the background. A NULL token is never cancelled. When the buffer can not
+/// be formatted the returned string starts with `error\t` followed by the
+/// reason.
+///
+/// The token must outlive the call, free it with `cancel_token_free` after
+/// the call returns.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_cancelable(code: *const c_char, path: *const c_char, token: *mut CancelToken) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let never = CancelToken::new();
+    let token = if token.is_null() { &never } else { unsafe { &*token } };
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    match rustfmt_cancelable(c_str_to_safe_string(code), config_path, token) {
+        Some(output) => to_c_str(output),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Return a new token for `format_cancelable`
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself calling
+/// `cancel_token_free`
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn cancel_token_new() -> *mut CancelToken {
+    Box::into_raw(Box::new(CancelToken::new()))
+}
+
+/// Cancel the calls using the token, they return as soon as they check it.
+/// It can be called from any thread and more than once
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn cancel_token_cancel(token: *mut CancelToken) {
+    if !token.is_null() {
+        unsafe { (*token).cancel() };
+    }
+}
+
+/// This function frees a token returned by `cancel_token_new`, no call can be
+/// using it anymore
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn cancel_token_free(token: *mut CancelToken) {
+    if !token.is_null() {
+        unsafe { Box::from_raw(token) };
+    }
+}
+
+/// Return the column where the given line (1-based) should start under the
+/// project configuration, the result is always expressed in columns so when
+/// `hard_tabs` is on every `tab_spaces` columns should be materialized as a
+/// single tab by the caller. `indent_string_for_line` does that already.
+///
+/// If the buffer is too broken to give an answer -1 is returned and the
+/// reason is logged
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn indent_for_line(code: *const c_char, path: *const c_char, line: uint32_t) -> c_int {
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_indent(c_str_to_safe_string(code), config_path, line as usize)
+}
+
+/// Return the indentation that the given line (1-based) should start with
+/// under the project configuration, tabs when `hard_tabs` is on and spaces
+/// otherwise. When the buffer is too broken to give an answer the returned
+/// string starts with `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn indent_string_for_line(code: *const c_char, path: *const c_char, line: uint32_t) -> *mut c_char {
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    match rustfmt_indent_string(c_str_to_safe_string(code), config_path, line as usize) {
+        Ok(indent) => to_c_str(indent),
+        Err(reason) => to_c_str(format!("error\t{}", reason)),
+    }
+}
+
+/// Return an hex encoded hash of the effective configuration that applies to
+/// the given path, two paths with the same effective configuration give the
+/// same fingerprint even if their config files are written differently
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn config_fingerprint(path: *const c_char) -> *mut c_char {
+    to_c_str(config_fingerprint_for(Some(c_str_to_safe_string(path))))
+}
+
+/// Describe how the configuration that applies to the given path is resolved
+/// to troubleshoot symlinked projects: the directories visited by every
+/// lookup in order, whether the config was found in them, the config file
+/// that is used and its canonical path, see `resolve_config_trace`.
+///
+/// `out_json` is required, the status is 4 without it. Returns 0 or 1 when
+/// the lookup fails, then the object has an `error` with the reason.
+///
+/// WARNING: the JSON memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn resolve_config_verbose(path: *const c_char, out_json: *mut *mut c_char) -> c_int {
+    if out_json.is_null() {
+        return INVALID_ARGUMENT;
+    }
+    let (status, trace) = resolve_config_trace(Some(c_str_to_safe_string(path)));
+    unsafe { *out_json = to_c_str(trace) };
+    status
+}
+
+/// Tell whether two configurations format the sample code the same way, the
+/// paths can be config files or the files and directories they apply to.
+///
+/// Returns 1 when both outputs are identical, 0 when they differ and -1 when
+/// a configuration can't be read (e.g. a `.toml` file that is not valid) or
+/// the sample can not be formatted (e.g. it does not parse), the reason is
+/// logged
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn configs_equivalent(path_a: *const c_char,
+                                 path_b: *const c_char,
+                                 sample_code: *const c_char)
+                                 -> c_int {
+    if input_too_large(sample_code) {
+        return -1;
+    }
+    match rustfmt_configs_equivalent(c_str_to_safe_string(path_a),
+                                     c_str_to_safe_string(path_b),
+                                     c_str_to_safe_string(sample_code)) {
+        Some(true) => 1,
+        Some(false) => 0,
+        None => -1,
+    }
+}
+
+/// Format the passed buffer like `format` does and then format the Rust code
+/// blocks found inside its doc comments (`///`, `//!` and `/** */`) too.
+///
+/// Code blocks that don't parse (e.g. `compile_fail` examples) are left
+/// untouched and logged
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_doc_comments(code: *const c_char, path: *const c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_with_docs(c_str_to_safe_string(code), config_path)
+}
+
+/// Format only the `use` and `extern crate` items of the passed buffer, every
+/// other byte of the buffer is printed back exactly as it was received.
+///
+/// If any run of imports does not parse nothing is printed and the returned
+/// status code reflects the error
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_imports(code: *const c_char, path: *const c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_imports(c_str_to_safe_string(code), config_path)
+}
+
+/// Format the passed buffer like `format` does but with explicit import
+/// options, so the order of the imports doesn't depend on the project
+/// configuration:
+///
+/// * `reorder` 0 keeps the imports (and the names inside `{}`) in the order
+///   they are written, any other value sorts them. rustfmt only sorts the
+///   imports of a run of consecutive `use` items, blank lines split runs
+/// * `group` 0 leaves the blank lines between imports alone, any other value
+///   puts every run of imports into groups separated by a blank line:
+///   `extern crate` items, `std`/`core`/`alloc` imports, imports of other
+///   crates and `self`/`super`/`crate` imports. Items keep their order inside
+///   their group
+///
+/// The same buffer and options always give the same output.
+///
+/// No memory need to be freed after use this function as it is automatically
+/// handled by Rust itself
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_import_options(code: *const c_char,
+                                         path: *const c_char,
+                                         reorder: c_int,
+                                         group: c_int)
+                                         -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_with_import_options(c_str_to_safe_string(code), config_path, reorder != 0, group != 0)
+}
+
+/// Strip trailing whitespace, normalize the indentation and line endings as
+/// the project configuration says (`hard_tabs`, `tab_spaces` and
+/// `newline_style`) and make sure that the buffer ends with a newline. The
+/// result is printed to the standard output like `format` does.
+///
+/// The code is never parsed so this works even in buffers with syntax errors,
+/// the contents of string literals are never modified
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn cleanup_whitespace(code: *const c_char, path: *const c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_cleanup(c_str_to_safe_string(code), config_path)
+}
+
+/// Strip trailing whitespace and make sure that the buffer ends with a newline
+/// and return the result, nothing else is changed (the indentation and the
+/// line endings are kept). The code is never parsed and no configuration is
+/// read, so this is the light touch for huge buffers or buffers with syntax
+/// errors. Whitespace inside string literals is left untouched.
+///
+/// When the buffer is too large the returned string starts with `error\t`
+/// followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn normalize_whitespace(code: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    to_c_str(rustfmt_normalize_whitespace(c_str_to_safe_string(code)))
+}
+
+/// Format a piece of code that is not a complete file and return the result,
+/// `fragment_kind` tells what the code is: 0 for an expression, 1 for a block
+/// (`{ ... }`), 2 for a series of statements and 3 when the kind is unknown,
+/// then the code is tried as a file, as statements and as items until one of
+/// them parses and the original indentation of the fragment is kept.
+///
+/// When the fragment can not be formatted the returned string starts with
+/// `error\t` followed by the reason, the same happens for unknown kinds.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_fragment(code: *const c_char, path: *const c_char, fragment_kind: c_int) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let kind = match FragmentKind::from_c_int(fragment_kind) {
+        Some(kind) => kind,
+        None => return to_c_str(format!("error\tunknown fragment kind {}", fragment_kind)),
+    };
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    to_c_str(rustfmt_fragment(c_str_to_safe_string(code), config_path, kind))
+}
+
+/// Format the body of a macro and return the result, the code is formatted as
+/// statements or as items (whatever parses) with its `$name` metavariables
+/// renamed while formatting, the original indentation is kept.
+///
+/// Bodies with unbalanced delimiters, unterminated literals or repetitions
+/// (`$(...)*`) are not touched, then the returned string starts with
+/// `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_macro_body(code: *const c_char, path: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    to_c_str(rustfmt_macro_body(c_str_to_safe_string(code), config_path))
+}
+
+/// Format the code and return the outcome as a JSON object: the status code,
+/// whether the code changed, the formatted output, the diagnostics, timings
+/// and edits, see `outcome::FormatOutcome` for the schema.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_outcome_json(code: *const c_char, path: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(FormatOutcome::new("", Err(FormatError::TooLarge(reason)), Timings::default()).to_json());
+    }
+    let options = FormatOptions { config_path: Some(c_str_to_safe_string(path)), ..FormatOptions::default() };
+    to_c_str(format_outcome(&c_str_to_safe_string(code), &options).to_json())
+}
+
+/// Format only the top level function, impl, struct (or any other named
+/// item) called `item_name` and return the whole buffer. Prefix the name with
+/// the kind of the item (e.g. `impl Foo`) when several items share it, impls
+/// are named after their type.
+///
+/// When the name is not found, it is ambiguous or the item can not be
+/// formatted the returned string starts with `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_item(code: *const c_char, path: *const c_char, item_name: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let name = c_str_to_safe_string(item_name);
+    to_c_str(rustfmt_item(c_str_to_safe_string(code), config_path, &name))
+}
+
+#[cfg(feature = "ffi")]
+thread_local! {
+    // warnings of the last `format_with_json_config` call of every thread
+    static JSON_CONFIG_WARNINGS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+/// Format the code with the rustfmt defaults and the options of a JSON object
+/// (e.g. `{"max_width": 80, "hard_tabs": true}`) and return the result, for
+/// hosts that don't want to write TOML. Unknown options and invalid values are
+/// ignored, `json_config_warnings` returns them.
+///
+/// When the JSON is not an object or the code can not be formatted the
+/// returned string starts with `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_json_config(code: *const c_char, config_json: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let (output, warnings) = rustfmt_json_config(c_str_to_safe_string(code), &c_str_to_safe_string(config_json));
+    JSON_CONFIG_WARNINGS.with(|last| *last.borrow_mut() = warnings);
+    to_c_str(output)
+}
+
+/// Return the warnings of the last `format_with_json_config` call made by the
+/// calling thread, one per line (an empty string when there were none)
+///
+/// NOTE: You should free the allocated string memory after is not need anymore
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn json_config_warnings() -> *mut c_char {
+    to_c_str(JSON_CONFIG_WARNINGS.with(|last| last.borrow().join("\n")))
+}
+
+/// Format a buffer that is not saved to disk and return the result, the
+/// configuration is resolved from `project_dir` as if the buffer was a file
+/// in that directory.
+///
+/// When the buffer can not be formatted the returned string starts with
+/// `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_virtual(code: *const c_char, project_dir: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    to_c_str(rustfmt_virtual(c_str_to_safe_string(code), c_str_to_safe_string(project_dir)))
+}
+
+/// Format the passed buffer and return the result with a `// rustfmt: ...`
+/// comment before every change naming the options that most likely caused it.
+/// This is a debugging aid for bug reports, release builds return the
+/// formatted code without annotations.
+///
+/// When the buffer can not be formatted the returned string starts with
+/// `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_annotated(code: *const c_char, path: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    to_c_str(rustfmt_annotated(c_str_to_safe_string(code), config_path))
+}
+
+/// Format the passed buffer like `format` does but looking up the files of the
+/// project in the given virtual files before the filesystem, so the unsaved
+/// views of the editor (e.g. a new `rustfmt.toml`) are taken into account.
+///
+/// `overlay_paths` and `overlay_contents` are arrays of `overlay_len` strings
+/// with the absolute path and the contents of every virtual file, the overlay
+/// only lives for this call. Both arrays must be valid for `overlay_len`
+/// elements, 4 is returned for NULL arrays or strings and for lengths that
+/// can't be right (e.g. a negative number converted to `size_t`)
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_overlay(code: *const c_char,
+                                  path: *const c_char,
+                                  overlay_paths: *const *const c_char,
+                                  overlay_contents: *const *const c_char,
+                                  overlay_len: size_t)
+                                  -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+
+    let overlay = match (c_str_array(overlay_paths, overlay_len), c_str_array(overlay_contents, overlay_len)) {
+        (Some(paths), Some(contents)) => paths.into_iter().map(PathBuf::from).zip(contents).collect(),
+        _ => return INVALID_ARGUMENT,
+    };
+
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_with_overlay(c_str_to_safe_string(code), config_path, overlay)
+}
+
+/// Format a buffer given as standard base64 of its UTF-8 bytes and return the
+/// output encoded the same way, this way no byte of the buffer can be mangled
+/// by the encoding layers of the host. Whitespace and missing padding in the
+/// input are tolerated.
+///
+/// When the buffer can not be formatted the returned string starts with
+/// `error\t` followed by the reason (never base64 encoded), invalid base64 is
+/// reported as `error\tinvalid base64: ...`.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_b64(code_b64: *const c_char, path: *const c_char) -> *mut c_char {
+    unsafe {
+        assert!(!code_b64.is_null());
+        if let Some(reason) = size_limit_error(CStr::from_ptr(code_b64).to_bytes().len() / 4 * 3) {
+            return to_c_str(format!("error\t{}", reason));
+        }
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    match rustfmt_b64(&c_str_to_safe_string(code_b64), config_path) {
+        Ok(output) => to_c_str(output),
+        Err(reason) => to_c_str(format!("error\t{}", reason)),
+    }
+}
+
+/// Format only the parts of the buffer touched by the given unified diff
+/// (e.g. the output of `git diff` for the file), the changed lines are
+/// expanded to the top level items that contain them and the whole buffer is
+/// printed to the standard output like `format` does.
+///
+/// If `report` is not null it is set to a string with the line ranges that
+/// were formatted (one `start\tend` per line), when the diff doesn't apply to
+/// the buffer (e.g. it is stale) nothing is printed, 4 is returned and the
+/// report starts with `error\t` followed by the reason.
+///
+/// WARNING: the report memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_diff(code: *const c_char,
+                          path: *const c_char,
+                          diff: *const c_char,
+                          report: *mut *mut c_char)
+                          -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, ranges) = rustfmt_diff(c_str_to_safe_string(code), config_path, c_str_to_safe_string(diff));
+    if !report.is_null() {
+        unsafe { *report = to_c_str(ranges) };
+    }
+    status
+}
+
+/// Format the lines of the file in `path` (as it is on disk) that changed
+/// relative to the git reference `ref_name` (e.g. `HEAD`) and print the whole
+/// file to the standard output like `format` does.
+///
+/// When git can not tell what changed (the file is untracked, it is not in a
+/// repository or git is not installed) the whole file is formatted and the
+/// reason is logged
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_changed(path: *const c_char, ref_name: *const c_char) -> c_int {
+    rustfmt_changed(c_str_to_safe_string(path), c_str_to_safe_string(ref_name))
+}
+
+/// Format the passed buffer and print the unified diff between it and the
+/// formatted code (nothing when it is already formatted), returning the same
+/// exit codes that the rustfmt command line tool uses in diff mode, so scripts
+/// can switch between them without changes:
+///
+///   0: no errors and the buffer was already formatted
+///   1: operational errors (e.g. IO errors or a buffer over the size limit)
+///   2: the buffer could not be parsed
+///   3: the code is valid but it is impossible to format it properly
+///   4: the formatted code differs from the given buffer
+///
+/// 4 means "invalid argument" for the other exported functions, this one never
+/// returns that status so the value is rustfmt's one. Any other error (e.g. a
+/// project config that can't be read) is reported as 1.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_cli_compat(code: *const c_char, path: *const c_char) -> c_int {
+    if input_too_large(code) {
+        return 1;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_cli_compat(c_str_to_safe_string(code), config_path)
+}
+
+/// Format the passed buffer and the current contents of the file in `path`
+/// using the same configuration and report which of them need formatting,
+/// nothing is printed to the standard output:
+///
+///   0: both the buffer and the file on disk are already formatted
+///   6: only the buffer needs formatting
+///   7: only the file on disk needs formatting
+///   8: both of them need formatting
+///   9: the file on disk is missing or can not be read
+///
+/// If any of them can not be formatted the usual error status (1, 2 or 3) is
+/// returned and the log tells which one failed
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn compare_with_disk(code: *const c_char, path: *const c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    rustfmt_compare_with_disk(c_str_to_safe_string(code), c_str_to_safe_string(path))
+}
+
+/// Format the passed buffer like `format` does but using the tab width and
+/// maximum line width that the editor has configured when the project doesn't
+/// have a `rustfmt.toml` file, if it has one the editor values are ignored.
+///
+/// Values lower than 1 are ignored and the rustfmt defaults are used instead
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_editor_defaults(code: *const c_char,
+                                          path: *const c_char,
+                                          tab_spaces: c_int,
+                                          max_width: c_int)
+                                          -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_with_editor_defaults(c_str_to_safe_string(code), config_path, tab_spaces, max_width)
+}
+
+/// Format the passed buffer like `format` does, if `keep_bom` is 0 the UTF-8
+/// BOM at the start of the buffer (if any) is dropped from the output
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_bom(code: *const c_char, path: *const c_char, keep_bom: c_int) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_with_bom(c_str_to_safe_string(code), config_path, keep_bom != 0)
+}
+
+/// Format the passed buffer like `format` does controlling the newline at the
+/// end of the output: 0 (Preserve, end the output like the buffer ends), 1
+/// (Always) and 2 (Never), any other value returns 4 without formatting
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_newline_at_eof(code: *const c_char, path: *const c_char, mode: c_int) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let mode = match EofNewline::from_c_int(mode) {
+        Some(mode) => mode,
+        None => return INVALID_ARGUMENT,
+    };
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_newline_at_eof(c_str_to_safe_string(code), config_path, mode)
+}
+
+/// Format the passed buffer like `format` does and store in `byte_delta` (if
+/// not NULL) the length of the output minus the length of the buffer in bytes,
+/// negative when formatting made the buffer shorter and 0 when it failed
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_byte_delta(code: *const c_char, path: *const c_char, byte_delta: *mut int32_t) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, delta) = rustfmt_byte_delta(c_str_to_safe_string(code), config_path);
+    if !byte_delta.is_null() {
+        let delta = if delta > i32::max_value() as i64 {
+            i32::max_value()
+        } else if delta < i32::min_value() as i64 {
+            i32::min_value()
+        } else {
+            delta as i32
+        };
+        unsafe { *byte_delta = delta };
+    }
+    status
+}
+
+/// Format the passed buffer like `format` does, if `out_hash` is not NULL it is
+/// set to a hex digest of the printed output so hosts can tell whether it is
+/// the same they already have without comparing the whole text. The digest is
+/// empty when nothing was printed.
+///
+/// WARNING: the hash memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_hashed(code: *const c_char, path: *const c_char, out_hash: *mut *mut c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, hash) = rustfmt_hashed(c_str_to_safe_string(code), config_path);
+    if !out_hash.is_null() {
+        unsafe { *out_hash = to_c_str(hash) };
+    }
+    status
+}
+
+/// Return a checkstyle XML report (the format of `rustfmt --write-mode
+/// checkstyle`) of the differences between the buffer and its formatted
+/// version, for CI dashboards. Every changed line is an `error` element of the
+/// `file` named after `path` with its line and a message telling what it
+/// should be, a formatted buffer gets a `file` element without errors.
+///
+/// When the buffer can not be formatted the returned string starts with
+/// `error\t` followed by the reason.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_checkstyle(code: *const c_char, path: *const c_char) -> *mut c_char {
+    if let Some(reason) = input_size_error(code) {
+        return to_c_str(format!("error\t{}", reason));
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    to_c_str(rustfmt_checkstyle(c_str_to_safe_string(code), config_path))
+}
+
+/// Format the passed buffer like `format` does, if `out_todos` is not NULL it
+/// is set to a JSON list with the `line` (1-based), `marker` and `text` of
+/// every TODO, FIXME and XXX comment of the printed output, or of the buffer
+/// when it could not be formatted, to fill the task list of the host. Markers
+/// in string literals are ignored.
+///
+/// WARNING: the JSON memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_todos(code: *const c_char, path: *const c_char, out_todos: *mut *mut c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, todos) = rustfmt_todos(c_str_to_safe_string(code), config_path);
+    if !out_todos.is_null() {
+        unsafe { *out_todos = to_c_str(todos) };
+    }
+    status
+}
+
+/// Format the passed buffer like `format` does but overriding the project
+/// `error_on_line_overflow` option, when `error_on_overflow` is 0 lines that
+/// can not be wrapped under `max_width` (e.g. a long string literal) don't
+/// make the function return 3, the code is formatted anyway
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_lenient_overflow(code: *const c_char, path: *const c_char, error_on_overflow: c_int) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    rustfmt_lenient_overflow(c_str_to_safe_string(code), config_path, error_on_overflow != 0)
+}
+
+#[cfg(feature = "ffi")]
+thread_local! {
+    // offending lines of the last `format_enforce_width` call of every thread
+    static WIDTH_VIOLATIONS: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
+
+/// Format the passed buffer like `format` does and check that no line of the
+/// output is wider than the effective `max_width`, for projects that enforce a
+/// hard limit where rustfmt can only try (e.g. long string literals). When
+/// some line is wider nothing is printed and 11 is returned,
+/// `width_violations` returns the offending lines of the output.
+///
+/// No memory need to be freed after use this function as it is automatically
+/// handled by Rust itself
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_enforce_width(code: *const c_char, path: *const c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, violations) = rustfmt_enforce_width(c_str_to_safe_string(code), config_path);
+    WIDTH_VIOLATIONS.with(|last| *last.borrow_mut() = violations);
+    status
+}
+
+/// Return the lines (1-based, one per line) wider than `max_width` found by
+/// the last `format_enforce_width` call made by the calling thread, an empty
+/// string when there were none
+///
+/// NOTE: You should free the allocated string memory after is not need anymore
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn width_violations() -> *mut c_char {
+    let lines = WIDTH_VIOLATIONS.with(|last| last.borrow().iter().map(|line| line.to_string()).collect::<Vec<_>>());
+    to_c_str(lines.join("\n"))
+}
+
+#[cfg(feature = "ffi")]
+thread_local! {
+    // passes made by the last `format_stable_n` call of every thread
+    static STABLE_PASSES: Cell<usize> = Cell::new(0);
+}
+
+/// Format the buffer and then reformat the output again and again, up to `n`
+/// passes, to check that formatting is idempotent. Returns 0 when some pass
+/// doesn't change its input (the first one when the buffer is already
+/// formatted), 12 when every pass changes it and the usual error status when
+/// a pass can not format its input, 4 when `n` is not positive. Nothing is
+/// printed, `stable_passes` returns the pass where the output stopped
+/// changing (or failed) to tell how deep a non-idempotent case goes.
+///
+/// No memory need to be freed after use this function as it is automatically
+/// handled by Rust itself
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_stable_n(code: *const c_char, path: *const c_char, n: c_int) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    if n <= 0 {
+        return INVALID_ARGUMENT;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, passes) = rustfmt_stable_n(c_str_to_safe_string(code), config_path, n as usize);
+    STABLE_PASSES.with(|last| last.set(passes));
+    status
+}
+
+/// Return the passes made by the last `format_stable_n` call of the calling
+/// thread, 0 before the first call
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn stable_passes() -> c_int {
+    STABLE_PASSES.with(|last| last.get()) as c_int
+}
+
+/// Format the passed buffer like `format` does but setting `out` to the result
+/// instead of printing it (an empty string when the buffer was not formatted)
+/// and `out_report` to the lines of the original buffer that had trailing
+/// whitespace or indentation mixing tabs and spaces, one `line\treason` per
+/// line. Any of both can be NULL if the caller is not interested in it.
+///
+/// WARNING: the memory of both strings is not freed by Rust so YOU MUST MAKE
+/// SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_whitespace_report(code: *const c_char,
+                                            path: *const c_char,
+                                            out: *mut *mut c_char,
+                                            out_report: *mut *mut c_char)
+                                            -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, output, report) = rustfmt_whitespace_report(c_str_to_safe_string(code), config_path);
+    if !out.is_null() {
+        unsafe { *out = to_c_str(output) };
+    }
+    if !out_report.is_null() {
+        unsafe { *out_report = to_c_str(report) };
+    }
+    status
+}
+
+/// Format the passed buffer like `format` does and set `out` (if not NULL) to
+/// the categories of the changes that formatting made, one per line: `imports
+/// reordered`, `whitespace normalized`, `lines wrapped`, `braces moved` and
+/// `code rewritten`. The categories are guessed comparing the buffer and the
+/// output, the string is empty if nothing changed or the buffer wasn't
+/// formatted.
+///
+/// WARNING: the memory of the summary is not freed by Rust so YOU MUST MAKE
+/// SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_with_change_summary(code: *const c_char, path: *const c_char, out: *mut *mut c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, changes) = rustfmt_change_summary(c_str_to_safe_string(code), config_path);
+    if !out.is_null() {
+        unsafe { *out = to_c_str(changes) };
+    }
+    status
+}
+
+/// Format the passed buffer with the configuration given by `argc` rustfmt
+/// style command line arguments in `argv` and print it to the standard
+/// output like `format` does. The known flags are:
+///
+/// * `--config key1=val1,key2=val2` overrides options (it can be repeated)
+/// * `--config-path PATH` resolves the configuration from PATH, the current
+///   directory is used when it is not given
+/// * `--edition 2015`, the only edition known to this rustfmt
+/// * `--write-mode plain`, the only write mode that makes sense here
+/// * `--help` logs the usage, nothing is formatted
+///
+/// Unknown flags, missing values, file arguments, unknown options and
+/// invalid values are logged and `4` is returned
+/// without formatting.
+///
+/// No memory need to be freed after use this function as it is automatically
+/// handled by Rust itself
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_args(code: *const c_char, argv: *const *const c_char, argc: c_int) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    if argc < 0 {
+        return INVALID_ARGUMENT;
+    }
+    match c_str_array(argv, argc as size_t) {
+        Some(args) => rustfmt_args(c_str_to_safe_string(code), args),
+        None => INVALID_ARGUMENT,
+    }
+}
+
+/// Format the buffer given as `count` lines (without their line endings) and
+/// set `out` to an array of the formatted lines and `out_count` to its
+/// length, nothing is printed to the standard output. A last empty line
+/// stands for the newline at the end of the buffer, the output has one only
+/// when the input has it. Both `out` and
+/// `out_count` are required, when the buffer is not formatted `out` is set to
+/// NULL and `out_count` to 0.
+///
+/// WARNING: the memory of the lines is not freed by Rust so YOU MUST MAKE
+/// SURE to free it yourself calling `free_c_char_array(*out, *out_count)`.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_lines(lines: *const *const c_char,
+                           count: c_int,
+                           path: *const c_char,
+                           out: *mut *mut *mut c_char,
+                           out_count: *mut c_int)
+                           -> c_int {
+    if count < 0 || out.is_null() || out_count.is_null() {
+        return INVALID_ARGUMENT;
+    }
+    unsafe {
+        *out = std::ptr::null_mut();
+        *out_count = 0;
+    }
+    let lines = match c_str_array(lines, count as size_t) {
+        Some(lines) => lines,
+        None => return INVALID_ARGUMENT,
+    };
+    if too_large(lines.iter().map(|line| line.len() + 1).sum()) {
+        return INPUT_TOO_LARGE;
+    }
+
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, formatted) = rustfmt_lines(lines, config_path);
+    unsafe {
+        *out_count = formatted.len() as c_int;
+        *out = to_c_str_array(formatted);
+    }
+    status
+}
+
+/// Format the passed buffer and return the result, if `out_cursor` is not NULL
+/// it is set to the byte offset of the result where the byte offset
+/// `cursor_byte` of the buffer goes, so the host can put the caret back in
+/// the same place after replacing the whole buffer. The offset is a best
+/// effort guess, the code before the cursor is matched ignoring whitespace.
+///
+/// When the buffer can not be formatted the returned string starts with
+/// `error\t` followed by the reason and `out_cursor` is set to -1, the same
+/// happens when `cursor_byte` is negative.
+///
+/// WARNING: this function forgets about the allocated memory so
+/// YOU MUST MAKE SURE to delete this memory yourself.
+#[cfg(feature = "ffi")]
 #[no_mangle]
-pub extern fn complete(code: *const c_char, path: *const c_char, line: uint32_t, col: uint32_t) -> *mut c_char {
-    to_c_str(racer_complete(c_str_to_safe_string(code), c_str_to_safe_string(path), line as usize, col as usize))
+pub extern fn format_with_cursor(code: *const c_char,
+                                 path: *const c_char,
+                                 cursor_byte: c_int,
+                                 out_cursor: *mut c_int)
+                                 -> *mut c_char {
+    let (result, cursor) = if let Some(reason) = input_size_error(code) {
+        (format!("error\t{}", reason), -1)
+    } else if cursor_byte < 0 {
+        (format!("error\tinvalid cursor {}", cursor_byte), -1)
+    } else {
+        let config_path: Option<String> = Some(c_str_to_safe_string(path));
+        match rustfmt_with_cursor(c_str_to_safe_string(code), config_path, cursor_byte as usize) {
+            Ok((output, cursor)) => (output, cursor as c_int),
+            Err(reason) => (format!("error\t{}", reason), -1),
+        }
+    };
+    if !out_cursor.is_null() {
+        unsafe { *out_cursor = cursor };
+    }
+    to_c_str(result)
 }
 
-/// Look for definitions of the word under the cursor using libracer and
-/// return a string with a result per line with fields separated by tabs
+/// Format the passed buffer and set `out_ptr` to the formatted output as raw
+/// bytes and `out_len` to its length, nothing is printed to the standard
+/// output. The output is not NUL terminated so hosts can use it as a sized
+/// buffer without scanning for its end. Both `out_ptr` and `out_len` are
+/// required, when the buffer is not formatted (or the output is empty)
+/// `out_ptr` is set to NULL and `out_len` to 0.
 ///
-/// WARNING: this function forgets about the allocated memory so
-/// YOU MUST MAKE SURE to delete this memory yourself
+/// WARNING: the memory of the output is not freed by Rust so YOU MUST MAKE
+/// SURE to free it yourself calling `free_bytes(*out_ptr, *out_len)`.
+#[cfg(feature = "ffi")]
 #[no_mangle]
-pub extern fn definitions(code: *const c_char, path: *const c_char, line: uint32_t, col: uint32_t) -> *mut c_char {
-    to_c_str(find_definition(c_str_to_safe_string(code), c_str_to_safe_string(path), line as usize, col as usize))
+pub extern fn format_to_bytes(code: *const c_char,
+                              path: *const c_char,
+                              out_ptr: *mut *mut uint8_t,
+                              out_len: *mut size_t)
+                              -> c_int {
+    if out_ptr.is_null() || out_len.is_null() {
+        return INVALID_ARGUMENT;
+    }
+    unsafe {
+        *out_ptr = std::ptr::null_mut();
+        *out_len = 0;
+    }
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+
+    let config_path: Option<String> = Some(c_str_to_safe_string(path));
+    let (status, output) = rustfmt_bytes(c_str_to_safe_string(code), config_path);
+    if !output.is_empty() {
+        unsafe {
+            *out_len = output.len() as size_t;
+            *out_ptr = Box::into_raw(output.into_boxed_slice()) as *mut uint8_t;
+        }
+    }
+    status
 }
 
-/// Look for documentation about the word under the cursor using libracer
-/// and return a string with a result per line with fields separated by tabs
+/// Check whether the passed buffer is valid Rust with the parser of rustfmt
+/// and without formatting it, nothing is printed. Returns 0 when it parses
+/// and 2 when it doesn't. If `out_message` is not NULL it is set to the first
+/// error of the parser, with its line, or to an empty string. Parsing doesn't
+/// depend on the configuration so `path` is not used.
 ///
-/// WARNING: this function forgets about the allocated memory so
-/// YOU MUST MAKE SURE to delete this memory yourself.
+/// WARNING: the message memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
 #[no_mangle]
-pub extern fn documentation(code: *const c_char, path: *const c_char, line: uint32_t, col: uint32_t) -> *mut c_char {
-    to_c_str(get_documentation(c_str_to_safe_string(code), c_str_to_safe_string(path), line as usize, col as usize))
+pub extern fn validate_syntax(code: *const c_char, _path: *const c_char, out_message: *mut *mut c_char) -> c_int {
+    if input_too_large(code) {
+        return INPUT_TOO_LARGE;
+    }
+    let (status, message) = rustfmt_validate(&c_str_to_safe_string(code));
+    if !out_message.is_null() {
+        unsafe { *out_message = to_c_str(message) };
+    }
+    status
+}
+
+/// Format every file of the Cargo package whose manifest (or directory) is
+/// given: the targets of the manifest, the ones that Cargo discovers by
+/// convention, the modules they declare and the path dependencies inside the
+/// package. `mode` is 0 to check the files, 1 to overwrite them and 2 to
+/// check them and get the unified diff of every file that needs formatting.
+/// Generated files (`@generated` or `DO NOT EDIT` in their first lines) are
+/// left alone.
+///
+/// `out_json` is required, the status is 4 without it or for an unknown mode.
+/// It is set to a `{"files": [...], "diagnostics": [...], "generated": [...]}`
+/// object where every file has a `path`, a `status` (formatted, needs_format,
+/// reformatted, parse_error, busy or error) and an `error`, the
+/// `changed_lines` or a `diff` when they apply, the diagnostics list the
+/// targets, modules and dependencies that were skipped and `generated` the
+/// generated files that were skipped. Returns 0, 10 when some file needs
+/// formatting and 1 when some file can not be formatted or the manifest can
+/// not be read, then the object has an `error` with the reason.
+///
+/// WARNING: the JSON memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_cargo_project(manifest_path: *const c_char, mode: c_int, out_json: *mut *mut c_char) -> c_int {
+    let mode = match project::Mode::from_c_int(mode) {
+        Some(mode) if !out_json.is_null() => mode,
+        _ => return INVALID_ARGUMENT,
+    };
+    let manifest = c_str_to_safe_string(manifest_path);
+    let report = project::format_cargo_project(Path::new(&manifest), &project::Generated::default(), mode);
+    let (status, json) = project_report_json(report);
+    unsafe { *out_json = to_c_str(json) };
+    status
+}
+
+/// Format the file in `path` and, when `follow_mods` is not 0, the files of
+/// the modules that it declares (`mod foo;`, `#[path = "..."]` attributes,
+/// `foo.rs` and `foo/mod.rs` layouts) and theirs, like the rustfmt command
+/// does. Every file is formatted with the configuration of its own project,
+/// see `format_cargo_project` for the modes, the JSON set to `out_json` and
+/// the status. Modules that can't be found and cycles are listed in the
+/// diagnostics, they never stop the other files from being formatted.
+///
+/// WARNING: the JSON memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_file(path: *const c_char, mode: c_int, follow_mods: c_int, out_json: *mut *mut c_char) -> c_int {
+    let mode = match project::Mode::from_c_int(mode) {
+        Some(mode) if !out_json.is_null() => mode,
+        _ => return INVALID_ARGUMENT,
+    };
+    let path = c_str_to_safe_string(path);
+    let report = project::format_file(Path::new(&path), follow_mods != 0, mode);
+    let (status, json) = project_report_json(Ok(report));
+    unsafe { *out_json = to_c_str(json) };
+    status
+}
+
+/// The status and the JSON of a project or directory report
+#[cfg(feature = "ffi")]
+fn project_report_json(result: Result<project::ProjectReport, String>) -> (c_int, String) {
+    match result {
+        Ok(report) => {
+            let status = match report.failures() {
+                (_, true) => 1,
+                (true, false) => PROJECT_NEEDS_FORMAT,
+                (false, false) => 0,
+            };
+            (status, serde_json::to_string(&report).unwrap_or_default())
+        }
+        Err(e) => (1, json!({"files": [], "diagnostics": [], "error": e}).to_string()),
+    }
+}
+
+// flags of `format_dir`
+#[cfg(feature = "ffi")]
+const FOLLOW_LINKS: c_int = 1;
+#[cfg(feature = "ffi")]
+const NO_IGNORE: c_int = 2;
+#[cfg(feature = "ffi")]
+const INCLUDE_GENERATED: c_int = 4;
+
+/// Format every file under the directory in `path` whose path relative to it
+/// matches one of the `includes` globs (`**/*.rs` when NULL or empty) and
+/// none of the `excludes` globs, both lists are separated by newlines. It is
+/// meant for builds that don't use Cargo, see `format_cargo_project` for the
+/// modes, the JSON set to `out_json` and the status. The files are listed in
+/// the same order every run.
+///
+/// The files that git ignores and `target` are skipped (the diagnostics say
+/// how many), the generated files are skipped (they are listed apart) and
+/// symlinked directories are not walked, `flags` changes that: 1 follows the
+/// symlinks, 2 walks the ignored files too and 4 formats the generated files.
+///
+/// WARNING: the JSON memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_dir(path: *const c_char,
+                         includes: *const c_char,
+                         excludes: *const c_char,
+                         mode: c_int,
+                         flags: c_int,
+                         out_json: *mut *mut c_char)
+                         -> c_int {
+    let mode = match project::Mode::from_c_int(mode) {
+        Some(mode) if !out_json.is_null() => mode,
+        _ => return INVALID_ARGUMENT,
+    };
+    let globs = |list: *const c_char| -> Vec<String> {
+        if list.is_null() {
+            return Vec::new();
+        }
+        c_str_to_safe_string(list).lines().map(str::trim).filter(|glob| !glob.is_empty()).map(String::from).collect()
+    };
+    let mut options = project::DirOptions::default();
+    let includes = globs(includes);
+    if !includes.is_empty() {
+        options.includes = includes;
+    }
+    options.excludes = globs(excludes);
+    options.follow_links = flags & FOLLOW_LINKS != 0;
+    options.honor_ignores = flags & NO_IGNORE == 0;
+    options.generated.include = flags & INCLUDE_GENERATED != 0;
+
+    let dir = c_str_to_safe_string(path);
+    let (status, json) = project_report_json(project::format_dir(Path::new(&dir), &options, mode));
+    unsafe { *out_json = to_c_str(json) };
+    status
+}
+
+/// List the files of a Cargo package (`path` is its manifest or its
+/// directory) or of a directory without a manifest (the `.rs` files that git
+/// doesn't ignore) that are not formatted, nothing is modified. Files that
+/// were formatted the last time they were seen are not formatted again.
+///
+/// `out_json` is required, the status is 4 without it. It is set to an object
+/// with the files sorted by path: `needs_format` (the `path` and the
+/// `changed_lines` of every file), `parse_errors` and `errors` (the `path` and
+/// the `error`), the `diagnostics` of the walk and the `generated` files that
+/// were skipped. Returns 0 when every file is formatted, 10 when some file
+/// needs formatting, 2 when some file doesn't parse and 1 for other errors,
+/// then the object may have an `error` with the reason why the package or
+/// directory can't be walked.
+///
+/// WARNING: the JSON memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn check_project(path: *const c_char, out_json: *mut *mut c_char) -> c_int {
+    if out_json.is_null() {
+        return INVALID_ARGUMENT;
+    }
+    let target = c_str_to_safe_string(path);
+    let (status, json) = match project::check_project(Path::new(&target), &project::DirOptions::default()) {
+        Ok(report) => (report.status(), serde_json::to_string(&report).unwrap_or_default()),
+        Err(e) => {
+            let report = project::CheckReport::default();
+            let mut json = serde_json::to_value(&report).unwrap_or_default();
+            json["error"] = json!(e);
+            (1, json.to_string())
+        }
+    };
+    unsafe { *out_json = to_c_str(json) };
+    status
+}
+
+/// Check the `count` files in `paths` without modifying them, for pre-commit
+/// hooks that pass every staged file: the ones that are not `.rs` files or
+/// don't exist are skipped and the diagnostics say so. `contents` can be NULL
+/// or an array of `count` buffers (or NULLs) that are checked instead of the
+/// files on disk, e.g. the staged versions. The configuration of every file is
+/// resolved like `format` does, the lookups of the same directories are only
+/// made once.
+///
+/// `out_json` is required, the status is 4 without it. It is set to an object
+/// like the one of `format_cargo_project` with a `formatted`, `needs_format`,
+/// `parse_error` or `error` status for every file checked, in the order
+/// given. Returns 0 when every file is formatted (the hook can pass), 10 when
+/// some file needs formatting and 1 when some file can not be checked.
+///
+/// WARNING: the JSON memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn check_staged(paths: *const *const c_char,
+                           contents: *const *const c_char,
+                           count: c_int,
+                           out_json: *mut *mut c_char)
+                           -> c_int {
+    if count < 0 || out_json.is_null() {
+        return INVALID_ARGUMENT;
+    }
+    let paths = match c_str_array(paths, count as size_t) {
+        Some(paths) => paths,
+        None => return INVALID_ARGUMENT,
+    };
+    let contents: Vec<Option<String>> = if contents.is_null() || count == 0 {
+        vec![None; paths.len()]
+    } else {
+        let contents = unsafe { std::slice::from_raw_parts(contents, paths.len()) };
+        contents.iter().map(|&item| if item.is_null() { None } else { Some(c_str_to_safe_string(item)) }).collect()
+    };
+    let files = paths.into_iter()
+        .zip(contents)
+        .map(|(path, contents)| {
+            project::StagedFile {
+                path: path,
+                contents: contents,
+            }
+        })
+        .collect();
+    let (status, json) = project_report_json(Ok(project::check_files(files)));
+    unsafe { *out_json = to_c_str(json) };
+    status
+}
+
+/// Format the buffer in `code` under every `rustfmt.toml` found under the
+/// directory in `workspace_root` to see how the configs of a repository
+/// differ, the files that git ignores and `target` are skipped. The edition
+/// specific config next to every file is merged like `format` does.
+///
+/// `out_json` is required, the status is 4 without it. It is set to an object
+/// that maps the path of every config to the output, an output is `error\t`
+/// followed by the reason when the config can't be read or the code can't be
+/// formatted under it. Returns 0, 5 when the code is too large or 1 when the
+/// root is not a directory, then the object has an `error` with the reason.
+///
+/// WARNING: the JSON memory is not freed by Rust so YOU MUST MAKE SURE
+/// to delete this memory yourself.
+#[cfg(feature = "ffi")]
+#[no_mangle]
+pub extern fn format_under_all_configs(code: *const c_char,
+                                       workspace_root: *const c_char,
+                                       out_json: *mut *mut c_char)
+                                       -> c_int {
+    if out_json.is_null() {
+        return INVALID_ARGUMENT;
+    }
+    if let Some(reason) = input_size_error(code) {
+        unsafe { *out_json = to_c_str(json!({"error": reason}).to_string()) };
+        return INPUT_TOO_LARGE;
+    }
+    let root = c_str_to_safe_string(workspace_root);
+    let (status, json) = match rustfmt_under_all_configs(&c_str_to_safe_string(code), Path::new(&root)) {
+        Ok(outputs) => (0, serde_json::Value::Object(outputs).to_string()),
+        Err(e) => (1, json!({"error": e}).to_string()),
+    };
+    unsafe { *out_json = to_c_str(json) };
+    status
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{lock_globals, TempDir};
+
+    fn render_default(buffer: &str, config: Config) -> Option<String> {
+        render(String::from(buffer), config, OutputOptions::default()).1
+    }
+
+    fn render_default_with(buffer: &str, options: OutputOptions) -> Option<String> {
+        render(String::from(buffer), Config::default(), options).1
+    }
+
+    #[test]
+    fn render_keeps_crlf_without_newline_style() {
+        let output = render_default("fn main() {\r\n    let x=1;\r\n}\r\n", Config::default());
+        assert_eq!(output.unwrap(), "fn main() {\r\n    let x = 1;\r\n}\r\n");
+    }
+
+    #[test]
+    fn render_follows_an_explicit_unix_newline_style() {
+        let config = backend::config_from_toml("newline_style = \"Unix\"\n").unwrap();
+        assert!(config.newline_style_set());
+        let output = render_default("fn main() {\r\n    let x=1;\r\n}\r\n", config);
+        assert_eq!(output.unwrap(), "fn main() {\n    let x = 1;\n}\n");
+    }
+
+    #[test]
+    fn render_follows_a_newline_style_override() {
+        let mut config = Config::default();
+        config.override_value("newline_style", "Windows");
+        let output = render_default("fn main() {\n    let x=1;\n}\n", config);
+        assert_eq!(output.unwrap(), "fn main() {\r\n    let x = 1;\r\n}\r\n");
+    }
+
+    #[test]
+    fn render_keeps_the_bom_and_the_missing_trailing_newline() {
+        let output = render_default("\u{feff}fn main() {\r\n    let x=1;\r\n}", Config::default());
+        assert_eq!(output.unwrap(), "\u{feff}fn main() {\r\n    let x = 1;\r\n}");
+    }
+
+    #[test]
+    fn fragments_keep_crlf() {
+        let output = render_fragment(String::from("let x=1;\r\nlet y=2;\r\n"),
+                                     Config::default(),
+                                     FragmentKind::Statements);
+        assert_eq!(output.unwrap(), "let x = 1;\r\nlet y = 2;\r\n");
+    }
+
+    #[test]
+    fn compare_with_disk_keeps_the_line_endings_and_the_bom() {
+        let dir = TempDir::new("compare");
+        let crlf = "fn main() {\r\n    let x = 1;\r\n}\r\n";
+        let path = dir.write("crlf.rs", crlf).to_string_lossy().into_owned();
+        assert_eq!(rustfmt_compare_with_disk(String::from(crlf), path.clone()), 0);
+        let unformatted = String::from("fn main() {\r\n    let x=1;\r\n}\r\n");
+        assert_eq!(rustfmt_compare_with_disk(unformatted, path), BUFFER_NEEDS_FORMAT);
+
+        let bom = "\u{feff}fn main() {\n    let x = 1;\n}\n";
+        let path = dir.write("bom.rs", bom).to_string_lossy().into_owned();
+        assert_eq!(rustfmt_compare_with_disk(String::from(bom), path), 0);
+    }
+
+    #[test]
+    fn indent_string_follows_hard_tabs() {
+        let code = "fn main() {\n    if true {\nfoo();\n    }\n}\n";
+        let mut config = Config::default();
+        assert_eq!(indent_string(code, 3, &config).unwrap(), "        ");
+        config.set_hard_tabs(true);
+        assert_eq!(indent_string(code, 3, &config).unwrap(), "\t\t");
+        assert_eq!(indent_string(code, 5, &config).unwrap(), "");
+    }
+
+    #[test]
+    fn indent_string_explains_broken_buffers() {
+        let config = Config::default();
+        assert!(indent_string("fn main() {\n", 7, &config).unwrap_err().contains("out of range"));
+        assert!(indent_string("fn main() { )\nfoo();\n", 2, &config).unwrap_err().contains("mismatched"));
+    }
+
+    #[test]
+    fn cli_compat_returns_the_rustfmt_exit_codes() {
+        let _globals = lock_globals();
+        assert_eq!(rustfmt_cli_compat(String::from("fn main() {\n    let x=1;\n}\n"), None), CLI_CHANGED);
+        assert_eq!(rustfmt_cli_compat(String::from("fn main() {\n    let x = 1;\n}\n"), None), 0);
+        assert_eq!(rustfmt_cli_compat(String::from("fn main() { @@ }\n"), None), 2);
+        let dir = TempDir::new("cli-compat");
+        dir.write("rustfmt.toml", "max_width\n");
+        let path = dir.write("main.rs", "").to_string_lossy().into_owned();
+        assert_eq!(rustfmt_cli_compat(String::from("fn main() {}\n"), Some(path)), 1);
+        // only rustfmt's exit codes, 4 is always "changed"
+        assert_eq!(cli_exit_code(INVALID_ARGUMENT), 1);
+        assert_eq!(cli_exit_code(INPUT_TOO_LARGE), 1);
+        assert_eq!(cli_exit_code(FILE_BUSY), 1);
+    }
+
+    #[test]
+    fn format_string_refuses_inputs_over_the_size_limit() {
+        let _globals = lock_globals();
+        let input = "// x\n".repeat(DEFAULT_MAX_INPUT_BYTES / 5 + 1);
+        match format_string(&input, &FormatOptions::default()) {
+            Err(FormatError::TooLarge(reason)) => {
+                let expected = format!("input too large: {} bytes, limit {} bytes",
+                                       input.len(),
+                                       DEFAULT_MAX_INPUT_BYTES);
+                assert_eq!(reason, expected)
+            }
+            result => panic!("unexpected result {:?}", result.map(|output| output.changed)),
+        }
+    }
+
+    #[test]
+    fn configs_equivalent_fails_for_invalid_config_files() {
+        let dir = TempDir::new("equivalent");
+        let valid = dir.write("valid.toml", "max_width = 100\n").to_string_lossy().into_owned();
+        let invalid = dir.write("invalid.toml", "max_width 100\n").to_string_lossy().into_owned();
+        let sample = String::from("fn main() {\n    let x=1;\n}\n");
+        assert_eq!(rustfmt_configs_equivalent(valid.clone(), valid.clone(), sample.clone()), Some(true));
+        assert_eq!(rustfmt_configs_equivalent(valid.clone(), invalid, sample.clone()), None);
+        let missing = dir.write("missing.rs", "").with_file_name("missing.toml").to_string_lossy().into_owned();
+        assert_eq!(rustfmt_configs_equivalent(missing, valid, sample), None);
+    }
+
+    #[test]
+    fn configs_equivalent_ignores_the_options_that_the_sample_does_not_use() {
+        let dir = TempDir::new("equivalent-options");
+        let base = dir.write("base.toml", "max_width = 100\n").to_string_lossy().into_owned();
+        // there are no imports to reorder in the sample
+        let imports = dir.write("imports.toml", "max_width = 100\nreorder_imports = true\n");
+        let windows = dir.write("windows.toml", "max_width = 100\nnewline_style = \"Windows\"\n");
+        let sample = String::from("fn main() {\n    let x=1;\n}\n");
+        assert_eq!(rustfmt_configs_equivalent(base.clone(), imports.to_string_lossy().into_owned(), sample.clone()),
+                   Some(true));
+        assert_eq!(rustfmt_configs_equivalent(base, windows.to_string_lossy().into_owned(), sample), Some(false));
+    }
+
+    #[test]
+    fn newline_modes_force_the_line_endings() {
+        let crlf = "fn main() {\r\n    let x=1;\r\n}\r\n";
+        let unix = render_default_with(crlf, newline_options(crlf, NewlineMode::Unix));
+        assert_eq!(unix.unwrap(), "fn main() {\n    let x = 1;\n}\n");
+        let lf = "fn main() {\n    let x=1;\n}\n";
+        let windows = render_default_with(lf, newline_options(lf, NewlineMode::Windows));
+        assert_eq!(windows.unwrap(), "fn main() {\r\n    let x = 1;\r\n}\r\n");
+        // two CRLF lines against one LF line
+        let mixed = "fn main() {\r\n    let x=1;\r\n}\n";
+        let auto = render_default_with(mixed, newline_options(mixed, NewlineMode::Auto));
+        assert_eq!(auto.unwrap(), "fn main() {\r\n    let x = 1;\r\n}\r\n");
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn unknown_newline_styles_are_rejected() {
+        assert_eq!(NewlineMode::from_c_int(3), Some(NewlineMode::Native));
+        assert_eq!(NewlineMode::from_c_int(4), None);
+        assert_eq!(NewlineMode::from_c_int(-1), None);
+        let code = CString::new("fn main() {}\n").unwrap();
+        assert_eq!(format_newline_style(code.as_ptr(), std::ptr::null(), 4), INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn indent_level_handles_braces_and_continuations() {
+        let code = "fn main() {\nlet x = foo()\n.bar();\nmatch x {\nSome(y) => {\ny\n}\n}\n}\n";
+        // the line after an opening brace
+        assert_eq!(indent_level(code, 2), Ok(1));
+        // a line continuing a multi-line expression
+        assert_eq!(indent_level(code, 3), Ok(2));
+        // inside a match arm
+        assert_eq!(indent_level(code, 6), Ok(3));
+        // the closing brace lines
+        assert_eq!(indent_level(code, 7), Ok(2));
+        assert_eq!(indent_level(code, 8), Ok(1));
+        assert_eq!(indent_level(code, 9), Ok(0));
+    }
+
+    #[test]
+    fn indent_level_fails_inside_comments_and_literals() {
+        let code = "fn main() {\n    /* a\ncomment */\n    let s = \"a\nstring\";\n}\n";
+        assert!(indent_level(code, 3).unwrap_err().contains("comment"));
+        assert!(indent_level(code, 5).unwrap_err().contains("literal"));
+    }
+
+    #[test]
+    fn config_fingerprint_follows_the_effective_config() {
+        let dir = TempDir::new("fingerprint");
+        let a = dir.write("a/main.rs", "fn main() {}\n");
+        dir.write("a/rustfmt.toml", "max_width = 80\n");
+        let b = dir.write("b/main.rs", "fn main() {}\n");
+        dir.write("b/rustfmt.toml", "max_width = 80\n");
+        let a = Some(a.to_string_lossy().into_owned());
+        let b = Some(b.to_string_lossy().into_owned());
+        let fingerprint = config_fingerprint_for(a.clone());
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(config_fingerprint_for(a.clone()), fingerprint);
+        assert_eq!(config_fingerprint_for(b), fingerprint);
+        dir.write("a/rustfmt.toml", "max_width = 90\n");
+        assert!(config_fingerprint_for(a) != fingerprint);
+    }
+
+    #[test]
+    fn size_limit_is_checked_at_the_boundary() {
+        assert_eq!(size_limit_error(DEFAULT_MAX_INPUT_BYTES), None);
+        assert!(size_limit_error(DEFAULT_MAX_INPUT_BYTES + 1).is_some());
+        assert_eq!(check_size(13, 13), None);
+        assert_eq!(check_size(14, 13).unwrap(), "input too large: 14 bytes, limit 13 bytes");
+        // 0 disables the limit, `set_max_input_bytes` stores the value plus one
+        let limit = AtomicUsize::new(1);
+        assert_eq!(check_size(usize::max_value(), limit_value(&limit, DEFAULT_MAX_INPUT_BYTES)), None);
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn reset_state_restores_the_defaults() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("reset");
+        let fallback = dir.write("fallback.toml", "max_width = 100\n").to_string_lossy().into_owned();
+        let fallback = CString::new(fallback).unwrap();
+        set_max_input_bytes(DEFAULT_MAX_INPUT_BYTES * 2);
+        set_max_line_bytes(DEFAULT_MAX_LINE_BYTES * 2);
+        set_fallback_config_path(fallback.as_ptr());
+        format_cache::remember(String::from("/reset/main.rs"), 1, 2);
+        set_max_threads(1);
+        JSON_CONFIG_WARNINGS.with(|last| last.borrow_mut().push(String::from("unknown option")));
+
+        reset_state();
+
+        assert_eq!(limit_value(&MAX_INPUT_BYTES, DEFAULT_MAX_INPUT_BYTES), DEFAULT_MAX_INPUT_BYTES);
+        assert_eq!(limit_value(&MAX_LINE_BYTES, DEFAULT_MAX_LINE_BYTES), DEFAULT_MAX_LINE_BYTES);
+        assert!(FALLBACK_CONFIG.lock().unwrap().is_none());
+        assert!(!format_cache::is_formatted("/reset/main.rs", 1, 2));
+        assert_eq!(pool::threads(), std::cmp::max(num_cpus::get(), 1));
+        assert!(JSON_CONFIG_WARNINGS.with(|last| last.borrow().is_empty()));
+    }
+
+    #[test]
+    fn shebang_lines_are_kept_verbatim() {
+        let code = "#!/usr/bin/env run-cargo-script\n\nfn main() {\n    let x=1;\n}\n";
+        let (_, output) = format_to_string(String::from(code), &Config::default());
+        assert_eq!(output.unwrap(), "#!/usr/bin/env run-cargo-script\n\nfn main() {\n    let x = 1;\n}\n");
+        assert_eq!(split_shebang("\u{feff}#!/bin/rs\r\nfn main() {}\r\n"), Some(("\u{feff}#!/bin/rs\r\n", 0, 14)));
+    }
+
+    #[test]
+    fn inner_attributes_are_not_shebangs() {
+        assert_eq!(split_shebang("#![allow(dead_code)]\nfn main() {}\n"), None);
+        assert_eq!(split_shebang("#! [allow(dead_code)]\nfn main() {}\n"), None);
+        assert_eq!(split_shebang("#!\n[allow(dead_code)]\nfn main() {}\n"), None);
+    }
+
+    #[test]
+    fn partial_outputs_are_never_returned() {
+        // rustfmt failing half way through a buffer still has some output
+        let buffer = String::from("fn main() {\n    let x=1;\n}\n");
+        let (summary, output) = render_with(buffer, Config::default(), OutputOptions::default(), |_, _| {
+            let mut summary = Summary::new();
+            summary.add_parsing_error();
+            (summary, Some(String::from("fn main() {\n")))
+        });
+        assert_eq!(formatted_or_reason(summary, output), Err(String::from("the code does not parse")));
+        assert_eq!(rustfmt_bytes(String::from("fn main() {\n    @@\n}\n"), None), (2, Vec::new()));
+    }
+
+    #[test]
+    fn b64_buffers_are_decoded_and_encoded() {
+        let encoded = base64::encode(b"fn main() {\n    let x=1;\n}\n");
+        let output = base64::decode(&rustfmt_b64(&encoded, None).unwrap()).unwrap();
+        assert_eq!(output, b"fn main() {\n    let x = 1;\n}\n");
+        assert_eq!(rustfmt_b64("Zm9v!", None).unwrap_err(), "invalid base64: invalid character at byte 4");
+        assert_eq!(rustfmt_b64("/w==", None).unwrap_err(), "the buffer is not valid UTF-8");
+    }
+
+    #[test]
+    fn fallback_config_applies_only_without_a_project_config() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("fallback");
+        let fallback = dir.write("fallback.toml", "wrap_match_arms = false\n");
+        dir.write("project/rustfmt.toml", "max_width = 80\n");
+        let project = dir.write("project/main.rs", "fn main() {}\n").to_string_lossy().into_owned();
+        let bare = dir.write("bare/main.rs", "fn main() {}\n").to_string_lossy().into_owned();
+
+        *FALLBACK_CONFIG.lock().unwrap() = Some(fallback);
+        let project_config = try_load_config(Some(project)).unwrap();
+        let bare_config = try_load_config(Some(bare.clone())).unwrap();
+        *FALLBACK_CONFIG.lock().unwrap() = None;
+
+        assert_eq!(project_config.max_width(), 80);
+        assert!(project_config.wrap_match_arms());
+        assert!(!bare_config.wrap_match_arms());
+        assert!(try_load_config(Some(bare)).unwrap().wrap_match_arms());
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn format_copies_the_buffer_once() {
+        use testing::count_allocations;
+
+        let code = "fn main() {\n    let x = 1;\n}\n".repeat(32 * 1024);
+        let c_code = CString::new(code.clone()).unwrap();
+        let path = CString::new("").unwrap();
+        let mut out = Vec::with_capacity(code.len() + 1);
+        let copies = count_allocations(code.len(), || {
+            assert_eq!(format_c_str(&c_code, &path, &mut out), 0);
+        });
+        assert_eq!(out, code.as_bytes());
+        // the String that rustfmt takes, the output that rustfmt builds and
+        // the copy of it that rustfmt writes to the library, nothing else
+        assert_eq!(copies, 3);
+    }
+
+    #[test]
+    fn an_invalid_project_config_is_an_error() {
+        let dir = TempDir::new("invalid-config");
+        dir.write("rustfmt.toml", "max_width\n");
+        let path = Some(dir.write("main.rs", "fn main() {}\n").to_string_lossy().into_owned());
+        let code = String::from("fn main() {\n    let x=1;\n}\n");
+
+        assert_eq!(rustfmt_hashed(code.clone(), path.clone()), (1, String::new()));
+        assert_eq!(rustfmt_stable_n(code.clone(), path.clone(), 3), (1, 0));
+        assert!(rustfmt_low_churn(code.clone(), path.clone()).starts_with("error\t"));
+        assert_eq!(rustfmt_with_editor_defaults(code, path, 2, 80), 1);
+    }
+
+    #[test]
+    fn format_string_returns_the_output() {
+        let dir = TempDir::new("format-string");
+        let path = dir.write("main.rs", "").to_string_lossy().into_owned();
+        let options = FormatOptions { config_path: Some(path), ..FormatOptions::default() };
+        let output = format_string("fn main() {\n    let x=1;\n}\n", &options).unwrap();
+        assert_eq!(output.text, "fn main() {\n    let x = 1;\n}\n");
+        assert!(output.changed);
+        assert!(!output.formatting_errors);
+        assert!(!output.cached);
+        // the output is the last one formatted for the path
+        let again = format_string(&output.text, &options).unwrap();
+        assert_eq!(again.text, output.text);
+        assert!(!again.changed);
+        assert!(again.cached);
+    }
+
+    #[test]
+    fn format_string_errors_are_typed() {
+        let dir = TempDir::new("format-errors");
+        let path = dir.write("main.rs", "").to_string_lossy().into_owned();
+        let options = FormatOptions { config_path: Some(path), ..FormatOptions::default() };
+        match format_string("fn main() {\n    @@\n}\n", &options) {
+            Err(e @ FormatError::InvalidInput(_)) => {
+                assert_eq!(e.status(), 2);
+                assert_eq!(error::Error::description(&e), "the code does not parse");
+            }
+            result => panic!("unexpected result {:?}", result.map(|output| output.text)),
+        }
+
+        dir.write("broken/rustfmt.toml", "max_width 100\n");
+        let path = dir.write("broken/main.rs", "").to_string_lossy().into_owned();
+        let options = FormatOptions { config_path: Some(path), ..FormatOptions::default() };
+        match format_string("fn main() {}\n", &options) {
+            Err(e @ FormatError::ConfigParse(_)) => assert!(e.to_string().starts_with("invalid config: ")),
+            result => panic!("unexpected result {:?}", result.map(|output| output.text)),
+        }
+    }
+
+    #[test]
+    fn todo_markers_are_only_found_in_comments() {
+        let code = "// TODO: first\nfn main() {\n    let s = \"TODO: not a comment\";\n    /* FIXME later */\n    \
+                    let x = 1; // XXX why\n    let r = r#\"FIXME raw\"#;\n}\n/// TODOS are not markers\n";
+        assert_eq!(todo_markers(code),
+                   vec![(1, "TODO", String::from("first")),
+                        (4, "FIXME", String::from("later")),
+                        (5, "XXX", String::from("why"))]);
+        assert!(todo_markers("fn main() {}\n").is_empty());
+    }
+
+    #[test]
+    fn enforce_width_reports_the_unbreakable_lines() {
+        let dir = TempDir::new("enforce_width");
+        let config = Some(dir.write("rustfmt.toml", "max_width = 40\n").to_string_lossy().into_owned());
+        let long = "fn main() {\n    let s = \"a string literal that can not be wrapped\";\n}\n";
+        assert_eq!(rustfmt_enforce_width(String::from(long), config.clone()), (WIDTH_VIOLATION, vec![2]));
+        let short = "fn main() {\n    let s = \"short\";\n}\n";
+        assert_eq!(rustfmt_enforce_width(String::from(short), config), (0, vec![]));
+    }
+
+    #[test]
+    fn wide_lines_count_tabs_as_tab_spaces() {
+        let mut config = Config::default();
+        config.set_max_width(10);
+        config.set_tab_spaces(4);
+        assert_eq!(wide_lines("\t\tab\r\n0123456789\n\t\t\tx\n", &config), vec![3]);
+    }
+
+    #[test]
+    fn alignment_thresholds_align_the_fields_of_structs() {
+        let code = "struct Point {\n    x: i32,\n    long_name: i32,\n}\n";
+        assert_eq!(rustfmt_with_alignment(String::from(code), None, Some(20), None),
+                   "struct Point {\n    x:         i32,\n    long_name: i32,\n}\n");
+        assert_eq!(rustfmt_with_alignment(String::from(code), None, Some(0), None), code);
+        assert_eq!(rustfmt_with_alignment(String::from("struct Point { @@ }\n"), None, Some(20), None),
+                   "error\tthe code does not parse");
+    }
+
+    #[test]
+    fn a_snippet_is_formatted_under_every_config_of_the_workspace() {
+        let dir = TempDir::new("all_configs");
+        let aligned = dir.write("aligned/rustfmt.toml", "struct_field_align_threshold = 20\n");
+        let plain = dir.write("plain/rustfmt.toml", "max_width = 80\n");
+        let broken = dir.write("broken/rustfmt.toml", "max_width 100\n");
+        dir.write("target/rustfmt.toml", "max_width = 80\n");
+        let code = "struct Point {\n    x: i32,\n    long_name: i32,\n}\n";
+
+        let outputs = rustfmt_under_all_configs(code, dir.path()).unwrap();
+        let output = |path: &PathBuf| outputs[&path.to_string_lossy().into_owned()].as_str().unwrap().to_owned();
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(output(&aligned), "struct Point {\n    x:         i32,\n    long_name: i32,\n}\n");
+        assert_eq!(output(&plain), code);
+        assert!(output(&broken).starts_with("error\t"));
+        assert!(rustfmt_under_all_configs(code, &aligned).is_err());
+    }
+
+    #[test]
+    fn the_fingerprint_only_hashes_what_changes_the_output() {
+        let first = fingerprint();
+        assert_eq!(first.len(), 16);
+        assert!(first.chars().all(|c| c.is_digit(16)));
+        assert_eq!(fingerprint(), first);
+
+        // the compiler, target, profile and commit of a build are left out,
+        // two builds from the same tree hash the same inputs
+        let inputs = format!("{}\n{}\n{}\n{}\n{}\nmax_line_bytes = {}\n",
+                             env!("CARGO_PKG_VERSION"),
+                             backend::NAME,
+                             option_env!("ANACONDA_RUSTFMT_VERSION").unwrap_or("unknown"),
+                             option_env!("ANACONDA_RUSTFMT_SOURCE").unwrap_or(""),
+                             serialize_config(&Config::default()),
+                             DEFAULT_MAX_LINE_BYTES);
+        assert_eq!(first, format!("{:016x}", fnv1a(inputs.as_bytes())));
+    }
+
+    #[test]
+    fn cancelled_calls_return_none() {
+        let code = String::from("fn main() {\n    let x=1;\n}\n");
+        let token = CancelToken::new();
+        assert_eq!(rustfmt_cancelable(code.clone(), None, &token),
+                   Some(String::from("fn main() {\n    let x = 1;\n}\n")));
+        token.cancel();
+        assert_eq!(rustfmt_cancelable(code, None, &token), None);
+    }
+
+    #[test]
+    fn validate_only_parses() {
+        // valid but not formatted, the width is not checked either
+        assert_eq!(rustfmt_validate("fn main() {\n    let x=1;   \n}\n"), (0, String::new()));
+        let (status, error) = rustfmt_validate("fn main() {\n    let x = 1;\n\n");
+        assert_eq!(status, 2);
+        assert!(error.starts_with("line "), "{}", error);
+        let (status, error) = rustfmt_validate("fn main() {\n    let x = @;\n}\n");
+        assert_eq!(status, 2);
+        assert!(error.starts_with("line 2: "), "{}", error);
+    }
+
+    #[test]
+    fn whitespace_report_lists_the_lines_of_the_original_buffer() {
+        let buffer = String::from("fn main() {\n    let x=1;  \n}\n");
+        let (status, output, report) = rustfmt_whitespace_report(buffer, None);
+        assert_eq!(status, 0);
+        assert_eq!(output, "fn main() {\n    let x = 1;\n}\n");
+        assert_eq!(report, "2\ttrailing whitespace");
+
+        let (_, _, report) = rustfmt_whitespace_report(String::from("fn main() {\n \tlet x = 1; \n}\n"), None);
+        assert_eq!(report, "2\tmixed indentation\n2\ttrailing whitespace");
+
+        let (status, output, report) = rustfmt_whitespace_report(String::from("fn main() { @@ }  \n"), None);
+        assert_eq!((status, output.as_str(), report.as_str()), (2, "", "1\ttrailing whitespace"));
+    }
+
+    #[test]
+    fn stable_n_counts_the_passes_until_the_output_settles() {
+        let formatted = String::from("fn main() {\n    let x = 1;\n}\n");
+        assert_eq!(rustfmt_stable_n(formatted, None, 5), (0, 1));
+        assert_eq!(rustfmt_stable_n(String::from("fn main() {\n    let x=1;\n}\n"), None, 5), (0, 2));
+
+        // a formatter that moves a comment back and forth never settles
+        let oscillating = |code: String| {
+            let (from, to) = if code.contains("// a") { ("// a", "// b") } else { ("// b", "// a") };
+            (Summary::new(), Some(code.replace(from, to)))
+        };
+        assert_eq!(format_until_stable(String::from("fn main() {} // a\n"), 4, oscillating), (NOT_IDEMPOTENT, 4));
+        assert_eq!(NOT_IDEMPOTENT, 12);
+    }
+
+    #[test]
+    fn compatibility_compares_the_version_numbers() {
+        // another major number is never compatible, older or newer
+        assert!(!satisfies((1, 2, 0, false), (0, 2, 0)));
+        assert!(!satisfies((1, 2, 0, false), (2, 0, 0)));
+        // numbers, not strings
+        assert!(satisfies((0, 10, 0, false), (0, 9, 5)));
+        assert!(!satisfies((0, 9, 5, false), (0, 10, 0)));
+        assert!(satisfies((0, 10, 3, false), (0, 10, 3)));
+        assert!(!satisfies((0, 10, 3, false), (0, 10, 4)));
+        // a pre-release comes before its version only
+        assert!(!satisfies((0, 10, 0, true), (0, 10, 0)));
+        assert!(satisfies((0, 10, 0, true), (0, 9, 5)));
+        assert!(is_compatible(version_triple().0, 0, 0));
+    }
+
+    #[test]
+    fn build_info_has_every_key() {
+        let info = build_info();
+        for key in &["version", "rustfmt", "backend", "rustc", "channel", "target", "profile", "git_commit"] {
+            assert!(info[key].as_str().map_or(false, |value| !value.is_empty()), "{}: {}", key, info);
+        }
+        assert_eq!(info["backend"], backend::NAME);
+        assert_eq!(info.as_object().map(|info| info.len()), Some(8));
+    }
+
+    #[test]
+    fn library_info_has_every_key() {
+        let info: serde_json::Value = serde_json::from_str(&library_info()).unwrap();
+        assert!(info["version"].is_string() && info["rustfmt"].is_string());
+        assert_eq!(info["abi"], ABI_VERSION);
+        assert_eq!(info["channel"], channel());
+        assert_eq!(info["backend"], backend::NAME);
+        assert_eq!(info["build"], build_info());
+        let capabilities: Vec<&str> = info["capabilities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|capability| capability.as_str())
+            .collect();
+        assert!(CAPABILITIES.iter().all(|capability| capabilities.contains(capability)));
+        // only the debug builds can annotate
+        assert_eq!(capabilities.contains(&"annotate"), cfg!(debug_assertions));
+    }
+
+    #[test]
+    fn the_channel_is_stable_unless_the_build_says_otherwise() {
+        assert_eq!(channel_of(Some("nightly")), "nightly");
+        assert_eq!(channel_of(Some("beta")), "beta");
+        assert_eq!(channel_of(Some("stable")), "stable");
+        assert_eq!(channel_of(Some("dev")), "stable");
+        assert_eq!(channel_of(None), "stable");
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn rustfmt_channel_returns_the_channel() {
+        let channel_ptr = rustfmt_channel();
+        let value = unsafe { CStr::from_ptr(channel_ptr) }.to_string_lossy().into_owned();
+        free_c_char_mem(channel_ptr);
+        assert_eq!(value, channel());
+    }
+
+    #[test]
+    fn brace_styles_change_the_output() {
+        let code = "fn main() {\n    if x {\n        y();\n    }\n}\n";
+        let same_line = rustfmt_brace_style(String::from(code),
+                                            None,
+                                            BraceStyle::SameLineWhere,
+                                            ControlBraceStyle::AlwaysSameLine);
+        assert_eq!(same_line, code);
+        let item_next_line = rustfmt_brace_style(String::from(code),
+                                                 None,
+                                                 BraceStyle::AlwaysNextLine,
+                                                 ControlBraceStyle::AlwaysSameLine);
+        assert_eq!(item_next_line, "fn main()\n{\n    if x {\n        y();\n    }\n}\n");
+        let control_next_line = rustfmt_brace_style(String::from(code),
+                                                    None,
+                                                    BraceStyle::SameLineWhere,
+                                                    ControlBraceStyle::AlwaysNextLine);
+        assert_eq!(control_next_line, "fn main() {\n    if x\n    {\n        y();\n    }\n}\n");
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn unknown_brace_styles_are_rejected() {
+        assert_eq!(brace_style_from_c_int(3), None);
+        assert_eq!(brace_style_from_c_int(-1), None);
+        assert_eq!(control_brace_style_from_c_int(2), None);
+        let code = CString::new("fn main() {\n    f();\n}\n").unwrap();
+        let path = CString::new("").unwrap();
+        let result = |item_style, control_style| {
+            let result_ptr = format_with_brace_style(code.as_ptr(), path.as_ptr(), item_style, control_style);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_string_lossy().into_owned();
+            free_c_char_mem(result_ptr);
+            result
+        };
+        assert_eq!(result(7, 0), "error\tunknown brace style 7");
+        assert_eq!(result(0, 5), "error\tunknown control brace style 5");
+        assert_eq!(result(0, 1), "fn main()\n{\n    f();\n}\n");
+    }
+
+    #[test]
+    fn lines_keep_the_newline_at_the_end_of_the_buffer() {
+        let lines = |code: &str| code.split('\n').map(String::from).collect::<Vec<_>>();
+        for code in &["fn main() {\n    let x=1;\n}\n", "fn main() {\n    let x=1;\n}"] {
+            let (status, formatted) = rustfmt_lines(lines(code), None);
+            assert_eq!(status, 0);
+            assert_eq!(formatted.join("\n"), code.replace("x=1", "x = 1"));
+        }
+        assert_eq!(rustfmt_lines(Vec::new(), None), (0, Vec::new()));
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn format_to_bytes_output_is_freed_with_free_bytes() {
+        let to_bytes = |code: &[u8]| {
+            let code = CString::new(code).unwrap();
+            let path = CString::new("").unwrap();
+            let mut out_ptr = std::ptr::null_mut();
+            let mut out_len = 0;
+            let status = format_to_bytes(code.as_ptr(), path.as_ptr(), &mut out_ptr, &mut out_len);
+            let output = if out_ptr.is_null() {
+                Vec::new()
+            } else {
+                unsafe { std::slice::from_raw_parts(out_ptr, out_len) }.to_vec()
+            };
+            free_bytes(out_ptr, out_len);
+            (status, output)
+        };
+        let (status, output) = to_bytes("// café\nfn main() {\n    let x=1;\n}\n".as_bytes());
+        assert_eq!(status, 0);
+        assert_eq!(output, "// café\nfn main() {\n    let x = 1;\n}\n".as_bytes());
+        // invalid UTF-8 is replaced, never handed back as it came
+        let (status, output) = to_bytes(b"// caf\xe9\nfn main() {}\n");
+        assert_eq!(status, 0);
+        assert_eq!(output, "// caf\u{fffd}\nfn main() {}\n".as_bytes());
+        assert_eq!(to_bytes(b"fn main() { @@ }\n"), (2, Vec::new()));
+        free_bytes(std::ptr::null_mut(), 0);
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn c_str_array_rejects_invalid_arrays() {
+        let strings = vec![CString::new("a").unwrap(), CString::new("b").unwrap()];
+        let array: Vec<*const c_char> = strings.iter().map(|item| item.as_ptr()).collect();
+        assert_eq!(c_str_array(array.as_ptr(), 2), Some(vec![String::from("a"), String::from("b")]));
+        assert_eq!(c_str_array(std::ptr::null(), 0), Some(Vec::new()));
+        assert_eq!(c_str_array(std::ptr::null(), 2), None);
+        // -1 passed as size_t
+        assert_eq!(c_str_array(array.as_ptr(), -1isize as size_t), None);
+        let with_null = [array[0], std::ptr::null()];
+        assert_eq!(c_str_array(with_null.as_ptr(), 2), None);
+    }
+
+    #[test]
+    fn editor_defaults_apply_only_without_a_project_config() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("editor-defaults");
+        let loose = Some(dir.write("loose/main.rs", "fn main() {}\n").to_string_lossy().into_owned());
+        let config = editor_config(loose.clone(), 2, 80).unwrap();
+        assert_eq!((config.tab_spaces(), config.max_width()), (2, 80));
+        // values lower than 1 keep the defaults
+        let config = editor_config(loose, 0, -1).unwrap();
+        assert_eq!((config.tab_spaces(), config.max_width()), (4, 100));
+        dir.write("project/rustfmt.toml", "max_width = 90\n");
+        let project = Some(dir.write("project/main.rs", "fn main() {}\n").to_string_lossy().into_owned());
+        let config = editor_config(project, 2, 80).unwrap();
+        assert_eq!((config.tab_spaces(), config.max_width()), (4, 90));
+    }
+
+    #[test]
+    fn byte_delta_is_negative_when_the_output_shrinks() {
+        let _globals = lock_globals();
+        assert_eq!(rustfmt_byte_delta(String::from("fn main() {\n    let x=1;\n}\n"), None), (0, 2));
+        assert_eq!(rustfmt_byte_delta(String::from("fn main() {}   \n"), None), (0, -3));
+        assert_eq!(rustfmt_byte_delta(String::from("fn main() {}\n"), None), (0, 0));
+        assert_eq!(rustfmt_byte_delta(String::from("fn main() { @@ }\n"), None), (2, 0));
+    }
+
+    #[test]
+    fn virtual_buffers_use_the_config_of_their_directory() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("virtual");
+        dir.write("project/rustfmt.toml", "newline_style = \"Windows\"\n");
+        let project = dir.path().join("project");
+        let code = String::from("fn main() {\n    let x=1;\n}\n");
+        let windows = "fn main() {\r\n    let x = 1;\r\n}\r\n";
+        assert_eq!(rustfmt_virtual(code.clone(), project.to_string_lossy().into_owned()), windows);
+        // the directory doesn't exist yet
+        let missing = project.join("src").join("new");
+        assert_eq!(rustfmt_virtual(code.clone(), missing.to_string_lossy().into_owned()), windows);
+        let outside = dir.path().join("other").to_string_lossy().into_owned();
+        assert_eq!(rustfmt_virtual(code, outside), "fn main() {\n    let x = 1;\n}\n");
+    }
+
+    #[test]
+    fn output_hash_is_stable_and_follows_the_output() {
+        let _globals = lock_globals();
+        // the FNV-1a test vectors, the digest must not change across builds
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        let (status, hash) = rustfmt_hashed(String::from("fn main() {\n    let x=1;\n}\n"), None);
+        assert_eq!((status, hash.len()), (0, 16));
+        // the same output, whatever the buffer was
+        assert_eq!(rustfmt_hashed(String::from("fn main() {\n    let x = 1;\n}\n"), None), (0, hash.clone()));
+        let (_, changed) = rustfmt_hashed(String::from("fn main() {\n    let x = 2;\n}\n"), None);
+        assert!(changed != hash);
+        assert_eq!(rustfmt_hashed(String::from("fn main() { @@ }\n"), None), (2, String::new()));
+    }
+
+    #[test]
+    fn items_with_absurdly_long_lines_are_left_alone() {
+        let _globals = lock_globals();
+        let long = format!("fn long() {{\n    let x=1;\n    let s=\"{}\";\n}}\n", "a".repeat(DEFAULT_MAX_LINE_BYTES));
+        let code = format!("{}\nfn short() {{\n    let x=1;\n}}\n", long);
+        let expected = format!("{}\nfn short() {{\n    let x = 1;\n}}\n", long);
+        assert_eq!(render_default(&code, Config::default()), Some(expected));
+    }
+
+    #[test]
+    fn only_debug_builds_annotate_the_changes() {
+        let _globals = lock_globals();
+        let annotated = rustfmt_annotated(String::from("fn main() {\n    let x=1;\n}\n"), None);
+        if cfg!(debug_assertions) {
+            assert_eq!(annotated, "fn main() {\n    // rustfmt: spacing\n    let x = 1;\n}\n");
+        } else {
+            assert_eq!(annotated, "fn main() {\n    let x = 1;\n}\n");
+        }
+    }
+
+    #[test]
+    fn lenient_overflow_overrides_the_project_config() {
+        let _globals = lock_globals();
+        let code = format!("fn main() {{\n    let s = \"{}\";\n}}\n", "a".repeat(120));
+        assert_eq!(rustfmt_lenient_overflow(code.clone(), None, true), 3);
+        assert_eq!(rustfmt_lenient_overflow(code.clone(), None, false), 0);
+        let dir = TempDir::new("lenient-overflow");
+        dir.write("rustfmt.toml", "error_on_line_overflow = false\n");
+        let path = Some(dir.write("main.rs", &code).to_string_lossy().into_owned());
+        assert_eq!(rustfmt_lenient_overflow(code.clone(), path.clone(), true), 3);
+        assert_eq!(rustfmt_lenient_overflow(code, path, false), 0);
+    }
+
+    #[test]
+    fn import_options_group_the_imports_the_same_way_every_time() {
+        let code = "use self::a;\nuse foo::bar;\nextern crate foo;\nuse std::io;\nuse std::fmt;\n\nfn main() {}\n";
+        let grouped = "extern crate foo;\n\nuse std::io;\nuse std::fmt;\n\nuse foo::bar;\n\nuse self::a;\n\n\
+                       fn main() {}\n";
+        let render = |code: &str, group| {
+            render_with_import_options(String::from(code), Config::default(), false, group).1.unwrap()
+        };
+        assert_eq!(render(code, false), code);
+        assert_eq!(render(code, true), grouped);
+        assert_eq!(render(code, true), render(code, true));
+        // grouped imports stay as they are
+        assert_eq!(render(grouped, true), grouped);
+    }
+
+    #[test]
+    fn format_item_formats_only_the_named_item() {
+        let _globals = lock_globals();
+        let code = "struct Foo;\n\nimpl Foo {\n    fn foo() {\n        let x=1;\n    }\n}\n\n\
+                    fn foo() {\n    let x=1;\n}\n";
+        let format = |name: &str| rustfmt_item(String::from(code), None, name);
+        assert_eq!(format("fn foo"), code.replacen("x=1", "x = 1", 2).replacen("x = 1", "x=1", 1));
+        assert_eq!(format("Foo"), "error\t2 top level items are named Foo, prefix the name with the kind");
+        assert_eq!(format("impl Foo"), code.replacen("x=1", "x = 1", 1));
+        assert_eq!(format("bar"), "error\tno top level item named bar");
+        assert_eq!(format("fn foo bar"), "error\tinvalid item name \"fn foo bar\"");
+    }
+
+    #[test]
+    fn json_config_formats_like_the_same_toml_config() {
+        let _globals = lock_globals();
+        let code = "struct Foo {\n    a: u8,\n    long_name: u8,\n}\n\nfn main() {\n    let x=1;\n}\n";
+        let dir = TempDir::new("json-config");
+        dir.write("rustfmt.toml", "newline_style = \"Windows\"\nstruct_field_align_threshold = 20\n");
+        let options = FormatOptions {
+            config_path: Some(dir.write("main.rs", code).to_string_lossy().into_owned()),
+            ..FormatOptions::default()
+        };
+        let toml = format_string(code, &options).unwrap().text;
+        assert!(toml != format_string(code, &FormatOptions::default()).unwrap().text);
+        let json = r#"{"newline_style": "Windows", "struct_field_align_threshold": 20}"#;
+        assert_eq!(rustfmt_json_config(String::from(code), json), (toml, Vec::new()));
+    }
+
+    #[test]
+    fn config_trace_lists_the_directories_from_the_file_up() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("trace");
+        let config = dir.write("rustfmt.toml", "max_width = 90\n");
+        let file = dir.write("a/b/main.rs", "fn main() {}\n");
+        let (status, trace) = resolve_config_trace(Some(file.to_string_lossy().into_owned()));
+        assert_eq!(status, 0);
+        let trace: serde_json::Value = serde_json::from_str(&trace).unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let steps: Vec<(String, bool)> = trace["lookups"][0]["steps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|step| (String::from(step["dir"].as_str().unwrap()), step["config"].as_bool().unwrap()))
+            .collect();
+        let dirs = vec![(root.join("a").join("b"), false), (root.join("a"), false), (root, true)];
+        let expected: Vec<(String, bool)> = dirs.into_iter()
+            .map(|(dir, config)| (dir.to_string_lossy().into_owned(), config))
+            .collect();
+        assert_eq!(steps, expected);
+        assert_eq!(trace["config"].as_str(), Some(&*config.to_string_lossy()));
+    }
+
+    #[test]
+    fn preserve_comments_keeps_ascii_art_byte_for_byte() {
+        let _globals = lock_globals();
+        let art = "/// +------+   +-----+  \n///  | a=b  |-->| c   |\n/// +------+   +-----+  \n";
+        let code = format!("{}fn main() {{\n    let x=1;   \n    /*  x=1  \n     *   \\__/  */\n}}\n", art);
+        let expected = format!("{}fn main() {{\n    let x = 1;\n    /*  x=1  \n     *   \\__/  */\n}}\n", art);
+        assert_eq!(rustfmt_preserve_comments(code, None), expected);
+    }
+
+    #[test]
+    fn format_with_matches_the_per_call_resolution() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("format-with");
+        dir.write("rustfmt.toml", "newline_style = \"Windows\"\nstruct_field_align_threshold = 20\n");
+        let path = dir.write("main.rs", "").to_string_lossy().into_owned();
+        let config = try_load_config(Some(path.clone())).unwrap();
+        let options = FormatOptions { config_path: Some(path), ..FormatOptions::default() };
+        let buffers = ["fn main() {\n    let x=1;\n}\n", "struct Foo {\n    a: u8,\n    long_name: u8,\n}\n", ""];
+        for buffer in &buffers {
+            assert_eq!(format_with(buffer, &config).unwrap(), format_string(buffer, &options).unwrap().text);
+        }
+        match format_with("fn main() { @@ }\n", &config) {
+            Err(AnacondaError::Summary(summary)) => assert!(summary.has_parsing_errors()),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn low_churn_changes_fewer_lines_than_the_project_config() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("low-churn");
+        dir.write("rustfmt.toml", "reorder_imports = true\nnormalize_comments = true\n");
+        let code = "use std::io;\nuse std::fmt;\n\nfn main() {\n    /* keep */\n    let x=1;\n}\n";
+        let path = Some(dir.write("main.rs", code).to_string_lossy().into_owned());
+        let changed = |output: &str| code.lines().zip(output.lines()).filter(|&(a, b)| a != b).count();
+        let options = FormatOptions { config_path: path.clone(), ..FormatOptions::default() };
+        assert_eq!(changed(&format_string(code, &options).unwrap().text), 4);
+        let low_churn = rustfmt_low_churn(String::from(code), path);
+        assert_eq!(low_churn, code.replace("x=1", "x = 1"));
+        assert_eq!(changed(&low_churn), 1);
+    }
+}
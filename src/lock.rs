@@ -0,0 +1,224 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serialize the in-place writes of a file. Overwrite mode, the watcher and
+//! every editor window can rewrite the same file, each read-format-write cycle
+//! holds the lock of the path: a process wide set of the paths in use plus an
+//! advisory `flock` for the other processes (only unix builds with the `ffi`
+//! feature have it). Waiting for a lock gives up after a timeout, the file is
+//! reported busy instead of blocking the editor. The new contents are
+//! written to a temporary file next to the original and renamed over it, a
+//! failed write never leaves the file truncated.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::process;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{Duration, Instant};
+
+use limit_value;
+
+const DEFAULT_TIMEOUT_MS: usize = 2000;
+
+// how long to wait for a lock in milliseconds plus one, 0 means the default
+static TIMEOUT_MS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+    // canonical paths locked by this process
+    static ref LOCKED: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+    static ref RELEASED: Condvar = Condvar::new();
+}
+
+/// The lock of a path, it is released when dropped
+pub struct PathLock {
+    path: PathBuf,
+    // closing the file releases the advisory lock
+    _file: Option<File>,
+}
+
+impl PathLock {
+    /// Replace the contents of the locked file, the original is left as it
+    /// was when the temporary file can't be written or renamed
+    pub fn write(&self, contents: &[u8]) -> io::Result<()> {
+        let name = self.path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let temp = self.path.with_file_name(format!(".{}.{}.anaconda", name, process::id()));
+        let result = File::create(&temp)
+            .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+            .and_then(|_| match fs::metadata(&self.path) {
+                Ok(metadata) => fs::set_permissions(&temp, metadata.permissions()),
+                Err(_) => Ok(()),
+            })
+            .and_then(|_| fs::rename(&temp, &self.path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+}
+
+impl Drop for PathLock {
+    fn drop(&mut self) {
+        LOCKED.lock().unwrap_or_else(PoisonError::into_inner).remove(&self.path);
+        RELEASED.notify_all();
+    }
+}
+
+/// Lock the path for a read-format-write cycle, the error tells that the file
+/// is still busy after the timeout
+pub fn lock(path: &Path) -> Result<PathLock, String> {
+    let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let deadline = Instant::now() + Duration::from_millis(limit_value(&TIMEOUT_MS, DEFAULT_TIMEOUT_MS) as u64);
+    let busy = || format!("{} is busy, another write is in progress", path.display());
+    {
+        let mut locked = LOCKED.lock().unwrap_or_else(PoisonError::into_inner);
+        while locked.contains(&key) {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(busy());
+            }
+            locked = RELEASED.wait_timeout(locked, deadline - now).unwrap_or_else(PoisonError::into_inner).0;
+        }
+        locked.insert(key.clone());
+    }
+    let mut lock = PathLock {
+        path: key,
+        _file: None,
+    };
+    // dropping the lock on error gives the path back to the other threads
+    lock._file = try!(advisory_lock(&lock.path, deadline).ok_or_else(busy));
+    Ok(lock)
+}
+
+/// Take the `flock` of the file, Some(None) when the file can't be opened
+/// (its read reports why) or locked and None when another process holds it
+/// until the deadline
+#[cfg(all(unix, feature = "ffi"))]
+fn advisory_lock(path: &Path, deadline: Instant) -> Option<Option<File>> {
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::thread;
+    use libc;
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Some(None),
+    };
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Some(Some(file));
+        }
+        // file systems without locks only get the process wide one
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EWOULDBLOCK) {
+            return Some(None);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(not(all(unix, feature = "ffi")))]
+fn advisory_lock(_: &Path, _: Instant) -> Option<Option<File>> {
+    Some(None)
+}
+
+/// Set how long (in milliseconds) to wait for a busy file, 0 gives up right
+/// away
+pub fn set_timeout(ms: usize) {
+    TIMEOUT_MS.store(ms.saturating_add(1), Ordering::Relaxed);
+}
+
+pub fn reset() {
+    TIMEOUT_MS.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use formatter::Formatter;
+    use testing::TempDir;
+    use super::*;
+
+    fn read(path: &Path) -> String {
+        let mut contents = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut contents)).unwrap();
+        contents
+    }
+
+    // the formatted version written by a thread, longer for every thread so
+    // an interleaved or truncated write can't look like one of them
+    fn version(id: usize, formatted: bool) -> String {
+        let statement = if formatted { format!("    let x = {};\n", id) } else { format!("    let x={};  \n", id) };
+        format!("fn main() {{\n{}}}\n", statement.repeat(id + 1))
+    }
+
+    fn is_formatted_version(contents: &str) -> bool {
+        (0..8).any(|id| contents == version(id, true))
+    }
+
+    #[test]
+    fn write_replaces_the_file_without_leaving_temporary_files() {
+        let dir = TempDir::new("lock-write");
+        let path = dir.write("main.rs", "fn main() {\n    let x=1;\n}\n");
+        lock(&path).unwrap().write(b"fn main() {}\n").unwrap();
+        let mut contents = String::new();
+        File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)).unwrap();
+        assert_eq!(contents, "fn main() {}\n");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn concurrent_writes_leave_one_formatted_version() {
+        let dir = TempDir::new("lock-hammer");
+        let path = dir.write("main.rs", &version(0, true));
+        let done = Arc::new(AtomicBool::new(false));
+        let writers: Vec<_> = (0..8)
+            .map(|id| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let formatter = Formatter::builder().build().unwrap();
+                    for _ in 0..25 {
+                        let lock = lock(&path).unwrap();
+                        assert!(is_formatted_version(&read(&path)));
+                        let output = formatter.format(&version(id, false)).unwrap();
+                        lock.write(output.text.as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let reader = {
+            let (path, done) = (path.clone(), done.clone());
+            // a reader without the lock sees the old or the new file, never a mix
+            thread::spawn(move || while !done.load(Ordering::SeqCst) {
+                assert!(is_formatted_version(&read(&path)));
+            })
+        };
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+        assert!(is_formatted_version(&read(&path)));
+        assert_eq!(fs::read_dir(&dir.path()).unwrap().count(), 1);
+    }
+}
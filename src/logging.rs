@@ -0,0 +1,229 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Route the `log` records of the crate to the host. Rust tools that use the
+//! crate install their own logger, C hosts get ours when they set a level.
+//! Until then the maximum level of the `log` crate is `Off` and the macros
+//! cost a single atomic load.
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use libc::{c_char, c_int};
+use log::{self, LogLevelFilter, LogMetadata, LogRecord, MaxLogLevelFilter};
+
+/// Receives the level (1 error to 5 trace) and the message of every record,
+/// the message is only valid during the call
+pub type Callback = extern fn(c_int, *const c_char);
+
+/// Where the records go
+enum Sink {
+    Stderr,
+    Callback(Callback),
+    File(File),
+}
+
+lazy_static! {
+    static ref SINK: Mutex<Sink> = Mutex::new(Sink::Stderr);
+    // set once our logger is installed, None while another logger (or none)
+    // receives the records
+    static ref MAX_LEVEL: Mutex<Option<MaxLogLevelFilter>> = Mutex::new(None);
+}
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= log::max_log_level()
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+        let mut sink = match SINK.lock() {
+            Ok(sink) => sink,
+            Err(_) => return,
+        };
+        let callback = match *sink {
+            Sink::Stderr => {
+                let _ = writeln!(io::stderr(), "{}", line);
+                return;
+            }
+            Sink::File(ref mut file) => {
+                let _ = writeln!(file, "{}", line);
+                return;
+            }
+            Sink::Callback(callback) => callback,
+        };
+        // the callback may log through us again
+        drop(sink);
+        if let Ok(message) = CString::new(format!("{}", record.args())) {
+            callback(record.level() as c_int, message.as_ptr());
+        }
+    }
+}
+
+pub fn level_from_c_int(level: c_int) -> Option<LogLevelFilter> {
+    match level {
+        0 => Some(LogLevelFilter::Off),
+        1 => Some(LogLevelFilter::Error),
+        2 => Some(LogLevelFilter::Warn),
+        3 => Some(LogLevelFilter::Info),
+        4 => Some(LogLevelFilter::Debug),
+        5 => Some(LogLevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// Install our logger (the first time) and set its level, false when the
+/// process already has another logger
+pub fn set_level(level: LogLevelFilter) -> bool {
+    let mut max_level = match MAX_LEVEL.lock() {
+        Ok(max_level) => max_level,
+        Err(_) => return false,
+    };
+    if max_level.is_none() {
+        let mut installed = None;
+        let _ = log::set_logger(|filter| {
+            installed = Some(filter);
+            Box::new(Logger)
+        });
+        *max_level = installed;
+    }
+    match *max_level {
+        Some(ref filter) => {
+            filter.set(level);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Send the records to the callback, None goes back to the standard error
+pub fn set_callback(callback: Option<Callback>) {
+    if let Ok(mut sink) = SINK.lock() {
+        *sink = callback.map_or(Sink::Stderr, Sink::Callback);
+    }
+}
+
+/// Append the records to the file, None goes back to the standard error
+pub fn set_file(path: Option<&Path>) -> io::Result<()> {
+    let sink = match path {
+        Some(path) => Sink::File(try!(OpenOptions::new().create(true).append(true).open(path))),
+        None => Sink::Stderr,
+    };
+    if let Ok(mut current) = SINK.lock() {
+        *current = sink;
+    }
+    Ok(())
+}
+
+/// Stop logging and go back to the standard error, the logger can't be
+/// removed once it is installed
+pub fn reset() {
+    if let Ok(max_level) = MAX_LEVEL.lock() {
+        if let Some(ref filter) = *max_level {
+            filter.set(LogLevelFilter::Off);
+        }
+    }
+    set_callback(None);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    use libc::{c_char, c_int};
+    use log::LogLevelFilter;
+
+    use testing::{lock_globals, TempDir};
+    use {format_string, FormatOptions, try_load_config};
+    use super::*;
+
+    lazy_static! {
+        static ref CAPTURED: Mutex<Vec<(c_int, String)>> = Mutex::new(Vec::new());
+    }
+
+    extern fn capture(level: c_int, message: *const c_char) {
+        let message = unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned();
+        CAPTURED.lock().unwrap().push((level, message));
+    }
+
+    #[test]
+    fn debug_level_traces_a_format_call() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("logging");
+        let path = dir.write("main.rs", "").to_string_lossy().into_owned();
+        assert!(set_level(LogLevelFilter::Debug));
+        set_callback(Some(capture));
+        let options = FormatOptions { config_path: Some(path), ..FormatOptions::default() };
+        let result = format_string("fn main() {}\n", &options);
+        reset();
+
+        assert!(result.is_ok());
+        let captured = CAPTURED.lock().unwrap();
+        let logged = |prefix: &str| {
+            captured.iter().any(|&(level, ref message)| level == 4 && message.starts_with(prefix))
+        };
+        assert!(logged("Looking up the project config from"));
+        assert!(logged("Formatting 13 bytes of"));
+        assert!(logged("Formatted 13 bytes"));
+    }
+
+    #[test]
+    fn records_can_go_to_a_file() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("logging-file");
+        let path = dir.write("anaconda.log", "");
+        assert!(set_level(LogLevelFilter::Warn));
+        set_file(Some(&path)).unwrap();
+        warn!("a warning");
+        debug!("too verbose");
+        reset();
+
+        let mut contents = String::new();
+        File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)).unwrap();
+        assert!(contents.contains("WARN rustanaconda::logging::tests: a warning\n"));
+        assert!(!contents.contains("too verbose"));
+    }
+
+    #[test]
+    fn a_directory_named_rustfmt_toml_is_skipped_with_a_warning() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("logging-config-dir");
+        dir.write("rustfmt.toml", "max_width = 90\n");
+        fs::create_dir_all(dir.path().join("project").join("rustfmt.toml")).unwrap();
+        let path = dir.write("project/main.rs", "").to_string_lossy().into_owned();
+        CAPTURED.lock().unwrap().clear();
+        assert!(set_level(LogLevelFilter::Warn));
+        set_callback(Some(capture));
+        let options = FormatOptions { config_path: Some(path.clone()), bypass_config_cache: true };
+        let result = format_string("fn main() {}\n", &options);
+        reset();
+
+        assert!(result.is_ok());
+        let warning = format!("{} is not a file, it is not used as the project config",
+                              fs::canonicalize(dir.path()).unwrap().join("project").join("rustfmt.toml").display());
+        assert!(CAPTURED.lock().unwrap().iter().any(|&(level, ref message)| level == 2 && *message == warning));
+        // the lookup goes on to the parent directory
+        assert_eq!(try_load_config(Some(path)).unwrap().max_width(), 90);
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal Language Server Protocol server so any LSP client can use the
+//! library as a formatter. Only the lifecycle (`initialize`, `shutdown` and
+//! `exit`), the full text synchronization of the documents and the
+//! `textDocument/formatting` and `textDocument/rangeFormatting` requests are
+//! supported, every other request gets a "method not found" error.
+//!
+//! The formatting results are a single `TextEdit` that replaces the lines
+//! between the common start and end of the document and its formatted text.
+//! Range formatting formats the top level items that the range touches.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use std::panic;
+
+use serde_json::{self, Value};
+
+use backend::Config;
+use {formatted_or_reason, items, render, render_with, try_project_config_with};
+use OutputOptions;
+
+// JSON-RPC and LSP error codes
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+// full document synchronization, every change carries the whole text
+const TEXT_DOCUMENT_SYNC_FULL: u64 = 1;
+
+/// Read the next message of the input, None when the input ends
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if try!(input.read_line(&mut header)) == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_right();
+        if header.is_empty() {
+            break;
+        }
+        let mut parts = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.trim().to_lowercase() == "content-length" {
+                length = value.trim().parse::<u64>().ok();
+            }
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")),
+    };
+
+    let mut body = Vec::new();
+    try!(input.by_ref().take(length).read_to_end(&mut body));
+    if body.len() as u64 != length {
+        return Ok(None);
+    }
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    try!(write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body));
+    output.flush()
+}
+
+fn error(code: i64, message: &str) -> Result<Value, (i64, String)> {
+    Err((code, String::from(message)))
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'...b'9' => Some(byte - b'0'),
+        b'a'...b'f' => Some(byte - b'a' + 10),
+        b'A'...b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Turn a `file://` URI into a path, percent escapes are decoded
+fn uri_to_path(uri: &str) -> String {
+    let path = if uri.starts_with("file://") { &uri[7..] } else { uri };
+    // Windows paths come as file:///C:/...
+    let path = if path.len() > 2 && path.as_bytes()[0] == b'/' && path.as_bytes()[2] == b':' {
+        &path[1..]
+    } else {
+        path
+    };
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            hex_digit(bytes[i + 1]).and_then(|high| hex_digit(bytes[i + 2]).map(|low| high << 4 | low))
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Number of UTF-16 code units of the text, LSP positions count them
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(|c| c.len_utf16()).sum()
+}
+
+/// Returns the edits that turn the original text into the formatted one, a
+/// single edit replacing the lines that are not common to the start and the
+/// end of both texts (or none when they are equal)
+fn text_edits(original: &str, formatted: &str) -> Value {
+    if original == formatted {
+        return json!([]);
+    }
+    let old: Vec<&str> = original.split('\n').collect();
+    let new: Vec<&str> = formatted.split('\n').collect();
+    let prefix = old.iter().zip(&new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b)
+        .count();
+
+    // every line but the last one ends with a newline
+    let (end, new_text) = if suffix > 0 {
+        let mut text = new[prefix..new.len() - suffix].join("\n");
+        if new.len() - suffix > prefix {
+            text.push('\n');
+        }
+        (json!({"line": old.len() - suffix, "character": 0}), text)
+    } else {
+        (json!({"line": old.len() - 1, "character": utf16_len(old[old.len() - 1])}),
+         new[prefix..].join("\n"))
+    };
+    json!([{
+        "range": {"start": {"line": prefix, "character": 0}, "end": end},
+        "newText": new_text,
+    }])
+}
+
+/// Resolve the configuration for the document, the editor options are only
+/// used when the project doesn't have a config file. The error tells why the
+/// configuration can't be read
+fn document_config(uri: &str, options: Option<&Value>) -> Result<Config, (i64, String)> {
+    let resolved = try_project_config_with(Some(uri_to_path(uri)), &[]).map_err(|e| (INTERNAL_ERROR, e.to_string()));
+    let (mut config, project_file) = try!(resolved);
+    if let (None, Some(options)) = (project_file, options) {
+        if let Some(tab_size) = options.get("tabSize").and_then(|tab_size| tab_size.as_u64()) {
+            if tab_size > 0 {
+                config.set_tab_spaces(tab_size as usize);
+            }
+        }
+        if let Some(insert_spaces) = options.get("insertSpaces").and_then(|spaces| spaces.as_bool()) {
+            config.set_hard_tabs(!insert_spaces);
+        }
+    }
+    Ok(config)
+}
+
+struct Server {
+    documents: HashMap<String, String>,
+    initialized: bool,
+    shutdown: bool,
+}
+
+impl Server {
+    fn document(&self, params: &Value) -> Result<(String, String), (i64, String)> {
+        let uri = params.get("textDocument").and_then(|document| document.get("uri")).and_then(|uri| uri.as_str());
+        let uri = match uri {
+            Some(uri) => uri,
+            None => return Err((INVALID_PARAMS, String::from("missing textDocument.uri"))),
+        };
+        match self.documents.get(uri) {
+            Some(text) => Ok((String::from(uri), text.clone())),
+            None => Err((INVALID_PARAMS, format!("unknown document {}", uri))),
+        }
+    }
+
+    fn format(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, text) = try!(self.document(params));
+        let config = try!(document_config(&uri, params.get("options")));
+        let (summary, output) = render(text.clone(), config, OutputOptions::default());
+        match formatted_or_reason(summary, output) {
+            Ok(output) => Ok(text_edits(&text, &output)),
+            Err(reason) => Err((INTERNAL_ERROR, reason)),
+        }
+    }
+
+    fn format_range(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, text) = try!(self.document(params));
+        let line = |position: &str| {
+            params.get("range")
+                .and_then(|range| range.get(position))
+                .and_then(|position| position.get("line"))
+                .and_then(|line| line.as_u64())
+        };
+        let (start, end) = match (line("start"), line("end")) {
+            (Some(start), Some(end)) if start <= end => (start as usize, end as usize),
+            _ => return error(INVALID_PARAMS, "invalid range"),
+        };
+        let config = try!(document_config(&uri, params.get("options")));
+        let lines: Vec<usize> = (start + 1..end + 2).collect();
+        let (summary, output) = render_with(text.clone(), config, OutputOptions::default(), |text, config| {
+            let (output, summary, _) = items::format_items(&text, config, &lines);
+            (summary, Some(output))
+        });
+        match output {
+            Some(ref output) if !summary.has_parsing_errors() => Ok(text_edits(&text, output)),
+            _ => error(INTERNAL_ERROR, "the code does not parse"),
+        }
+    }
+
+    fn sync(&mut self, method: &str, params: &Value) {
+        let document = params.get("textDocument");
+        let uri = match document.and_then(|document| document.get("uri")).and_then(|uri| uri.as_str()) {
+            Some(uri) => String::from(uri),
+            None => return,
+        };
+        let text = match method {
+            "textDocument/didOpen" => document.and_then(|document| document.get("text")),
+            "textDocument/didChange" => {
+                params.get("contentChanges")
+                    .and_then(|changes| changes.as_array())
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+            }
+            _ => {
+                self.documents.remove(&uri);
+                return;
+            }
+        };
+        if let Some(text) = text.and_then(|text| text.as_str()) {
+            self.documents.insert(uri, String::from(text));
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if self.shutdown {
+            return error(INVALID_REQUEST, "the server is shutting down");
+        }
+        if method == "initialize" {
+            self.initialized = true;
+            return Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": TEXT_DOCUMENT_SYNC_FULL,
+                    "documentFormattingProvider": true,
+                    "documentRangeFormattingProvider": true,
+                },
+                "serverInfo": {
+                    "name": "anaconda-rust",
+                    "version": option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"),
+                },
+            }));
+        }
+        if !self.initialized {
+            return error(SERVER_NOT_INITIALIZED, "the server is not initialized");
+        }
+        match method {
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/formatting" => self.format(params),
+            "textDocument/rangeFormatting" => self.format_range(params),
+            _ => Err((METHOD_NOT_FOUND, format!("method {} is not supported", method))),
+        }
+    }
+}
+
+/// Serve LSP messages from the input until the `exit` notification or the
+/// end of the input, the returned value is the exit code for the process: 0
+/// when `shutdown` was requested before exiting, 1 otherwise
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<i32> {
+    let mut server = Server {
+        documents: HashMap::new(),
+        initialized: false,
+        shutdown: false,
+    };
+    while let Some(message) = try!(read_message(&mut input)) {
+        let method = String::from(message.get("method").and_then(|method| method.as_str()).unwrap_or(""));
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            // notifications are never answered
+            None => {
+                match &method[..] {
+                    "exit" => return Ok(if server.shutdown { 0 } else { 1 }),
+                    "textDocument/didOpen" | "textDocument/didChange" | "textDocument/didClose" => {
+                        server.sync(&method, &params)
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        };
+
+        // a panic in rustfmt must not take the server down with it
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| server.request(&method, &params)));
+        let response = match result {
+            Ok(Ok(result)) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Ok(Err((code, message))) => {
+                json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+            }
+            Err(_) => {
+                let message = format!("{} panicked", method);
+                json!({"jsonrpc": "2.0", "id": id, "error": {"code": INTERNAL_ERROR, "message": message}})
+            }
+        };
+        try!(write_message(&mut output, &response));
+    }
+    Ok(if server.shutdown { 0 } else { 1 })
+}
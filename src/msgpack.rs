@@ -0,0 +1,259 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The subset of MessagePack that maps to JSON values, so the daemon can
+//! speak the same protocol without escaping multi-megabyte buffers. Strings
+//! and binaries are read as strings (they must be valid UTF-8), map keys must
+//! be strings and extension types are not supported.
+
+use std::io::{self, Read, Write};
+
+use serde_json::{Map, Number, Value};
+
+// arrays and maps nested deeper than this are rejected
+const MAX_DEPTH: usize = 64;
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Whether the byte starts a MessagePack map, a request always is one
+pub fn starts_map(byte: u8) -> bool {
+    byte & 0xf0 == 0x80 || byte == 0xde || byte == 0xdf
+}
+
+fn write_length<W: Write>(output: &mut W, len: usize, small: Option<(u8, usize)>, markers: [u8; 3]) -> io::Result<()> {
+    match small {
+        Some((marker, limit)) if len < limit => output.write_all(&[marker | len as u8]),
+        _ if len <= 0xff && markers[0] != 0 => output.write_all(&[markers[0], len as u8]),
+        _ if len <= 0xffff => output.write_all(&[markers[1], (len >> 8) as u8, len as u8]),
+        _ if len <= 0xffff_ffff => {
+            output.write_all(&[markers[2], (len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8])
+        }
+        _ => Err(invalid("value too long for MessagePack")),
+    }
+}
+
+fn write_number<W: Write>(output: &mut W, number: &Number) -> io::Result<()> {
+    if let Some(n) = number.as_u64() {
+        return match n {
+            0...0x7f => output.write_all(&[n as u8]),
+            0x80...0xff => output.write_all(&[0xcc, n as u8]),
+            0x100...0xffff => output.write_all(&[0xcd, (n >> 8) as u8, n as u8]),
+            0x1_0000...0xffff_ffff => {
+                output.write_all(&[0xce, (n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8])
+            }
+            _ => {
+                try!(output.write_all(&[0xcf]));
+                output.write_all(&be_bytes(n))
+            }
+        };
+    }
+    if let Some(n) = number.as_i64() {
+        if n >= -32 {
+            return output.write_all(&[n as u8]);
+        }
+        try!(output.write_all(&[0xd3]));
+        return output.write_all(&be_bytes(n as u64));
+    }
+    let n = number.as_f64().unwrap_or(0.0);
+    try!(output.write_all(&[0xcb]));
+    output.write_all(&be_bytes(n.to_bits()))
+}
+
+fn be_bytes(n: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (n >> (56 - 8 * i)) as u8;
+    }
+    bytes
+}
+
+/// Write the value encoded as MessagePack
+pub fn write<W: Write>(output: &mut W, value: &Value) -> io::Result<()> {
+    match *value {
+        Value::Null => output.write_all(&[0xc0]),
+        Value::Bool(false) => output.write_all(&[0xc2]),
+        Value::Bool(true) => output.write_all(&[0xc3]),
+        Value::Number(ref number) => write_number(output, number),
+        Value::String(ref string) => {
+            try!(write_length(output, string.len(), Some((0xa0, 32)), [0xd9, 0xda, 0xdb]));
+            output.write_all(string.as_bytes())
+        }
+        Value::Array(ref array) => {
+            try!(write_length(output, array.len(), Some((0x90, 16)), [0, 0xdc, 0xdd]));
+            for item in array {
+                try!(write(output, item));
+            }
+            Ok(())
+        }
+        Value::Object(ref map) => {
+            try!(write_length(output, map.len(), Some((0x80, 16)), [0, 0xde, 0xdf]));
+            for (key, item) in map {
+                try!(write(output, &Value::String(key.clone())));
+                try!(write(output, item));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn read_bytes<R: Read>(input: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    // read through `take` so a bogus length can't allocate gigabytes at once
+    try!(input.by_ref().take(len as u64).read_to_end(&mut bytes));
+    if bytes.len() != len {
+        return Err(invalid("truncated MessagePack value"));
+    }
+    Ok(bytes)
+}
+
+fn read_uint<R: Read>(input: &mut R, size: usize) -> io::Result<u64> {
+    let bytes = try!(read_bytes(input, size));
+    Ok(bytes.iter().fold(0, |n, &byte| n << 8 | byte as u64))
+}
+
+fn read_string<R: Read>(input: &mut R, len: usize) -> io::Result<Value> {
+    let bytes = try!(read_bytes(input, len));
+    String::from_utf8(bytes).map(Value::String).map_err(|_| invalid("MessagePack string is not valid UTF-8"))
+}
+
+fn read_array<R: Read>(input: &mut R, len: usize, depth: usize) -> io::Result<Value> {
+    let mut array = Vec::new();
+    for _ in 0..len {
+        array.push(try!(read_value(input, depth + 1)));
+    }
+    Ok(Value::Array(array))
+}
+
+fn read_map<R: Read>(input: &mut R, len: usize, depth: usize) -> io::Result<Value> {
+    let mut map = Map::new();
+    for _ in 0..len {
+        let key = match try!(read_value(input, depth + 1)) {
+            Value::String(key) => key,
+            _ => return Err(invalid("MessagePack map keys must be strings")),
+        };
+        let value = try!(read_value(input, depth + 1));
+        map.insert(key, value);
+    }
+    Ok(Value::Object(map))
+}
+
+fn float(n: f64) -> Value {
+    Number::from_f64(n).map(Value::Number).unwrap_or(Value::Null)
+}
+
+fn read_value<R: Read>(input: &mut R, depth: usize) -> io::Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(invalid("MessagePack value nested too deep"));
+    }
+    let marker = try!(read_uint(input, 1)) as u8;
+    match marker {
+        0x00...0x7f => Ok(Value::from(marker)),
+        0x80...0x8f => read_map(input, (marker & 0x0f) as usize, depth),
+        0x90...0x9f => read_array(input, (marker & 0x0f) as usize, depth),
+        0xa0...0xbf => read_string(input, (marker & 0x1f) as usize),
+        0xc0 => Ok(Value::Null),
+        0xc2 => Ok(Value::Bool(false)),
+        0xc3 => Ok(Value::Bool(true)),
+        0xc4 | 0xd9 => {
+            let len = try!(read_uint(input, 1)) as usize;
+            read_string(input, len)
+        }
+        0xc5 | 0xda => {
+            let len = try!(read_uint(input, 2)) as usize;
+            read_string(input, len)
+        }
+        0xc6 | 0xdb => {
+            let len = try!(read_uint(input, 4)) as usize;
+            read_string(input, len)
+        }
+        0xca => Ok(float(f32::from_bits(try!(read_uint(input, 4)) as u32) as f64)),
+        0xcb => Ok(float(f64::from_bits(try!(read_uint(input, 8))))),
+        0xcc => Ok(Value::from(try!(read_uint(input, 1)))),
+        0xcd => Ok(Value::from(try!(read_uint(input, 2)))),
+        0xce => Ok(Value::from(try!(read_uint(input, 4)))),
+        0xcf => Ok(Value::from(try!(read_uint(input, 8)))),
+        0xd0 => Ok(Value::from(try!(read_uint(input, 1)) as u8 as i8)),
+        0xd1 => Ok(Value::from(try!(read_uint(input, 2)) as u16 as i16)),
+        0xd2 => Ok(Value::from(try!(read_uint(input, 4)) as u32 as i32)),
+        0xd3 => Ok(Value::from(try!(read_uint(input, 8)) as i64)),
+        0xdc => {
+            let len = try!(read_uint(input, 2)) as usize;
+            read_array(input, len, depth)
+        }
+        0xdd => {
+            let len = try!(read_uint(input, 4)) as usize;
+            read_array(input, len, depth)
+        }
+        0xde => {
+            let len = try!(read_uint(input, 2)) as usize;
+            read_map(input, len, depth)
+        }
+        0xdf => {
+            let len = try!(read_uint(input, 4)) as usize;
+            read_map(input, len, depth)
+        }
+        0xe0...0xff => Ok(Value::from(marker as i8)),
+        _ => Err(invalid("unsupported MessagePack type")),
+    }
+}
+
+/// Read the next value of the input, None when the input ends before it
+pub fn read<R: Read>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut first = [0u8; 1];
+    loop {
+        match input.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    read_value(&mut (&first[..]).chain(input.by_ref()), 0).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{i64, u64};
+
+    use super::*;
+
+    fn round_trip(value: &Value) -> Value {
+        let mut encoded = Vec::new();
+        write(&mut encoded, value).unwrap();
+        read(&mut &encoded[..]).unwrap().unwrap()
+    }
+
+    #[test]
+    fn nested_maps_and_large_integers_round_trip() {
+        let long: String = (0..300).map(|_| 'x').collect();
+        let value = json!({
+            "request": {"id": 1, "params": {"path": "src/main.rs", "lines": [1, 2, [3, {"deep": null}]]}},
+            "integers": [u64::MAX, 0xffff_ffffu64, 0x1_0000_0000u64, -1, -33, i64::MIN, i64::MAX],
+            "floats": [0.5, -1.25e300],
+            "strings": ["", "é", long],
+            "flags": [true, false],
+        });
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn binaries_are_read_as_strings() {
+        let bin8 = [0x81, 0xa4, b'c', b'o', b'd', b'e', 0xc4, 0x03, b'f', b'n', b' '];
+        assert_eq!(read(&mut &bin8[..]).unwrap(), Some(json!({"code": "fn "})));
+        let bin16 = [0xc5, 0x00, 0x02, 0xc3, 0xa9];
+        assert_eq!(read(&mut &bin16[..]).unwrap(), Some(json!("é")));
+        assert!(read(&mut &[0xc4, 0x01, 0xff][..]).is_err());
+    }
+}
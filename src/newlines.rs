@@ -0,0 +1,108 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Line endings handling. librustfmt converts every newline of its output
+//! (even the ones inside string literals) when it writes Windows newlines, so
+//! we always format with Unix newlines and convert them ourselves, leaving the
+//! line endings that are part of a literal exactly as they were written.
+
+use backend::NewlineStyle;
+use scanner::{self, Kind};
+
+/// Count the (CRLF, LF) line endings of the buffer outside literals
+fn count(code: &str) -> (usize, usize) {
+    let kinds = scanner::kinds(code);
+    let bytes = code.as_bytes();
+    let (mut crlf, mut lf) = (0, 0);
+    for (i, &b) in bytes.iter().enumerate() {
+        if b != b'\n' || kinds[i] == Kind::Literal {
+            continue;
+        }
+        if i > 0 && bytes[i - 1] == b'\r' {
+            crlf += 1;
+        } else {
+            lf += 1;
+        }
+    }
+    (crlf, lf)
+}
+
+/// Returns the newline style used by most of the lines in the buffer, ties
+/// (and buffers without newlines at all) are resolved as Unix
+pub fn dominant(code: &str) -> NewlineStyle {
+    if !code.contains('\r') {
+        return NewlineStyle::Unix;
+    }
+    let (crlf, lf) = count(code);
+    if crlf > lf {
+        NewlineStyle::Windows
+    } else {
+        NewlineStyle::Unix
+    }
+}
+
+/// Whether the buffer mixes CRLF and LF line endings outside literals
+pub fn is_mixed(code: &str) -> bool {
+    if !code.contains('\r') {
+        return false;
+    }
+    let (crlf, lf) = count(code);
+    crlf > 0 && lf > 0
+}
+
+/// Replace every CRLF line ending outside literals with a bare LF, buffers
+/// without CR characters are returned as they are
+pub fn normalize(code: String) -> String {
+    if !code.contains('\r') {
+        return code;
+    }
+
+    let kinds = scanner::kinds(&code);
+    let bytes = code.as_bytes();
+    let mut output = String::with_capacity(code.len());
+    let mut last = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'\n' && i > 0 && bytes[i - 1] == b'\r' && kinds[i] != Kind::Literal {
+            output.push_str(&code[last..i - 1]);
+            last = i;
+        }
+    }
+    output.push_str(&code[last..]);
+    output
+}
+
+/// Write the LF line endings outside literals using the given style
+pub fn restore(code: String, style: NewlineStyle) -> String {
+    let windows = match style {
+        NewlineStyle::Windows => true,
+        NewlineStyle::Unix => false,
+        NewlineStyle::Native => cfg!(windows),
+    };
+    if !windows {
+        return code;
+    }
+
+    let kinds = scanner::kinds(&code);
+    let bytes = code.as_bytes();
+    let mut output = String::with_capacity(code.len() + code.len() / 32);
+    let mut last = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'\n' && kinds[i] != Kind::Literal && !(i > 0 && bytes[i - 1] == b'\r') {
+            output.push_str(&code[last..i]);
+            output.push_str("\r\n");
+            last = i + 1;
+        }
+    }
+    output.push_str(&code[last..]);
+    output
+}
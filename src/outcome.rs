@@ -0,0 +1,152 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The result of a format call as a single serializable value, the Rust API
+//! returns it and the exported functions and the daemon send it as JSON, so
+//! every host sees the same fields.
+//!
+//! The schema only grows: new fields must be optional (`#[serde(default)]`)
+//! so older payloads still deserialize, and existing fields never change
+//! their name, type or meaning. A change that can't follow those rules bumps
+//! `SCHEMA_VERSION`, hosts check `schema_version` before reading the rest.
+
+use serde_json;
+
+use {FormatError, FormatOutput};
+
+/// Version of the serialized schema, see the module documentation
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Something the host may want to show to the user
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// How long (in milliseconds) each step of the call took
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+    /// Resolving the project configuration
+    pub config_ms: u64,
+    /// Formatting the code
+    pub format_ms: u64,
+}
+
+/// The original lines `start_line..end_line` (0-based, the end excluded) are
+/// replaced by `lines`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Edit {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub lines: Vec<String>,
+}
+
+/// Result of `format_outcome`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FormatOutcome {
+    pub schema_version: u32,
+    /// The status code that the exported functions return
+    pub status: i32,
+    /// Whether formatting changed the input
+    pub changed: bool,
+    /// Whether rustfmt left some lines longer than `max_width`
+    pub formatting_errors: bool,
+    /// Whether rustfmt was skipped because the input was already formatted
+    pub cached: bool,
+    /// The formatted code, None when the input can't be formatted
+    pub output: Option<String>,
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(default)]
+    pub timings: Timings,
+    /// The edits that turn the input into the output
+    #[serde(default)]
+    pub edits: Vec<Edit>,
+}
+
+impl FormatOutcome {
+    /// Describe the result of formatting the original code
+    pub fn new(original: &str, result: Result<FormatOutput, FormatError>, timings: Timings) -> FormatOutcome {
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => {
+                return FormatOutcome {
+                    schema_version: SCHEMA_VERSION,
+                    status: e.status(),
+                    changed: false,
+                    formatting_errors: false,
+                    cached: false,
+                    output: None,
+                    diagnostics: vec![Diagnostic {
+                                          severity: Severity::Error,
+                                          message: e.to_string(),
+                                      }],
+                    timings: timings,
+                    edits: Vec::new(),
+                }
+            }
+        };
+        let mut diagnostics = Vec::new();
+        if output.formatting_errors {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                message: String::from("some lines are still longer than max_width"),
+            });
+        }
+        FormatOutcome {
+            schema_version: SCHEMA_VERSION,
+            status: output.status(),
+            changed: output.changed,
+            formatting_errors: output.formatting_errors,
+            cached: output.cached,
+            edits: edits(original, &output.text),
+            output: Some(output.text),
+            diagnostics: diagnostics,
+            timings: timings,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// A single edit replacing the lines that are not common to the start and
+/// the end of both texts, none when they are equal
+pub fn edits(original: &str, formatted: &str) -> Vec<Edit> {
+    if original == formatted {
+        return Vec::new();
+    }
+    let old: Vec<&str> = original.split('\n').collect();
+    let new: Vec<&str> = formatted.split('\n').collect();
+    let prefix = old.iter().zip(&new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    vec![Edit {
+             start_line: prefix,
+             end_line: old.len() - suffix,
+             lines: new[prefix..new.len() - suffix].iter().map(|line| String::from(*line)).collect(),
+         }]
+}
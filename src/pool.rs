@@ -0,0 +1,153 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A bounded pool of worker threads for the calls that format many files
+//! (`format_cargo_project`, `format_dir`, `check_project`, `check_staged`).
+//! There is a worker per CPU unless the host sets a lower cap with
+//! `set_max_threads`, the files are handed out one at a time and the results
+//! come back in the order of the files. A file whose formatting panics gets
+//! an error of its own, the other files are formatted as usual.
+
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::mpsc;
+use std::thread;
+
+use num_cpus;
+
+use limit_value;
+
+// maximum number of workers plus one, 0 and 1 mean one per CPU
+static MAX_THREADS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Cap the number of workers, 0 goes back to one per CPU
+pub fn set_max_threads(n: usize) {
+    MAX_THREADS.store(n.saturating_add(1), Ordering::Relaxed);
+}
+
+pub fn reset() {
+    MAX_THREADS.store(0, Ordering::Relaxed);
+}
+
+/// How many workers a call gets
+pub fn threads() -> usize {
+    let cpus = cmp::max(num_cpus::get(), 1);
+    match limit_value(&MAX_THREADS, 0) {
+        0 => cpus,
+        cap => cmp::min(cpus, cap),
+    }
+}
+
+fn run<T, R, F: Fn(T) -> R>(work: &F, item: T) -> thread::Result<R> {
+    panic::catch_unwind(AssertUnwindSafe(|| work(item)))
+}
+
+/// Run `work` over the items on the workers and return the results in the
+/// order of the items, Err for the items whose work panicked. A single item
+/// (or worker) runs on the calling thread
+pub fn map<T, R, F>(items: Vec<T>, work: F) -> Vec<thread::Result<R>>
+    where T: Send + 'static,
+          R: Send + 'static,
+          F: Fn(T) -> R + Send + Sync + 'static
+{
+    map_on(threads(), items, work)
+}
+
+fn map_on<T, R, F>(workers: usize, items: Vec<T>, work: F) -> Vec<thread::Result<R>>
+    where T: Send + 'static,
+          R: Send + 'static,
+          F: Fn(T) -> R + Send + Sync + 'static
+{
+    let len = items.len();
+    let workers = cmp::min(workers, len);
+    if workers <= 1 {
+        return items.into_iter().map(|item| run(&work, item)).collect();
+    }
+
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let work = Arc::new(work);
+    let (sender, receiver) = mpsc::channel();
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let (queue, work, sender) = (queue.clone(), work.clone(), sender.clone());
+            thread::spawn(move || {
+                loop {
+                    // the lock is never held while an item is worked on
+                    let next = queue.lock().ok().and_then(|mut queue| queue.next());
+                    let (i, item) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    let _ = sender.send((i, run(&*work, item)));
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut results: Vec<Option<thread::Result<R>>> = (0..len).map(|_| None).collect();
+    for (i, result) in receiver {
+        results[i] = Some(result);
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    // a worker sends a result for every item that it takes
+    results.into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(Box::new("the worker stopped"))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use testing::lock_globals;
+    use super::*;
+
+    #[test]
+    fn the_results_are_in_the_order_of_the_items() {
+        // the first items take the longest so they end last
+        let results = map_on(4, (0..8).collect(), |i: u64| {
+            thread::sleep(Duration::from_millis(40 - i * 5));
+            i * 10
+        });
+        let results: Vec<u64> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, vec![0, 10, 20, 30, 40, 50, 60, 70]);
+    }
+
+    #[test]
+    fn a_panic_fails_only_its_own_item() {
+        let results = map_on(2, vec![1, 2, 3], |i: i32| {
+            if i == 2 {
+                panic!("formatting panicked");
+            }
+            i
+        });
+        assert_eq!(results[0].as_ref().ok(), Some(&1));
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().ok(), Some(&3));
+    }
+
+    #[test]
+    fn the_workers_are_capped() {
+        let _globals = lock_globals();
+        set_max_threads(1);
+        assert_eq!(threads(), 1);
+        set_max_threads(0);
+        assert!(threads() >= 1);
+    }
+}
@@ -0,0 +1,1029 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Format a whole Cargo package. The targets are the ones listed in the
+//! manifest plus the ones that Cargo discovers by convention (`src/lib.rs`,
+//! `src/main.rs`, `src/bin`, `examples`, `tests`, `benches` and `build.rs`)
+//! and every file reachable through their `mod` declarations is formatted.
+//! Path dependencies inside the package directory are formatted as packages
+//! of their own, anything outside of it is skipped with a diagnostic.
+//!
+//! The manifest is read line by line like `edition` does, that is enough for
+//! the target tables and the dependencies and it never fails on the rest.
+//!
+//! `format_dir` is the blunt version for builds without Cargo: every file of
+//! a directory that matches the include globs and none of the exclude globs.
+//! `format_file` is the other way around, a single file and optionally the
+//! modules that it declares.
+//!
+//! Both skip generated code (protobuf, bindgen...): the files with a marker
+//! like `@generated` or `DO NOT EDIT` in their first lines and the ones that
+//! match the globs of `Generated`. Only the head of every file is read.
+//!
+//! The files are formatted on the workers of `pool`, the reports list them
+//! in the same order whatever worker ends first.
+
+use std::cmp;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use {size_limit_error, FormatError, PROJECT_NEEDS_FORMAT};
+use changes;
+use formatter::Formatter;
+use ignore::{self, glob_match};
+use items;
+use lock;
+use pool;
+use scanner::{self, Kind};
+use udiff;
+
+/// What to do with the files that are not formatted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Report them
+    Check,
+    /// Format them in place
+    Overwrite,
+    /// Report them with the unified diff of the changes
+    Diff,
+}
+
+impl Mode {
+    pub fn from_c_int(mode: i32) -> Option<Mode> {
+        match mode {
+            0 => Some(Mode::Check),
+            1 => Some(Mode::Overwrite),
+            2 => Some(Mode::Diff),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Formatted,
+    NeedsFormat,
+    Reformatted,
+    /// The file does not parse
+    ParseError,
+    /// Another write of the file did not end before the lock timeout
+    Busy,
+    Error,
+}
+
+/// What happened to a file of the package
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FileReport {
+    pub path: String,
+    pub status: FileStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// How many lines formatting changes (or changed) when it changes any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed_lines: Option<usize>,
+    /// The changes that formatting makes, only in `Diff` mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+/// Result of `format_cargo_project`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProjectReport {
+    pub files: Vec<FileReport>,
+    /// Targets, modules and dependencies that were skipped and why
+    pub diagnostics: Vec<String>,
+    /// Files skipped because they are generated
+    #[serde(default)]
+    pub generated: Vec<String>,
+}
+
+impl ProjectReport {
+    /// Whether some file needs formatting (it never happens in `Overwrite`
+    /// mode) and whether some file or the manifest could not be processed
+    pub fn failures(&self) -> (bool, bool) {
+        let needs_format = self.files.iter().any(|file| file.status == FileStatus::NeedsFormat);
+        let errors = self.files.iter().any(|file| {
+            file.status == FileStatus::Error || file.status == FileStatus::ParseError || file.status == FileStatus::Busy
+        });
+        (needs_format, errors)
+    }
+}
+
+/// A file that needs formatting
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Offender {
+    pub path: String,
+    pub changed_lines: usize,
+}
+
+/// A file that could not be checked
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FileError {
+    pub path: String,
+    pub error: String,
+}
+
+/// Result of `check_project`, the files are sorted by path
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CheckReport {
+    pub needs_format: Vec<Offender>,
+    /// Files that don't parse, they can't be checked
+    pub parse_errors: Vec<FileError>,
+    /// Files that can't be checked for any other reason
+    pub errors: Vec<FileError>,
+    pub diagnostics: Vec<String>,
+    /// Files skipped because they are generated
+    #[serde(default)]
+    pub generated: Vec<String>,
+}
+
+impl CheckReport {
+    fn new(report: ProjectReport) -> CheckReport {
+        let mut check = CheckReport {
+            diagnostics: report.diagnostics,
+            generated: report.generated,
+            ..CheckReport::default()
+        };
+        for file in report.files {
+            let error = || {
+                FileError {
+                    path: file.path.clone(),
+                    error: file.error.clone().unwrap_or_default(),
+                }
+            };
+            match file.status {
+                FileStatus::NeedsFormat | FileStatus::Reformatted => {
+                    check.needs_format.push(Offender {
+                        path: file.path.clone(),
+                        changed_lines: file.changed_lines.unwrap_or(0),
+                    })
+                }
+                FileStatus::ParseError => check.parse_errors.push(error()),
+                FileStatus::Error | FileStatus::Busy => check.errors.push(error()),
+                FileStatus::Formatted => {}
+            }
+        }
+        check
+    }
+
+    /// 0 when every file is formatted, `PROJECT_NEEDS_FORMAT` when some file
+    /// needs formatting, 2 when some file doesn't parse and 1 when some file
+    /// can't be checked
+    pub fn status(&self) -> i32 {
+        if !self.errors.is_empty() {
+            1
+        } else if !self.parse_errors.is_empty() {
+            2
+        } else if !self.needs_format.is_empty() {
+            PROJECT_NEEDS_FORMAT
+        } else {
+            0
+        }
+    }
+}
+
+/// The targets and the path dependencies of a manifest
+#[derive(Debug, Default)]
+struct Manifest {
+    is_package: bool,
+    // explicit target paths, relative to the package directory
+    targets: Vec<String>,
+    // None when `build = false`
+    build: Option<String>,
+    dependencies: Vec<String>,
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim().trim_matches('"').trim_matches('\'')
+}
+
+/// The value of `key` in an inline table like `{ path = "../a", version = "1" }`
+fn inline_value<'a>(table: &'a str, key: &str) -> Option<&'a str> {
+    let table = table.trim().trim_left_matches('{').trim_right_matches('}');
+    table.split(',').filter_map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        match (parts.next().map(str::trim), parts.next()) {
+            (Some(name), Some(value)) if name == key => Some(unquote(value)),
+            _ => None,
+        }
+    }).next()
+}
+
+fn parse_manifest(manifest: &str) -> Manifest {
+    let mut parsed = Manifest { build: Some(String::from("build.rs")), ..Manifest::default() };
+    let mut section = String::new();
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            section = String::from(line.trim_matches(|c| c == '[' || c == ']').trim());
+            parsed.is_package |= section == "package";
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let (key, value) = match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+            (Some(key), Some(value)) if !key.starts_with('#') => (key, value),
+            _ => continue,
+        };
+        let is_target = ["lib", "bin", "example", "test", "bench"].contains(&&section[..]);
+        if is_target && key == "path" {
+            parsed.targets.push(String::from(unquote(value)));
+        } else if section == "package" && key == "build" {
+            parsed.build = if value == "false" { None } else { Some(String::from(unquote(value))) };
+        } else if section.ends_with("dependencies") && value.starts_with('{') {
+            // `name = { path = "..." }` in a dependencies table
+            if let Some(path) = inline_value(value, "path") {
+                parsed.dependencies.push(String::from(path));
+            }
+        } else if section.contains("dependencies.") && key == "path" {
+            // `path = "..."` in a `[dependencies.name]` table
+            parsed.dependencies.push(String::from(unquote(value)));
+        }
+    }
+    parsed
+}
+
+/// The `.rs` files of a target directory and the `main.rs` of its
+/// subdirectories, the way Cargo discovers binaries, examples, tests and
+/// benches
+fn discover(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut found: Vec<PathBuf> = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            if path.is_dir() {
+                Some(path.join("main.rs")).filter(|main| main.is_file())
+            } else if path.extension().map_or(false, |ext| ext == "rs") {
+                Some(path)
+            } else {
+                None
+            }
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+/// The crate roots of the package, the diagnostics tell about the explicit
+/// targets that don't exist
+fn entry_points(dir: &Path, manifest: &Manifest, diagnostics: &mut Vec<String>) -> Vec<PathBuf> {
+    let mut entries = Vec::new();
+    for target in &manifest.targets {
+        let path = dir.join(target);
+        if path.is_file() {
+            entries.push(path);
+        } else {
+            diagnostics.push(format!("target {} not found, skipped", path.display()));
+        }
+    }
+    for root in &["src/lib.rs", "src/main.rs"] {
+        entries.push(dir.join(root));
+    }
+    for subdir in &["src/bin", "examples", "tests", "benches"] {
+        entries.extend(discover(&dir.join(subdir)));
+    }
+    if let Some(ref build) = manifest.build {
+        entries.push(dir.join(build));
+    }
+    entries.retain(|path| path.is_file());
+    entries
+}
+
+/// The directory of the modules declared by the file
+fn module_dir(file: &Path, is_root: bool) -> PathBuf {
+    let parent = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    match file.file_stem() {
+        Some(stem) if !is_root && stem != "mod" => parent.join(stem),
+        _ => parent,
+    }
+}
+
+/// The value of a `#[path = "..."]` attribute starting at `start`
+fn path_attribute(code: &str, masked: &str, start: usize) -> Option<String> {
+    let rest = masked[start + 1..].trim_left();
+    let rest = if rest.starts_with('[') { rest[1..].trim_left() } else { return None };
+    let rest = if rest.starts_with("path") { rest["path".len()..].trim_left() } else { return None };
+    let rest = if rest.starts_with('=') { &rest[1..] } else { return None };
+    // the literal is masked, its value is read from the code
+    let value = code[masked.len() - rest.len()..].trim_left();
+    if !value.starts_with('"') {
+        return None;
+    }
+    value[1..].find('"').map(|end| String::from(&value[1..end + 1]))
+}
+
+/// The files of the `mod name;` declarations of the file, the candidates are
+/// listed in the order that rustc tries them
+fn declared_modules(code: &str, file: &Path, is_root: bool) -> Vec<(String, Vec<PathBuf>)> {
+    let kinds = scanner::kinds(code);
+    let masked: Vec<u8> = code.as_bytes()
+        .iter()
+        .zip(&kinds)
+        .map(|(&b, &kind)| if kind == Kind::Code { b } else { b' ' })
+        .collect();
+    let masked = String::from_utf8_lossy(&masked).into_owned();
+    let words = items::idents(&masked);
+
+    let mut found = Vec::new();
+    // inline modules and the depth of their body
+    let mut inline: Vec<(&str, usize)> = Vec::new();
+    let mut path = None;
+    let mut depth = 0;
+    let mut word = 0;
+    for (i, b) in masked.bytes().enumerate() {
+        match b {
+            b'#' => path = path_attribute(code, &masked, i).or(path),
+            b'{' => {
+                depth += 1;
+                path = None;
+            }
+            b'}' => {
+                if inline.last().map_or(false, |&(_, body)| body == depth) {
+                    inline.pop();
+                }
+                depth -= if depth > 0 { 1 } else { 0 };
+                path = None;
+            }
+            b';' => path = None,
+            _ => {}
+        }
+        while word < words.len() && words[word].0 < i {
+            word += 1;
+        }
+        if word + 1 >= words.len() || words[word].0 != i || words[word].1 != "mod" {
+            continue;
+        }
+        let (name_start, name) = words[word + 1];
+        let next = masked[name_start + name.len()..].trim_left().bytes().next();
+        let mut dir = module_dir(file, is_root);
+        for &(module, _) in &inline {
+            dir.push(module);
+        }
+        match (next, path.take()) {
+            (Some(b'{'), _) => inline.push((name, depth + 1)),
+            // outside inline modules the path is relative to the file
+            (Some(b';'), Some(path)) => {
+                let base = if inline.is_empty() { module_dir(file, true) } else { dir };
+                found.push((String::from(name), vec![base.join(path)]));
+            }
+            (Some(b';'), None) => {
+                found.push((String::from(name),
+                            vec![dir.join(format!("{}.rs", name)), dir.join(name).join("mod.rs")]));
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+/// How many lines of the original text are changed or removed by formatting
+/// plus how many lines are added
+fn changed_lines(original: &str, formatted: &str) -> usize {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = formatted.lines().collect();
+    changes::hunks(&old, &new).iter().map(|&((a, b), (c, d))| cmp::max(b - a, d - c)).sum()
+}
+
+/// Format the files like `format_one` does on the workers of the pool, the
+/// reports are added in the order of the files. The files bigger than the
+/// input size limit are skipped with a diagnostic
+fn add_files(report: &mut ProjectReport, files: Vec<(PathBuf, Option<String>)>, mode: Mode) {
+    let mut jobs = Vec::new();
+    for (path, contents) in files {
+        let size = match contents {
+            Some(ref contents) => contents.len(),
+            None => fs::metadata(&path).map(|metadata| metadata.len() as usize).unwrap_or(0),
+        };
+        match size_limit_error(size) {
+            Some(reason) => report.diagnostics.push(format!("{} skipped: {}", path.display(), reason)),
+            None => jobs.push((path, contents)),
+        }
+    }
+    let paths: Vec<String> = jobs.iter().map(|&(ref path, _)| path.to_string_lossy().into_owned()).collect();
+    let results = pool::map(jobs, move |(path, contents)| format_one(&path, contents, mode));
+    for (path, result) in paths.into_iter().zip(results) {
+        report.files.push(result.unwrap_or_else(|_| {
+            FileReport {
+                path: path,
+                status: FileStatus::Error,
+                error: Some(String::from("formatting the file panicked")),
+                changed_lines: None,
+                diff: None,
+            }
+        }));
+    }
+}
+
+/// Format (or check) a single file, a file that was formatted the last time
+/// it was seen is not formatted again. The contents, if given, are checked
+/// instead of the file (that may not exist), they are never written
+fn format_one(path: &Path, contents: Option<String>, mode: Mode) -> FileReport {
+    let mut report = FileReport {
+        path: path.to_string_lossy().into_owned(),
+        status: FileStatus::Formatted,
+        error: None,
+        changed_lines: None,
+        diff: None,
+    };
+    let mode = if contents.is_some() && mode == Mode::Overwrite { Mode::Check } else { mode };
+    // the config of a file that doesn't exist is the one of its directory
+    let config_path = match path.parent() {
+        Some(dir) if !path.is_file() && !dir.as_os_str().is_empty() => dir.to_string_lossy().into_owned(),
+        _ => report.path.clone(),
+    };
+    // held until the file is written
+    let lock = match mode {
+        Mode::Overwrite => {
+            match lock::lock(path) {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    report.status = FileStatus::Busy;
+                    report.error = Some(e);
+                    return report;
+                }
+            }
+        }
+        _ => None,
+    };
+    let code = match contents {
+        Some(contents) => Ok(contents),
+        None => read(path).map_err(FormatError::Internal),
+    };
+    let result = code.and_then(|code| {
+        let formatter = try!(Formatter::builder().config_path(config_path).build());
+        let output = try!(formatter.format_buffer(code.clone(), Some(report.path.clone())));
+        Ok((code, output.text))
+    });
+    let (code, formatted) = match result {
+        Ok((code, formatted)) => (code, formatted),
+        Err(e) => {
+            report.status = match e {
+                FormatError::InvalidInput(_) => FileStatus::ParseError,
+                _ => FileStatus::Error,
+            };
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+    if code == formatted {
+        return report;
+    }
+    report.changed_lines = Some(changed_lines(&code, &formatted));
+    match mode {
+        Mode::Check => report.status = FileStatus::NeedsFormat,
+        Mode::Diff => {
+            report.status = FileStatus::NeedsFormat;
+            report.diff = Some(udiff::unified(&report.path, &code, &formatted));
+        }
+        Mode::Overwrite => {
+            match lock.map_or(Ok(()), |lock| lock.write(formatted.as_bytes())) {
+                Ok(()) => report.status = FileStatus::Reformatted,
+                Err(e) => {
+                    report.status = FileStatus::Error;
+                    report.error = Some(format!("can not write the file: {}", e));
+                }
+            }
+        }
+    }
+    report
+}
+
+// how many lines of a file are searched for the generated markers, a line
+// is never read past `GENERATED_HEAD_BYTES` bytes of the file
+const GENERATED_HEAD_LINES: usize = 10;
+const GENERATED_HEAD_BYTES: u64 = 4096;
+
+/// How generated files are told apart
+#[derive(Clone, Debug)]
+pub struct Generated {
+    /// Text searched in every line of the head of a file, `@generated` and
+    /// `DO NOT EDIT` by default
+    pub markers: Vec<String>,
+    /// Globs of the paths of generated files, relative to the directory or to
+    /// the package
+    pub globs: Vec<String>,
+    /// Format the generated files too
+    pub include: bool,
+}
+
+impl Default for Generated {
+    fn default() -> Generated {
+        Generated {
+            markers: vec![String::from("@generated"), String::from("DO NOT EDIT")],
+            globs: Vec::new(),
+            include: false,
+        }
+    }
+}
+
+impl Generated {
+    /// Whether the file is generated, `relative` is its path relative to the
+    /// walked directory with `/` separators
+    pub fn is_generated(&self, path: &Path, relative: &str) -> bool {
+        if self.include {
+            return false;
+        }
+        if self.globs.iter().any(|glob| glob_match(glob.as_bytes(), relative.as_bytes())) {
+            return true;
+        }
+        if self.markers.is_empty() {
+            return false;
+        }
+        let head = match File::open(path) {
+            Ok(file) => BufReader::new(file.take(GENERATED_HEAD_BYTES)),
+            Err(_) => return false,
+        };
+        // the bytes that are not UTF-8 end the head
+        head.lines()
+            .take(GENERATED_HEAD_LINES)
+            .take_while(Result::is_ok)
+            .filter_map(Result::ok)
+            .any(|line| self.markers.iter().any(|marker| line.contains(&marker[..])))
+    }
+}
+
+/// The path relative to the directory, with `/` separators
+fn relative_to(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).to_string_lossy().replace('\\', "/")
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    let mut code = String::new();
+    try!(File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut code))
+        .map_err(|e| format!("can not read {}: {}", path.display(), e)));
+    Ok(code)
+}
+
+/// Add the files (and whether they are crate roots) and every file reachable
+/// through their `mod` declarations, the files already collected are not
+/// walked again
+fn walk_modules(mut pending: Vec<(PathBuf, bool)>, files: &mut BTreeSet<PathBuf>, diagnostics: &mut Vec<String>) {
+    while let Some((file, is_root)) = pending.pop() {
+        if !files.insert(file.clone()) {
+            continue;
+        }
+        // a file that can't be read is reported when it is formatted
+        let code = read(&file).unwrap_or_default();
+        for (name, candidates) in declared_modules(&code, &file, is_root) {
+            // `#[path]` attributes with `..` would hide a cycle
+            let found = candidates.iter()
+                .find(|path| path.is_file())
+                .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()));
+            match found {
+                Some(ref path) if files.contains(path) => {
+                    diagnostics.push(format!("module {} of {} ({}) is already included, skipped",
+                                             name,
+                                             file.display(),
+                                             path.display()));
+                }
+                Some(path) => pending.push((path, false)),
+                None => {
+                    diagnostics.push(format!("module {} of {} not found, skipped", name, file.display()));
+                }
+            }
+        }
+    }
+}
+
+/// Collect the files of the package (and of its path dependencies), the
+/// manifests already visited are skipped
+fn collect(manifest_path: &Path,
+           files: &mut BTreeSet<PathBuf>,
+           visited: &mut BTreeSet<PathBuf>,
+           diagnostics: &mut Vec<String>)
+           -> Result<(), String> {
+    let dir = manifest_path.parent().map(Path::to_path_buf).unwrap_or_default();
+    if !visited.insert(dir.clone()) {
+        return Ok(());
+    }
+    let manifest = parse_manifest(&try!(read(manifest_path)));
+    if !manifest.is_package {
+        diagnostics.push(format!("{} has no [package] section, skipped", manifest_path.display()));
+        return Ok(());
+    }
+
+    let entries = entry_points(&dir, &manifest, diagnostics).into_iter().map(|path| (path, true)).collect();
+    walk_modules(entries, files, diagnostics);
+
+    for dependency in &manifest.dependencies {
+        let dependency_dir = dir.join(dependency);
+        let inside = match (dependency_dir.canonicalize(), dir.canonicalize()) {
+            (Ok(dependency_dir), Ok(dir)) => dependency_dir.starts_with(&dir),
+            _ => false,
+        };
+        if !inside {
+            diagnostics.push(format!("path dependency {} is outside the package, skipped", dependency));
+            continue;
+        }
+        if let Err(e) = collect(&dependency_dir.join("Cargo.toml"), files, visited, diagnostics) {
+            diagnostics.push(format!("path dependency {} skipped: {}", dependency, e));
+        }
+    }
+    Ok(())
+}
+
+/// Format every file of the Cargo package, `manifest` is its `Cargo.toml` or
+/// the package directory. Every file is formatted with the configuration of
+/// its own project, the generated ones are skipped (their modules are still
+/// walked). The error tells why the manifest could not be read
+pub fn format_cargo_project(manifest: &Path, generated: &Generated, mode: Mode) -> Result<ProjectReport, String> {
+    let manifest = if manifest.is_dir() { manifest.join("Cargo.toml") } else { manifest.to_path_buf() };
+    let manifest = &manifest;
+    let dir = manifest.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut files = BTreeSet::new();
+    let mut report = ProjectReport::default();
+    try!(collect(manifest, &mut files, &mut BTreeSet::new(), &mut report.diagnostics));
+    let mut jobs = Vec::new();
+    for path in files {
+        if generated.is_generated(&path, &relative_to(&dir, &path)) {
+            report.generated.push(path.to_string_lossy().into_owned());
+        } else {
+            jobs.push((path, None));
+        }
+    }
+    add_files(&mut report, jobs, mode);
+    Ok(report)
+}
+
+/// Format the file and, with `follow_mods`, every file reachable through its
+/// `mod` declarations like the rustfmt command does. `lib.rs`, `main.rs` and
+/// `mod.rs` declare the modules of their own directory, any other file the
+/// ones of the directory named after it. Every file is formatted with the
+/// configuration of its own project, the modules that can't be found or that
+/// are already included (e.g. `#[path]` cycles) are skipped with a diagnostic
+pub fn format_file(path: &Path, follow_mods: bool, mode: Mode) -> ProjectReport {
+    let mut report = ProjectReport::default();
+    if !follow_mods {
+        add_files(&mut report, vec![(path.to_path_buf(), None)], mode);
+        return report;
+    }
+    let is_crate_root = path.file_name().map_or(false, |name| name == "lib.rs" || name == "main.rs");
+    let mut files = BTreeSet::new();
+    walk_modules(vec![(path.to_path_buf(), is_crate_root)], &mut files, &mut report.diagnostics);
+    add_files(&mut report, files.into_iter().map(|file| (file, None)).collect(), mode);
+    report
+}
+
+/// Options of `format_dir`
+#[derive(Clone, Debug)]
+pub struct DirOptions {
+    /// Globs of the files to format (`**/*.rs` by default), relative to the
+    /// directory
+    pub includes: Vec<String>,
+    /// Globs of the files to leave alone, relative to the directory
+    pub excludes: Vec<String>,
+    /// Skip the files that git ignores and `target`
+    pub honor_ignores: bool,
+    /// Walk symlinked directories, a directory is never walked twice
+    pub follow_links: bool,
+    pub generated: Generated,
+}
+
+impl Default for DirOptions {
+    fn default() -> DirOptions {
+        DirOptions {
+            includes: vec![String::from("**/*.rs")],
+            excludes: Vec::new(),
+            honor_ignores: true,
+            follow_links: false,
+            generated: Generated::default(),
+        }
+    }
+}
+
+/// Format every file under the directory that matches the include globs and
+/// none of the exclude ones and is not generated, the files are processed
+/// (and reported) in the same order every time. The error tells why the
+/// directory can't be walked
+pub fn format_dir(dir: &Path, options: &DirOptions, mode: Mode) -> Result<ProjectReport, String> {
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    let walk = ignore::walk(dir, options.honor_ignores, options.follow_links);
+    let mut report = ProjectReport::default();
+    report.diagnostics.extend(walk.skipped());
+    match walk.links {
+        0 => {}
+        1 => report.diagnostics.push(String::from("1 symlinked directory not followed")),
+        links => report.diagnostics.push(format!("{} symlinked directories not followed", links)),
+    }
+
+    let matches = |globs: &[String], relative: &str| {
+        globs.iter().any(|glob| glob_match(glob.as_bytes(), relative.as_bytes()))
+    };
+    let mut jobs = Vec::new();
+    for path in walk.files {
+        let relative = relative_to(dir, &path);
+        if !matches(&options.includes, &relative) || matches(&options.excludes, &relative) {
+            continue;
+        }
+        if options.generated.is_generated(&path, &relative) {
+            report.generated.push(path.to_string_lossy().into_owned());
+        } else {
+            jobs.push((path, None));
+        }
+    }
+    add_files(&mut report, jobs, mode);
+    Ok(report)
+}
+
+/// List the files that are not formatted without changing anything. `target`
+/// is a Cargo package (its manifest or its directory) or, when there is no
+/// manifest, a directory whose files are selected by the options. The error
+/// tells why the package or the directory can't be walked
+pub fn check_project(target: &Path, options: &DirOptions) -> Result<CheckReport, String> {
+    let is_package = target.file_name().map_or(false, |name| name == "Cargo.toml") ||
+                     target.join("Cargo.toml").is_file();
+    let report = if is_package {
+        try!(format_cargo_project(target, &options.generated, Mode::Check))
+    } else {
+        try!(format_dir(target, options, Mode::Check))
+    };
+    Ok(CheckReport::new(report))
+}
+
+/// A file given to `check_files`
+#[derive(Clone, Debug, Default)]
+pub struct StagedFile {
+    pub path: String,
+    /// Checked instead of the file on disk, e.g. the staged version
+    pub contents: Option<String>,
+}
+
+/// Check the files in the order given, for pre-commit hooks that pass every
+/// staged file. The files that are not `.rs` files or that don't exist (and
+/// have no contents) are skipped with a diagnostic, nothing is modified
+pub fn check_files(files: Vec<StagedFile>) -> ProjectReport {
+    let mut report = ProjectReport::default();
+    let mut jobs = Vec::new();
+    for file in files {
+        let path = PathBuf::from(&file.path);
+        if path.extension().map_or(true, |ext| ext != "rs") {
+            report.diagnostics.push(format!("{} is not a Rust file, skipped", file.path));
+        } else if file.contents.is_none() && !path.is_file() {
+            report.diagnostics.push(format!("{} does not exist, skipped", file.path));
+        } else {
+            jobs.push((path, file.contents));
+        }
+    }
+    add_files(&mut report, jobs, Mode::Check);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use testing::{lock_globals, TempDir};
+    use super::*;
+
+    const FORMATTED: &'static str = "fn main() {\n    let x = 1;\n}\n";
+    const UNFORMATTED: &'static str = "fn main() {\n    let x=1;\n}\n";
+
+    fn statuses(report: &ProjectReport, dir: &Path) -> Vec<(String, FileStatus)> {
+        report.files.iter().map(|file| (relative_to(dir, Path::new(&file.path)), file.status)).collect()
+    }
+
+    #[test]
+    fn a_workspace_member_uses_its_own_config_or_the_workspace_one() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("workspace");
+        // above the workspace root, never reached
+        dir.write("rustfmt.toml", "newline_style = \"Windows\"\n");
+        dir.write("ws/Cargo.toml", "[workspace]\nmembers = [\"member\"]\n");
+        dir.write("ws/member/Cargo.toml", "[package]\nname = \"member\"\n");
+        dir.write("ws/member/src/main.rs", FORMATTED);
+        let member = dir.path().join("ws/member");
+        let check = || {
+            let report = format_cargo_project(&member, &Generated::default(), Mode::Check).unwrap();
+            statuses(&report, &member)
+        };
+        let main = String::from("src/main.rs");
+
+        assert_eq!(check(), vec![(main.clone(), FileStatus::Formatted)]);
+
+        dir.write("ws/rustfmt.toml", "newline_style = \"Windows\"\n");
+        assert_eq!(check(), vec![(main.clone(), FileStatus::NeedsFormat)]);
+
+        dir.write("ws/member/rustfmt.toml", "newline_style = \"Unix\"\n");
+        assert_eq!(check(), vec![(main, FileStatus::Formatted)]);
+    }
+
+    #[test]
+    fn a_package_is_formatted_from_its_targets_and_modules() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("package");
+        dir.write("Cargo.toml",
+                  "[package]\nname = \"a\"\n\n[[bin]]\nname = \"tool\"\npath = \"tools/tool.rs\"\n\n\
+                   [[example]]\nname = \"gone\"\npath = \"examples/gone.rs\"\n\n\
+                   [dependencies]\nlocal = { path = \"local\" }\nfar = { path = \"../far\" }\n");
+        dir.write("src/lib.rs", "mod a;\nmod b;\nmod missing;\n");
+        dir.write("src/a.rs", UNFORMATTED);
+        dir.write("src/b/mod.rs", FORMATTED);
+        dir.write("src/unreachable.rs", UNFORMATTED);
+        dir.write("tools/tool.rs", FORMATTED);
+        dir.write("tests/it.rs", FORMATTED);
+        dir.write("local/Cargo.toml", "[package]\nname = \"local\"\n");
+        dir.write("local/src/lib.rs", UNFORMATTED);
+
+        let report = format_cargo_project(&dir.path().join("Cargo.toml"), &Generated::default(), Mode::Check).unwrap();
+        assert_eq!(statuses(&report, dir.path()),
+                   vec![(String::from("local/src/lib.rs"), FileStatus::NeedsFormat),
+                        (String::from("src/a.rs"), FileStatus::NeedsFormat),
+                        (String::from("src/b/mod.rs"), FileStatus::Formatted),
+                        (String::from("src/lib.rs"), FileStatus::Formatted),
+                        (String::from("tests/it.rs"), FileStatus::Formatted),
+                        (String::from("tools/tool.rs"), FileStatus::Formatted)]);
+        assert_eq!(report.files[1].changed_lines, Some(1));
+        let diagnostics = report.diagnostics.join("\n");
+        assert!(diagnostics.contains("examples/gone.rs not found"), "{}", diagnostics);
+        assert!(diagnostics.contains("module missing of"), "{}", diagnostics);
+        assert!(diagnostics.contains("path dependency ../far is outside the package"), "{}", diagnostics);
+
+        let report = format_cargo_project(dir.path(), &Generated::default(), Mode::Overwrite).unwrap();
+        assert_eq!(report.failures(), (false, false));
+        assert_eq!(read(&dir.path().join("src/a.rs")).unwrap(), FORMATTED);
+        assert_eq!(read(&dir.path().join("src/unreachable.rs")).unwrap(), UNFORMATTED);
+        assert!(format_cargo_project(&dir.path().join("missing"), &Generated::default(), Mode::Check).is_err());
+    }
+
+    #[test]
+    fn a_directory_is_filtered_by_globs_in_a_stable_order() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("format-dir");
+        dir.write("z.rs", UNFORMATTED);
+        dir.write("a/b.rs", FORMATTED);
+        dir.write("a/vendored/c.rs", UNFORMATTED);
+        dir.write("a/notes.txt", UNFORMATTED);
+        dir.write("build/out.rs", UNFORMATTED);
+        let options = DirOptions {
+            excludes: vec![String::from("**/vendored/**"), String::from("build/*.rs")],
+            honor_ignores: false,
+            ..DirOptions::default()
+        };
+        let expected = vec![(String::from("a/b.rs"), FileStatus::Formatted),
+                            (String::from("z.rs"), FileStatus::NeedsFormat)];
+        for _ in 0..3 {
+            assert_eq!(statuses(&format_dir(dir.path(), &options, Mode::Check).unwrap(), dir.path()), expected);
+        }
+
+        let options = DirOptions { includes: vec![String::from("a/*.rs")], ..options };
+        let report = format_dir(dir.path(), &options, Mode::Check).unwrap();
+        assert_eq!(statuses(&report, dir.path()), vec![(String::from("a/b.rs"), FileStatus::Formatted)]);
+        assert!(format_dir(&dir.path().join("z.rs"), &options, Mode::Check).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_directories_are_not_followed_by_default() {
+        use std::os::unix::fs::symlink;
+
+        let _globals = lock_globals();
+        let dir = TempDir::new("format-dir-links");
+        let outside = TempDir::new("format-dir-outside");
+        dir.write("real/a.rs", FORMATTED);
+        outside.write("b.rs", FORMATTED);
+        symlink(outside.path(), dir.path().join("link")).unwrap();
+        let options = DirOptions { honor_ignores: false, ..DirOptions::default() };
+        let report = format_dir(dir.path(), &options, Mode::Check).unwrap();
+        assert_eq!(statuses(&report, dir.path()), vec![(String::from("real/a.rs"), FileStatus::Formatted)]);
+        assert_eq!(report.diagnostics, vec![String::from("1 symlinked directory not followed")]);
+
+        let options = DirOptions { follow_links: true, ..options };
+        let report = format_dir(dir.path(), &options, Mode::Check).unwrap();
+        assert_eq!(statuses(&report, dir.path()),
+                   vec![(String::from("link/b.rs"), FileStatus::Formatted),
+                        (String::from("real/a.rs"), FileStatus::Formatted)]);
+    }
+
+    #[test]
+    fn check_project_lists_the_offenders_apart_from_the_parse_errors() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("check-project");
+        dir.write("b.rs", UNFORMATTED);
+        dir.write("a.rs", "fn main() {\n    let x=1;\n    let y=2;\n}\n");
+        dir.write("c.rs", FORMATTED);
+        let options = DirOptions { honor_ignores: false, ..DirOptions::default() };
+        let report = check_project(dir.path(), &options).unwrap();
+        let offenders: Vec<(String, usize)> = report.needs_format
+            .iter()
+            .map(|offender| (relative_to(dir.path(), Path::new(&offender.path)), offender.changed_lines))
+            .collect();
+        assert_eq!(offenders, vec![(String::from("a.rs"), 2), (String::from("b.rs"), 1)]);
+        assert_eq!(report.status(), PROJECT_NEEDS_FORMAT);
+        // nothing is written
+        assert_eq!(read(&dir.path().join("b.rs")).unwrap(), UNFORMATTED);
+
+        dir.write("d.rs", "fn main() { @@ }\n");
+        let report = check_project(dir.path(), &options).unwrap();
+        assert_eq!(report.needs_format.len(), 2);
+        assert_eq!(report.parse_errors.len(), 1);
+        assert!(report.parse_errors[0].path.ends_with("d.rs"));
+        assert_eq!(report.status(), 2);
+
+        let clean = TempDir::new("check-project-clean");
+        clean.write("Cargo.toml", "[package]\nname = \"clean\"\n");
+        clean.write("src/main.rs", FORMATTED);
+        let report = check_project(&clean.path().join("Cargo.toml"), &options).unwrap();
+        assert_eq!((report.status(), report.needs_format.len()), (0, 0));
+    }
+
+    #[test]
+    fn staged_files_are_checked_in_order_and_the_rest_skipped() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("staged");
+        let on_disk = dir.write("disk.rs", FORMATTED).to_string_lossy().into_owned();
+        let staged = dir.path().join("staged.rs").to_string_lossy().into_owned();
+        let readme = dir.write("README.md", "# a\n").to_string_lossy().into_owned();
+        let gone = dir.path().join("gone.rs").to_string_lossy().into_owned();
+        let files = vec![StagedFile { path: staged.clone(), contents: Some(String::from(UNFORMATTED)) },
+                         StagedFile { path: readme.clone(), contents: None },
+                         StagedFile { path: on_disk.clone(), contents: None },
+                         StagedFile { path: gone.clone(), contents: None }];
+        let report = check_files(files);
+        assert_eq!(statuses(&report, dir.path()),
+                   vec![(String::from("staged.rs"), FileStatus::NeedsFormat),
+                        (String::from("disk.rs"), FileStatus::Formatted)]);
+        assert_eq!(report.diagnostics,
+                   vec![format!("{} is not a Rust file, skipped", readme),
+                        format!("{} does not exist, skipped", gone)]);
+        assert_eq!(report.failures(), (true, false));
+        // the staged contents are never written
+        assert!(!Path::new(&staged).exists());
+    }
+
+    #[test]
+    fn generated_files_are_reported_apart_unless_included() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("generated");
+        dir.write("proto.rs", &format!("// @generated by protoc\n{}", UNFORMATTED));
+        dir.write("bindings.rs", &format!("/* DO NOT EDIT */\n{}", UNFORMATTED));
+        // the marker is past the head of the file
+        dir.write("late.rs", &format!("{}// @generated\n", FORMATTED.repeat(4)));
+        dir.write("gen/out.rs", UNFORMATTED);
+        let generated = Generated { globs: vec![String::from("gen/**")], ..Generated::default() };
+        let options = DirOptions { honor_ignores: false, generated: generated, ..DirOptions::default() };
+        let report = format_dir(dir.path(), &options, Mode::Check).unwrap();
+        assert_eq!(statuses(&report, dir.path()), vec![(String::from("late.rs"), FileStatus::Formatted)]);
+        let mut skipped: Vec<String> = report.generated
+            .iter()
+            .map(|path| relative_to(dir.path(), Path::new(path)))
+            .collect();
+        skipped.sort();
+        assert_eq!(skipped, vec!["bindings.rs", "gen/out.rs", "proto.rs"]);
+
+        let custom = Generated { markers: vec![String::from("protoc")], ..Generated::default() };
+        assert!(custom.is_generated(&dir.path().join("proto.rs"), "proto.rs"));
+        assert!(!custom.is_generated(&dir.path().join("bindings.rs"), "bindings.rs"));
+
+        let options = DirOptions { generated: Generated { include: true, ..options.generated }, ..options };
+        let report = format_dir(dir.path(), &options, Mode::Check).unwrap();
+        assert_eq!(report.files.len(), 4);
+        assert!(report.generated.is_empty());
+    }
+
+    #[test]
+    fn follow_mods_formats_the_declared_modules() {
+        let _globals = lock_globals();
+        let dir = TempDir::new("follow-mods");
+        let lib = dir.write("src/lib.rs",
+                            "mod a;\nmod b;\n#[path = \"other/c.rs\"]\nmod c;\nmod missing;\n\
+                             mod inline {\n    mod d;\n}\n");
+        dir.write("src/a.rs", "mod sub;\n");
+        dir.write("src/a/sub.rs", UNFORMATTED);
+        dir.write("src/b/mod.rs", FORMATTED);
+        dir.write("src/other/c.rs", "#[path = \"../lib.rs\"]\nmod cycle;\n");
+        dir.write("src/inline/d.rs", FORMATTED);
+
+        let report = format_file(&lib, false, Mode::Check);
+        assert_eq!(statuses(&report, dir.path()), vec![(String::from("src/lib.rs"), FileStatus::Formatted)]);
+
+        let report = format_file(&lib, true, Mode::Check);
+        assert_eq!(statuses(&report, dir.path()),
+                   vec![(String::from("src/a/sub.rs"), FileStatus::NeedsFormat),
+                        (String::from("src/a.rs"), FileStatus::Formatted),
+                        (String::from("src/b/mod.rs"), FileStatus::Formatted),
+                        (String::from("src/inline/d.rs"), FileStatus::Formatted),
+                        (String::from("src/lib.rs"), FileStatus::Formatted),
+                        (String::from("src/other/c.rs"), FileStatus::Formatted)]);
+        let diagnostics = report.diagnostics.join("\n");
+        assert!(diagnostics.contains("module missing of"), "{}", diagnostics);
+        assert!(diagnostics.contains("module cycle of"), "{}", diagnostics);
+        assert!(diagnostics.contains("already included"), "{}", diagnostics);
+    }
+}
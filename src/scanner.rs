@@ -0,0 +1,227 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A very small lexer that is only able to tell apart code, comments and
+//! string/char literals. It never fails, it is not a parser and it does not
+//! know anything about the Rust grammar, it just allows the rest of the crate
+//! to avoid touching the contents of literals and comments when it performs
+//! mechanical transformations over a buffer that may not even parse.
+
+use std::cmp;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Code,
+    Comment,
+    Literal,
+}
+
+/// A contiguous region of the buffer, `start` and `end` are byte offsets
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub kind: Kind,
+    /// false for comments and literals that reach the end of the buffer
+    /// without being terminated
+    pub closed: bool,
+}
+
+/// Split the given buffer into code, comment and literal spans, the spans
+/// are returned in order and they cover the whole buffer
+pub fn scan(src: &str) -> Vec<Span> {
+    let bytes = src.as_bytes();
+    let mut spans = Vec::new();
+    let mut code_start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let token = match bytes[i] {
+            b'/' if peek(bytes, i + 1) == Some(b'/') => Some((Kind::Comment, line_comment(bytes, i))),
+            b'/' if peek(bytes, i + 1) == Some(b'*') => Some((Kind::Comment, block_comment(bytes, i))),
+            b'"' => Some((Kind::Literal, string(bytes, i + 1))),
+            b'\'' => char_literal(bytes, i + 1).map(|end| (Kind::Literal, end)),
+            b'b' | b'r' if starts_token(bytes, i) => prefixed_literal(bytes, i).map(|end| (Kind::Literal, end)),
+            _ => None,
+        };
+
+        match token {
+            Some((kind, (end, closed))) => {
+                if code_start < i {
+                    spans.push(Span { start: code_start, end: i, kind: Kind::Code, closed: true });
+                }
+                spans.push(Span { start: i, end: end, kind: kind, closed: closed });
+                i = end;
+                code_start = end;
+            }
+            None => i += 1,
+        }
+    }
+
+    if code_start < bytes.len() {
+        spans.push(Span { start: code_start, end: bytes.len(), kind: Kind::Code, closed: true });
+    }
+    spans
+}
+
+/// Returns a vector with the kind of every byte in the buffer, convenient
+/// when the caller needs random access instead of walking the spans
+pub fn kinds(src: &str) -> Vec<Kind> {
+    let mut kinds = Vec::with_capacity(src.len());
+    for span in scan(src) {
+        for _ in span.start..span.end {
+            kinds.push(span.kind);
+        }
+    }
+    kinds
+}
+
+fn peek(bytes: &[u8], i: usize) -> Option<u8> {
+    bytes.get(i).cloned()
+}
+
+fn is_ident(b: u8) -> bool {
+    b == b'_' || (b as char).is_alphanumeric() || b >= 0x80
+}
+
+fn starts_token(bytes: &[u8], i: usize) -> bool {
+    i == 0 || !is_ident(bytes[i - 1])
+}
+
+fn line_comment(bytes: &[u8], start: usize) -> (usize, bool) {
+    match bytes[start..].iter().position(|&b| b == b'\n') {
+        Some(pos) => (start + pos, true),
+        None => (bytes.len(), true),
+    }
+}
+
+fn block_comment(bytes: &[u8], start: usize) -> (usize, bool) {
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < bytes.len() {
+        if bytes[i] == b'/' && bytes[i + 1] == b'*' {
+            depth += 1;
+            i += 2;
+        } else if bytes[i] == b'*' && bytes[i + 1] == b'/' {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return (i, true);
+            }
+        } else {
+            i += 1;
+        }
+    }
+    (bytes.len(), false)
+}
+
+// `start` points right after the opening quote
+fn string(bytes: &[u8], start: usize) -> (usize, bool) {
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return (i + 1, true),
+            _ => i += 1,
+        }
+    }
+    (bytes.len(), false)
+}
+
+// `start` points right after the opening `r`, returns None if this is not
+// a raw string at all (e.g. a raw identifier or just an `r` identifier)
+fn raw_string(bytes: &[u8], start: usize) -> Option<(usize, bool)> {
+    let hashes = bytes[start..].iter().take_while(|&&b| b == b'#').count();
+    if peek(bytes, start + hashes) != Some(b'"') {
+        return None;
+    }
+    let mut i = start + hashes + 1;
+    while i < bytes.len() {
+        if bytes[i] == b'"' && bytes[i + 1..].iter().take(hashes).filter(|&&b| b == b'#').count() == hashes {
+            return Some((i + 1 + hashes, true));
+        }
+        i += 1;
+    }
+    Some((bytes.len(), false))
+}
+
+// `start` points right after the opening quote, lifetimes and labels are
+// not literals so None is returned for them
+fn char_literal(bytes: &[u8], start: usize) -> Option<(usize, bool)> {
+    match peek(bytes, start) {
+        Some(b'\\') => {
+            let mut i = start + 2;
+            while i < bytes.len() && bytes[i] != b'\'' && bytes[i] != b'\n' {
+                i += 1;
+            }
+            Some((cmp::min(i + 1, bytes.len()), i < bytes.len() && bytes[i] == b'\''))
+        }
+        Some(b'\n') | None => None,
+        Some(first) => {
+            // skip a whole (maybe multibyte) character before looking for the
+            // closing quote, otherwise 'a is a lifetime
+            let width = match first {
+                b if b < 0x80 => 1,
+                b if b < 0xe0 => 2,
+                b if b < 0xf0 => 3,
+                _ => 4,
+            };
+            if peek(bytes, start + width) == Some(b'\'') {
+                Some((start + width + 1, true))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// byte strings, byte chars and raw (byte) strings
+fn prefixed_literal(bytes: &[u8], start: usize) -> Option<(usize, bool)> {
+    match (bytes[start], peek(bytes, start + 1)) {
+        (b'b', Some(b'"')) => Some(string(bytes, start + 2)),
+        (b'b', Some(b'\'')) => char_literal(bytes, start + 2),
+        (b'b', Some(b'r')) => raw_string(bytes, start + 2),
+        (b'r', _) => raw_string(bytes, start + 1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(src: &str) -> Vec<(usize, usize, Kind, bool)> {
+        scan(src).iter().map(|span| (span.start, span.end, span.kind, span.closed)).collect()
+    }
+
+    #[test]
+    fn an_unterminated_escape_ends_at_the_buffer() {
+        assert_eq!(spans("'\\"), vec![(0, 2, Kind::Literal, false)]);
+        assert_eq!(spans("x = '\\n"), vec![(0, 4, Kind::Code, true), (4, 7, Kind::Literal, false)]);
+        assert_eq!(kinds("b'\\").len(), 3);
+    }
+
+    #[test]
+    fn an_unterminated_string_ends_at_the_buffer() {
+        assert_eq!(spans("\"a\\"), vec![(0, 3, Kind::Literal, false)]);
+        assert_eq!(spans("b\"a"), vec![(0, 3, Kind::Literal, false)]);
+        assert_eq!(spans("r#\"a\""), vec![(0, 5, Kind::Literal, false)]);
+    }
+
+    #[test]
+    fn multibyte_chars_are_literals_and_lifetimes_are_not() {
+        assert_eq!(spans("'\u{e9}'"), vec![(0, 4, Kind::Literal, true)]);
+        assert_eq!(spans("'\u{1f600}'"), vec![(0, 6, Kind::Literal, true)]);
+        assert_eq!(spans("&'a T"), vec![(0, 5, Kind::Code, true)]);
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unix domain socket server so several editor processes can share a single
+//! warm formatting process. Every connection is a daemon session with its own
+//! thread speaking the same newline delimited JSON protocol as the stdio
+//! daemon (see the `daemon` module), responses of a connection are never
+//! mixed with the ones of other connections.
+
+use std::fs;
+use std::io::{self, BufReader, ErrorKind};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use daemon::{self, Next};
+
+static STOP: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Ask the server to stop, this is safe to call from a signal handler
+pub fn stop() {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Remove the socket left behind by a server that is not running anymore, it
+/// is an error if the path is in use by a live server or it is not a socket
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{} is not a socket", path.display())));
+    }
+    if UnixStream::connect(path).is_ok() {
+        let reason = format!("a server is already listening on {}", path.display());
+        return Err(io::Error::new(ErrorKind::AddrInUse, reason));
+    }
+    fs::remove_file(path)
+}
+
+fn handle(stream: UnixStream) {
+    let reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(_) => return,
+    };
+    if let Ok(Next::StopServer) = daemon::serve_session(reader, stream) {
+        stop();
+    }
+}
+
+/// Listen on the given socket path until `stop` is called or a client sends a
+/// `stop_server` request, the socket is only accessible by the current user
+/// and it is removed when the server stops
+pub fn listen(path: &Path) -> io::Result<()> {
+    try!(remove_stale_socket(path));
+    // the socket must never be accessible by other users, not even for a moment
+    let mask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(mask) };
+    let listener = try!(listener);
+    let result = serve(&listener);
+    let _ = fs::remove_file(path);
+    result
+}
+
+fn serve(listener: &UnixListener) -> io::Result<()> {
+    // accept doesn't block so the stop flag is checked regularly
+    try!(listener.set_nonblocking(true));
+
+    while !STOP.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                try!(stream.set_nonblocking(false));
+                thread::spawn(move || handle(stream));
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the unit tests
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+    static ref GLOBALS: Mutex<()> = Mutex::new(());
+}
+
+thread_local! {
+    // allocations of the thread of at least this size are counted, 0 when
+    // nothing is counted
+    static COUNT_FROM: Cell<usize> = Cell::new(0);
+    static COUNTED: Cell<usize> = Cell::new(0);
+}
+
+/// The system allocator counting the big allocations of the threads inside
+/// `count_allocations`, a copy of a buffer shows up as one of them
+struct CountingAlloc;
+
+impl CountingAlloc {
+    fn count(&self, size: usize) {
+        let _ = COUNT_FROM.try_with(|from| {
+            if from.get() != 0 && size >= from.get() {
+                COUNTED.with(|counted| counted.set(counted.get() + 1));
+            }
+        });
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+/// How many allocations (reallocations included) of at least `size` bytes
+/// the closure makes in the current thread
+// only the exported functions are checked
+#[cfg_attr(not(feature = "ffi"), allow(dead_code))]
+pub fn count_allocations<F: FnOnce()>(size: usize, f: F) -> usize {
+    COUNTED.with(|counted| counted.set(0));
+    COUNT_FROM.with(|from| from.set(size));
+    f();
+    COUNT_FROM.with(|from| from.set(0));
+    COUNTED.with(|counted| counted.get())
+}
+
+/// Serialize the tests that change (or depend on) the global settings of the
+/// library, the tests run in parallel threads
+pub fn lock_globals() -> MutexGuard<'static, ()> {
+    GLOBALS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A directory of the system temp dir that is removed with its contents when
+/// it is dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let unique = format!("anaconda-{}-{}-{}", name, process::id(), NEXT_DIR.fetch_add(1, Ordering::SeqCst));
+        let path = env::temp_dir().join(unique);
+        fs::create_dir_all(&path).unwrap();
+        // symlinked temp dirs (e.g. macOS) would not match canonical paths
+        TempDir { path: path.canonicalize().unwrap() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the file (and its missing parents) and return its path
+    pub fn write(&self, relative: &str, contents: &str) -> PathBuf {
+        let path = self.path.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).and_then(|mut file| file.write_all(contents.as_bytes())).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
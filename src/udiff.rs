@@ -0,0 +1,229 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unified diff parsing, just enough of it to know which lines of a buffer
+//! were added or modified by a change (e.g. the output of `git diff`), and
+//! the unified diff of a formatted file for the hosts that show it.
+
+use std::cmp;
+
+use changes;
+
+// lines of context around the changes of a hunk
+const CONTEXT: usize = 3;
+
+struct Hunk {
+    // line of the diff where the hunk header is, for error messages
+    header_line: usize,
+    new_start: usize,
+    lines: Vec<(char, String)>,
+}
+
+struct FileDiff {
+    path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+/// Parse the `-a,b +c,d` ranges of an `@@` header into (b, c, d)
+fn parse_header(header: &str) -> Option<(usize, usize, usize)> {
+    let mut parts = header.trim_left_matches('@').split_whitespace();
+    let old = try_opt!(parts.next());
+    let new = try_opt!(parts.next());
+    if !old.starts_with('-') || !new.starts_with('+') {
+        return None;
+    }
+
+    fn range(range: &str) -> Option<(usize, usize)> {
+        let mut numbers = range[1..].splitn(2, ',');
+        let start = try_opt!(numbers.next().and_then(|n| n.parse().ok()));
+        let len = match numbers.next() {
+            Some(n) => try_opt!(n.parse().ok()),
+            None => 1,
+        };
+        Some((start, len))
+    }
+
+    let (_, old_len) = try_opt!(range(old));
+    let (new_start, new_len) = try_opt!(range(new));
+    Some((old_len, new_start, new_len))
+}
+
+/// Strip the `a/` or `b/` prefix and the optional timestamp of a file header
+fn header_path(header: &str) -> Option<String> {
+    let path = header[4..].split('\t').next().unwrap_or("").trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    if path.starts_with("a/") || path.starts_with("b/") {
+        return Some(String::from(&path[2..]));
+    }
+    Some(String::from(path))
+}
+
+fn parse(diff: &str) -> Result<Vec<FileDiff>, String> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    let mut lines = diff.lines().map(|line| line.trim_right_matches('\r')).enumerate().peekable();
+
+    while let Some((number, line)) = lines.next() {
+        if line.starts_with("diff ") {
+            files.push(FileDiff { path: None, hunks: Vec::new() });
+        } else if line.starts_with("--- ") {
+            if files.last().map(|f| !f.hunks.is_empty()).unwrap_or(true) {
+                files.push(FileDiff { path: None, hunks: Vec::new() });
+            }
+        } else if line.starts_with("+++ ") {
+            if let Some(file) = files.last_mut() {
+                file.path = header_path(line);
+            }
+        } else if line.starts_with("@@ ") {
+            let (mut old_left, new_start, mut new_left) = match parse_header(line) {
+                Some(ranges) => ranges,
+                None => return Err(format!("malformed hunk header at line {} of the diff", number + 1)),
+            };
+            let mut hunk = Hunk { header_line: number + 1, new_start: new_start, lines: Vec::new() };
+
+            while old_left > 0 || new_left > 0 {
+                let (_, body) = match lines.next() {
+                    Some(line) => line,
+                    None => return Err(format!("hunk at line {} of the diff is truncated", number + 1)),
+                };
+                // some tools strip the single space of empty context lines
+                let (kind, text) = if body.is_empty() { (' ', "") } else { (body.as_bytes()[0] as char, &body[1..]) };
+                match kind {
+                    ' ' if old_left > 0 && new_left > 0 => {
+                        old_left -= 1;
+                        new_left -= 1;
+                    }
+                    '-' if old_left > 0 => old_left -= 1,
+                    '+' if new_left > 0 => new_left -= 1,
+                    '\\' => continue,
+                    _ => {
+                        return Err(format!("hunk at line {} of the diff does not match its header",
+                                           number + 1))
+                    }
+                }
+                hunk.lines.push((kind, String::from(text)));
+            }
+
+            // skip the "\ No newline at end of file" marker after the last line
+            while lines.peek().map(|&(_, l)| l.starts_with('\\')).unwrap_or(false) {
+                lines.next();
+            }
+            match files.last_mut() {
+                Some(file) => file.hunks.push(hunk),
+                None => return Err(format!("hunk at line {} of the diff has no file header", number + 1)),
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the lines (1-based) of `code` that the unified diff marks as added
+/// or modified for the given path, lines next to a deletion are considered
+/// modified. Every hunk is checked against `code` so a stale diff is reported
+/// as an error instead of pointing to the wrong lines.
+pub fn changed_lines(diff: &str, path: &str, code: &str) -> Result<Vec<usize>, String> {
+    let files: Vec<FileDiff> = try!(parse(diff)).into_iter().filter(|f| !f.hunks.is_empty()).collect();
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let path = path.replace('\\', "/");
+    let file = match files.iter().position(|f| f.path.as_ref().map(|p| path.ends_with(&p[..])).unwrap_or(false)) {
+        Some(index) => &files[index],
+        None if files.len() == 1 => &files[0],
+        None => return Err(format!("the diff does not contain changes for {}", path)),
+    };
+
+    let buffer: Vec<&str> = code.lines().map(|line| line.trim_right_matches('\r')).collect();
+    let mut changed = Vec::new();
+    for hunk in &file.hunks {
+        // an empty new side starts at the line before the hunk
+        let has_new_side = hunk.lines.iter().any(|&(kind, _)| kind != '-');
+        let mut line = if has_new_side { hunk.new_start } else { hunk.new_start + 1 };
+        for &(kind, ref text) in &hunk.lines {
+            match kind {
+                '-' => {
+                    let near = if line > buffer.len() { buffer.len() } else { line };
+                    if near > 0 {
+                        changed.push(near);
+                    }
+                }
+                _ => {
+                    if line == 0 || buffer.get(line - 1) != Some(&&text[..]) {
+                        return Err(format!("hunk at line {} of the diff does not match the buffer at line {}, \
+                                            the diff may be stale",
+                                           hunk.header_line,
+                                           line));
+                    }
+                    if kind == '+' {
+                        changed.push(line);
+                    }
+                    line += 1;
+                }
+            }
+        }
+    }
+
+    changed.sort();
+    changed.dedup();
+    Ok(changed)
+}
+
+/// Returns the unified diff that turns the original contents of the file in
+/// `path` into the formatted ones, hunks closer than their context are merged
+pub fn unified(path: &str, original: &str, formatted: &str) -> String {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = formatted.lines().collect();
+    let mut groups: Vec<Vec<((usize, usize), (usize, usize))>> = Vec::new();
+    for hunk in changes::hunks(&old, &new) {
+        let ((start, _), _) = hunk;
+        match groups.last_mut() {
+            Some(group) if start <= (group[group.len() - 1].0).1 + 2 * CONTEXT => group.push(hunk),
+            _ => groups.push(vec![hunk]),
+        }
+    }
+
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", path, path);
+    for group in groups {
+        let ((old_first, _), (new_first, _)) = group[0];
+        let ((_, old_last), (_, new_last)) = group[group.len() - 1];
+        let (old_start, new_start) = (old_first.saturating_sub(CONTEXT), new_first.saturating_sub(CONTEXT));
+        let old_end = cmp::min(old_last + CONTEXT, old.len());
+        let new_end = cmp::min(new_last + CONTEXT, new.len());
+        // an empty side starts at the line before the hunk
+        let start = |start: usize, end: usize| if end > start { start + 1 } else { start };
+        diff.push_str(&format!("@@ -{},{} +{},{} @@\n",
+                               start(old_start, old_end),
+                               old_end - old_start,
+                               start(new_start, new_end),
+                               new_end - new_start));
+        let mut line = old_start;
+        for &((removed_start, removed_end), (added_start, added_end)) in &group {
+            for text in &old[line..removed_start] {
+                diff.push_str(&format!(" {}\n", text));
+            }
+            for text in &old[removed_start..removed_end] {
+                diff.push_str(&format!("-{}\n", text));
+            }
+            for text in &new[added_start..added_end] {
+                diff.push_str(&format!("+{}\n", text));
+            }
+            line = removed_end;
+        }
+        for text in &old[line..old_end] {
+            diff.push_str(&format!(" {}\n", text));
+        }
+    }
+    diff
+}
@@ -0,0 +1,190 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keep a directory formatted. A watch polls the modification times of the
+//! files under its directory that match its patterns and checks (or formats
+//! in place) every file that is created or modified once it stops changing.
+//! Polling works the same everywhere and a few hundred stats every now and
+//! then are cheap compared with formatting a single file.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use serde_json::Value;
+
+use {formatted_or_reason, render, size_limit_error, try_load_config};
+use OutputOptions;
+use ignore::{self, glob_match};
+use lock;
+
+// time between two scans of the directory
+const POLL_MS: u64 = 250;
+
+/// What to do with the files that change
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    // report the files that are not formatted
+    Check,
+    // format the files in place
+    Overwrite,
+}
+
+/// Patterns without `/` are matched against the file name, the rest against
+/// the path relative to the watched directory
+fn matches(patterns: &[String], relative: &Path) -> bool {
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    let name = relative.rsplit('/').next().unwrap_or("");
+    patterns.iter().any(|pattern| {
+        let target = if pattern.contains('/') { &relative[..] } else { name };
+        glob_match(pattern.as_bytes(), target.as_bytes())
+    })
+}
+
+/// Collect the matching files under the directory with their modification
+/// times and sizes, hidden files and directories are skipped and so are the
+/// ignored ones unless `honor_ignores` is false. Returns what was ignored,
+/// see `Walk::skipped`
+fn scan(root: &Path,
+        patterns: &[String],
+        honor_ignores: bool,
+        files: &mut HashMap<PathBuf, (SystemTime, u64)>)
+        -> Option<String> {
+    let walk = ignore::walk(root, honor_ignores, false);
+    let skipped = walk.skipped();
+    for path in walk.files {
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        let hidden = relative.components().any(|part| part.as_os_str().to_string_lossy().starts_with('.'));
+        if hidden || !matches(patterns, &relative) {
+            continue;
+        }
+        if let Ok(metadata) = fs::metadata(&path) {
+            if let Ok(modified) = metadata.modified() {
+                files.insert(path, (modified, metadata.len()));
+            }
+        }
+    }
+    skipped
+}
+
+/// Check or format the file, the result is the status of the event
+fn process(path: &Path, mode: Mode) -> Result<&'static str, String> {
+    // held until the file is written
+    let lock = if mode == Mode::Overwrite { Some(try!(lock::lock(path))) } else { None };
+    let mut code = String::new();
+    try!(File::open(path).and_then(|mut file| file.read_to_string(&mut code)).map_err(|e| e.to_string()));
+    let config = try!(try_load_config(Some(path.to_string_lossy().into_owned())).map_err(|e| e.to_string()));
+    let (summary, output) = render(code.clone(), config, OutputOptions::default());
+    let output = try!(formatted_or_reason(summary, output));
+    if output == code {
+        return Ok("formatted");
+    }
+    match lock {
+        Some(lock) => try!(lock.write(output.as_bytes()).map_err(|e| e.to_string())),
+        None => return Ok("needs_format"),
+    }
+    Ok("reformatted")
+}
+
+/// A directory being watched, the watch stops when it is dropped
+pub struct Watch {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watch {
+    /// Watch the files of `dir` that match the patterns, `notify` gets an
+    /// event when the first scan is done (with what was ignored) and an event
+    /// for every file that is processed
+    pub fn start(dir: PathBuf,
+                 patterns: Vec<String>,
+                 mode: Mode,
+                 honor_ignores: bool,
+                 notify: Box<Fn(Value) + Send>)
+                 -> Watch {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut known = HashMap::new();
+            let skipped = scan(&dir, &patterns, honor_ignores, &mut known);
+            notify(json!({"event": "watch", "path": dir.to_string_lossy(), "status": "started", "skipped": skipped}));
+            // changed files wait until they look the same in two scans in a row
+            let mut pending: HashMap<PathBuf, (SystemTime, u64)> = HashMap::new();
+            while !stopped.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(POLL_MS));
+                let mut current = HashMap::new();
+                scan(&dir, &patterns, honor_ignores, &mut current);
+
+                let mut ready = Vec::new();
+                for (path, &state) in &current {
+                    if known.get(path) == Some(&state) {
+                        continue;
+                    }
+                    if pending.get(path) == Some(&state) {
+                        ready.push(path.clone());
+                    } else {
+                        pending.insert(path.clone(), state);
+                    }
+                }
+                pending.retain(|path, _| current.contains_key(path));
+
+                for path in ready {
+                    pending.remove(&path);
+                    let size = current.get(&path).map_or(0, |&(_, size)| size);
+                    let result = match size_limit_error(size as usize) {
+                        Some(reason) => Err(("skipped", reason)),
+                        None => process(&path, mode).map_err(|reason| ("error", reason)),
+                    };
+                    let event = match result {
+                        Ok(status) => json!({"event": "watch", "path": path.to_string_lossy(), "status": status}),
+                        Err((status, reason)) => {
+                            json!({"event": "watch", "path": path.to_string_lossy(), "status": status, "error": reason})
+                        }
+                    };
+                    notify(event);
+                    // our own write must not trigger the file again
+                    if let Ok(metadata) = fs::metadata(&path) {
+                        if let Ok(modified) = metadata.modified() {
+                            current.insert(path, (modified, metadata.len()));
+                        }
+                    }
+                }
+                // pending files keep their old state until they are processed
+                known.retain(|path, _| current.contains_key(path));
+                for (path, state) in current {
+                    if !pending.contains_key(&path) {
+                        known.insert(path, state);
+                    }
+                }
+            }
+        });
+        Watch {
+            stop: stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mechanical whitespace transformations that never parse the code, so they
+//! work on buffers that rustfmt would refuse. String and char literals are
+//! always left untouched, including the line endings inside them.
+
+use backend::{Config, NewlineStyle};
+use scanner::{self, Kind};
+
+fn newline_for(style: NewlineStyle) -> &'static str {
+    match style {
+        NewlineStyle::Windows => "\r\n",
+        NewlineStyle::Unix => "\n",
+        NewlineStyle::Native => if cfg!(windows) { "\r\n" } else { "\n" },
+    }
+}
+
+/// Re-create the given indentation (that may mix tabs and spaces) using tabs
+/// or spaces as the configuration says
+fn indentation(whitespace: &str, config: &Config) -> String {
+    let tab_spaces = if config.tab_spaces() == 0 { 1 } else { config.tab_spaces() };
+    let mut width = 0;
+    for c in whitespace.chars() {
+        width = if c == '\t' { (width / tab_spaces + 1) * tab_spaces } else { width + 1 };
+    }
+    if config.hard_tabs() {
+        format!("{}{}", "\t".repeat(width / tab_spaces), " ".repeat(width % tab_spaces))
+    } else {
+        " ".repeat(width)
+    }
+}
+
+/// Strip trailing whitespace, normalize the indentation to tabs or spaces and
+/// the line endings to the configured newline style and make sure that the
+/// buffer ends with a newline, an empty buffer stays empty
+pub fn cleanup(code: &str, config: &Config) -> String {
+    let kinds = scanner::kinds(code);
+    let in_literal = |i: usize| i < kinds.len() && kinds[i] == Kind::Literal;
+    let newline = newline_for(config.newline_style());
+    let mut output = String::with_capacity(code.len());
+    let mut start = 0;
+
+    while start < code.len() {
+        let (mut end, next) = match code[start..].find('\n') {
+            Some(pos) => (start + pos, Some(start + pos + 1)),
+            None => (code.len(), None),
+        };
+        let ending_in_literal = next.is_some() && in_literal(end);
+        if !ending_in_literal && code[start..end].ends_with('\r') {
+            end -= 1;
+        }
+
+        // leading whitespace that continues a multiline literal is content
+        let mut line_start = start;
+        if !(start > 0 && in_literal(start) && in_literal(start - 1)) {
+            let line = &code[start..end];
+            line_start += line.len() - line.trim_left_matches(|c| c == ' ' || c == '\t').len();
+            output.push_str(&indentation(&code[start..line_start], config));
+        }
+
+        if ending_in_literal {
+            output.push_str(&code[line_start..end]);
+            output.push('\n');
+        } else {
+            while end > line_start && (code.as_bytes()[end - 1] as char).is_whitespace() &&
+                  !in_literal(end - 1) {
+                end -= 1;
+            }
+            output.push_str(&code[line_start..end]);
+            output.push_str(newline);
+        }
+
+        start = match next {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    output
+}
+
+/// Strip trailing whitespace and make sure that the buffer ends with a
+/// newline, nothing else: the indentation and the line endings are kept and
+/// an empty buffer stays empty
+pub fn normalize(code: &str) -> String {
+    let kinds = scanner::kinds(code);
+    let in_literal = |i: usize| i < kinds.len() && kinds[i] == Kind::Literal;
+    let mut output = String::with_capacity(code.len() + 1);
+    let mut start = 0;
+
+    for line in code.split('\n') {
+        let content = line.trim_right_matches('\r');
+        let mut end = start + content.len();
+        while end > start && (code.as_bytes()[end - 1] == b' ' || code.as_bytes()[end - 1] == b'\t') &&
+              !in_literal(end - 1) {
+            end -= 1;
+        }
+        output.push_str(&code[start..end]);
+        output.push_str(&line[content.len()..]);
+        start += line.len() + 1;
+        if start <= code.len() {
+            output.push('\n');
+        }
+    }
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+/// Returns the lines (1-based) of the buffer that end with whitespace or
+/// indent with both tabs and spaces, with the reason. Whitespace inside a
+/// literal is content so it is not reported
+pub fn report(code: &str) -> Vec<(usize, &'static str)> {
+    let kinds = scanner::kinds(code);
+    let in_literal = |i: usize| i < kinds.len() && kinds[i] == Kind::Literal;
+    let mut problems = Vec::new();
+    let mut start = 0;
+
+    for (number, line) in code.split('\n').enumerate() {
+        let end = start + line.trim_right_matches('\r').len();
+        let content = &code[start..end];
+        let indent = &content[..content.len() - content.trim_left_matches(|c| c == ' ' || c == '\t').len()];
+        if !in_literal(start) && indent.contains(' ') && indent.contains('\t') {
+            problems.push((number + 1, "mixed indentation"));
+        }
+        let trimmed = content.trim_right_matches(|c| c == ' ' || c == '\t').len();
+        if trimmed < content.len() && !in_literal(start + trimmed) {
+            problems.push((number + 1, "trailing whitespace"));
+        }
+        start += line.len() + 1;
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_strips_trailing_whitespace_and_ends_with_a_newline() {
+        assert_eq!(normalize("fn main() {  \n    let x = 1;\t\n}"), "fn main() {\n    let x = 1;\n}\n");
+        assert_eq!(normalize("fn main() {}\r\n  \r\n"), "fn main() {}\r\n\r\n");
+        assert_eq!(normalize(""), "");
+    }
+
+    #[test]
+    fn normalize_keeps_the_whitespace_of_literals() {
+        let code = "let s = \"a  \nb\";   \n";
+        assert_eq!(normalize(code), "let s = \"a  \nb\";\n");
+    }
+
+    #[test]
+    fn normalize_does_not_need_the_code_to_parse() {
+        assert_eq!(normalize("fn main() { @@   \n    let x=1 \n"), "fn main() { @@\n    let x=1\n");
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Drives `anaconda-rust --daemon` as a subprocess, the way the hosts that
+//! can't load the library use it.
+
+#[macro_use]
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdout, Command, Stdio};
+
+use serde_json::Value;
+
+/// The daemon binary, cargo builds it next to the directory of the tests
+fn binary() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    let mut dir = exe.parent().unwrap().to_path_buf();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+    dir.join(format!("anaconda-rust{}", env::consts::EXE_SUFFIX))
+}
+
+fn spawn() -> (Child, BufReader<ChildStdout>) {
+    spawn_in(&env::temp_dir())
+}
+
+fn spawn_in(dir: &Path) -> (Child, BufReader<ChildStdout>) {
+    let mut child = Command::new(binary())
+        .arg("--daemon")
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    (child, stdout)
+}
+
+fn send(child: &mut Child, line: &str) {
+    let stdin = child.stdin.as_mut().unwrap();
+    writeln!(stdin, "{}", line).unwrap();
+    stdin.flush().unwrap();
+}
+
+fn receive(stdout: &mut BufReader<ChildStdout>) -> Value {
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+fn format_request(code: &str) -> String {
+    let path = env::temp_dir().to_string_lossy().into_owned();
+    serde_json::to_string(&json!({"method": "format", "params": {"code": code, "path": path}})).unwrap()
+}
+
+#[test]
+fn malformed_requests_get_an_error_object() {
+    let (mut child, mut stdout) = spawn();
+    send(&mut child, "{\"method\": ");
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], Value::Null);
+    assert!(response["error"].as_str().unwrap().starts_with("malformed request"));
+    send(&mut child, "{\"method\": \"version\"}");
+    assert_eq!(receive(&mut stdout)["status"], 0);
+    drop(child.stdin.take());
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn responses_follow_the_order_of_the_requests() {
+    let (mut child, mut stdout) = spawn();
+    let codes = ["fn a() {\n    let x=1;\n}\n", "fn b() {\n    let y=2;\n}\n", "fn c() {\n    let z=3;\n}\n"];
+    for code in codes.iter() {
+        send(&mut child, &format_request(code));
+        send(&mut child, "{\"method\": \"version\"}");
+    }
+    for code in codes.iter() {
+        let response = receive(&mut stdout);
+        assert_eq!(response["status"], 0);
+        assert_eq!(response["result"].as_str().unwrap(), code.replace("=", " = "));
+        assert_eq!(receive(&mut stdout)["result"], env!("CARGO_PKG_VERSION"));
+    }
+    drop(child.stdin.take());
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn exits_on_end_of_input() {
+    let (mut child, mut stdout) = spawn();
+    drop(child.stdin.take());
+    assert!(child.wait().unwrap().success());
+    let mut rest = String::new();
+    stdout.read_line(&mut rest).unwrap();
+    assert_eq!(rest, "");
+}
+
+#[test]
+fn exits_on_shutdown() {
+    let (mut child, mut stdout) = spawn();
+    send(&mut child, "{\"method\": \"shutdown\"}");
+    send(&mut child, "{\"method\": \"version\"}");
+    assert_eq!(receive(&mut stdout)["status"], 0);
+    // the input is still open, the daemon must not wait for it
+    assert!(child.wait().unwrap().success());
+    let mut rest = String::new();
+    stdout.read_line(&mut rest).unwrap();
+    assert_eq!(rest, "");
+}
+
+#[cfg(unix)]
+#[test]
+fn survives_a_removed_current_directory() {
+    let dir = env::temp_dir().join(format!("anaconda-removed-cwd-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (mut child, mut stdout) = spawn_in(&dir);
+    fs::remove_dir(&dir).unwrap();
+    // the config lookup reads the current directory too
+    send(&mut child, &format_request("fn main() {\n    let x=1;\n}\n"));
+    let response = receive(&mut stdout);
+    assert_eq!(response["status"], 0);
+    assert_eq!(response["result"], "fn main() {\n    let x = 1;\n}\n");
+    drop(child.stdin.take());
+    assert!(child.wait().unwrap().success());
+}
@@ -0,0 +1,80 @@
+// Copyright 2016 Oscar Campos <damnwidget@gmail.com>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Round trips of `FormatOutcome` through the checked-in JSON fixtures. A
+//! change of the schema that breaks the payloads of older versions (a field
+//! renamed, retyped or made required) fails here.
+
+extern crate rustanaconda;
+extern crate serde_json;
+
+use rustanaconda::outcome::{Edit, FormatOutcome, Severity, Timings, SCHEMA_VERSION};
+use serde_json::Value;
+
+const FORMATTED: &'static str = include_str!("fixtures/outcome_formatted.json");
+const ERROR: &'static str = include_str!("fixtures/outcome_error.json");
+const MINIMAL: &'static str = include_str!("fixtures/outcome_minimal.json");
+
+/// Deserialize the fixture, serialize it back and compare both JSON values
+fn round_trip(fixture: &str) -> FormatOutcome {
+    let outcome: FormatOutcome = serde_json::from_str(fixture).unwrap();
+    let serialized: Value = serde_json::from_str(&outcome.to_json()).unwrap();
+    let again: FormatOutcome = serde_json::from_value(serialized.clone()).unwrap();
+    assert_eq!(again, outcome);
+    assert_eq!(serialized["schema_version"], SCHEMA_VERSION);
+    outcome
+}
+
+#[test]
+fn formatted_outcome_round_trips() {
+    let outcome = round_trip(FORMATTED);
+    assert_eq!(serde_json::to_value(&outcome).unwrap(), serde_json::from_str::<Value>(FORMATTED).unwrap());
+    assert_eq!(outcome.output, Some(String::from("fn main() {\n    let x = 1;\n}\n")));
+    assert_eq!(outcome.timings,
+               Timings {
+                   config_ms: 2,
+                   format_ms: 15,
+               });
+    assert_eq!(outcome.edits,
+               vec![Edit {
+                        start_line: 1,
+                        end_line: 2,
+                        lines: vec![String::from("    let x = 1;")],
+                    }]);
+}
+
+#[test]
+fn error_outcome_round_trips() {
+    let outcome = round_trip(ERROR);
+    assert_eq!(serde_json::to_value(&outcome).unwrap(), serde_json::from_str::<Value>(ERROR).unwrap());
+    assert_eq!(outcome.output, None);
+    assert_eq!(outcome.diagnostics[0].severity, Severity::Error);
+}
+
+#[test]
+fn optional_fields_default_when_missing() {
+    let outcome = round_trip(MINIMAL);
+    assert!(outcome.diagnostics.is_empty());
+    assert_eq!(outcome.timings, Timings::default());
+    assert!(outcome.edits.is_empty());
+}
+
+#[test]
+fn new_outcomes_have_the_fields_of_the_fixtures() {
+    let outcome = rustanaconda::format_outcome("fn main() {}\n", &Default::default());
+    let outcome: Value = serde_json::from_str(&outcome.to_json()).unwrap();
+    let fixture: Value = serde_json::from_str(FORMATTED).unwrap();
+    let keys = |value: &Value| value.as_object().unwrap().keys().cloned().collect::<Vec<String>>();
+    assert_eq!(keys(&outcome), keys(&fixture));
+}